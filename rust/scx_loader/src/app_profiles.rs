@@ -0,0 +1,857 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2024 Vladislav Nepogodin <vnepogodin@cachyos.org>
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs;
+use std::future::pending;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use std::os::unix::fs::DirEntryExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use nix::sys::inotify::AddWatchFlags;
+use nix::sys::inotify::InitFlags;
+use nix::sys::inotify::Inotify;
+use scx_loader::config::AppProfiles;
+use scx_loader::config::AppRule;
+use scx_loader::dbus::LoaderClientProxy;
+use scx_loader::SchedMode;
+use scx_loader::SupportedSched;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::watch;
+use tokio::time::Duration;
+use tokio::time::Instant;
+use zbus::Connection;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Polls between re-reading the attributes of a known process, to notice
+/// exec, comm changes and cgroup migrations
+const PROC_REFRESH_POLLS: u64 = 10;
+/// Processes seen for fewer polls are re-read on every poll, as they're
+/// likely to exec right after fork
+const PROC_FRESH_POLLS: u64 = 3;
+
+/// Match the text against a glob pattern, where '*' matches any sequence of
+/// characters and '?' a single character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last '*' and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // let the last '*' consume one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn has_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Patterns without a '/' are matched against the file name of the executable
+fn exe_matches(pattern: &str, exe: &str) -> bool {
+    if pattern.contains('/') {
+        return glob_match(pattern, exe);
+    }
+    let file_name = exe.rsplit('/').next().unwrap_or(exe);
+    glob_match(pattern, file_name)
+}
+
+/// Process attributes referenced by any rule
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ProcFields {
+    comm: bool,
+    exe: bool,
+    cgroup: bool,
+}
+
+impl ProcFields {
+    fn new(rules: &[&AppRule]) -> Self {
+        Self {
+            comm: rules.iter().any(|rule| rule.comm.is_some()),
+            exe: rules.iter().any(|rule| rule.exe.is_some()),
+            cgroup: rules.iter().any(|rule| rule.cgroup.is_some()),
+        }
+    }
+}
+
+/// Attributes of a process the rules are matched against, only the ones
+/// referenced by any rule are read
+#[derive(Debug, Default)]
+struct ProcInfo {
+    comm: Option<String>,
+    exe: Option<String>,
+    cgroup: Option<String>,
+}
+
+impl ProcInfo {
+    fn read(proc_dir: &Path, fields: ProcFields) -> Self {
+        let mut info = ProcInfo::default();
+        if fields.comm {
+            info.comm = fs::read_to_string(proc_dir.join("comm"))
+                .ok()
+                .map(|comm| comm.trim_end().to_owned());
+        }
+        if fields.exe {
+            // kernel threads have no executable
+            info.exe = fs::read_link(proc_dir.join("exe"))
+                .ok()
+                .map(|exe| exe.to_string_lossy().into_owned());
+        }
+        if fields.cgroup {
+            // the unified hierarchy is listed as "0::<path>"
+            info.cgroup = fs::read_to_string(proc_dir.join("cgroup"))
+                .ok()
+                .and_then(|content| {
+                    content
+                        .lines()
+                        .find_map(|line| line.strip_prefix("0::").map(str::to_owned))
+                });
+        }
+        info
+    }
+}
+
+struct CachedProc {
+    /// Inode of the /proc/<pid> directory, which changes when the pid is
+    /// reused by another process
+    ino: u64,
+    info: ProcInfo,
+    nr_seen: u64,
+}
+
+/// Attributes of the running processes carried over between polls, so that
+/// each poll only lists /proc and reads the new processes and a fraction of
+/// the known ones
+#[derive(Default)]
+struct ProcCache {
+    procs: HashMap<u32, CachedProc>,
+    fields: ProcFields,
+    nr_polls: u64,
+}
+
+/// Whether the attributes of a known process are re-read on this poll. The
+/// refreshes are spread over the polls by pid.
+fn needs_refresh(pid: u32, nr_seen: u64, nr_polls: u64) -> bool {
+    nr_seen < PROC_FRESH_POLLS || (nr_polls + pid as u64).is_multiple_of(PROC_REFRESH_POLLS)
+}
+
+impl ProcCache {
+    /// Bring the cache up to date with the running processes
+    fn update(&mut self, fields: ProcFields) {
+        if fields != self.fields {
+            self.procs.clear();
+            self.fields = fields;
+        }
+        self.nr_polls += 1;
+
+        let mut procs = HashMap::with_capacity(self.procs.len());
+        for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let ino = entry.ino();
+            let proc = match self.procs.remove(&pid).filter(|proc| proc.ino == ino) {
+                Some(mut proc) => {
+                    if needs_refresh(pid, proc.nr_seen, self.nr_polls) {
+                        proc.info = ProcInfo::read(&entry.path(), fields);
+                    }
+                    proc.nr_seen += 1;
+                    proc
+                }
+                None => CachedProc {
+                    ino,
+                    info: ProcInfo::read(&entry.path(), fields),
+                    nr_seen: 1,
+                },
+            };
+            procs.insert(pid, proc);
+        }
+        // processes which exited are dropped with the old map
+        self.procs = procs;
+    }
+}
+
+fn rule_matches(rule: &AppRule, info: &ProcInfo) -> bool {
+    let field_matches = |pattern: &Option<String>, value: &Option<String>, exe: bool| {
+        let Some(pattern) = pattern else {
+            return true;
+        };
+        value.as_deref().is_some_and(|value| match exe {
+            true => exe_matches(pattern, value),
+            false => glob_match(pattern, value),
+        })
+    };
+    field_matches(&rule.exe, &info.exe, true)
+        && field_matches(&rule.comm, &info.comm, false)
+        && field_matches(&rule.cgroup, &info.cgroup, false)
+}
+
+/// Rules which only name a specific cgroup are checked through its
+/// cgroup.events file, without scanning the processes
+fn cgroup_events_path(rule: &AppRule) -> Option<String> {
+    if rule.exe.is_some() || rule.comm.is_some() {
+        return None;
+    }
+    let cgroup = rule.cgroup.as_deref()?;
+    if has_wildcard(cgroup) {
+        return None;
+    }
+    Some(format!(
+        "{CGROUP_ROOT}/{}/cgroup.events",
+        cgroup.trim_matches('/')
+    ))
+}
+
+fn cgroup_populated(events_path: &str) -> bool {
+    fs::read_to_string(events_path)
+        .is_ok_and(|content| content.lines().any(|line| line.trim_end() == "populated 1"))
+}
+
+/// Find the rule with the highest priority matching any running process
+fn find_matching_rule(rules: &[AppRule], cache: &mut ProcCache) -> Option<AppRule> {
+    let mut matched = vec![false; rules.len()];
+    let mut scan_rules: Vec<(usize, &AppRule)> = vec![];
+    for (idx, rule) in rules.iter().enumerate() {
+        match cgroup_events_path(rule) {
+            Some(events_path) => matched[idx] = cgroup_populated(&events_path),
+            None => scan_rules.push((idx, rule)),
+        }
+    }
+
+    if scan_rules.is_empty() {
+        cache.procs.clear();
+    } else {
+        let referenced: Vec<&AppRule> = scan_rules.iter().map(|(_, rule)| *rule).collect();
+        cache.update(ProcFields::new(&referenced));
+        for proc in cache.procs.values() {
+            for (idx, rule) in &scan_rules {
+                if !matched[*idx] && rule_matches(rule, &proc.info) {
+                    matched[*idx] = true;
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..rules.len()).collect();
+    // stable sort, so the first rule in the config wins on a tie
+    order.sort_by_key(|&idx| Reverse(rules[idx].priority));
+    order
+        .into_iter()
+        .find(|&idx| matched[idx])
+        .map(|idx| rules[idx].clone())
+}
+
+/// Lowest pid of the processes matching a rule which isn't checked through
+/// cgroup.events
+fn matching_pid(rule: &AppRule, cache: &ProcCache) -> Option<u32> {
+    if cgroup_events_path(rule).is_some() {
+        return None;
+    }
+    cache
+        .procs
+        .iter()
+        .filter(|(_, proc)| rule_matches(rule, &proc.info))
+        .map(|(pid, _)| *pid)
+        .min()
+}
+
+/// pidfd of the process, which becomes readable once the process exits
+fn pidfd_open(pid: u32) -> std::io::Result<AsyncFd<OwnedFd>> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+    AsyncFd::with_interest(fd, Interest::READABLE)
+}
+
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+/// Watch the cgroup.events files, the ones of cgroups which don't exist yet
+/// are left to the polling
+fn watch_cgroup_events(events_paths: &[String]) -> std::io::Result<AsyncFd<InotifyFd>> {
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    for events_path in events_paths {
+        if let Err(err) = inotify.add_watch(events_path.as_str(), AddWatchFlags::IN_MODIFY) {
+            log::debug!("Failed to watch {events_path}: {err}");
+        }
+    }
+    AsyncFd::with_interest(InotifyFd(inotify), Interest::READABLE)
+}
+
+/// Wakes the polling up as soon as the process matching the active rule
+/// exits or a cgroup-only rule's cgroup.events changes. New processes are
+/// still only picked up by the polling.
+#[derive(Default)]
+struct MatchWatch {
+    /// Watched process and its pidfd, None once it exited or if it couldn't
+    /// be opened
+    pidfd: Option<(u32, Option<AsyncFd<OwnedFd>>)>,
+    events_paths: Vec<String>,
+    inotify: Option<AsyncFd<InotifyFd>>,
+}
+
+impl MatchWatch {
+    fn update(&mut self, pid: Option<u32>, events_paths: Vec<String>) {
+        if self.pidfd.as_ref().map(|(pid, _)| *pid) != pid {
+            self.pidfd = pid.map(|pid| {
+                let pidfd = pidfd_open(pid)
+                    .inspect_err(|err| log::debug!("Failed to open pidfd of {pid}: {err}"))
+                    .ok();
+                (pid, pidfd)
+            });
+        }
+        if events_paths != self.events_paths {
+            self.inotify = match events_paths.is_empty() {
+                true => None,
+                false => watch_cgroup_events(&events_paths)
+                    .inspect_err(|err| log::warn!("Failed to watch cgroup.events: {err}"))
+                    .ok(),
+            };
+            self.events_paths = events_paths;
+        }
+    }
+
+    /// Resolves once the watched process exited or a cgroup.events changed
+    async fn changed(&mut self) {
+        let exited = async {
+            match self.pidfd.as_ref().and_then(|(_, pidfd)| pidfd.as_ref()) {
+                Some(pidfd) => {
+                    let _ = pidfd.readable().await;
+                }
+                None => pending().await,
+            }
+        };
+        let events = async {
+            let Some(inotify) = &self.inotify else {
+                return pending().await;
+            };
+            loop {
+                let Ok(mut guard) = inotify.readable().await else {
+                    return pending().await;
+                };
+                // the readiness is cleared once the events are drained
+                if guard
+                    .try_io(|inotify| Ok(inotify.get_ref().0.read_events()?))
+                    .is_ok()
+                {
+                    return;
+                }
+            }
+        };
+
+        let exited = tokio::select! {
+            _ = exited => true,
+            _ = events => false,
+        };
+        if let (true, Some((_, pidfd))) = (exited, &mut self.pidfd) {
+            *pidfd = None;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ProfileChange {
+    Activate(AppRule),
+    Revert,
+}
+
+/// Decides when to switch profiles. A rule with a higher priority than the
+/// active one applies right away, anything else only after the grace period.
+#[derive(Debug, Default)]
+struct ProfileSwitcher {
+    active: Option<AppRule>,
+    /// Since when the active rule no longer is the best match
+    leaving_since: Option<Instant>,
+}
+
+impl ProfileSwitcher {
+    fn update(
+        &mut self,
+        matched: Option<AppRule>,
+        now: Instant,
+        grace_period: Duration,
+    ) -> Option<ProfileChange> {
+        if matched == self.active {
+            self.leaving_since = None;
+            return None;
+        }
+
+        let preempts = match (&matched, &self.active) {
+            (Some(_), None) => true,
+            (Some(new), Some(active)) => new.priority > active.priority,
+            (None, _) => false,
+        };
+        if !preempts {
+            let since = *self.leaving_since.get_or_insert(now);
+            if now.duration_since(since) < grace_period {
+                return None;
+            }
+        }
+
+        self.leaving_since = None;
+        self.active = matched.clone();
+        Some(match matched {
+            Some(rule) => ProfileChange::Activate(rule),
+            None => ProfileChange::Revert,
+        })
+    }
+
+    /// Time left until a pending switch is due, if any
+    fn pending(&self, now: Instant, grace_period: Duration) -> Option<Duration> {
+        self.leaving_since
+            .map(|since| grace_period.saturating_sub(now.duration_since(since)))
+    }
+}
+
+/// Scheduler which was running before the first profile applied
+struct SavedSched {
+    sched: Option<SupportedSched>,
+    mode: SchedMode,
+    args: Vec<String>,
+}
+
+/// Switches the scheduler through the loader interface, so the state
+/// reported to the clients stays consistent
+struct ProfileApplier<'a> {
+    loader: LoaderClientProxy<'a>,
+    saved: Option<SavedSched>,
+    /// Scheduler and mode set by the active profile
+    applied: Option<(String, SchedMode)>,
+}
+
+impl ProfileApplier<'_> {
+    async fn current(&self) -> Result<(String, SchedMode)> {
+        Ok((
+            self.loader.current_scheduler().await?,
+            self.loader.scheduler_mode().await?,
+        ))
+    }
+
+    async fn activate(&mut self, rule: &AppRule) -> Result<()> {
+        let current = self.current().await?;
+        // keep the scheduler the user switched to while a profile was active
+        if self.saved.is_none() || self.applied.as_ref() != Some(&current) {
+            let (current_scx, mode) = current;
+            self.saved = Some(SavedSched {
+                sched: SupportedSched::from_str(&current_scx).ok(),
+                mode,
+                args: self.loader.current_args().await?,
+            });
+        }
+
+        let sched_bin_name: &str = rule.sched.clone().into();
+        log::info!(
+            "app profile {:?} applies, switching to {sched_bin_name}",
+            rule.display_name()
+        );
+        let mode = match &rule.args {
+            Some(args) => {
+                self.loader
+                    .switch_scheduler_with_args(rule.sched.clone(), args)
+                    .await?;
+                SchedMode::Auto
+            }
+            None => {
+                let mode = rule.mode.clone().unwrap_or(SchedMode::Auto);
+                self.loader
+                    .switch_scheduler(rule.sched.clone(), mode.clone())
+                    .await?;
+                mode
+            }
+        };
+        self.applied = Some((sched_bin_name.to_owned(), mode));
+        Ok(())
+    }
+
+    async fn revert(&mut self) -> Result<()> {
+        let (Some(saved), Some(applied)) = (self.saved.take(), self.applied.take()) else {
+            return Ok(());
+        };
+        if self.current().await? != applied {
+            log::info!("no app profile applies anymore, keeping the manually selected scheduler");
+            return Ok(());
+        }
+
+        match saved.sched {
+            None => {
+                log::info!("no app profile applies anymore, stopping the scheduler");
+                self.loader.stop_scheduler().await?;
+            }
+            Some(sched) => {
+                log::info!("no app profile applies anymore, switching back to {sched:?}");
+                if saved.mode == SchedMode::Auto {
+                    self.loader
+                        .switch_scheduler_with_args(sched, &saved.args)
+                        .await?;
+                } else {
+                    self.loader.switch_scheduler(sched, saved.mode).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Watch the running processes and switch to the profile of the best
+/// matching rule, the rules are replaced whenever the config is reloaded
+pub async fn run_app_profiles(
+    connection: Connection,
+    mut profiles_rx: watch::Receiver<AppProfiles>,
+) -> Result<()> {
+    let mut applier = ProfileApplier {
+        loader: LoaderClientProxy::new(&connection).await?,
+        saved: None,
+        applied: None,
+    };
+    let mut switcher = ProfileSwitcher::default();
+    let mut cache = ProcCache::default();
+    let mut match_watch = MatchWatch::default();
+
+    loop {
+        let app_profiles = profiles_rx.borrow_and_update().clone();
+
+        if app_profiles.rules.is_empty() && switcher.active.is_none() {
+            if profiles_rx.changed().await.is_err() {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let rules = app_profiles.rules.clone();
+        let (matched, matched_pid, new_cache) = tokio::task::spawn_blocking(move || {
+            let matched = find_matching_rule(&rules, &mut cache);
+            let pid = matched.as_ref().and_then(|rule| matching_pid(rule, &cache));
+            (matched, pid, cache)
+        })
+        .await?;
+        cache = new_cache;
+
+        let now = Instant::now();
+        let grace_period = app_profiles.grace_period();
+        let change = switcher.update(matched.clone(), now, grace_period);
+        let res = match &change {
+            Some(ProfileChange::Activate(rule)) => applier.activate(rule).await,
+            Some(ProfileChange::Revert) => applier.revert().await,
+            None => Ok(()),
+        };
+        if let Err(err) = res {
+            log::error!("Failed to switch app profile: {err:#}");
+        }
+
+        // only the process keeping the active rule applied is worth watching
+        let watch_pid = matched_pid.filter(|_| matched.is_some() && matched == switcher.active);
+        let events_paths = app_profiles
+            .rules
+            .iter()
+            .filter_map(cgroup_events_path)
+            .collect();
+        match_watch.update(watch_pid, events_paths);
+
+        let mut wait = app_profiles.poll_interval();
+        if let Some(pending) = switcher.pending(now, grace_period) {
+            wait = wait.min(pending);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = match_watch.changed() => {}
+            res = profiles_rx.changed() => {
+                if res.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(comm: &str, priority: i32) -> AppRule {
+        AppRule {
+            name: None,
+            exe: None,
+            comm: Some(comm.to_owned()),
+            cgroup: None,
+            sched: SupportedSched::Bpfland,
+            mode: Some(SchedMode::Gaming),
+            args: None,
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("cs2", "cs2"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("wine*", "wine64-preloader"));
+        assert!(glob_match("*.exe", "game.exe"));
+        assert!(glob_match(
+            "/home/*/.steam/*",
+            "/home/user/.steam/steamapps/game"
+        ));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(!glob_match("*.exe", "game.exe.bak"));
+        assert!(!glob_match("cs2", "cs"));
+    }
+
+    #[test]
+    fn test_exe_matches() {
+        assert!(exe_matches("steam", "/usr/bin/steam"));
+        assert!(exe_matches("/usr/bin/*", "/usr/bin/steam"));
+        assert!(!exe_matches("bin", "/usr/bin/steam"));
+        assert!(!exe_matches("/opt/*", "/usr/bin/steam"));
+    }
+
+    #[test]
+    fn test_rule_matches() {
+        let mut game_rule = rule("game*", 0);
+        game_rule.cgroup = Some("/user.slice/*".to_owned());
+        let info = ProcInfo {
+            comm: Some("game-main".to_owned()),
+            exe: None,
+            cgroup: Some("/user.slice/user-1000.slice/app.scope".to_owned()),
+        };
+        assert!(rule_matches(&game_rule, &info));
+
+        // all the given patterns have to match
+        game_rule.exe = Some("game".to_owned());
+        assert!(!rule_matches(&game_rule, &info));
+    }
+
+    #[test]
+    fn test_cgroup_events_path() {
+        let mut cgroup_rule = rule("x", 0);
+        cgroup_rule.comm = None;
+        cgroup_rule.cgroup = Some("/system.slice/game.service".to_owned());
+        assert_eq!(
+            cgroup_events_path(&cgroup_rule).as_deref(),
+            Some("/sys/fs/cgroup/system.slice/game.service/cgroup.events")
+        );
+
+        cgroup_rule.cgroup = Some("/system.slice/*.service".to_owned());
+        assert_eq!(cgroup_events_path(&cgroup_rule), None);
+    }
+
+    #[test]
+    fn test_find_matching_rule() {
+        let current_exe = std::env::current_exe().unwrap();
+        let mut own_rule = rule("*", 0);
+        own_rule.comm = None;
+        own_rule.exe = Some(current_exe.to_string_lossy().into_owned());
+        let missing_rule = rule("no-such-process-name", 10);
+
+        let rules = vec![missing_rule, own_rule.clone()];
+        let mut cache = ProcCache::default();
+        assert_eq!(
+            find_matching_rule(&rules, &mut cache),
+            Some(own_rule.clone())
+        );
+
+        // the second poll is answered from the cache
+        let own_pid = std::process::id();
+        assert_eq!(cache.procs[&own_pid].nr_seen, 1);
+        assert_eq!(matching_pid(&own_rule, &cache), Some(own_pid));
+        assert_eq!(matching_pid(&rules[0], &cache), None);
+        assert_eq!(find_matching_rule(&rules, &mut cache), Some(own_rule));
+        assert_eq!(cache.procs[&own_pid].nr_seen, 2);
+
+        // the cache is dropped when no rule needs the processes
+        let mut cgroup_rule = rule("x", 0);
+        cgroup_rule.comm = None;
+        cgroup_rule.cgroup = Some("/no-such.slice".to_owned());
+        assert_eq!(find_matching_rule(&[cgroup_rule], &mut cache), None);
+        assert!(cache.procs.is_empty());
+    }
+
+    #[test]
+    fn test_needs_refresh() {
+        // new processes are re-read until they've been seen a few times
+        for nr_seen in 0..PROC_FRESH_POLLS {
+            assert!(needs_refresh(7, nr_seen, 1));
+        }
+        // then once every PROC_REFRESH_POLLS polls
+        let refreshed: Vec<u64> = (0..PROC_REFRESH_POLLS * 3)
+            .filter(|&nr_polls| needs_refresh(7, PROC_FRESH_POLLS, nr_polls))
+            .collect();
+        assert_eq!(refreshed, vec![3, 13, 23]);
+        // spread over the polls by pid
+        assert!(!needs_refresh(8, PROC_FRESH_POLLS, 3));
+        assert!(needs_refresh(8, PROC_FRESH_POLLS, 2));
+    }
+
+    #[test]
+    fn test_switcher_grace_period() {
+        let grace = Duration::from_secs(5);
+        let start = Instant::now();
+        let game = rule("game", 0);
+        let mut switcher = ProfileSwitcher::default();
+
+        assert_eq!(
+            switcher.update(Some(game.clone()), start, grace),
+            Some(ProfileChange::Activate(game.clone()))
+        );
+        assert_eq!(switcher.update(Some(game.clone()), start, grace), None);
+
+        // the game exits, but comes back within the grace period
+        assert_eq!(
+            switcher.update(None, start + Duration::from_secs(1), grace),
+            None
+        );
+        assert_eq!(
+            switcher.update(Some(game.clone()), start + Duration::from_secs(2), grace),
+            None
+        );
+
+        // the game exits for good
+        assert_eq!(
+            switcher.update(None, start + Duration::from_secs(3), grace),
+            None
+        );
+        assert_eq!(
+            switcher.update(None, start + Duration::from_secs(7), grace),
+            None
+        );
+        assert_eq!(
+            switcher.pending(start + Duration::from_secs(7), grace),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            switcher.update(None, start + Duration::from_secs(8), grace),
+            Some(ProfileChange::Revert)
+        );
+        assert_eq!(
+            switcher.update(None, start + Duration::from_secs(9), grace),
+            None
+        );
+        assert_eq!(
+            switcher.pending(start + Duration::from_secs(9), grace),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_watch_exit() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let mut watch = MatchWatch::default();
+        watch.update(Some(child.id()), vec![]);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), watch.changed())
+                .await
+                .is_err()
+        );
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), watch.changed())
+            .await
+            .unwrap();
+
+        // the exited process isn't watched again
+        watch.update(Some(child.id()), vec![]);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), watch.changed())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_watch_cgroup_events() {
+        let events_path =
+            std::env::temp_dir().join(format!("scx_loader_cgroup_events_{}", std::process::id()));
+        fs::write(&events_path, "populated 0\n").unwrap();
+        let events_path = events_path.to_string_lossy().into_owned();
+
+        let mut watch = MatchWatch::default();
+        watch.update(
+            None,
+            vec![events_path.clone(), "/no-such/cgroup.events".into()],
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), watch.changed())
+                .await
+                .is_err()
+        );
+
+        fs::write(&events_path, "populated 1\n").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), watch.changed())
+            .await
+            .unwrap();
+        // the events were drained
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), watch.changed())
+                .await
+                .is_err()
+        );
+
+        fs::remove_file(&events_path).unwrap();
+    }
+
+    #[test]
+    fn test_switcher_priority() {
+        let grace = Duration::from_secs(5);
+        let start = Instant::now();
+        let low = rule("low", 0);
+        let high = rule("high", 10);
+        let mut switcher = ProfileSwitcher::default();
+
+        switcher.update(Some(low.clone()), start, grace);
+        // a rule with a higher priority applies right away
+        assert_eq!(
+            switcher.update(Some(high.clone()), start, grace),
+            Some(ProfileChange::Activate(high.clone()))
+        );
+        // falling back to a lower priority rule waits for the grace period
+        assert_eq!(switcher.update(Some(low.clone()), start, grace), None);
+        assert_eq!(
+            switcher.update(Some(low.clone()), start + grace, grace),
+            Some(ProfileChange::Activate(low))
+        );
+    }
+}
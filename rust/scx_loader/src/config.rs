@@ -7,21 +7,27 @@
 
 use std::collections::HashMap;
 use std::fs;
+use std::ops::Range;
 use std::path::Path;
 
 use anyhow::Result;
 use serde::Deserialize;
 use serde::Serialize;
+use toml_edit::TableLike;
 
 use crate::SchedMode;
 use crate::SupportedSched;
 
+/// Paths searched for the config file, in order
+pub const CONFIG_PATHS: [&str; 2] = ["/etc/scx_loader/config.toml", "/etc/scx_loader.toml"];
+
 #[derive(Debug, PartialEq, Default, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub default_sched: Option<SupportedSched>,
     pub default_mode: Option<SchedMode>,
     pub scheds: HashMap<String, Sched>,
+    pub app_profiles: AppProfiles,
 }
 
 #[derive(Debug, PartialEq, Default, Serialize, Deserialize)]
@@ -33,6 +39,77 @@ pub struct Sched {
     pub server_mode: Option<Vec<String>>,
 }
 
+/// Rules switching the scheduler while matching applications are running
+#[derive(Debug, PartialEq, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppProfiles {
+    /// Seconds to wait after the last matching application exited before
+    /// switching back
+    pub grace_period_secs: Option<u64>,
+    /// Milliseconds between checks for matching applications
+    pub poll_interval_ms: Option<u64>,
+    pub rules: Vec<AppRule>,
+}
+
+/// Scheduler profile applied while a process matching all the given
+/// patterns is running
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AppRule {
+    pub name: Option<String>,
+    /// Pattern for the executable path, or its file name if the pattern
+    /// contains no '/'
+    pub exe: Option<String>,
+    /// Pattern for the process name as in /proc/<pid>/comm
+    pub comm: Option<String>,
+    /// Pattern for the cgroup v2 path of the process
+    pub cgroup: Option<String>,
+    pub sched: SupportedSched,
+    pub mode: Option<SchedMode>,
+    /// Scheduler arguments, used instead of the flags of the mode
+    pub args: Option<Vec<String>>,
+    /// Rules with a higher priority win, the first one on a tie
+    #[serde(default)]
+    pub priority: i32,
+}
+
+impl AppRule {
+    /// Name of the rule used in the logs
+    pub fn display_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        [
+            ("exe", &self.exe),
+            ("comm", &self.comm),
+            ("cgroup", &self.cgroup),
+        ]
+        .iter()
+        .filter_map(|(key, pattern)| pattern.as_ref().map(|pattern| format!("{key}={pattern}")))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+pub const DEFAULT_APP_GRACE_PERIOD_SECS: u64 = 5;
+pub const DEFAULT_APP_POLL_INTERVAL_MS: u64 = 1000;
+
+impl AppProfiles {
+    pub fn grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(
+            self.grace_period_secs
+                .unwrap_or(DEFAULT_APP_GRACE_PERIOD_SECS),
+        )
+    }
+
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(
+            self.poll_interval_ms
+                .unwrap_or(DEFAULT_APP_POLL_INTERVAL_MS)
+                .max(100),
+        )
+    }
+}
+
 /// Initialize config from first found config path, overwise fallback to default config
 pub fn init_config() -> Result<Config> {
     if let Ok(config_path) = get_config_path() {
@@ -42,6 +119,14 @@ pub fn init_config() -> Result<Config> {
     }
 }
 
+/// Load config from the given path, or from the first found config path if none is given
+pub fn load_config(config_path: Option<&str>) -> Result<Config> {
+    match config_path {
+        Some(config_path) => parse_config_file(config_path),
+        None => init_config(),
+    }
+}
+
 pub fn parse_config_file(filepath: &str) -> Result<Config> {
     let file_content = fs::read_to_string(filepath)?;
     parse_config_content(&file_content)
@@ -49,29 +134,140 @@ pub fn parse_config_file(filepath: &str) -> Result<Config> {
 
 pub fn get_config_path() -> Result<String> {
     // Search in system directories
-    let check_paths = [
-        "/etc/scx_loader/config.toml".to_owned(),
-        "/etc/scx_loader.toml".to_owned(),
-    ];
-    for check_path in check_paths {
-        if !Path::new(&check_path).exists() {
+    for check_path in CONFIG_PATHS {
+        if !Path::new(check_path).exists() {
             continue;
         }
         // we found config path
-        return Ok(check_path);
+        return Ok(check_path.to_owned());
     }
 
     anyhow::bail!("Failed to find config!");
 }
 
-fn parse_config_content(file_content: &str) -> Result<Config> {
+pub fn parse_config_content(file_content: &str) -> Result<Config> {
     if file_content.is_empty() {
         anyhow::bail!("The config file is empty!")
     }
     let config: Config = toml::from_str(file_content)?;
+    validate_app_rules(&config.app_profiles.rules)?;
+    // unknown keys are only warned about so that configs with obsolete keys
+    // keep working, --check-config rejects them
+    for key in find_unknown_keys(file_content)? {
+        log::warn!("Ignoring unknown config key '{}'", key.path);
+    }
     Ok(config)
 }
 
+/// Known keys at each level of the config file
+const CONFIG_KEYS: [&str; 4] = ["default_sched", "default_mode", "scheds", "app_profiles"];
+const SCHED_KEYS: [&str; 5] = [
+    "auto_mode",
+    "gaming_mode",
+    "lowlatency_mode",
+    "powersave_mode",
+    "server_mode",
+];
+const APP_PROFILES_KEYS: [&str; 3] = ["grace_period_secs", "poll_interval_ms", "rules"];
+const APP_RULE_KEYS: [&str; 8] = [
+    "name", "exe", "comm", "cgroup", "sched", "mode", "args", "priority",
+];
+
+/// Key of the config file which isn't known and is ignored
+#[derive(Debug, PartialEq)]
+pub struct UnknownKey {
+    /// Dotted path of the key, e.g. "scheds.scx_lavd.gamin_mode"
+    pub path: String,
+    /// Location of the key in the file
+    pub span: Option<Range<usize>>,
+}
+
+/// Find the keys of the config file which aren't part of the config
+/// structure and thus are ignored when it's loaded
+pub fn find_unknown_keys(file_content: &str) -> Result<Vec<UnknownKey>> {
+    let doc = toml_edit::ImDocument::parse(file_content)?;
+    let mut unknown = vec![];
+
+    let table = doc.as_table();
+    check_keys(table, &CONFIG_KEYS, "", &mut unknown);
+
+    if let Some(scheds) = table.get("scheds").and_then(|item| item.as_table_like()) {
+        for (sched_name, sched) in scheds.iter() {
+            if let Some(sched) = sched.as_table_like() {
+                let prefix = format!("scheds.{sched_name}");
+                check_keys(sched, &SCHED_KEYS, &prefix, &mut unknown);
+            }
+        }
+    }
+
+    if let Some(app_profiles) = table
+        .get("app_profiles")
+        .and_then(|item| item.as_table_like())
+    {
+        check_keys(
+            app_profiles,
+            &APP_PROFILES_KEYS,
+            "app_profiles",
+            &mut unknown,
+        );
+
+        // either [[app_profiles.rules]] or an array of inline tables
+        let rules: Vec<&dyn TableLike> = match app_profiles.get("rules") {
+            Some(toml_edit::Item::ArrayOfTables(rules)) => {
+                rules.iter().map(|rule| rule as &dyn TableLike).collect()
+            }
+            Some(toml_edit::Item::Value(toml_edit::Value::Array(rules))) => rules
+                .iter()
+                .filter_map(|rule| rule.as_inline_table())
+                .map(|rule| rule as &dyn TableLike)
+                .collect(),
+            _ => vec![],
+        };
+        for (idx, rule) in rules.into_iter().enumerate() {
+            let prefix = format!("app_profiles.rules[{idx}]");
+            check_keys(rule, &APP_RULE_KEYS, &prefix, &mut unknown);
+        }
+    }
+
+    Ok(unknown)
+}
+
+fn check_keys(table: &dyn TableLike, known: &[&str], prefix: &str, unknown: &mut Vec<UnknownKey>) {
+    for (key, _) in table.iter() {
+        if known.contains(&key) {
+            continue;
+        }
+        unknown.push(UnknownKey {
+            path: match prefix {
+                "" => key.to_owned(),
+                _ => format!("{prefix}.{key}"),
+            },
+            span: table.get_key_value(key).and_then(|(key, _)| key.span()),
+        });
+    }
+}
+
+fn validate_app_rules(rules: &[AppRule]) -> Result<()> {
+    for (idx, rule) in rules.iter().enumerate() {
+        if rule.exe.is_none() && rule.comm.is_none() && rule.cgroup.is_none() {
+            anyhow::bail!(
+                "app_profiles.rules[{idx}] needs at least one of 'exe', 'comm' or 'cgroup'"
+            );
+        }
+        if rule.mode.is_some() && rule.args.is_some() {
+            anyhow::bail!("app_profiles.rules[{idx}] can't set both 'mode' and 'args'");
+        }
+        if let Some(cgroup) = &rule.cgroup {
+            if !cgroup.starts_with('/') && !cgroup.starts_with('*') {
+                anyhow::bail!(
+                    "app_profiles.rules[{idx}]: cgroup pattern {cgroup:?} must start with '/'"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn get_default_config() -> Config {
     Config {
         default_sched: None,
@@ -102,6 +298,7 @@ pub fn get_default_config() -> Config {
                 get_default_sched_for_config(&SupportedSched::Tickless),
             ),
         ]),
+        app_profiles: AppProfiles::default(),
     }
 }
 
@@ -326,6 +523,147 @@ auto_mode = ["--help"]
         );
     }
 
+    #[test]
+    fn test_unknown_keys_config() {
+        let config_str = r#"
+default_mode = "Auto"
+
+[scheds.scx_lavd]
+gamin_mode = ["--performance"]
+"#;
+
+        // unknown keys are ignored when loading the config
+        let parsed_config = parse_config_content(config_str).expect("Failed to parse config");
+        assert_eq!(parsed_config.scheds["scx_lavd"], Sched::default());
+
+        let unknown = find_unknown_keys(config_str).expect("Failed to parse config");
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].path, "scheds.scx_lavd.gamin_mode");
+        let span = unknown[0].span.clone().expect("expected a location");
+        assert_eq!(&config_str[span], "gamin_mode");
+    }
+
+    #[test]
+    fn test_unknown_keys_app_profiles() {
+        let config_str = r#"
+obsolete = true
+
+[app_profiles]
+grace_period = 5
+rules = [{ exe = "game", sched = "scx_lavd", prio = 1 }]
+"#;
+
+        let unknown = find_unknown_keys(config_str).expect("Failed to parse config");
+        assert_eq!(
+            unknown
+                .iter()
+                .map(|key| key.path.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "obsolete",
+                "app_profiles.grace_period",
+                "app_profiles.rules[0].prio"
+            ]
+        );
+
+        let config_str = r#"
+[[app_profiles.rules]]
+comm = "build"
+sched = "scx_rusty"
+nmae = "build"
+"#;
+        let unknown = find_unknown_keys(config_str).expect("Failed to parse config");
+        assert_eq!(unknown.len(), 1);
+        assert_eq!(unknown[0].path, "app_profiles.rules[0].nmae");
+    }
+
+    #[test]
+    fn test_known_keys_in_sync() {
+        // every key of a fully populated config must be known
+        let mode_flags = Some(vec!["--flag".to_owned()]);
+        let config = Config {
+            default_sched: Some(SupportedSched::Lavd),
+            default_mode: Some(SchedMode::Gaming),
+            scheds: HashMap::from([(
+                "scx_lavd".to_owned(),
+                Sched {
+                    auto_mode: mode_flags.clone(),
+                    gaming_mode: mode_flags.clone(),
+                    lowlatency_mode: mode_flags.clone(),
+                    powersave_mode: mode_flags.clone(),
+                    server_mode: mode_flags.clone(),
+                },
+            )]),
+            app_profiles: AppProfiles {
+                grace_period_secs: Some(1),
+                poll_interval_ms: Some(1),
+                rules: vec![AppRule {
+                    name: Some("rule".to_owned()),
+                    exe: Some("exe".to_owned()),
+                    comm: Some("comm".to_owned()),
+                    cgroup: Some("/cgroup".to_owned()),
+                    sched: SupportedSched::Lavd,
+                    mode: Some(SchedMode::Gaming),
+                    args: mode_flags.clone(),
+                    priority: 1,
+                }],
+            },
+        };
+        let config_str = toml::to_string(&config).expect("Failed to serialize config");
+        assert_eq!(find_unknown_keys(&config_str).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_app_profiles_config() {
+        let config_str = r#"
+[app_profiles]
+grace_period_secs = 10
+
+[[app_profiles.rules]]
+name = "games"
+exe = "/home/*/.steam/*"
+sched = "scx_bpfland"
+mode = "Gaming"
+priority = 10
+
+[[app_profiles.rules]]
+comm = "pipewire*"
+sched = "scx_lavd"
+args = ["--performance"]
+"#;
+
+        let parsed_config = parse_config_content(config_str).expect("Failed to parse config");
+        let app_profiles = &parsed_config.app_profiles;
+        assert_eq!(app_profiles.grace_period().as_secs(), 10);
+        assert_eq!(
+            app_profiles.poll_interval().as_millis() as u64,
+            DEFAULT_APP_POLL_INTERVAL_MS
+        );
+        assert_eq!(app_profiles.rules.len(), 2);
+        assert_eq!(app_profiles.rules[0].mode, Some(SchedMode::Gaming));
+        assert_eq!(app_profiles.rules[0].priority, 10);
+        assert_eq!(app_profiles.rules[1].priority, 0);
+        assert_eq!(app_profiles.rules[1].display_name(), "comm=pipewire*");
+    }
+
+    #[test]
+    fn test_invalid_app_rules_config() {
+        let no_pattern = r#"
+[[app_profiles.rules]]
+sched = "scx_bpfland"
+"#;
+        assert!(parse_config_content(no_pattern).is_err());
+
+        let mode_and_args = r#"
+[[app_profiles.rules]]
+comm = "game"
+sched = "scx_bpfland"
+mode = "Gaming"
+args = ["-m", "performance"]
+"#;
+        assert!(parse_config_content(mode_and_args).is_err());
+    }
+
     #[test]
     fn test_empty_config() {
         let config_str = "";
@@ -0,0 +1,279 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2024 Vladislav Nepogodin <vnepogodin@cachyos.org>
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+
+use anyhow::Result;
+use scx_loader::config;
+use scx_loader::SchedMode;
+use scx_loader::SupportedSched;
+use serde::Deserialize;
+use toml::Spanned;
+
+const ALL_MODES: [SchedMode; 5] = [
+    SchedMode::Auto,
+    SchedMode::Gaming,
+    SchedMode::PowerSave,
+    SchedMode::LowLatency,
+    SchedMode::Server,
+];
+
+/// Mirror of the config structure which keeps the location of each value in
+/// the file, used to point the errors at the offending lines.
+#[derive(Deserialize)]
+struct SpannedConfig {
+    #[serde(default)]
+    scheds: HashMap<String, Spanned<SpannedSched>>,
+}
+
+#[derive(Deserialize)]
+struct SpannedSched {
+    auto_mode: Option<Spanned<Vec<String>>>,
+    gaming_mode: Option<Spanned<Vec<String>>>,
+    lowlatency_mode: Option<Spanned<Vec<String>>>,
+    powersave_mode: Option<Spanned<Vec<String>>>,
+    server_mode: Option<Spanned<Vec<String>>>,
+}
+
+impl SpannedSched {
+    fn mode_span(&self, sched_mode: &SchedMode) -> Option<Range<usize>> {
+        let mode_flags = match sched_mode {
+            SchedMode::Auto => &self.auto_mode,
+            SchedMode::Gaming => &self.gaming_mode,
+            SchedMode::LowLatency => &self.lowlatency_mode,
+            SchedMode::PowerSave => &self.powersave_mode,
+            SchedMode::Server => &self.server_mode,
+        };
+        mode_flags.as_ref().map(|flags| flags.span())
+    }
+}
+
+struct Diagnostic {
+    span: Option<Range<usize>>,
+    message: String,
+}
+
+impl Diagnostic {
+    fn new(span: Option<Range<usize>>, message: String) -> Self {
+        Self { span, message }
+    }
+
+    fn print(&self, config_path: &str, content: &str) {
+        match &self.span {
+            Some(span) => {
+                let (line, column) = line_column(content, span.start);
+                eprintln!("{config_path}:{line}:{column}: error: {}", self.message);
+            }
+            None => eprintln!("{config_path}: error: {}", self.message),
+        }
+    }
+}
+
+/// Convert the byte offset into 1-based line and column numbers
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+    (line, column)
+}
+
+/// Search for the executable in PATH
+fn find_in_path(bin_name: &str) -> Option<PathBuf> {
+    let path_var = env::var_os("PATH")?;
+    env::split_paths(&path_var)
+        .map(|dir| dir.join(bin_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Let the scheduler parse the arguments. Parsing stops at '--help', so no
+/// scheduler is loaded, but unknown flags or invalid values are reported.
+fn dry_run_args(sched_bin: &Path, args: &[String]) -> Result<(), String> {
+    let output = Command::new(sched_bin)
+        .args(args)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| format!("failed to run {}: {err}", sched_bin.display()))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = stderr
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("no error output")
+        .to_owned();
+    Err(format!("{reason} ({})", output.status))
+}
+
+fn check_config_content(content: &str) -> Vec<Diagnostic> {
+    let config = match config::parse_config_content(content) {
+        Ok(config) => config,
+        Err(err) => {
+            let diag = match err.downcast_ref::<toml::de::Error>() {
+                Some(toml_err) => {
+                    Diagnostic::new(toml_err.span(), toml_err.message().trim().to_owned())
+                }
+                None => Diagnostic::new(None, format!("{err:#}")),
+            };
+            return vec![diag];
+        }
+    };
+    // the config was parsed successfully, so the same content can't fail here
+    let spanned: SpannedConfig = toml::from_str(content).expect("config was already parsed");
+
+    // unknown keys are only warned about when the config is loaded, but most
+    // likely are typos or obsolete keys
+    let mut diags: Vec<Diagnostic> = config::find_unknown_keys(content)
+        .expect("config was already parsed")
+        .into_iter()
+        .map(|key| Diagnostic::new(key.span, format!("unknown key '{}'", key.path)))
+        .collect();
+    let mut referenced: Vec<(SupportedSched, Option<&Spanned<SpannedSched>>)> = vec![];
+
+    let mut sched_names: Vec<&String> = spanned.scheds.keys().collect();
+    sched_names.sort();
+    for sched_name in sched_names {
+        let sched_table = &spanned.scheds[sched_name];
+        match SupportedSched::from_str(sched_name) {
+            Ok(scx_sched) => referenced.push((scx_sched, Some(sched_table))),
+            Err(err) => diags.push(Diagnostic::new(Some(sched_table.span()), err.to_string())),
+        }
+    }
+    let rule_scheds = config.app_profiles.rules.iter().map(|rule| &rule.sched);
+    for sched in config.default_sched.iter().chain(rule_scheds) {
+        if !referenced.iter().any(|(scx_sched, _)| scx_sched == sched) {
+            referenced.push((sched.clone(), None));
+        }
+    }
+
+    for (scx_sched, sched_table) in referenced {
+        let table_span = sched_table.map(|table| table.span());
+        let sched_bin_name: &str = scx_sched.clone().into();
+        let Some(sched_bin) = find_in_path(sched_bin_name) else {
+            diags.push(Diagnostic::new(
+                table_span,
+                format!("scheduler binary {sched_bin_name} not found in PATH"),
+            ));
+            continue;
+        };
+
+        for sched_mode in ALL_MODES {
+            let args = config::get_scx_flags_for_mode(&config, &scx_sched, sched_mode.clone());
+            if let Err(reason) = dry_run_args(&sched_bin, &args) {
+                let mode_span =
+                    sched_table.and_then(|table| table.get_ref().mode_span(&sched_mode));
+                let mode_name: &str = sched_mode.into();
+                let origin = if mode_span.is_some() { "" } else { "default " };
+                diags.push(Diagnostic::new(
+                    mode_span.or(table_span.clone()),
+                    format!(
+                        "{sched_bin_name} rejected {origin}{mode_name} mode flags {args:?}: {reason}"
+                    ),
+                ));
+            }
+        }
+    }
+
+    for rule in &config.app_profiles.rules {
+        let Some(args) = &rule.args else {
+            continue;
+        };
+        let sched_bin_name: &str = rule.sched.clone().into();
+        let Some(sched_bin) = find_in_path(sched_bin_name) else {
+            continue;
+        };
+        if let Err(reason) = dry_run_args(&sched_bin, args) {
+            diags.push(Diagnostic::new(
+                None,
+                format!(
+                    "{sched_bin_name} rejected the args {args:?} of app profile {:?}: {reason}",
+                    rule.display_name()
+                ),
+            ));
+        }
+    }
+
+    diags
+}
+
+/// Validate the config file, printing every problem found. Fails if the
+/// config is not usable as is.
+pub fn check_config(config_path: Option<&str>) -> Result<()> {
+    let config_path = match config_path {
+        Some(config_path) => config_path.to_owned(),
+        None => match config::get_config_path() {
+            Ok(config_path) => config_path,
+            Err(_) => {
+                println!("No config file found, the built-in default config is used");
+                return Ok(());
+            }
+        },
+    };
+
+    let content = fs::read_to_string(&config_path)
+        .map_err(|err| anyhow::anyhow!("Failed to read {config_path}: {err}"))?;
+
+    let diags = check_config_content(&content);
+    for diag in &diags {
+        diag.print(&config_path, &content);
+    }
+
+    if !diags.is_empty() {
+        anyhow::bail!("{config_path}: {} error(s) found", diags.len());
+    }
+    println!("{config_path}: OK");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_column() {
+        let content = "a = 1\n[scheds.scx_lavd]\nauto_mode = []\n";
+        assert_eq!(line_column(content, 0), (1, 1));
+        assert_eq!(line_column(content, 4), (1, 5));
+        assert_eq!(line_column(content, 6), (2, 1));
+        assert_eq!(line_column(content, content.find("auto").unwrap()), (3, 1));
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let content = "default_mode = \"Auto\"\n\n[scheds.scx_lavd]\ngamin_mode = []\n";
+        let diags = check_config_content(content);
+        // the unknown key comes first, followed by the scheduler checks
+        assert_eq!(diags[0].message, "unknown key 'scheds.scx_lavd.gamin_mode'");
+        let span = diags[0].span.clone().expect("expected a location");
+        assert_eq!(line_column(content, span.start), (4, 1));
+    }
+
+    #[test]
+    fn test_unsupported_sched_location() {
+        let content = "[scheds.scx_foo]\nauto_mode = []\n";
+        let diags = check_config_content(content);
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].message.contains("scx_foo"));
+        assert!(diags[0].span.is_some());
+    }
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2024 Vladislav Nepogodin <vnepogodin@cachyos.org>
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use nix::sys::inotify::AddWatchFlags;
+use nix::sys::inotify::InitFlags;
+use nix::sys::inotify::Inotify;
+use nix::sys::inotify::WatchDescriptor;
+
+/// Time to wait for further changes before reporting one, editors tend to
+/// write files in several steps
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// Watch the given config files and call `on_change` whenever one of them is
+/// written, replaced or removed. The parent directories are watched, so files
+/// which don't exist yet are picked up once created.
+pub fn spawn_config_watcher<F>(config_paths: Vec<PathBuf>, on_change: F) -> Result<()>
+where
+    F: Fn() + Send + 'static,
+{
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC).context("Failed to initialize inotify")?;
+
+    let flags = AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_MOVED_FROM
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE;
+
+    let mut watched: Vec<(WatchDescriptor, OsString)> = vec![];
+    for config_path in config_paths {
+        let (Some(dir), Some(file_name)) = (config_path.parent(), config_path.file_name()) else {
+            continue;
+        };
+        if !dir.is_dir() {
+            continue;
+        }
+        let wd = inotify
+            .add_watch(dir, flags)
+            .with_context(|| format!("Failed to watch {}", dir.display()))?;
+        watched.push((wd, file_name.to_owned()));
+    }
+
+    if watched.is_empty() {
+        log::debug!("No config directory to watch");
+        return Ok(());
+    }
+
+    thread::spawn(move || loop {
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(err) => {
+                log::error!("Failed to read inotify events, config watching stopped: {err}");
+                return;
+            }
+        };
+
+        let changed = events.iter().any(|event| {
+            watched
+                .iter()
+                .any(|(wd, file_name)| event.wd == *wd && event.name.as_ref() == Some(file_name))
+        });
+        if changed {
+            thread::sleep(SETTLE_DELAY);
+            on_change();
+        }
+    });
+
+    Ok(())
+}
@@ -27,6 +27,30 @@ pub trait LoaderClient {
     /// Stops the currently running scheduler.
     fn stop_scheduler(&self) -> zbus::Result<()>;
 
+    /// Reloads the config file. Fails if the config file is not valid, in
+    /// which case the previous config stays in use. The new config applies to
+    /// the next started scheduler.
+    fn reload_config(&self) -> zbus::Result<()>;
+
+    /// Returns the last `lines` lines of output captured from the schedulers
+    /// started by the loader, oldest first. If `lines` is 0, all stored lines
+    /// are returned.
+    fn get_scheduler_log(&self, lines: u32) -> zbus::Result<Vec<String>>;
+
+    /// Returns the lines of output captured from the schedulers following the
+    /// line with the given sequence number, along with the sequence number of
+    /// the last stored line. Pass 0 to get all stored lines, then the returned
+    /// sequence number to only get new lines.
+    fn get_scheduler_log_since(&self, seq: u64) -> zbus::Result<(u64, Vec<String>)>;
+
+    /// Emitted when a scheduler process was spawned.
+    #[zbus(signal)]
+    fn scheduler_started(&self, scx_name: String, pid: u32) -> zbus::Result<()>;
+
+    /// Emitted when a scheduler process exited, `reason` describes how.
+    #[zbus(signal)]
+    fn scheduler_exited(&self, scx_name: String, reason: String) -> zbus::Result<()>;
+
     /// Method for switching to the specified scheduler with the given mode.
     /// This method will stop the currently running scheduler (if any) and
     /// then start the new scheduler.
@@ -54,6 +78,28 @@ pub trait LoaderClient {
     #[zbus(property)]
     fn scheduler_mode(&self) -> zbus::Result<SchedMode>;
 
+    /// The arguments the current scheduler was started with.
+    #[zbus(property)]
+    fn current_args(&self) -> zbus::Result<Vec<String>>;
+
+    /// The PID of the scheduler process, 0 if no scheduler process is running.
+    #[zbus(property)]
+    fn scheduler_pid(&self) -> zbus::Result<u32>;
+
+    /// The time the scheduler process was started at, in seconds since the
+    /// Unix epoch, 0 if no scheduler process is running.
+    #[zbus(property)]
+    fn scheduler_start_time(&self) -> zbus::Result<u64>;
+
+    /// The number of times the current scheduler was restarted after it
+    /// failed.
+    #[zbus(property)]
+    fn scheduler_restarts(&self) -> zbus::Result<u32>;
+
+    /// How the last scheduler process exited, empty if none has exited yet.
+    #[zbus(property)]
+    fn last_exit_reason(&self) -> zbus::Result<String>;
+
     /// A list of the schedulers currently supported by the Scheduler Loader.
     /// The names of the supported schedulers will be listed as strings in
     /// this array.
@@ -34,6 +34,16 @@ pub enum SupportedSched {
     Tickless,
 }
 
+/// Schedulers which can be started by the loader
+pub const SUPPORTED_SCHEDS: [SupportedSched; 6] = [
+    SupportedSched::Bpfland,
+    SupportedSched::Flash,
+    SupportedSched::Lavd,
+    SupportedSched::P2DQ,
+    SupportedSched::Tickless,
+    SupportedSched::Rusty,
+];
+
 impl FromStr for SupportedSched {
     type Err = anyhow::Error;
 
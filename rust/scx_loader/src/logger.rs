@@ -26,7 +26,7 @@ impl log::Log for SimpleLogger {
             let level_str = match record.level() {
                 Level::Error => "[ERROR]".red(),
                 Level::Warn => "[WARN]".yellow(),
-                Level::Info => "[INFO]".red(),
+                Level::Info => "[INFO]".green(),
                 Level::Debug => "[DEBUG]".white(),
                 Level::Trace => "[TRACE]".black(),
             };
@@ -5,18 +5,29 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
 
+mod app_profiles;
+mod config_check;
+mod config_watch;
 mod logger;
+mod sched_log;
 
 use scx_loader::dbus::LoaderClientProxy;
 use scx_loader::*;
 
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
 use std::process::ExitStatus;
 use std::process::Stdio;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::Context;
 use anyhow::Result;
 use clap::Parser;
+use sched_log::LogStream;
+use sched_log::SchedLog;
 use sysinfo::System;
 use tokio::process::Child;
 use tokio::process::Command;
@@ -25,8 +36,11 @@ use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::Duration;
 use tokio::time::Instant;
 use zbus::interface;
+use zbus::object_server::SignalEmitter;
 use zbus::Connection;
 
+const LOADER_OBJECT_PATH: &str = "/org/scx/Loader";
+
 #[derive(Debug, PartialEq)]
 enum ScxMessage {
     /// Quit the scx_loader
@@ -41,22 +55,52 @@ enum ScxMessage {
     SwitchSched((SupportedSched, SchedMode)),
     /// Switch to another scheduler with the given scx arguments
     SwitchSchedArgs((SupportedSched, Vec<String>)),
+    /// Replace the config used to start schedulers
+    ApplyConfig(config::Config),
 }
 
 #[derive(Debug, PartialEq)]
 enum RunnerMessage {
-    /// Switch to another scheduler with the given scx arguments
-    Switch((SupportedSched, Vec<String>)),
-    /// Start the scheduler with the given scx arguments
-    Start((SupportedSched, Vec<String>)),
+    /// Switch to another scheduler with the given scx arguments, the mode is
+    /// set if the arguments are derived from it
+    Switch((SupportedSched, Option<SchedMode>, Vec<String>)),
+    /// Start the scheduler with the given scx arguments, the mode is set if
+    /// the arguments are derived from it
+    Start((SupportedSched, Option<SchedMode>, Vec<String>)),
     /// Stop the scheduler, if any
     Stop,
 }
 
+/// State of the scheduler process managed by the runner
+#[derive(Debug, Default)]
+struct SchedStatus {
+    /// Arguments the scheduler was started with
+    args: Vec<String>,
+    /// PID of the running scheduler process
+    pid: Option<u32>,
+    /// Time the running scheduler process was spawned at
+    started_at: Option<SystemTime>,
+    /// Number of restarts after the scheduler failed
+    restarts: u32,
+    /// How the last scheduler process exited
+    last_exit: Option<String>,
+}
+
+/// Everything the runner needs to manage the scheduler processes
+#[derive(Clone)]
+struct RunnerContext {
+    sched_log: Arc<SchedLog>,
+    status: Arc<Mutex<SchedStatus>>,
+    connection: Connection,
+}
+
 struct ScxLoader {
     current_scx: Option<SupportedSched>,
     current_mode: SchedMode,
     channel: UnboundedSender<ScxMessage>,
+    sched_log: Arc<SchedLog>,
+    status: Arc<Mutex<SchedStatus>>,
+    config_path: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -64,6 +108,14 @@ struct ScxLoader {
 struct Args {
     #[clap(long, short, action)]
     auto: bool,
+
+    /// Path to the config file, instead of searching the default locations
+    #[clap(long, value_name = "PATH")]
+    config: Option<String>,
+
+    /// Validate the config file and the scheduler flags it references, then exit
+    #[clap(long, action)]
+    check_config: bool,
 }
 
 #[interface(name = "org.scx.Loader")]
@@ -85,17 +137,54 @@ impl ScxLoader {
         self.current_mode.clone()
     }
 
+    /// Get arguments of the running scheduler
+    #[zbus(property)]
+    async fn current_args(&self) -> Vec<String> {
+        self.status.lock().unwrap().args.clone()
+    }
+
+    /// Get PID of the running scheduler, 0 if none is running
+    #[zbus(property)]
+    async fn scheduler_pid(&self) -> u32 {
+        self.status.lock().unwrap().pid.unwrap_or(0)
+    }
+
+    /// Get time the running scheduler was started at, in seconds since the
+    /// epoch, 0 if none is running
+    #[zbus(property)]
+    async fn scheduler_start_time(&self) -> u64 {
+        self.status
+            .lock()
+            .unwrap()
+            .started_at
+            .and_then(|started_at| started_at.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_secs())
+    }
+
+    /// Get number of times the scheduler was restarted after failing
+    #[zbus(property)]
+    async fn scheduler_restarts(&self) -> u32 {
+        self.status.lock().unwrap().restarts
+    }
+
+    /// Get how the last scheduler process exited, empty if none has exited yet
+    #[zbus(property)]
+    async fn last_exit_reason(&self) -> String {
+        self.status
+            .lock()
+            .unwrap()
+            .last_exit
+            .clone()
+            .unwrap_or_default()
+    }
+
     /// Get list of supported schedulers
     #[zbus(property)]
     async fn supported_schedulers(&self) -> Vec<&str> {
-        vec![
-            "scx_bpfland",
-            "scx_flash",
-            "scx_lavd",
-            "scx_p2dq",
-            "scx_tickless",
-            "scx_rusty",
-        ]
+        SUPPORTED_SCHEDS
+            .iter()
+            .map(|scx_sched| scx_sched.clone().into())
+            .collect()
     }
 
     async fn start_scheduler(
@@ -166,6 +255,46 @@ impl ScxLoader {
         Ok(())
     }
 
+    /// Reload the config file, the new config applies to the next started scheduler
+    async fn reload_config(&self) -> zbus::fdo::Result<()> {
+        log::info!("reloading config..");
+
+        reload_config(self.config_path.as_deref(), &self.channel)
+            .map_err(|err| zbus::fdo::Error::Failed(format!("{err:#}")))
+    }
+
+    /// Get the last lines of the scheduler output, all stored lines if 0 is passed
+    async fn get_scheduler_log(&self, lines: u32) -> Vec<String> {
+        self.sched_log
+            .tail(lines as usize)
+            .iter()
+            .map(|line| line.format())
+            .collect()
+    }
+
+    /// Get the lines of the scheduler output following the given sequence
+    /// number, along with the sequence number of the last line
+    async fn get_scheduler_log_since(&self, seq: u64) -> (u64, Vec<String>) {
+        let (last_seq, lines) = self.sched_log.since(seq);
+        (last_seq, lines.iter().map(|line| line.format()).collect())
+    }
+
+    /// Emitted when a scheduler process was spawned
+    #[zbus(signal)]
+    async fn scheduler_started(
+        emitter: &SignalEmitter<'_>,
+        scx_name: &str,
+        pid: u32,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when a scheduler process exited
+    #[zbus(signal)]
+    async fn scheduler_exited(
+        emitter: &SignalEmitter<'_>,
+        scx_name: &str,
+        reason: &str,
+    ) -> zbus::Result<()>;
+
     async fn stop_scheduler(&mut self) -> zbus::fdo::Result<()> {
         if let Some(current_scx) = &self.current_scx {
             let scx_name: &str = current_scx.clone().into();
@@ -246,6 +375,43 @@ async fn monitor_cpu_util() -> Result<()> {
     }
 }
 
+/// Read the config again and hand it over to the worker loop
+fn reload_config(config_path: Option<&str>, channel: &UnboundedSender<ScxMessage>) -> Result<()> {
+    let config = config::load_config(config_path).context("Failed to reload config")?;
+    let _ = channel.send(ScxMessage::ApplyConfig(config));
+    Ok(())
+}
+
+/// Reload the config on SIGHUP and whenever the config file changes
+fn setup_config_reload(
+    config_path: Option<String>,
+    channel: UnboundedSender<ScxMessage>,
+) -> Result<()> {
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .context("Failed to install SIGHUP handler")?;
+    let sighup_path = config_path.clone();
+    let sighup_channel = channel.clone();
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            log::info!("Got SIGHUP, reloading config..");
+            if let Err(err) = reload_config(sighup_path.as_deref(), &sighup_channel) {
+                log::error!("{err:#}");
+            }
+        }
+    });
+
+    let watch_paths = match &config_path {
+        Some(config_path) => vec![PathBuf::from(config_path)],
+        None => config::CONFIG_PATHS.iter().map(PathBuf::from).collect(),
+    };
+    config_watch::spawn_config_watcher(watch_paths, move || {
+        log::info!("Config file changed, reloading config..");
+        if let Err(err) = reload_config(config_path.as_deref(), &channel) {
+            log::error!("{err:#}");
+        }
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // initialize the logger
@@ -253,8 +419,13 @@ async fn main() -> Result<()> {
 
     let args = Args::parse();
 
+    if args.check_config {
+        return config_check::check_config(args.config.as_deref());
+    }
+
     // initialize the config
-    let config = config::init_config().context("Failed to initialize config")?;
+    let config =
+        config::load_config(args.config.as_deref()).context("Failed to initialize config")?;
 
     // If --auto is passed, start scx_loader as a standard background process
     // that swaps schedulers out automatically
@@ -276,22 +447,36 @@ async fn main() -> Result<()> {
     })
     .context("Error setting Ctrl-C handler")?;
 
+    if let Err(err) = setup_config_reload(args.config.clone(), channel.clone()) {
+        log::warn!("Config reloading is not available: {err:#}");
+    }
+
+    // scheduler output and status are shared between the runner and the dbus interface
+    let sched_log = Arc::new(SchedLog::new());
+    let status = Arc::new(Mutex::new(SchedStatus::default()));
+
     // register dbus interface
     let connection = Connection::system().await?;
     connection
         .object_server()
         .at(
-            "/org/scx/Loader",
+            LOADER_OBJECT_PATH,
             ScxLoader {
                 current_scx: None,
                 current_mode: SchedMode::Auto,
                 channel: channel.clone(),
+                sched_log: sched_log.clone(),
+                status: status.clone(),
+                config_path: args.config.clone(),
             },
         )
         .await?;
 
     connection.request_name("org.scx.Loader").await?;
 
+    // switch profiles while matching applications are running
+    let (profiles_tx, profiles_rx) = tokio::sync::watch::channel(config.app_profiles.clone());
+
     // if user set default scheduler, then start it
     if let Some(default_sched) = &config.default_sched {
         log::info!("Starting default scheduler: {default_sched:?}");
@@ -304,20 +489,35 @@ async fn main() -> Result<()> {
             .await?;
     }
 
+    let profiles_connection = connection.clone();
+    tokio::spawn(async move {
+        if let Err(err) = app_profiles::run_app_profiles(profiles_connection, profiles_rx).await {
+            log::error!("App profile switching stopped: {err:#}");
+        }
+    });
+
     // run worker/receiver loop
-    worker_loop(config, rx).await?;
+    let runner_ctx = RunnerContext {
+        sched_log,
+        status,
+        connection,
+    };
+    worker_loop(config, rx, runner_ctx, profiles_tx).await?;
 
     Ok(())
 }
 
 async fn worker_loop(
-    config: config::Config,
+    mut config: config::Config,
     mut receiver: UnboundedReceiver<ScxMessage>,
+    runner_ctx: RunnerContext,
+    profiles_tx: tokio::sync::watch::Sender<config::AppProfiles>,
 ) -> Result<()> {
     // setup channel for scheduler runner
     let (runner_tx, runner_rx) = tokio::sync::mpsc::channel::<RunnerMessage>(1);
 
-    let run_sched_future = tokio::spawn(async move { handle_child_process(runner_rx).await });
+    let run_sched_future =
+        tokio::spawn(async move { handle_child_process(runner_rx, runner_ctx).await });
 
     // prepare future for tokio
     tokio::pin!(run_sched_future);
@@ -341,6 +541,11 @@ async fn worker_loop(
 
         match msg {
             ScxMessage::Quit => return Ok(()),
+            ScxMessage::ApplyConfig(new_config) => {
+                log::info!("Config reloaded, it applies to the next started scheduler");
+                profiles_tx.send_replace(new_config.app_profiles.clone());
+                config = new_config;
+            }
             ScxMessage::StopSched => {
                 log::info!("Got event to stop scheduler!");
 
@@ -351,11 +556,11 @@ async fn worker_loop(
                 log::info!("Got event to start scheduler!");
 
                 // get scheduler args for the mode
-                let args = config::get_scx_flags_for_mode(&config, &scx_sched, sched_mode);
+                let args = config::get_scx_flags_for_mode(&config, &scx_sched, sched_mode.clone());
 
                 // send message with scheduler and asociated args to the runner
                 runner_tx
-                    .send(RunnerMessage::Start((scx_sched, args)))
+                    .send(RunnerMessage::Start((scx_sched, Some(sched_mode), args)))
                     .await?;
             }
             ScxMessage::StartSchedArgs((scx_sched, sched_args)) => {
@@ -363,18 +568,18 @@ async fn worker_loop(
 
                 // send message with scheduler and asociated args to the runner
                 runner_tx
-                    .send(RunnerMessage::Start((scx_sched, sched_args)))
+                    .send(RunnerMessage::Start((scx_sched, None, sched_args)))
                     .await?;
             }
             ScxMessage::SwitchSched((scx_sched, sched_mode)) => {
                 log::info!("Got event to switch scheduler!");
 
                 // get scheduler args for the mode
-                let args = config::get_scx_flags_for_mode(&config, &scx_sched, sched_mode);
+                let args = config::get_scx_flags_for_mode(&config, &scx_sched, sched_mode.clone());
 
                 // send message with scheduler and asociated args to the runner
                 runner_tx
-                    .send(RunnerMessage::Switch((scx_sched, args)))
+                    .send(RunnerMessage::Switch((scx_sched, Some(sched_mode), args)))
                     .await?;
             }
             ScxMessage::SwitchSchedArgs((scx_sched, sched_args)) => {
@@ -382,25 +587,36 @@ async fn worker_loop(
 
                 // send message with scheduler and asociated args to the runner
                 runner_tx
-                    .send(RunnerMessage::Switch((scx_sched, sched_args)))
+                    .send(RunnerMessage::Switch((scx_sched, None, sched_args)))
                     .await?;
             }
         }
     }
 }
 
-async fn handle_child_process(mut rx: tokio::sync::mpsc::Receiver<RunnerMessage>) -> Result<()> {
+async fn handle_child_process(
+    mut rx: tokio::sync::mpsc::Receiver<RunnerMessage>,
+    runner_ctx: RunnerContext,
+) -> Result<()> {
     let mut task: Option<tokio::task::JoinHandle<Result<Option<ExitStatus>>>> = None;
     let mut cancel_token = Arc::new(tokio_util::sync::CancellationToken::new());
 
     while let Some(message) = rx.recv().await {
         match message {
-            RunnerMessage::Switch((scx_sched, sched_args)) => {
+            RunnerMessage::Switch((scx_sched, sched_mode, sched_args)) => {
                 // stop the sched if its running
                 stop_scheduler(&mut task, &mut cancel_token).await;
 
                 // overwise start scheduler
-                match start_scheduler(scx_sched, sched_args, cancel_token.clone()).await {
+                match start_scheduler(
+                    scx_sched,
+                    sched_mode,
+                    sched_args,
+                    cancel_token.clone(),
+                    runner_ctx.clone(),
+                )
+                .await
+                {
                     Ok(handle) => {
                         task = Some(handle);
                         log::debug!("Scheduler started");
@@ -410,14 +626,22 @@ async fn handle_child_process(mut rx: tokio::sync::mpsc::Receiver<RunnerMessage>
                     }
                 }
             }
-            RunnerMessage::Start((scx_sched, sched_args)) => {
+            RunnerMessage::Start((scx_sched, sched_mode, sched_args)) => {
                 // check if sched is running or not
                 if task.is_some() {
                     log::error!("Scheduler wasn't finished yet. Stop already running scheduler!");
                     continue;
                 }
                 // overwise start scheduler
-                match start_scheduler(scx_sched, sched_args, cancel_token.clone()).await {
+                match start_scheduler(
+                    scx_sched,
+                    sched_mode,
+                    sched_args,
+                    cancel_token.clone(),
+                    runner_ctx.clone(),
+                )
+                .await
+                {
                     Ok(handle) => {
                         task = Some(handle);
                         log::debug!("Scheduler started");
@@ -436,51 +660,149 @@ async fn handle_child_process(mut rx: tokio::sync::mpsc::Receiver<RunnerMessage>
     Ok(())
 }
 
+/// Describe how the scheduler process exited
+fn exit_reason(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exited with code {code}");
+    }
+    match status.signal() {
+        Some(signum) => match nix::sys::signal::Signal::try_from(signum) {
+            Ok(signal) => format!("killed by {signal}"),
+            Err(_) => format!("killed by signal {signum}"),
+        },
+        None => status.to_string(),
+    }
+}
+
+/// Let the clients know about the changed scheduler status properties
+async fn notify_status_changed(connection: &Connection) -> zbus::Result<()> {
+    let iface_ref = connection
+        .object_server()
+        .interface::<_, ScxLoader>(LOADER_OBJECT_PATH)
+        .await?;
+    let emitter = iface_ref.signal_emitter();
+    let iface = iface_ref.get().await;
+    iface.current_args_changed(emitter).await?;
+    iface.scheduler_pid_changed(emitter).await?;
+    iface.scheduler_start_time_changed(emitter).await?;
+    iface.scheduler_restarts_changed(emitter).await?;
+    iface.last_exit_reason_changed(emitter).await?;
+    Ok(())
+}
+
+/// Record the spawned scheduler process and notify the clients
+async fn report_sched_started(runner_ctx: &RunnerContext, sched_bin_name: &str, pid: u32) {
+    {
+        let mut status = runner_ctx.status.lock().unwrap();
+        status.pid = Some(pid);
+        status.started_at = Some(SystemTime::now());
+    }
+
+    if let Err(err) = notify_sched_event(runner_ctx, sched_bin_name, Some(pid), None).await {
+        log::warn!("Failed to notify about started scheduler: {err}");
+    }
+}
+
+/// Record how the scheduler process exited and notify the clients
+async fn report_sched_exited(runner_ctx: &RunnerContext, sched_bin_name: &str, reason: String) {
+    log::info!("{sched_bin_name} {reason}");
+    {
+        let mut status = runner_ctx.status.lock().unwrap();
+        status.pid = None;
+        status.started_at = None;
+        status.last_exit = Some(reason.clone());
+    }
+
+    if let Err(err) = notify_sched_event(runner_ctx, sched_bin_name, None, Some(&reason)).await {
+        log::warn!("Failed to notify about exited scheduler: {err}");
+    }
+}
+
+async fn notify_sched_event(
+    runner_ctx: &RunnerContext,
+    sched_bin_name: &str,
+    started_pid: Option<u32>,
+    exit_reason: Option<&str>,
+) -> zbus::Result<()> {
+    let emitter = SignalEmitter::new(&runner_ctx.connection, LOADER_OBJECT_PATH)?;
+    if let Some(pid) = started_pid {
+        ScxLoader::scheduler_started(&emitter, sched_bin_name, pid).await?;
+    }
+    if let Some(reason) = exit_reason {
+        ScxLoader::scheduler_exited(&emitter, sched_bin_name, reason).await?;
+    }
+    notify_status_changed(&runner_ctx.connection).await
+}
+
 /// Start the scheduler with the given arguments
 async fn start_scheduler(
     scx_crate: SupportedSched,
+    sched_mode: Option<SchedMode>,
     args: Vec<String>,
     cancel_token: Arc<tokio_util::sync::CancellationToken>,
+    runner_ctx: RunnerContext,
 ) -> Result<tokio::task::JoinHandle<Result<Option<ExitStatus>>>> {
+    {
+        let mut status = runner_ctx.status.lock().unwrap();
+        status.args = args.clone();
+        status.restarts = 0;
+    }
+
     // Ensure the child process exit is handled correctly in the runtime
     let handle = tokio::spawn(async move {
+        let sched_bin_name: &str = scx_crate.clone().into();
         let mut retries = 0u32;
         let max_retries = 5u32;
 
         let mut last_status: Option<ExitStatus> = None;
 
         while retries < max_retries {
-            let child = spawn_scheduler(scx_crate.clone(), args.clone()).await;
+            let run = runner_ctx
+                .sched_log
+                .begin_run(&scx_crate, sched_mode.as_ref());
+            let child =
+                spawn_scheduler(scx_crate.clone(), args.clone(), run, &runner_ctx.sched_log).await;
 
             let mut failed = false;
-            if let Ok(mut child) = child {
-                tokio::select! {
-                    status = child.wait() => {
-                        let status = status.expect("child process encountered an error");
-                        last_status = Some(status);
-                        if !status.success() {
-                            failed = true;
-                        }
-                        log::debug!("Child process exited with status: {status:?}");
+            match child {
+                Ok(mut child) => {
+                    if let Some(child_id) = child.id() {
+                        report_sched_started(&runner_ctx, sched_bin_name, child_id).await;
                     }
 
-                    _ = cancel_token.cancelled() => {
-                        log::debug!("Received cancellation signal");
-                        // Send SIGINT
-                        if let Some(child_id) = child.id() {
-                            nix::sys::signal::kill(
-                                nix::unistd::Pid::from_raw(child_id as i32),
-                                nix::sys::signal::SIGINT,
-                            ).context("Failed to send termination signal to the child")?;
+                    tokio::select! {
+                        status = child.wait() => {
+                            let status = status.expect("child process encountered an error");
+                            last_status = Some(status);
+                            if !status.success() {
+                                failed = true;
+                            }
+                            log::debug!("Child process exited with status: {status:?}");
+                            report_sched_exited(&runner_ctx, sched_bin_name, exit_reason(&status)).await;
                         }
-                        let status = child.wait().await.expect("child process encountered an error");
-                        last_status = Some(status);
-                        break;
-                    }
-                };
-            } else {
-                log::debug!("Failed to spawn child process");
-                failed = true;
+
+                        _ = cancel_token.cancelled() => {
+                            log::debug!("Received cancellation signal");
+                            // Send SIGINT
+                            if let Some(child_id) = child.id() {
+                                nix::sys::signal::kill(
+                                    nix::unistd::Pid::from_raw(child_id as i32),
+                                    nix::sys::signal::SIGINT,
+                                ).context("Failed to send termination signal to the child")?;
+                            }
+                            let status = child.wait().await.expect("child process encountered an error");
+                            last_status = Some(status);
+                            let reason = format!("stopped, {}", exit_reason(&status));
+                            report_sched_exited(&runner_ctx, sched_bin_name, reason).await;
+                            break;
+                        }
+                    };
+                }
+                Err(err) => {
+                    log::debug!("Failed to spawn child process");
+                    report_sched_exited(&runner_ctx, sched_bin_name, format!("{err:#}")).await;
+                    failed = true;
+                }
             }
 
             // retrying if failed, otherwise exit
@@ -494,6 +816,9 @@ async fn start_scheduler(
                 retries,
                 max_retries,
             );
+            if retries < max_retries {
+                runner_ctx.status.lock().unwrap().restarts = retries;
+            }
         }
 
         Ok(last_status)
@@ -503,22 +828,41 @@ async fn start_scheduler(
 }
 
 /// Starts the scheduler as a child process and returns child object to manage lifecycle by the
-/// caller.
-async fn spawn_scheduler(scx_crate: SupportedSched, args: Vec<String>) -> Result<Child> {
+/// caller. The output of the child is captured into the scheduler log.
+async fn spawn_scheduler(
+    scx_crate: SupportedSched,
+    args: Vec<String>,
+    run: sched_log::RunInfo,
+    sched_log: &Arc<SchedLog>,
+) -> Result<Child> {
     let sched_bin_name: &str = scx_crate.into();
-    log::info!("starting {sched_bin_name} command");
+    log::info!("starting {sched_bin_name} command (run {})", run.run_id);
 
     let mut cmd = Command::new(sched_bin_name);
     // set arguments
     cmd.args(args);
 
-    // by default child IO handles are inherited from parent process
-
     // pipe stdin of child proc to /dev/null
     cmd.stdin(Stdio::null());
 
+    // capture the output of the child proc
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
     // spawn process
-    let child = cmd.spawn().expect("failed to spawn command");
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to spawn {sched_bin_name}"))?;
+
+    if let Some(stdout) = child.stdout.take() {
+        let sched_log = sched_log.clone();
+        let run = run.clone();
+        tokio::spawn(async move { sched_log.capture(run, LogStream::Stdout, stdout).await });
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let sched_log = sched_log.clone();
+        tokio::spawn(async move { sched_log.capture(run, LogStream::Stderr, stderr).await });
+    }
 
     Ok(child)
 }
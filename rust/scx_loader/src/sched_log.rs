@@ -0,0 +1,388 @@
+// SPDX-License-Identifier: GPL-2.0
+//
+// Copyright (c) 2024 Vladislav Nepogodin <vnepogodin@cachyos.org>
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use scx_loader::SchedMode;
+use scx_loader::SupportedSched;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncRead;
+use tokio::io::BufReader;
+
+/// Maximum number of scheduler output lines kept in memory
+const SCHED_LOG_CAPACITY: usize = 4096;
+
+/// Path of the systemd-journald native protocol socket
+const JOURNAL_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    /// Sequence number of the line, increasing across runs
+    pub seq: u64,
+    pub run_id: u64,
+    pub timestamp: SystemTime,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+impl LogLine {
+    /// Format the line as "[<unix secs>.<millis>] run <run id> <stream>: <line>"
+    pub fn format(&self) -> String {
+        let ts = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        format!(
+            "[{}.{:03}] run {} {}: {}",
+            ts.as_secs(),
+            ts.subsec_millis(),
+            self.run_id,
+            self.stream.as_str(),
+            self.line
+        )
+    }
+}
+
+/// Identifies a single spawned scheduler process
+#[derive(Debug, Clone)]
+pub struct RunInfo {
+    pub run_id: u64,
+    pub sched_name: String,
+    pub mode: String,
+}
+
+struct SchedLogInner {
+    next_run_id: u64,
+    next_seq: u64,
+    lines: VecDeque<LogLine>,
+    /// Number of stored lines of each run
+    nr_run_lines: BTreeMap<u64, usize>,
+}
+
+impl SchedLogInner {
+    /// Drop the oldest line of the run with the most stored lines, the
+    /// oldest run on a tie. A chatty run thus can't push out the tail of
+    /// the run before it, which usually tells why it exited.
+    fn evict(&mut self) {
+        let Some((&run_id, _)) = self
+            .nr_run_lines
+            .iter()
+            .max_by_key(|(&run_id, &nr)| (nr, Reverse(run_id)))
+        else {
+            return;
+        };
+        if let Some(idx) = self.lines.iter().position(|line| line.run_id == run_id) {
+            self.lines.remove(idx);
+        }
+        match self.nr_run_lines.get_mut(&run_id) {
+            Some(nr) if *nr > 1 => *nr -= 1,
+            _ => {
+                self.nr_run_lines.remove(&run_id);
+            }
+        }
+    }
+}
+
+/// Bounded buffer holding the output of the scheduler processes spawned by
+/// the loader, see SchedLogInner::evict() for which lines are dropped when
+/// it's full. Every line is also forwarded to journald.
+pub struct SchedLog {
+    inner: Mutex<SchedLogInner>,
+    capacity: usize,
+    journal: Option<UnixDatagram>,
+}
+
+impl SchedLog {
+    pub fn new() -> Self {
+        let mut sched_log = Self::with_capacity(SCHED_LOG_CAPACITY);
+
+        // only forward to journald if its socket is around. The socket is
+        // written from the async output capture, so it must not block when
+        // journald falls behind.
+        sched_log.journal = UnixDatagram::unbound().ok().filter(|sock| {
+            sock.connect(JOURNAL_SOCKET_PATH).is_ok() && sock.set_nonblocking(true).is_ok()
+        });
+        if sched_log.journal.is_none() {
+            log::debug!("journald socket is not available, scheduler output won't be forwarded");
+        }
+
+        sched_log
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(SchedLogInner {
+                next_run_id: 1,
+                next_seq: 1,
+                lines: VecDeque::with_capacity(capacity),
+                nr_run_lines: BTreeMap::new(),
+            }),
+            capacity,
+            journal: None,
+        }
+    }
+
+    /// Allocate a new run id for the scheduler which is about to be spawned
+    pub fn begin_run(&self, scx_sched: &SupportedSched, sched_mode: Option<&SchedMode>) -> RunInfo {
+        let mut inner = self.inner.lock().unwrap();
+        let run_id = inner.next_run_id;
+        inner.next_run_id += 1;
+
+        let sched_name: &str = scx_sched.clone().into();
+        let mode: &str = match sched_mode {
+            Some(mode) => mode.clone().into(),
+            None => "custom",
+        };
+        RunInfo {
+            run_id,
+            sched_name: sched_name.to_owned(),
+            mode: mode.to_owned(),
+        }
+    }
+
+    /// Store a line of scheduler output and forward it to journald. If
+    /// journald isn't available or can't keep up, the line goes to the
+    /// loader log instead.
+    pub fn push(&self, run: &RunInfo, stream: LogStream, line: String) {
+        if !self.forward_to_journal(run, stream, &line) {
+            // keep the output visible in the loader log when journald isn't available
+            log::info!("{}: {line}", run.sched_name);
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.lines.len() == self.capacity {
+            inner.evict();
+        }
+        *inner.nr_run_lines.entry(run.run_id).or_default() += 1;
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner.lines.push_back(LogLine {
+            seq,
+            run_id: run.run_id,
+            timestamp: SystemTime::now(),
+            stream,
+            line,
+        });
+    }
+
+    /// Get the last `count` lines of scheduler output, oldest first.
+    /// If `count` is 0, all stored lines are returned.
+    pub fn tail(&self, count: usize) -> Vec<LogLine> {
+        let inner = self.inner.lock().unwrap();
+        let skip = if count == 0 {
+            0
+        } else {
+            inner.lines.len().saturating_sub(count)
+        };
+        inner.lines.iter().skip(skip).cloned().collect()
+    }
+
+    /// Get the stored lines with a sequence number greater than `seq`, oldest
+    /// first, along with the sequence number of the last stored line.
+    pub fn since(&self, seq: u64) -> (u64, Vec<LogLine>) {
+        let inner = self.inner.lock().unwrap();
+        let lines = inner
+            .lines
+            .iter()
+            .filter(|line| line.seq > seq)
+            .cloned()
+            .collect();
+        (inner.next_seq - 1, lines)
+    }
+
+    /// Read lines from the given scheduler output stream until EOF
+    pub async fn capture<R>(&self, run: RunInfo, stream: LogStream, reader: R)
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => self.push(&run, stream, line),
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!("Failed to read scheduler {}: {err}", stream.as_str());
+                    break;
+                }
+            }
+        }
+    }
+
+    fn forward_to_journal(&self, run: &RunInfo, stream: LogStream, line: &str) -> bool {
+        let Some(journal) = &self.journal else {
+            return false;
+        };
+
+        // stderr is where schedulers report failures, give it a higher priority
+        let priority = match stream {
+            LogStream::Stdout => "6",
+            LogStream::Stderr => "5",
+        };
+
+        let mut msg = Vec::with_capacity(line.len() + 128);
+        append_journal_field(&mut msg, "MESSAGE", line);
+        append_journal_field(&mut msg, "PRIORITY", priority);
+        append_journal_field(&mut msg, "SYSLOG_IDENTIFIER", &run.sched_name);
+        append_journal_field(&mut msg, "SCHEDULER", &run.sched_name);
+        append_journal_field(&mut msg, "MODE", &run.mode);
+        append_journal_field(&mut msg, "RUN_ID", &run.run_id.to_string());
+        append_journal_field(&mut msg, "STREAM", stream.as_str());
+
+        // fails with EAGAIN instead of blocking if journald falls behind
+        journal.send(&msg).is_ok()
+    }
+}
+
+/// Append a field to a message using the journald native protocol
+fn append_journal_field(msg: &mut Vec<u8>, key: &str, value: &str) {
+    msg.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        // values containing newlines have to be length-prefixed
+        msg.push(b'\n');
+        msg.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        msg.push(b'=');
+    }
+    msg.extend_from_slice(value.as_bytes());
+    msg.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_run(sched_log: &SchedLog) -> RunInfo {
+        sched_log.begin_run(&SupportedSched::Bpfland, Some(&SchedMode::Gaming))
+    }
+
+    #[test]
+    fn test_ring_buffer_bounded() {
+        let sched_log = SchedLog::with_capacity(3);
+        let run = test_run(&sched_log);
+        for i in 0..5 {
+            sched_log.push(&run, LogStream::Stdout, format!("line {i}"));
+        }
+
+        let lines: Vec<String> = sched_log.tail(0).into_iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec!["line 2", "line 3", "line 4"]);
+
+        let lines: Vec<String> = sched_log.tail(2).into_iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec!["line 3", "line 4"]);
+    }
+
+    #[test]
+    fn test_previous_run_tail_kept() {
+        let sched_log = SchedLog::with_capacity(4);
+        let first = test_run(&sched_log);
+        for i in 0..3 {
+            sched_log.push(&first, LogStream::Stderr, format!("first {i}"));
+        }
+        let second = test_run(&sched_log);
+        for i in 0..5 {
+            sched_log.push(&second, LogStream::Stdout, format!("second {i}"));
+        }
+
+        let lines: Vec<String> = sched_log.tail(0).into_iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec!["first 2", "second 2", "second 3", "second 4"]);
+
+        // the runs share the capacity, the newest one can't take it all
+        let third = test_run(&sched_log);
+        for i in 0..4 {
+            sched_log.push(&third, LogStream::Stdout, format!("third {i}"));
+        }
+        let lines: Vec<String> = sched_log.tail(0).into_iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec!["first 2", "second 4", "third 2", "third 3"]);
+    }
+
+    #[test]
+    fn test_journal_nonblocking() {
+        let (journal, _journald) = UnixDatagram::pair().unwrap();
+        journal.set_nonblocking(true).unwrap();
+        let mut sched_log = SchedLog::with_capacity(8);
+        sched_log.journal = Some(journal);
+        let run = test_run(&sched_log);
+
+        // nobody reads from the socket, sending fails instead of blocking
+        // once its buffer is full
+        assert!(sched_log.forward_to_journal(&run, LogStream::Stdout, "hello"));
+        assert!((0..100_000).any(|_| !sched_log.forward_to_journal(
+            &run,
+            LogStream::Stdout,
+            "hello"
+        )));
+        sched_log.push(&run, LogStream::Stdout, "kept".into());
+        assert_eq!(sched_log.tail(1)[0].line, "kept");
+    }
+
+    #[test]
+    fn test_since() {
+        let sched_log = SchedLog::with_capacity(3);
+        let run = test_run(&sched_log);
+        assert_eq!(sched_log.since(0).0, 0);
+
+        for i in 0..2 {
+            sched_log.push(&run, LogStream::Stderr, format!("line {i}"));
+        }
+        let (last_seq, lines) = sched_log.since(0);
+        assert_eq!(last_seq, 2);
+        assert_eq!(lines.len(), 2);
+
+        for i in 2..5 {
+            sched_log.push(&run, LogStream::Stderr, format!("line {i}"));
+        }
+        let (last_seq, lines) = sched_log.since(last_seq);
+        assert_eq!(last_seq, 5);
+        let lines: Vec<String> = lines.into_iter().map(|l| l.line).collect();
+        assert_eq!(lines, vec!["line 2", "line 3", "line 4"]);
+    }
+
+    #[test]
+    fn test_run_ids() {
+        let sched_log = SchedLog::with_capacity(8);
+        let first = test_run(&sched_log);
+        let second = sched_log.begin_run(&SupportedSched::Lavd, None);
+        assert_eq!(first.run_id + 1, second.run_id);
+        assert_eq!(first.mode, "gaming");
+        assert_eq!(second.mode, "custom");
+        assert_eq!(second.sched_name, "scx_lavd");
+    }
+
+    #[test]
+    fn test_journal_field_encoding() {
+        let mut msg = Vec::new();
+        append_journal_field(&mut msg, "MESSAGE", "hello");
+        assert_eq!(msg, b"MESSAGE=hello\n");
+
+        let mut msg = Vec::new();
+        append_journal_field(&mut msg, "MESSAGE", "a\nb");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(msg, expected);
+    }
+}
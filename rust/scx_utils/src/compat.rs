@@ -270,6 +270,17 @@ pub fn is_sched_ext_enabled() -> io::Result<bool> {
     }
 }
 
+/// Name of the sched_ext scheduler currently loaded, None if no scheduler is
+/// loaded.
+pub fn read_sched_ext_ops() -> io::Result<Option<String>> {
+    match std::fs::read_to_string("/sys/kernel/sched_ext/root/ops") {
+        Ok(content) => Ok(Some(content.trim().to_string())),
+        // the root directory only exists while a scheduler is loaded
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 #[macro_export]
 macro_rules! unwrap_or_break {
     ($expr: expr, $label: lifetime) => {{
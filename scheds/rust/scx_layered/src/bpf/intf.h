@@ -41,6 +41,9 @@ enum consts {
 	USAGE_HALF_LIFE		= 100000000,	/* 100ms */
 	RUNTIME_DECAY_FACTOR	= 4,
 	LAYER_LAT_DECAY_FACTOR	= 32,
+	NR_LAT_BUCKETS		= 32,	/* log2 usecs, see cpu_ctx->lat_hist */
+	BW_REFILL_INTERVAL_NS	= 1000000,	/* 1ms */
+	BW_MIN_SLICE_NS		= 100000,	/* 100us */
 
 	DSQ_ID_SPECIAL_MASK	= 0xc0000000,
 	HI_FB_DSQ_BASE		= 0x40000000,
@@ -65,6 +68,7 @@ enum consts {
 	SCXCMD_PREFIX		= 0x5C10,
 	SCXCMD_COMLEN		= 13,
 	MAX_GPU_PIDS 		= 100000,
+	MAX_USER_MATCHES	= 64,
 };
 
 static inline void ___consts_sanity_check___(void) {
@@ -136,6 +140,8 @@ enum layer_stat_id {
 	LSTAT_XLAYER_REWAKE,
 	LSTAT_LLC_DRAIN_TRY,
 	LSTAT_LLC_DRAIN,
+	LSTAT_BW_THROTTLE,
+	LSTAT_BW_THROTTLE_NS,
 	NR_LSTATS,
 };
 
@@ -175,6 +181,11 @@ struct cpu_ctx {
 	u64			layer_usages[MAX_LAYERS][NR_LAYER_USAGES];
 	u64			gstats[NR_GSTATS];
 	u64			lstats[MAX_LAYERS][NR_LSTATS];
+	/*
+	 * Histogram of enqueue-to-running latencies. Bucket N counts the
+	 * latencies in [2^(N-1), 2^N) usecs. Bucket 1 also counts < 1us.
+	 */
+	u64			lat_hist[MAX_LAYERS][NR_LAT_BUCKETS];
 	u64			ran_current_for;
 
 	u64			usage;
@@ -248,6 +259,7 @@ enum layer_match_kind {
 	MATCH_IS_KTHREAD,
 	MATCH_USED_GPU_TID,
 	MATCH_USED_GPU_PID,
+	MATCH_USER_HINT,
 
 	NR_LAYER_MATCH_KINDS,
 };
@@ -268,6 +280,31 @@ struct layer_match {
 	bool		is_kthread;
 	bool		used_gpu_tid;
 	bool		used_gpu_pid;
+	u32		user_match_id;
+};
+
+/*
+ * Matches which can't be evaluated in BPF are resolved by userspace. Bit N
+ * of @hints is set if the task satisfies the user match with ID N. @seq is
+ * bumped whenever @hints changes.
+ */
+struct user_match_hint {
+	u64		hints;
+	u64		seq;
+};
+
+/*
+ * Record emitted for each task by the dump_task_ctxs iterator. @match_or_id
+ * is the index of the OR block which placed the task in @layer_id and
+ * @runtime is the total runtime in ns since the task joined the layer.
+ */
+struct task_ctx_dump {
+	s32		pid;
+	s32		tgid;
+	u32		layer_id;
+	u32		match_or_id;
+	u64		runtime;
+	char		comm[MAX_COMM];
 };
 
 struct layer_match_ands {
@@ -326,6 +363,13 @@ struct layer {
 	u64			llcs_to_drain;
 	u32			llc_drain_cnt;
 
+	/* CPU bandwidth cap, see layer_bw_charge() */
+	u64			bw_quota_ns;
+	u64			bw_period_ns;
+	u64			bw_period_at;
+	u64			bw_used;
+	u64			bw_throttled_at;
+
 	char			name[MAX_LAYER_NAME];
 	bool			is_protected;
 };
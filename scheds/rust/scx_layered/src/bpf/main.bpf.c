@@ -56,6 +56,8 @@ const volatile bool percpu_kthread_preempt = true;
 /* Flag to enable or disable antistall feature */
 const volatile bool enable_antistall = true;
 const volatile bool enable_gpu_support = false;
+/* Set if any layer has a CPU bandwidth cap */
+const volatile bool enable_bw_caps = false;
 /* Delay permitted, in seconds, before antistall activates */
 const volatile u64 antistall_sec = 3;
 const u32 zero_u32 = 0;
@@ -72,6 +74,15 @@ u32 layered_root_tgid = 0;
 u32 empty_layer_ids[MAX_LAYERS];
 u32 nr_empty_layer_ids;
 
+/*
+ * Bumped by userspace when the layer matches are updated. Tasks whose
+ * match_seq doesn't match are re-matched when they become runnable.
+ */
+u64 layer_match_seq;
+
+/* number of user matches in use, user_match_hints is ignored if zero */
+u32 nr_user_matches;
+
 UEI_DEFINE(uei);
 
 static inline s32 prio_to_nice(s32 static_prio)
@@ -202,6 +213,14 @@ struct {
 	__uint(map_flags, BPF_F_NO_PREALLOC);
 } gpu_tid SEC(".maps");
 
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, u32);
+	__type(value, struct user_match_hint);
+	__uint(max_entries, MAX_TASKS);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+} user_match_hints SEC(".maps");
+
 int save_gpu_tgid_pid() {
 	if (!enable_gpu_support)
 		return 0;
@@ -302,6 +321,72 @@ static void lstat_inc(u32 id, struct layer *layer, struct cpu_ctx *cpuc)
 	lstat_add(id, layer, cpuc, 1);
 }
 
+static void lat_hist_add(struct layer *layer, struct cpu_ctx *cpuc, u64 lat_ns)
+{
+	u32 bucket = log2_u64(lat_ns / NSEC_PER_USEC);
+	u64 *vptr;
+
+	if (bucket >= NR_LAT_BUCKETS)
+		bucket = NR_LAT_BUCKETS - 1;
+
+	if ((vptr = MEMBER_VPTR(*cpuc, .lat_hist[layer->id][bucket])))
+		(*vptr)++;
+	else
+		scx_bpf_error("invalid layer or bucket ids: %d, %d", layer->id, bucket);
+}
+
+/*
+ * CPU bandwidth cap. The execution time of the layer's tasks is charged to
+ * ->bw_used from layered_stopping(). Once ->bw_used reaches ->bw_quota_ns, the
+ * layer is throttled until the current period ends - its queued tasks aren't
+ * dispatched and the running ones aren't allowed to continue.
+ *
+ * Tasks with custom affinities are throttled in the low fallback DSQ by
+ * try_consume_lo_fb(). Per-cpu kthreads and scx_layered itself, which go to
+ * the high fallback DSQ, are charged but never throttled.
+ *
+ * The period is refilled by whoever notices that it has ended first. The
+ * updates are racy but the BW_TIMER guarantees that a layer never stays
+ * throttled much past the end of the period and kicks the idle CPUs so that
+ * the tasks which piled up while throttled can run again.
+ */
+static void layer_bw_refill(struct layer *layer, struct cpu_ctx *cpuc, u64 now)
+{
+	u64 throttled_at = layer->bw_throttled_at;
+
+	layer->bw_period_at = now;
+	layer->bw_used = 0;
+
+	if (throttled_at &&
+	    __sync_bool_compare_and_swap(&layer->bw_throttled_at, throttled_at, 0))
+		lstat_add(LSTAT_BW_THROTTLE_NS, layer, cpuc, now - throttled_at);
+}
+
+static bool layer_bw_throttled(struct layer *layer, struct cpu_ctx *cpuc, u64 now)
+{
+	if (!layer->bw_quota_ns)
+		return false;
+
+	if (!time_before(now, layer->bw_period_at + layer->bw_period_ns))
+		layer_bw_refill(layer, cpuc, now);
+
+	return layer->bw_throttled_at;
+}
+
+static void layer_bw_charge(struct layer *layer, struct cpu_ctx *cpuc, u64 used, u64 now)
+{
+	if (!layer->bw_quota_ns)
+		return;
+
+	if (!time_before(now, layer->bw_period_at + layer->bw_period_ns))
+		layer_bw_refill(layer, cpuc, now);
+
+	if (__sync_add_and_fetch(&layer->bw_used, used) >= layer->bw_quota_ns &&
+	    !layer->bw_throttled_at &&
+	    __sync_bool_compare_and_swap(&layer->bw_throttled_at, 0, now))
+		lstat_inc(LSTAT_BW_THROTTLE, layer, cpuc);
+}
+
 struct layer_cpumask_wrapper {
 	struct bpf_cpumask __kptr *cpumask;
 };
@@ -473,8 +558,11 @@ struct task_ctx {
 	int			pid;
 	int			last_cpu;
 	u32			layer_id;
+	u32			match_or_id;
 	pid_t			last_waker;
 	bool			refresh_layer;
+	u64			match_seq;
+	u64			user_match_seq;
 	struct cached_cpus	layered_cpus;
 	/*
 	 * XXX: Old kernels can't track a bpf_cpumask on nested structs
@@ -489,8 +577,10 @@ struct task_ctx {
 	bool			all_cpus_allowed;
 	bool			cpus_node_aligned;
 	u64			runnable_at;
+	u64			queued_at;
 	u64			running_at;
 	u64			runtime_avg;
+	u64			layer_runtime;
 	u64			dsq_id;
 	u32			llc_id;
 
@@ -1070,6 +1160,10 @@ s32 BPF_STRUCT_OPS(layered_select_cpu, struct task_struct *p, s32 prev_cpu, u64
 	if (taskc->layer_id == MAX_LAYERS || !(layer = lookup_layer(taskc->layer_id)))
 		return prev_cpu;
 
+	/* throttled tasks should wait in the layer DSQ, see layered_enqueue() */
+	if (layer_bw_throttled(layer, cpuc, scx_bpf_now()))
+		return prev_cpu;
+
 	cpu = pick_idle_cpu(p, prev_cpu, cpuc, taskc, layer, true);
 	if (cpu >= 0) {
 		lstat_inc(LSTAT_SEL_LOCAL, layer, cpuc);
@@ -1190,7 +1284,7 @@ void BPF_STRUCT_OPS(layered_enqueue, struct task_struct *p, u64 enq_flags)
 	s32 cpu, task_cpu = scx_bpf_task_cpu(p);
 	u64 vtime = p->scx.dsq_vtime;
 	u32 llc_id, layer_id;
-	bool yielding, try_preempt_first;
+	bool yielding, try_preempt_first, throttled;
 	u64 queued_runtime;
 	u64 *lstats;
 
@@ -1219,20 +1313,28 @@ void BPF_STRUCT_OPS(layered_enqueue, struct task_struct *p, u64 enq_flags)
 	try_preempt_first = cpuc->try_preempt_first;
 	cpuc->try_preempt_first = false;
 
+	/*
+	 * If the layer has used up its CPU bandwidth, skip direct dispatching
+	 * and preemption. The task is queued on the layer DSQ and won't be
+	 * consumed until the layer is refilled.
+	 */
+	throttled = layer_bw_throttled(layer, cpuc, scx_bpf_now());
+
 	/*
 	 * Does @p prefer to preempt its previous CPU even when there are other
 	 * idle CPUs? If @p was already on the CPU (!wakeup), layered_dispatch()
 	 * already decided that @p shouldn't continue running on it. Don't
 	 * override the decision.
 	 */
-	if (try_preempt_first && wakeup && !yielding &&
+	if (try_preempt_first && wakeup && !yielding && !throttled &&
 	    try_preempt_cpu(task_cpu, p, taskc, layer, true))
 		return;
 
 	/*
 	 * If select_cpu() was skipped, try direct dispatching to an idle CPU.
 	 */
-	if (!__COMPAT_is_enq_cpu_selected(enq_flags) || try_preempt_first) {
+	if (!throttled &&
+	    (!__COMPAT_is_enq_cpu_selected(enq_flags) || try_preempt_first)) {
 		cpu = pick_idle_cpu(p, task_cpu, cpuc, taskc, layer, false);
 		if (cpu >= 0) {
 			lstat_inc(LSTAT_ENQ_LOCAL, layer, cpuc);
@@ -1248,7 +1350,7 @@ void BPF_STRUCT_OPS(layered_enqueue, struct task_struct *p, u64 enq_flags)
 	/*
 	 * No idle CPU, try preempting.
 	 */
-	if ((layer->preempt || is_preempt_kthread(p)) && !yielding) {
+	if ((layer->preempt || is_preempt_kthread(p)) && !yielding && !throttled) {
 		/*
 		 * See try_preempt_first block above for explanation on the
 		 * wakeup test.
@@ -1401,6 +1503,10 @@ static bool keep_running(struct cpu_ctx *cpuc, struct task_struct *p)
 	if (!(taskc = lookup_task_ctx(p)) || !(layer = lookup_layer(taskc->layer_id)))
 		goto no;
 
+	/* throttled layers don't get to continue */
+	if (layer_bw_throttled(layer, cpuc, scx_bpf_now()))
+		goto no;
+
 	/* tasks running in low fallback doesn't get to continue */
 	if (taskc->dsq_id & LO_FB_DSQ_BASE)
 		goto no;
@@ -1581,6 +1687,9 @@ static bool try_drain_layer_llcs(struct layer *layer, struct cpu_ctx *cpuc)
 	if (cnt & 1)
 		return false;
 
+	if (layer_bw_throttled(layer, cpuc, scx_bpf_now()))
+		return false;
+
 	lstat_inc(LSTAT_LLC_DRAIN_TRY, layer, cpuc);
 
 	bpf_for(u, 0, nr_llcs) {
@@ -1643,6 +1752,9 @@ static __always_inline bool try_consume_layer(u32 layer_id, struct cpu_ctx *cpuc
 	if (!(layer = lookup_layer(layer_id)))
 		return false;
 
+	if (layer_bw_throttled(layer, cpuc, scx_bpf_now()))
+		return false;
+
 	bpf_for(u, 0, llc_pmap->sys_end) {
 		u16 *llc_idp;
 
@@ -1697,6 +1809,40 @@ bool try_consume_layers(u32 *layer_order, u32 nr, u32 exclude_layer_id,
 	return false;
 }
 
+/*
+ * Tasks with custom affinities are queued on the low fallback DSQ instead of
+ * their layer DSQs. Skip the ones whose layers are throttled so that they
+ * don't escape the layer's CPU bandwidth cap. The skipped tasks are picked
+ * up once the layer is refilled. Without scx_bpf_dsq_move(), the DSQ is
+ * consumed in order and such tasks aren't throttled.
+ */
+static bool try_consume_lo_fb(struct cpu_ctx *cpuc)
+{
+	struct task_struct *p;
+	u64 now;
+
+	if (!enable_bw_caps ||
+	    !(bpf_ksym_exists(scx_bpf_dsq_move) ||
+	      bpf_ksym_exists(scx_bpf_dispatch_from_dsq___compat)))
+		return scx_bpf_dsq_move_to_local(cpuc->lo_fb_dsq_id);
+
+	now = scx_bpf_now();
+	bpf_for_each(scx_dsq, p, cpuc->lo_fb_dsq_id, 0) {
+		struct task_ctx *taskc;
+		struct layer *layer;
+
+		if (!(taskc = lookup_task_ctx(p)) ||
+		    !(layer = lookup_layer(taskc->layer_id)))
+			continue;
+		if (layer_bw_throttled(layer, cpuc, now))
+			continue;
+		if (__COMPAT_scx_bpf_dsq_move(BPF_FOR_EACH_ITER, p, SCX_DSQ_LOCAL, 0))
+			return true;
+	}
+
+	return false;
+}
+
 void BPF_STRUCT_OPS(layered_dispatch, s32 cpu, struct task_struct *prev)
 {
 	struct cpu_ctx *cpuc, *sib_cpuc;
@@ -1788,7 +1934,7 @@ void BPF_STRUCT_OPS(layered_dispatch, s32 cpu, struct task_struct *prev)
 			cpuc->lo_fb_usage_base;
 
 		if (dur > lo_fb_wait_ns && 1024 * usage < lo_fb_share_ppk * dur) {
-			if (scx_bpf_dsq_move_to_local(cpuc->lo_fb_dsq_id))
+			if (try_consume_lo_fb(cpuc))
 				return;
 			tried_lo_fb = true;
 		}
@@ -1868,7 +2014,7 @@ void BPF_STRUCT_OPS(layered_dispatch, s32 cpu, struct task_struct *prev)
 			return;
 	}
 
-	if (!tried_lo_fb && scx_bpf_dsq_move_to_local(cpuc->lo_fb_dsq_id))
+	if (!tried_lo_fb && try_consume_lo_fb(cpuc))
 		return;
 }
 
@@ -1995,6 +2141,16 @@ static __noinline bool match_one(struct layer_match *match,
 
 			return pid_present == match->used_gpu_pid;
 	}
+	case MATCH_USER_HINT: {
+		struct user_match_hint *hint;
+		u32 pid = p->pid;
+
+		if (match->user_match_id >= MAX_USER_MATCHES)
+			return false;
+		if (!(hint = bpf_map_lookup_elem(&user_match_hints, &pid)))
+			return false;
+		return hint->hints & (1LLU << match->user_match_id);
+	}
 
 	default:
 		scx_bpf_error("invalid match kind %d", match->kind);
@@ -2002,6 +2158,10 @@ static __noinline bool match_one(struct layer_match *match,
 	}
 }
 
+/*
+ * Returns the index of the first OR block of @layer_id which matches @pid,
+ * -ENOENT if none does.
+ */
 int match_layer(u32 layer_id, pid_t pid, const char *cgrp_path)
 {
 
@@ -2052,7 +2212,7 @@ int match_layer(u32 layer_id, pid_t pid, const char *cgrp_path)
 
 		if (matched) {
 			bpf_task_release(p);
-			return 0;
+			return or_id;
 		}
 	}
 
@@ -2064,16 +2224,34 @@ err:
 	return -EINVAL;
 }
 
+static bool user_match_hint_changed(struct task_struct *p, struct task_ctx *taskc)
+{
+	struct user_match_hint *hint;
+	u32 pid = p->pid;
+
+	if (!nr_user_matches)
+		return false;
+	if (!(hint = bpf_map_lookup_elem(&user_match_hints, &pid)) ||
+	    hint->seq == taskc->user_match_seq)
+		return false;
+
+	taskc->user_match_seq = hint->seq;
+	return true;
+}
+
 static void maybe_refresh_layer(struct task_struct *p, struct task_ctx *taskc)
 {
 	const char *cgrp_path;
 	bool matched = false;
 	u64 layer_id;	// XXX - int makes verifier unhappy
 	pid_t pid = p->pid;
+	int or_id;
 
-	if (!taskc->refresh_layer)
+	if (!taskc->refresh_layer && taskc->match_seq == layer_match_seq &&
+	    !user_match_hint_changed(p, taskc))
 		return;
 	taskc->refresh_layer = false;
+	taskc->match_seq = layer_match_seq;
 
 	if (!(cgrp_path = format_cgrp_path(p->cgroups->dfl_cgrp)))
 		return;
@@ -2082,7 +2260,7 @@ static void maybe_refresh_layer(struct task_struct *p, struct task_ctx *taskc)
 		__sync_fetch_and_add(&layers[taskc->layer_id].nr_tasks, -1);
 
 	bpf_for(layer_id, 0, nr_layers) {
-		if (match_layer(layer_id, pid, cgrp_path) == 0) {
+		if ((or_id = match_layer(layer_id, pid, cgrp_path)) >= 0) {
 			matched = true;
 			break;
 		}
@@ -2097,7 +2275,10 @@ static void maybe_refresh_layer(struct task_struct *p, struct task_ctx *taskc)
 		    !(llcc = lookup_llc_ctx(cpuc->llc_id)))
 			return;
 
+		if (taskc->layer_id != layer_id)
+			taskc->layer_runtime = 0;
 		taskc->layer_id = layer_id;
+		taskc->match_or_id = or_id;
 		taskc->llc_id = cpuc->llc_id;
 		taskc->layered_cpus.seq = -1;
 		taskc->layered_cpus_llc.seq = -1;
@@ -2123,6 +2304,32 @@ static void maybe_refresh_layer(struct task_struct *p, struct task_ctx *taskc)
 		      taskc->layer_id, p->comm, p->pid, cgrp_path);
 }
 
+/*
+ * Dumps the layer membership of all tasks for the layer_tasks stats request.
+ * See struct task_ctx_dump.
+ */
+SEC("iter/task")
+int dump_task_ctxs(struct bpf_iter__task *ctx)
+{
+	struct seq_file *seq = ctx->meta->seq;
+	struct task_struct *p = ctx->task;
+	struct task_ctx_dump dump = {};
+	struct task_ctx *taskc;
+
+	if (!p || !(taskc = lookup_task_ctx_may_fail(p)))
+		return 0;
+
+	dump.pid = p->pid;
+	dump.tgid = p->tgid;
+	dump.layer_id = taskc->layer_id;
+	dump.match_or_id = taskc->match_or_id;
+	dump.runtime = taskc->layer_runtime;
+	__builtin_memcpy(dump.comm, p->comm, MAX_COMM);
+
+	bpf_seq_write(seq, &dump, sizeof(dump));
+	return 0;
+}
+
 static s32 create_save_cpumask(struct bpf_cpumask **kptr)
 {
 	struct bpf_cpumask *cpumask;
@@ -2293,6 +2500,7 @@ void BPF_STRUCT_OPS(layered_runnable, struct task_struct *p, u64 enq_flags)
 		return;
 
 	taskc->runnable_at = now;
+	taskc->queued_at = now;
 	maybe_refresh_layer(p, taskc);
 
 	if (enq_flags & SCX_ENQ_WAKEUP)
@@ -2342,6 +2550,21 @@ void BPF_STRUCT_OPS(layered_running, struct task_struct *p)
 	cpuc->task_layer_id = taskc->layer_id;
 	cpuc->running_at = now;
 	taskc->running_at = now;
+
+	if (taskc->queued_at) {
+		lat_hist_add(layer, cpuc, now - taskc->queued_at);
+		taskc->queued_at = 0;
+	}
+
+	/* don't let @p overrun the remaining CPU bandwidth by a full slice */
+	if (layer->bw_quota_ns) {
+		u64 bw_used = layer->bw_used, bw_left = BW_MIN_SLICE_NS;
+
+		if (bw_used + BW_MIN_SLICE_NS < layer->bw_quota_ns)
+			bw_left = layer->bw_quota_ns - bw_used;
+		if (p->scx.slice > bw_left)
+			p->scx.slice = bw_left;
+	}
 	cpuc->is_protected = layer->is_protected;
 
 	/* running an owned task if the task is on the layer owning the CPU */
@@ -2400,6 +2623,12 @@ void BPF_STRUCT_OPS(layered_stopping, struct task_struct *p, bool runnable)
 
 	used = now - taskc->running_at;
 	cpuc->usage += used;
+	taskc->layer_runtime += used;
+	layer_bw_charge(task_layer, cpuc, used, now);
+
+	/* still runnable, will be queued again */
+	if (runnable)
+		taskc->queued_at = now;
 
 	taskc->runtime_avg =
 		((RUNTIME_DECAY_FACTOR - 1) * taskc->runtime_avg + used) /
@@ -2948,6 +3177,40 @@ static bool antistall_scan(void)
 	return true;
 }
 
+/*
+ * Refill the throttled layers whose bandwidth period has ended and kick the
+ * idle CPUs so that the tasks queued while throttled get dispatched.
+ */
+static bool layer_bw_timer(void)
+{
+	struct cpu_ctx *cpuc;
+	struct layer *layer;
+	u64 now = scx_bpf_now();
+	u32 layer_id;
+	bool refilled = false;
+	s32 cpu;
+
+	if (!enable_bw_caps)
+		return false;
+
+	if (!(cpuc = lookup_cpu_ctx(-1)))
+		return true;
+
+	bpf_for(layer_id, 0, nr_layers) {
+		if (!(layer = lookup_layer(layer_id)))
+			return false;
+		if (layer->bw_throttled_at && !layer_bw_throttled(layer, cpuc, now))
+			refilled = true;
+	}
+
+	if (refilled) {
+		bpf_for(cpu, 0, nr_possible_cpus)
+			scx_bpf_kick_cpu(cpu, SCX_KICK_IDLE);
+	}
+
+	return true;
+}
+
 bool run_timer_cb(int key)
 {
 	switch (key) {
@@ -2955,6 +3218,8 @@ bool run_timer_cb(int key)
 		return layered_monitor();
 	case ANTISTALL_TIMER:
 		return antistall_scan();
+	case BW_TIMER:
+		return layer_bw_timer();
 	case NOOP_TIMER:
 	case MAX_TIMERS:
 	default:
@@ -2965,6 +3230,7 @@ bool run_timer_cb(int key)
 struct layered_timer layered_timers[MAX_TIMERS] = {
 	{15LLU * NSEC_PER_SEC, CLOCK_BOOTTIME, 0},
 	{1LLU * NSEC_PER_SEC, CLOCK_BOOTTIME, 0},
+	{BW_REFILL_INTERVAL_NS, CLOCK_BOOTTIME, 0},
 	{0LLU, CLOCK_BOOTTIME, 0},
 };
 
@@ -30,6 +30,7 @@ struct layered_timer {
 enum layer_timer_callbacks {
 	LAYERED_MONITOR,
 	ANTISTALL_TIMER,
+	BW_TIMER,
 	NOOP_TIMER,
 	MAX_TIMERS,
 };
@@ -2,43 +2,259 @@
 
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
-use std::fs;
-use std::io::Read;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
 
+use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::bpf_intf;
+use crate::config_file;
 use crate::LayerGrowthAlgo;
+use crate::LayerProfile;
+use crate::DEFAULT_PROFILE;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(transparent)]
+const MAX_LAYER_MATCH_ORS: usize = bpf_intf::consts_MAX_LAYER_MATCH_ORS as usize;
+const NR_LAYER_MATCH_KINDS: usize = bpf_intf::layer_match_kind_NR_LAYER_MATCH_KINDS as usize;
+
+/// Either a bare array of layer specs or an object which can also carry
+/// profiles, see LayerProfile.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct LayerConfig {
     pub specs: Vec<LayerSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<LayerProfile>,
+    /// The config files the specs and profiles were loaded from.
+    #[serde(skip)]
+    pub files: BTreeSet<PathBuf>,
+}
+
+impl LayerConfig {
+    /// Loads @input which is either a JSON string or "f:"/"file:" followed
+    /// by the path to a JSON, TOML or YAML file. Includes, templates and
+    /// defaults are expanded but nested layer specs are left as-is. See
+    /// config_file::load() for the format.
+    pub fn load(input: &str) -> Result<Self> {
+        let values = config_file::load(input)?;
+        let mut config = Self {
+            files: values.files,
+            ..Default::default()
+        };
+
+        for (idx, val) in values.specs.into_iter().enumerate() {
+            let name = val
+                .get("name")
+                .and_then(|name| name.as_str())
+                .unwrap_or("?");
+            let ctx = format!("Invalid spec [{}] {:?}", idx, name);
+            let spec = serde_json::from_value(val).context(ctx)?;
+            config.specs.push(spec);
+        }
+        for (idx, val) in values.profiles.into_iter().enumerate() {
+            let profile = serde_json::from_value(val)
+                .with_context(|| format!("Invalid profile [{}]", idx))?;
+            config.profiles.push(profile);
+        }
+
+        Ok(config)
+    }
+
+    /// Loads @input and flattens the nested layer specs.
+    pub fn parse(input: &str) -> Result<Self> {
+        Self::load(input)?.flatten()
+    }
+
+    /// Flattens the nested layer specs.
+    pub fn flatten(self) -> Result<Self> {
+        Ok(Self {
+            specs: LayerSpec::flatten(self.specs)?,
+            profiles: self.profiles,
+            files: self.files,
+        })
+    }
+
+    /// Returns the specs with @profile applied, DEFAULT_PROFILE for none.
+    pub fn resolve(&self, profile: &str) -> Result<Vec<LayerSpec>> {
+        if profile == DEFAULT_PROFILE {
+            return Ok(self.specs.clone());
+        }
+        match self.profiles.iter().find(|p| p.name == profile) {
+            Some(p) => p.apply(&self.specs),
+            None => bail!("Unknown profile {:?}", profile),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LayerSpec {
     pub name: String,
     pub comment: Option<String>,
     pub matches: Vec<Vec<LayerMatch>>,
     pub kind: LayerKind,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<LayerSpec>,
+    /// Ancestors of a flattened nested layer, outermost first.
+    #[serde(skip)]
+    pub parents: Vec<LayerGroup>,
+}
+
+/// A layer spec with children. It isn't a layer by itself but owns the CPU
+/// budget shared by the layers below it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerGroup {
+    pub name: String,
+    pub kind: LayerKind,
+}
+
+/// Resolves cpus_range or cpus_range_frac into a CPU count range.
+pub fn resolve_cpus_pct_range(
+    cpus_range: &Option<(usize, usize)>,
+    cpus_range_frac: &Option<(f64, f64)>,
+    max_cpus: usize,
+) -> Result<(usize, usize)> {
+    match (cpus_range, cpus_range_frac) {
+        (Some(_x), Some(_y)) => {
+            bail!("cpus_range cannot be used with cpus_pct.");
+        }
+        (Some((cpus_range_min, cpus_range_max)), None) => Ok((*cpus_range_min, *cpus_range_max)),
+        (None, Some((cpus_frac_min, cpus_frac_max))) => {
+            if *cpus_frac_min < 0_f64
+                || *cpus_frac_min > 1_f64
+                || *cpus_frac_max < 0_f64
+                || *cpus_frac_max > 1_f64
+            {
+                bail!("cpus_range_frac values must be between 0.0 and 1.0");
+            }
+            let cpus_min_count = ((max_cpus as f64) * cpus_frac_min).round_ties_even() as usize;
+            let cpus_max_count = ((max_cpus as f64) * cpus_frac_max).round_ties_even() as usize;
+            Ok((
+                std::cmp::max(cpus_min_count, 1),
+                std::cmp::min(cpus_max_count, max_cpus),
+            ))
+        }
+        (None, None) => Ok((0, max_cpus)),
+    }
+}
+
+/// Returns the resolved cpus_range of @group.
+pub fn group_cpus_range(group: &LayerGroup, max_cpus: usize) -> (usize, usize) {
+    match &group.kind {
+        LayerKind::Confined {
+            cpus_range,
+            cpus_range_frac,
+            ..
+        }
+        | LayerKind::Grouped {
+            cpus_range,
+            cpus_range_frac,
+            ..
+        } => resolve_cpus_pct_range(cpus_range, cpus_range_frac, max_cpus).unwrap(),
+        LayerKind::Open { .. } => (0, max_cpus),
+    }
 }
 
 impl LayerSpec {
     pub fn parse(input: &str) -> Result<Vec<Self>> {
-        let config: LayerConfig = if input.starts_with("f:") || input.starts_with("file:") {
-            let mut f = fs::OpenOptions::new()
-                .read(true)
-                .open(input.split_once(':').unwrap().1)?;
-            let mut content = String::new();
-            f.read_to_string(&mut content)?;
-            serde_json::from_str(&content)?
-        } else {
-            serde_json::from_str(input)?
-        };
-        Ok(config.specs)
+        Ok(LayerConfig::parse(input)?.specs)
+    }
+
+    /// Flattens nested layer specs into the leaf layers in depth-first
+    /// order. The matches of each child are ANDed with those of its parent
+    /// so that matching is effectively top-down. Children using the default
+    /// growth algorithm inherit the parent's.
+    pub fn flatten(specs: Vec<Self>) -> Result<Vec<Self>> {
+        let mut flat = vec![];
+        for spec in specs.into_iter() {
+            spec.flatten_into(&[], &mut flat)?;
+        }
+        Ok(flat)
+    }
+
+    fn flatten_into(mut self, parents: &[LayerGroup], flat: &mut Vec<Self>) -> Result<()> {
+        if self.children.is_empty() {
+            self.parents = parents.to_vec();
+            flat.push(self);
+            return Ok(());
+        }
+
+        if let LayerKind::Open { .. } = self.kind {
+            bail!(
+                "Spec {:?} has children but isn't confined or grouped",
+                self.name
+            );
+        }
+        if !self
+            .children
+            .last()
+            .is_some_and(|child| child.matches.len() == 1 && child.matches[0].is_empty())
+        {
+            bail!(
+                "The last child of spec {:?} must have an empty match",
+                self.name
+            );
+        }
+
+        let mut parents = parents.to_vec();
+        parents.push(LayerGroup {
+            name: self.name.clone(),
+            kind: self.kind.clone(),
+        });
+
+        for mut child in std::mem::take(&mut self.children).into_iter() {
+            let nr_child_ors = child.matches.len();
+            child.matches = self
+                .matches
+                .iter()
+                .flat_map(|ands| {
+                    child
+                        .matches
+                        .iter()
+                        .map(move |child_ands| ands.iter().chain(child_ands).cloned().collect())
+                })
+                .collect();
+
+            // Catch the blowup here where both the parent and the child can
+            // still be named. verify_layer_specs() only sees the result.
+            if child.matches.len() > MAX_LAYER_MATCH_ORS {
+                bail!(
+                    "Spec {:?} under {:?} has too many ({}) OR match blocks after combining \
+                     its {} with the parent's {}, max {}",
+                    child.name,
+                    self.name,
+                    child.matches.len(),
+                    nr_child_ors,
+                    self.matches.len(),
+                    MAX_LAYER_MATCH_ORS
+                );
+            }
+            if let Some(ands) = child
+                .matches
+                .iter()
+                .find(|ands| ands.len() > NR_LAYER_MATCH_KINDS)
+            {
+                bail!(
+                    "Spec {:?} under {:?} has an OR block with too many ({}) match conditions \
+                     after combining with the parent's, max {}",
+                    child.name,
+                    self.name,
+                    ands.len(),
+                    NR_LAYER_MATCH_KINDS
+                );
+            }
+
+            let growth_algo = &self.kind.common().growth_algo;
+            let child_common = child.kind.common_mut();
+            if child_common.growth_algo == LayerGrowthAlgo::default() {
+                child_common.growth_algo = growth_algo.clone();
+            }
+
+            child.flatten_into(&parents, flat)?;
+        }
+
+        Ok(())
     }
 
     pub fn nodes(&self) -> &Vec<usize> {
@@ -58,7 +274,7 @@ impl LayerSpec {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LayerMatch {
     CgroupPrefix(String),
     CommPrefix(String),
@@ -78,9 +294,32 @@ pub enum LayerMatch {
     IsKthread(bool),
     UsedGpuTid(bool),
     UsedGpuPid(bool),
+    CommRegex(String),
+    CgroupRegex(String),
+    CgroupSuffix(String),
+    ExePath(String),
+    ExePrefix(String),
+    EnvVarEquals(String, String),
+    SystemdUnit(String),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl LayerMatch {
+    /// Returns whether the match is resolved in userspace rather than in BPF.
+    pub fn is_user_match(&self) -> bool {
+        matches!(
+            self,
+            LayerMatch::CommRegex(_)
+                | LayerMatch::CgroupRegex(_)
+                | LayerMatch::CgroupSuffix(_)
+                | LayerMatch::ExePath(_)
+                | LayerMatch::ExePrefix(_)
+                | LayerMatch::EnvVarEquals(_, _)
+                | LayerMatch::SystemdUnit(_)
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LayerCommon {
     #[serde(default)]
     pub min_exec_us: u64,
@@ -115,12 +354,22 @@ pub struct LayerCommon {
     #[serde(default)]
     pub idle_resume_us: Option<u32>,
     #[serde(default)]
+    pub latency_target_us: Option<u64>,
+    #[serde(default)]
+    pub latency_percentile: Option<f64>,
+    #[serde(default)]
+    pub quota_us: Option<u64>,
+    #[serde(default)]
+    pub period_us: Option<u64>,
+    #[serde(default)]
+    pub max_cpu_pct: Option<f64>,
+    #[serde(default)]
     pub nodes: Vec<usize>,
     #[serde(default)]
     pub llcs: Vec<usize>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LayerKind {
     Confined {
         util_range: (f64, f64),
@@ -190,3 +439,146 @@ impl LayerKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(parent_matches: &str, child_matches: &str) -> String {
+        format!(
+            r#"[
+                {{
+                    "name": "services",
+                    "matches": {},
+                    "kind": {{"Confined": {{
+                        "util_range": [0.5, 0.6],
+                        "cpus_range_frac": [0.4, 0.6],
+                        "growth_algo": "Linear"
+                    }}}},
+                    "children": [
+                        {{
+                            "name": "frontend",
+                            "matches": {},
+                            "kind": {{"Grouped": {{"util_range": [0.5, 0.6]}}}}
+                        }},
+                        {{
+                            "name": "backend",
+                            "matches": [[]],
+                            "kind": {{"Grouped": {{
+                                "util_range": [0.5, 0.6],
+                                "growth_algo": "Reverse"
+                            }}}}
+                        }}
+                    ]
+                }},
+                {{"name": "rest", "matches": [[]], "kind": {{"Open": {{}}}}}}
+            ]"#,
+            parent_matches, child_matches
+        )
+    }
+
+    #[test]
+    fn test_flatten() {
+        let specs = LayerSpec::parse(&nested(
+            r#"[[{"CgroupPrefix": "services/"}], [{"NiceBelow": 0}]]"#,
+            r#"[[{"CommPrefix": "fe"}], [{"PcommPrefix": "fe"}]]"#,
+        ))
+        .unwrap();
+
+        let names: Vec<&str> = specs.iter().map(|spec| spec.name.as_str()).collect();
+        assert_eq!(names, vec!["frontend", "backend", "rest"]);
+
+        // The cross product of the parent's and the child's OR blocks.
+        let cgroup = LayerMatch::CgroupPrefix("services/".into());
+        let nice = LayerMatch::NiceBelow(0);
+        let comm = LayerMatch::CommPrefix("fe".into());
+        let pcomm = LayerMatch::PcommPrefix("fe".into());
+        assert_eq!(
+            specs[0].matches,
+            vec![
+                vec![cgroup.clone(), comm.clone()],
+                vec![cgroup.clone(), pcomm.clone()],
+                vec![nice.clone(), comm],
+                vec![nice.clone(), pcomm],
+            ]
+        );
+        assert_eq!(specs[1].matches, vec![vec![cgroup], vec![nice]]);
+        assert!(specs.iter().all(|spec| spec.children.is_empty()));
+
+        assert_eq!(specs[0].parents.len(), 1);
+        assert_eq!(specs[0].parents[0].name, "services");
+        assert_eq!(specs[0].parents, specs[1].parents);
+        assert!(specs[2].parents.is_empty());
+
+        // Only the default growth algorithm is inherited.
+        assert_eq!(specs[0].kind.common().growth_algo, LayerGrowthAlgo::Linear);
+        assert_eq!(specs[1].kind.common().growth_algo, LayerGrowthAlgo::Reverse);
+    }
+
+    #[test]
+    fn test_flatten_errors() {
+        // The last child must be a catch-all.
+        let input = nested("[[]]", "[[]]").replace(
+            r#""matches": [[]],
+                            "kind": {"Grouped""#,
+            r#""matches": [[{"NiceAbove": 0}]],
+                            "kind": {"Grouped""#,
+        );
+        let err = LayerSpec::parse(&input).unwrap_err().to_string();
+        assert!(err.contains("last child of spec \"services\""), "{}", err);
+
+        let input = nested("[[]]", "[[]]").replace(
+            r#""kind": {"Confined": {
+                        "util_range": [0.5, 0.6],
+                        "cpus_range_frac": [0.4, 0.6],
+                        "growth_algo": "Linear"
+                    }}"#,
+            r#""kind": {"Open": {}}"#,
+        );
+        let err = LayerSpec::parse(&input).unwrap_err().to_string();
+        assert!(err.contains("isn't confined or grouped"), "{}", err);
+    }
+
+    #[test]
+    fn test_flatten_match_limits() {
+        let ors = |nr: usize| {
+            let ors: Vec<String> = (0..nr)
+                .map(|i| format!(r#"[{{"NiceEquals": {}}}]"#, i))
+                .collect();
+            format!("[{}]", ors.join(","))
+        };
+
+        // Right at the limit.
+        let nr_ors = (MAX_LAYER_MATCH_ORS as f64).sqrt() as usize;
+        let specs = LayerSpec::parse(&nested(&ors(nr_ors), &ors(nr_ors))).unwrap();
+        assert_eq!(specs[0].matches.len(), nr_ors * nr_ors);
+
+        let err = LayerSpec::parse(&nested(&ors(nr_ors + 1), &ors(nr_ors + 1)))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Spec \"frontend\" under \"services\" has too many"),
+            "{}",
+            err
+        );
+
+        let ands = |nr: usize| {
+            let ands: Vec<String> = (0..nr)
+                .map(|i| format!(r#"{{"NiceEquals": {}}}"#, i))
+                .collect();
+            format!("[[{}]]", ands.join(","))
+        };
+        let nr_ands = NR_LAYER_MATCH_KINDS / 2;
+        let specs = LayerSpec::parse(&nested(&ands(nr_ands), &ands(nr_ands))).unwrap();
+        assert_eq!(specs[0].matches[0].len(), nr_ands * 2);
+
+        let err = LayerSpec::parse(&nested(&ands(nr_ands + 1), &ands(nr_ands + 1)))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Spec \"frontend\" under \"services\" has an OR block"),
+            "{}",
+            err
+        );
+    }
+}
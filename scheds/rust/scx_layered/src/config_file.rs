@@ -0,0 +1,500 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::LayerKind;
+
+/// Layer specs and profiles with includes, templates and defaults expanded.
+#[derive(Debug, Default)]
+pub struct ConfigValues {
+    pub specs: Vec<Value>,
+    pub profiles: Vec<Value>,
+    /// The loaded files including the included ones.
+    pub files: BTreeSet<PathBuf>,
+}
+
+/// What a config file and the files it includes contribute.
+#[derive(Debug, Default)]
+struct ConfigParts {
+    specs: Vec<Value>,
+    profiles: Vec<Value>,
+    templates: Map<String, Value>,
+    defaults: Map<String, Value>,
+}
+
+/// The files being loaded and the ones already loaded.
+#[derive(Debug, Default)]
+struct Includes {
+    stack: Vec<PathBuf>,
+    loaded: BTreeSet<PathBuf>,
+}
+
+/// Loads @input which is either an inline JSON string or "f:"/"file:"
+/// followed by the path to a JSON, TOML (.toml) or YAML (.yaml, .yml) file.
+///
+/// The content is either an array of layer specs or an object with the
+/// following optional keys:
+///
+/// - include: A path or a list of paths to other config files, relative to
+///   the including file. Their specs and profiles come first. A file which
+///   is reached through more than one include is loaded only once, at the
+///   first include.
+/// - templates: Named match templates in the OR-of-AND format of matches.
+/// - defaults: Layer kind fields which are applied to specs which don't set
+///   them.
+/// - specs: Layer specs.
+/// - profiles: Layer profiles.
+///
+/// Templates and defaults are shared with the included files with the
+/// including file's taking precedence. The paths of all the loaded files
+/// are returned along with the values.
+pub fn load(input: &str) -> Result<ConfigValues> {
+    let mut includes = Includes::default();
+    let parts = match input.split_once(':') {
+        Some(("f" | "file", path)) => load_file(Path::new(path), &mut includes)?,
+        _ => load_value(serde_json::from_str(input)?, Path::new("."), &mut includes)?,
+    };
+
+    let mut all_fields = BTreeSet::new();
+    for kind in LAYER_KINDS.iter() {
+        all_fields.extend(kind_fields(kind)?);
+    }
+    if let Some(key) = parts.defaults.keys().find(|key| !all_fields.contains(*key)) {
+        bail!("Unknown field {:?} in defaults", key);
+    }
+
+    let mut specs = parts.specs;
+    for spec in specs.iter_mut() {
+        expand_spec(spec, &parts.templates, &parts.defaults)?;
+    }
+
+    Ok(ConfigValues {
+        specs,
+        profiles: parts.profiles,
+        files: includes.loaded,
+    })
+}
+
+fn load_file(path: &Path, includes: &mut Includes) -> Result<ConfigParts> {
+    let canon = fs::canonicalize(path).with_context(|| format!("Failed to open {:?}", path))?;
+    if includes.stack.contains(&canon) {
+        bail!("Circular include of {:?}", path);
+    }
+    // Loading the same file again, e.g. a common file included by two
+    // others, would duplicate its specs and profiles.
+    if !includes.loaded.insert(canon.clone()) {
+        return Ok(ConfigParts::default());
+    }
+
+    let content =
+        fs::read_to_string(&canon).with_context(|| format!("Failed to read {:?}", path))?;
+    let val: Value = match canon.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content)?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&content)?,
+        _ => serde_json::from_str(&content)?,
+    };
+
+    includes.stack.push(canon.clone());
+    let parts = load_value(val, canon.parent().unwrap_or(Path::new("/")), includes)
+        .with_context(|| format!("Failed to load {:?}", path));
+    includes.stack.pop();
+    parts
+}
+
+fn load_value(val: Value, dir: &Path, includes: &mut Includes) -> Result<ConfigParts> {
+    let mut obj = match val {
+        Value::Array(specs) => {
+            return Ok(ConfigParts {
+                specs,
+                ..Default::default()
+            })
+        }
+        Value::Object(obj) => obj,
+        _ => bail!("Config must be an array of specs or an object"),
+    };
+
+    let mut parts = ConfigParts::default();
+
+    let paths: Vec<String> = match obj.remove("include") {
+        None => vec![],
+        Some(Value::String(path)) => vec![path],
+        Some(val) => serde_json::from_value(val).context("Invalid include")?,
+    };
+    for path in paths.iter() {
+        let inc = load_file(&dir.join(path), includes)?;
+        parts.specs.extend(inc.specs);
+        parts.profiles.extend(inc.profiles);
+        parts.templates.extend(inc.templates);
+        parts.defaults.extend(inc.defaults);
+    }
+
+    for (key, val) in obj.into_iter() {
+        match (key.as_str(), val) {
+            ("specs", Value::Array(specs)) => parts.specs.extend(specs),
+            ("profiles", Value::Array(profiles)) => parts.profiles.extend(profiles),
+            ("templates", Value::Object(templates)) => parts.templates.extend(templates),
+            ("defaults", Value::Object(defaults)) => parts.defaults.extend(defaults),
+            ("specs" | "profiles", _) => bail!("{:?} must be an array", key),
+            ("templates" | "defaults", _) => bail!("{:?} must be an object", key),
+            _ => bail!("Unknown config key {:?}", key),
+        }
+    }
+
+    Ok(parts)
+}
+
+const LAYER_KINDS: [&str; 3] = ["Confined", "Grouped", "Open"];
+
+/// Returns the fields which can be set for layer @kind, e.g. "Confined".
+fn kind_fields(kind: &str) -> Result<BTreeSet<String>> {
+    // util_range is the only required field and ignored by Open.
+    let mut tagged = Map::new();
+    tagged.insert(kind.to_string(), json!({"util_range": [0.0, 1.0]}));
+    let kind: LayerKind = serde_json::from_value(Value::Object(tagged))?;
+
+    Ok(serde_json::to_value(kind)?
+        .as_object()
+        .and_then(|tagged| tagged.values().next())
+        .and_then(|fields| fields.as_object())
+        .map(|fields| fields.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+fn expand_spec(
+    spec: &mut Value,
+    templates: &Map<String, Value>,
+    defaults: &Map<String, Value>,
+) -> Result<()> {
+    let name = spec
+        .get("name")
+        .and_then(|name| name.as_str())
+        .unwrap_or("?")
+        .to_string();
+
+    if let Some(matches) = spec.get_mut("matches") {
+        let mut expanded = vec![];
+        for ands in serde_json::from_value::<Vec<Vec<Value>>>(matches.take())
+            .with_context(|| format!("Spec {:?} has invalid matches", &name))?
+        {
+            expanded.extend(
+                expand_ands(ands, templates, &mut vec![])
+                    .with_context(|| format!("Spec {:?} has invalid template", &name))?,
+            );
+        }
+        *matches = expanded
+            .into_iter()
+            .map(Value::Array)
+            .collect::<Vec<_>>()
+            .into();
+    }
+
+    if !defaults.is_empty() {
+        apply_defaults(spec, defaults).with_context(|| format!("Spec {:?}", &name))?;
+    }
+
+    if let Some(Value::Array(children)) = spec.get_mut("children") {
+        for child in children.iter_mut() {
+            expand_spec(child, templates, defaults)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands the {"Template": NAME} matches in an AND block. As a template is
+/// a list of OR blocks, the result is the combinations of the template's OR
+/// blocks and the rest of the AND block.
+fn expand_ands(
+    ands: Vec<Value>,
+    templates: &Map<String, Value>,
+    stack: &mut Vec<String>,
+) -> Result<Vec<Vec<Value>>> {
+    let mut ors: Vec<Vec<Value>> = vec![vec![]];
+
+    for mt in ands.into_iter() {
+        let Some(name) = mt.get("Template").and_then(|name| name.as_str()) else {
+            ors.iter_mut().for_each(|ands| ands.push(mt.clone()));
+            continue;
+        };
+
+        if stack.iter().any(|n| n == name) {
+            bail!("Circular template {:?}", name);
+        }
+        let Some(template) = templates.get(name) else {
+            bail!("Unknown template {:?}", name);
+        };
+
+        stack.push(name.to_string());
+        let mut tmpl_ors = vec![];
+        for tmpl_ands in serde_json::from_value::<Vec<Vec<Value>>>(template.clone())
+            .with_context(|| format!("Template {:?} isn't a list of AND blocks", name))?
+        {
+            tmpl_ors.extend(expand_ands(tmpl_ands, templates, stack)?);
+        }
+        stack.pop();
+
+        ors = ors
+            .iter()
+            .flat_map(|ands| {
+                tmpl_ors
+                    .iter()
+                    .map(move |tmpl_ands| ands.iter().chain(tmpl_ands).cloned().collect())
+            })
+            .collect();
+    }
+
+    Ok(ors)
+}
+
+fn apply_defaults(spec: &mut Value, defaults: &Map<String, Value>) -> Result<()> {
+    // LayerKind is externally tagged, {"Confined": {...}}.
+    let Some((kind, fields)) = spec
+        .get_mut("kind")
+        .and_then(|kind| kind.as_object_mut())
+        .and_then(|tagged| tagged.iter_mut().next())
+    else {
+        bail!("Missing or invalid kind");
+    };
+    let Some(fields) = fields.as_object_mut() else {
+        bail!("Invalid {} kind", kind);
+    };
+
+    // Only apply the fields which are valid for this kind.
+    let kind_fields = kind_fields(kind)?;
+    for (key, val) in defaults.iter() {
+        if kind_fields.contains(key) && !fields.contains_key(key) {
+            fields.insert(key.clone(), val.clone());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "scx_layered_config_file_{}_{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn names(specs: &[Value]) -> Vec<&str> {
+        specs
+            .iter()
+            .map(|spec| spec["name"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_ands() {
+        let templates = json!({
+            "batch": [[{"CgroupPrefix": "batch/"}], [{"NiceAbove": 0}]],
+            "fe": [[{"CommPrefix": "fe"}, {"UIDEquals": 1000}]],
+            "nested": [[{"Template": "batch"}, {"Template": "fe"}]],
+            "loop": [[{"Template": "loop2"}]],
+            "loop2": [[{"Template": "loop"}]],
+            "bad": [{"CommPrefix": "x"}]
+        });
+        let templates = templates.as_object().unwrap();
+        let expand = |ands: Value| {
+            expand_ands(
+                serde_json::from_value(ands).unwrap(),
+                templates,
+                &mut vec![],
+            )
+        };
+
+        // Without templates, the AND block is unchanged.
+        let ands = json!([{"CommPrefix": "a"}, {"NiceBelow": 0}]);
+        assert_eq!(
+            expand(ands.clone()).unwrap(),
+            vec![serde_json::from_value::<Vec<Value>>(ands).unwrap()]
+        );
+        assert_eq!(expand(json!([])).unwrap(), vec![Vec::<Value>::new()]);
+
+        // One AND block per OR block of the template, keeping the order.
+        assert_eq!(
+            expand(json!([{"CommPrefix": "a"}, {"Template": "batch"}, {"NiceBelow": 5}])).unwrap(),
+            vec![
+                vec![
+                    json!({"CommPrefix": "a"}),
+                    json!({"CgroupPrefix": "batch/"}),
+                    json!({"NiceBelow": 5}),
+                ],
+                vec![
+                    json!({"CommPrefix": "a"}),
+                    json!({"NiceAbove": 0}),
+                    json!({"NiceBelow": 5}),
+                ],
+            ]
+        );
+
+        // Templates can use other templates.
+        assert_eq!(
+            expand(json!([{"Template": "nested"}])).unwrap(),
+            vec![
+                vec![
+                    json!({"CgroupPrefix": "batch/"}),
+                    json!({"CommPrefix": "fe"}),
+                    json!({"UIDEquals": 1000}),
+                ],
+                vec![
+                    json!({"NiceAbove": 0}),
+                    json!({"CommPrefix": "fe"}),
+                    json!({"UIDEquals": 1000}),
+                ],
+            ]
+        );
+        // Two multi-block templates in one AND block multiply.
+        assert_eq!(
+            expand(json!([{"Template": "batch"}, {"Template": "batch"}]))
+                .unwrap()
+                .len(),
+            4
+        );
+
+        assert!(expand(json!([{"Template": "nosuch"}])).is_err());
+        assert!(expand(json!([{"Template": "loop"}])).is_err());
+        assert!(expand(json!([{"Template": "bad"}])).is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load(
+            r#"{
+                "defaults": {"slice_us": 1000, "util_range": [0.1, 0.2]},
+                "specs": [
+                    {"name": "a", "matches": [[]], "kind": {"Confined": {"util_range": [0.5, 0.6]}}},
+                    {"name": "b", "matches": [[]], "kind": {"Open": {"slice_us": 2000}}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // A spec's own fields win over the defaults.
+        assert_eq!(config.specs[0]["kind"]["Confined"]["slice_us"], 1000);
+        assert_eq!(
+            config.specs[0]["kind"]["Confined"]["util_range"],
+            json!([0.5, 0.6])
+        );
+        assert_eq!(config.specs[1]["kind"]["Open"]["slice_us"], 2000);
+        // Fields which aren't valid for the kind are skipped.
+        assert!(config.specs[1]["kind"]["Open"].get("util_range").is_none());
+
+        assert!(load(r#"{"defaults": {"nosuch": 1}, "specs": []}"#).is_err());
+        assert!(load(r#"{"defaults": [], "specs": []}"#).is_err());
+        assert!(load(r#"{"nosuch": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = tmp_dir("includes");
+        fs::write(
+            dir.join("common.toml"),
+            r#"
+            [templates]
+            batch = [[{ CgroupPrefix = "common/" }]]
+            idle = [[{ NiceAbove = 10 }]]
+
+            [defaults]
+            slice_us = 1000
+            preempt = true
+
+            [[specs]]
+            name = "common"
+            matches = [[{ Template = "idle" }]]
+            kind = { Open = {} }
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("left.json"),
+            r#"{"include": "common.toml", "specs": [
+                {"name": "left", "matches": [[]], "kind": {"Open": {}}}
+            ]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("right.yaml"),
+            "include: [common.toml]\nspecs:\n  - name: right\n    matches: [[]]\n    kind: {Open: {}}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("top.json"),
+            r#"{
+                "include": ["left.json", "right.yaml"],
+                "templates": {"batch": [[{"CgroupPrefix": "top/"}]]},
+                "defaults": {"slice_us": 3000},
+                "specs": [
+                    {"name": "top", "matches": [[{"Template": "batch"}]], "kind": {"Open": {}}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let config = load(&format!("f:{}", dir.join("top.json").display())).unwrap();
+
+        // Included specs come first and the diamond include of common.toml
+        // is loaded only once.
+        assert_eq!(names(&config.specs), vec!["common", "left", "right", "top"]);
+
+        // The including file's templates and defaults take precedence and
+        // apply to the included specs too.
+        assert_eq!(
+            config.specs[3]["matches"],
+            json!([[{"CgroupPrefix": "top/"}]])
+        );
+        assert_eq!(config.specs[0]["matches"], json!([[{"NiceAbove": 10}]]));
+        for spec in config.specs.iter() {
+            assert_eq!(spec["kind"]["Open"]["slice_us"], 3000);
+            assert_eq!(spec["kind"]["Open"]["preempt"], true);
+        }
+
+        // All the loaded files are reported so that they can be watched.
+        let dir = fs::canonicalize(&dir).unwrap();
+        let files: Vec<PathBuf> = ["common.toml", "left.json", "right.yaml", "top.json"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        assert_eq!(config.files.into_iter().collect::<Vec<_>>(), files);
+        assert!(load("[]").unwrap().files.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = tmp_dir("include_errors");
+        fs::write(dir.join("a.json"), r#"{"include": "b.json"}"#).unwrap();
+        fs::write(dir.join("b.json"), r#"{"include": "a.json"}"#).unwrap();
+        fs::write(dir.join("self.json"), r#"{"include": "./self.json"}"#).unwrap();
+        fs::write(dir.join("missing.json"), r#"{"include": "nosuch.json"}"#).unwrap();
+        fs::write(dir.join("bad.json"), r#"{"include": 1}"#).unwrap();
+
+        for name in ["a.json", "self.json", "missing.json", "bad.json"] {
+            let input = format!("f:{}", dir.join(name).display());
+            assert!(load(&input).is_err(), "{}", name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
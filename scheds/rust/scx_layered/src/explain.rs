@@ -0,0 +1,444 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::user_match::ProcInfo;
+use crate::user_match::ThreadInfo;
+use crate::user_match::ThreadStat;
+use crate::user_match::PF_KTHREAD;
+use crate::LayerMatch;
+use crate::LayerSpec;
+use crate::UserMatches;
+
+/// Attributes of a task the layer matches are evaluated against. These are
+/// read from /proc and mirror what the BPF matcher looks at.
+#[derive(Debug, Default)]
+struct TaskInfo {
+    tgid: u32,
+    tid: u32,
+    ppid: u32,
+    pcomm: String,
+    nice: i32,
+    flags: u64,
+    euid: u32,
+    egid: u32,
+    nsid: u64,
+    nspid: u32,
+    thread: ThreadInfo,
+}
+
+impl TaskInfo {
+    /// Returns None if the task is gone.
+    fn read(tgid: &str, tid: &str, pcomm: &str) -> Option<Self> {
+        let dir = format!("/proc/{}/task/{}", tgid, tid);
+        let mut task = Self {
+            tgid: tgid.parse().ok()?,
+            tid: tid.parse().ok()?,
+            pcomm: pcomm.to_string(),
+            thread: ThreadInfo::read(tgid, tid, true, true)
+                .or_else(|| ThreadInfo::read(tgid, tid, true, false))?,
+            ..Default::default()
+        };
+
+        let stat = ThreadStat::read(tgid, tid)?;
+        task.ppid = stat.ppid;
+        task.flags = stat.flags;
+        task.nice = stat.nice;
+
+        let status = fs::read_to_string(format!("{}/status", dir)).ok()?;
+        for line in status.lines() {
+            let Some((key, val)) = line.split_once(':') else {
+                continue;
+            };
+            let mut vals = val.split_whitespace();
+            match key {
+                // real, effective, saved and fs IDs
+                "Uid" => task.euid = vals.nth(1)?.parse().ok()?,
+                "Gid" => task.egid = vals.nth(1)?.parse().ok()?,
+                // from the outermost to the innermost namespace
+                "NSpid" => task.nspid = vals.last()?.parse().ok()?,
+                _ => {}
+            }
+        }
+
+        // "pid:[INUM]"
+        if let Ok(link) = fs::read_link(format!("{}/ns/pid", dir)) {
+            let link = link.to_string_lossy();
+            task.nsid = link
+                .trim_start_matches("pid:[")
+                .trim_end_matches(']')
+                .parse()
+                .unwrap_or(0);
+        }
+
+        Some(task)
+    }
+}
+
+/// Evaluates a single match the same way match_one() in main.bpf.c does.
+/// Matches which depend on runtime scheduler state are evaluated as if the
+/// task was just discovered by the scheduler.
+fn match_one(
+    mt: &LayerMatch,
+    task: &TaskInfo,
+    proc_info: &ProcInfo,
+    user_matches: &UserMatches,
+) -> bool {
+    match mt {
+        LayerMatch::CgroupPrefix(prefix) => task.thread.cgroup.starts_with(prefix.as_str()),
+        LayerMatch::CommPrefix(prefix) => task.thread.comm.starts_with(prefix.as_str()),
+        LayerMatch::PcommPrefix(prefix) => task.pcomm.starts_with(prefix.as_str()),
+        LayerMatch::NiceAbove(nice) => task.nice > *nice,
+        LayerMatch::NiceBelow(nice) => task.nice < *nice,
+        LayerMatch::NiceEquals(nice) => task.nice == *nice,
+        LayerMatch::UIDEquals(uid) => task.euid == *uid,
+        LayerMatch::GIDEquals(gid) => task.egid == *gid,
+        LayerMatch::PIDEquals(pid) => task.tid == *pid,
+        LayerMatch::PPIDEquals(ppid) => task.ppid == *ppid,
+        LayerMatch::TGIDEquals(tgid) => task.tgid == *tgid,
+        LayerMatch::NSPIDEquals(nsid, pid) => task.nsid == *nsid && task.nspid == *pid,
+        LayerMatch::NSEquals(nsid) => task.nsid == *nsid as u64,
+        // No join command has been issued yet.
+        LayerMatch::CmdJoin(_) => false,
+        LayerMatch::IsGroupLeader(leader) => (task.tgid == task.tid) == *leader,
+        // The BPF side ignores the polarity.
+        LayerMatch::IsKthread(_) => task.flags & PF_KTHREAD != 0,
+        // No GPU usage has been observed yet.
+        LayerMatch::UsedGpuTid(used) | LayerMatch::UsedGpuPid(used) => !*used,
+        _ => match user_matches.id(mt) {
+            Some(id) => user_matches.is_match(id, proc_info, &task.thread),
+            None => false,
+        },
+    }
+}
+
+/// Per OR-block matching statistics.
+#[derive(Debug, Default)]
+struct BlockStats {
+    nr_matched: usize,
+    nr_taken: usize,
+    taken_by: BTreeSet<usize>,
+}
+
+/// Tracks which layer and OR-block each task is assigned to.
+struct Explainer<'a> {
+    specs: &'a [LayerSpec],
+    user_matches: UserMatches,
+    blocks: Vec<Vec<BlockStats>>,
+    nr_tasks: Vec<usize>,
+    nr_unmatched: usize,
+}
+
+impl<'a> Explainer<'a> {
+    fn new(specs: &'a [LayerSpec]) -> Result<Self> {
+        Ok(Self {
+            specs,
+            user_matches: UserMatches::new(specs)?,
+            blocks: specs
+                .iter()
+                .map(|spec| spec.matches.iter().map(|_| BlockStats::default()).collect())
+                .collect(),
+            nr_tasks: vec![0; specs.len()],
+            nr_unmatched: 0,
+        })
+    }
+
+    /// Returns the layer and the OR-block @task is assigned to. The first
+    /// matching OR-block of the first matching layer is chosen and the
+    /// matching blocks of later layers are recorded as taken.
+    fn select(&mut self, task: &TaskInfo, proc_info: &ProcInfo) -> Option<(usize, usize)> {
+        let mut chosen: Option<(usize, usize)> = None;
+        for (layer_idx, spec) in self.specs.iter().enumerate() {
+            for (or_idx, ands) in spec.matches.iter().enumerate() {
+                if !ands
+                    .iter()
+                    .all(|mt| match_one(mt, task, proc_info, &self.user_matches))
+                {
+                    continue;
+                }
+
+                let block = &mut self.blocks[layer_idx][or_idx];
+                block.nr_matched += 1;
+                match chosen {
+                    Some((chosen_idx, _)) if chosen_idx < layer_idx => {
+                        block.nr_taken += 1;
+                        block.taken_by.insert(chosen_idx);
+                    }
+                    Some(_) => {}
+                    None => chosen = Some((layer_idx, or_idx)),
+                }
+            }
+        }
+
+        match chosen {
+            Some((layer_idx, _)) => self.nr_tasks[layer_idx] += 1,
+            None => self.nr_unmatched += 1,
+        }
+        chosen
+    }
+
+    fn write_task(
+        &mut self,
+        task: &TaskInfo,
+        proc_info: &ProcInfo,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let specs = self.specs;
+        let (layer, block) = match self.select(task, proc_info) {
+            Some((layer_idx, or_idx)) => (
+                specs[layer_idx].name.as_str(),
+                format!("[{}] {:?}", or_idx, &specs[layer_idx].matches[or_idx]),
+            ),
+            None => ("-", "-".to_string()),
+        };
+        writeln!(
+            out,
+            "{:>8} {:>8} {:<16} {:<16} {}",
+            task.tgid, task.tid, task.thread.comm, layer, block
+        )?;
+        Ok(())
+    }
+
+    fn write_summary(&self, out: &mut dyn Write) -> Result<()> {
+        let specs = self.specs;
+
+        writeln!(out)?;
+        for (spec, nr) in specs.iter().zip(self.nr_tasks.iter()) {
+            writeln!(out, "{:<16} {:>8} tasks", spec.name, nr)?;
+        }
+        if self.nr_unmatched > 0 {
+            writeln!(out, "{:<16} {:>8} tasks", "(no layer)", self.nr_unmatched)?;
+        }
+
+        let empty: Vec<&str> = specs
+            .iter()
+            .zip(self.nr_tasks.iter())
+            .filter(|(_, nr)| **nr == 0)
+            .map(|(spec, _)| spec.name.as_str())
+            .collect();
+        if !empty.is_empty() {
+            writeln!(out, "\nLayers matching no task: {}", empty.join(", "))?;
+        }
+
+        let mut shadowed = vec![];
+        for (layer_idx, spec) in specs.iter().enumerate() {
+            for (or_idx, block) in self.blocks[layer_idx].iter().enumerate() {
+                if block.nr_matched == 0 || block.nr_taken < block.nr_matched {
+                    continue;
+                }
+                let taken_by: Vec<&str> = block
+                    .taken_by
+                    .iter()
+                    .map(|idx| specs[*idx].name.as_str())
+                    .collect();
+                shadowed.push(format!(
+                    "  {}[{}] {:?}: all {} matching tasks taken by {}",
+                    spec.name,
+                    or_idx,
+                    &spec.matches[or_idx],
+                    block.nr_matched,
+                    taken_by.join(", ")
+                ));
+            }
+        }
+        if !shadowed.is_empty() {
+            writeln!(out, "\nMatch blocks shadowed by earlier layers:")?;
+            for line in shadowed.iter() {
+                writeln!(out, "{}", line)?;
+            }
+        }
+
+        if specs.iter().any(|spec| {
+            spec.matches.iter().flatten().any(|mt| {
+                matches!(
+                    mt,
+                    LayerMatch::CmdJoin(_) | LayerMatch::UsedGpuTid(_) | LayerMatch::UsedGpuPid(_)
+                )
+            })
+        }) {
+            writeln!(
+                out,
+                "\nNOTE: CmdJoin, UsedGpuTid and UsedGpuPid depend on runtime state and were \
+                 evaluated as if no join command was issued and no GPU usage was observed"
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks /proc and prints which layer each task would be assigned to along
+/// with the first matching OR-block of the layer. Layers which don't match
+/// any task and OR-blocks whose tasks are all claimed by earlier layers are
+/// listed at the end.
+pub fn explain_layer_specs(specs: &[LayerSpec], out: &mut dyn Write) -> Result<()> {
+    let mut explainer = Explainer::new(specs)?;
+
+    let mut tgids: Vec<u32> = fs::read_dir("/proc")?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_string_lossy().parse().ok())
+        .collect();
+    tgids.sort();
+
+    writeln!(
+        out,
+        "{:>8} {:>8} {:<16} {:<16} MATCH",
+        "TGID", "TID", "COMM", "LAYER"
+    )?;
+
+    for tgid in tgids.iter().map(|tgid| tgid.to_string()) {
+        let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", tgid)) else {
+            continue;
+        };
+        let mut tids: Vec<u32> = tasks
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_string_lossy().parse().ok())
+            .collect();
+        tids.sort();
+
+        let proc_info = ProcInfo::read(&tgid, true, true);
+        let pcomm = fs::read_to_string(format!("/proc/{}/comm", tgid)).unwrap_or_default();
+        let pcomm = pcomm.trim_end_matches('\n');
+
+        for tid in tids.iter().map(|tid| tid.to_string()) {
+            if let Some(task) = TaskInfo::read(&tgid, &tid, pcomm) {
+                explainer.write_task(&task, &proc_info, out)?;
+            }
+        }
+    }
+
+    explainer.write_summary(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explainer(specs: &[LayerSpec]) -> Explainer<'_> {
+        Explainer::new(specs).unwrap()
+    }
+
+    fn specs(matches: &[&str]) -> Vec<LayerSpec> {
+        let layers: Vec<String> = matches
+            .iter()
+            .enumerate()
+            .map(|(idx, matches)| {
+                format!(
+                    r#"{{"name": "l{}", "matches": {}, "kind": {{"Open": {{}}}}}}"#,
+                    idx, matches
+                )
+            })
+            .collect();
+        LayerSpec::parse(&format!("[{}]", layers.join(","))).unwrap()
+    }
+
+    fn task(comm: &str) -> TaskInfo {
+        TaskInfo {
+            tgid: 100,
+            tid: 100,
+            thread: ThreadInfo {
+                comm: comm.to_string(),
+                cgroup: "/".to_string(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_is_kthread_ignores_polarity() {
+        let specs = specs(&[r#"[[{"IsKthread": false}]]"#, "[[]]"]);
+        let mut explainer = explainer(&specs);
+        let proc_info = ProcInfo::default();
+
+        let mut kthread = task("kworker/0:0");
+        kthread.flags = PF_KTHREAD;
+        assert_eq!(explainer.select(&kthread, &proc_info), Some((0, 0)));
+        assert_eq!(explainer.select(&task("bash"), &proc_info), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_used_gpu_without_usage() {
+        let specs = specs(&[
+            r#"[[{"UsedGpuTid": true}], [{"UsedGpuPid": true}]]"#,
+            r#"[[{"UsedGpuPid": false}]]"#,
+            r#"[[{"UsedGpuTid": false}]]"#,
+        ]);
+        let mut explainer = explainer(&specs);
+
+        assert_eq!(
+            explainer.select(&task("python"), &ProcInfo::default()),
+            Some((1, 0))
+        );
+        assert_eq!(explainer.blocks[2][0].nr_taken, 1);
+    }
+
+    #[test]
+    fn test_nspid_equals() {
+        let specs = specs(&[r#"[[{"NSPIDEquals": [4026531836, 1]}]]"#, "[[]]"]);
+        let mut explainer = explainer(&specs);
+        let proc_info = ProcInfo::default();
+
+        let mut init = task("systemd");
+        init.nsid = 4026531836;
+        init.nspid = 1;
+        assert_eq!(explainer.select(&init, &proc_info), Some((0, 0)));
+
+        init.nsid = 4026532000;
+        assert_eq!(explainer.select(&init, &proc_info), Some((1, 0)));
+
+        init.nsid = 4026531836;
+        init.nspid = 2;
+        assert_eq!(explainer.select(&init, &proc_info), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_cgroup_format() {
+        assert_eq!(ThreadInfo::parse_cgroup("0::/\n").as_deref(), Some("/"));
+        assert_eq!(
+            ThreadInfo::parse_cgroup("1:name=systemd:/\n0::/system.slice/foo.service\n").as_deref(),
+            Some("system.slice/foo.service/")
+        );
+        assert_eq!(ThreadInfo::parse_cgroup("1:name=systemd:/\n"), None);
+
+        let specs = specs(&[
+            r#"[[{"CgroupPrefix": "system.slice/"}]]"#,
+            r#"[[{"CgroupPrefix": "/"}]]"#,
+        ]);
+        let mut explainer = explainer(&specs);
+        let proc_info = ProcInfo::default();
+
+        let mut service = task("foo");
+        service.thread.cgroup = "system.slice/foo.service/".to_string();
+        assert_eq!(explainer.select(&service, &proc_info), Some((0, 0)));
+        assert_eq!(explainer.select(&task("init"), &proc_info), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_first_block_and_shadowed() {
+        let specs = specs(&[
+            r#"[[{"CommPrefix": "foo"}]]"#,
+            r#"[[{"CommPrefix": "ba"}], [{"CommPrefix": "bar"}], [{"CommPrefix": "foo"}]]"#,
+            "[[]]",
+        ]);
+        let mut explainer = explainer(&specs);
+        let proc_info = ProcInfo::default();
+
+        assert_eq!(explainer.select(&task("foo"), &proc_info), Some((0, 0)));
+        assert_eq!(explainer.select(&task("bar"), &proc_info), Some((1, 0)));
+        assert_eq!(explainer.select(&task("qux"), &proc_info), Some((2, 0)));
+
+        let mut out = vec![];
+        explainer.write_summary(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("l1[2] [CommPrefix(\"foo\")]: all 1 matching tasks taken by l0"));
+        assert!(!out.contains("l1[1]"));
+        assert!(!out.contains("l2[0]"));
+        assert!(!out.contains("Layers matching no task"));
+    }
+}
@@ -8,10 +8,11 @@ use serde::Deserialize;
 use serde::Serialize;
 
 use crate::bpf_intf;
+use crate::group_cpus_range;
 use crate::CpuPool;
 use crate::LayerSpec;
 
-#[derive(Clone, Debug, Parser, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Parser, Serialize, Deserialize)]
 #[clap(rename_all = "snake_case")]
 pub enum LayerGrowthAlgo {
     /// Sticky attempts to place layers evenly spaced across cores.
@@ -87,11 +88,56 @@ impl LayerGrowthAlgo {
         topo: &Topology,
     ) -> BTreeMap<usize, Vec<usize>> {
         let mut core_orders = BTreeMap::new();
+        let mut group_cores: BTreeMap<Vec<&str>, Vec<usize>> = BTreeMap::new();
 
         for (idx, spec) in layer_specs.iter().enumerate() {
+            // Nested layers grow within the cores of their groups. Each
+            // group takes the cores covering its maximum CPU budget out of
+            // its own core order, preferring the cores of its parent, and
+            // the group is computed once with the index of its first layer.
+            let mut scope: Option<Vec<usize>> = None;
+            for depth in 0..spec.parents.len() {
+                let path: Vec<&str> = spec.parents[..=depth]
+                    .iter()
+                    .map(|group| group.name.as_str())
+                    .collect();
+                let cores = group_cores.entry(path).or_insert_with(|| {
+                    let group = &spec.parents[depth];
+                    let group_spec = LayerSpec {
+                        name: group.name.clone(),
+                        comment: None,
+                        matches: vec![],
+                        kind: group.kind.clone(),
+                        children: vec![],
+                        parents: spec.parents[..depth].to_vec(),
+                    };
+                    let order = group.kind.common().growth_algo.layer_core_order(
+                        cpu_pool,
+                        layer_specs,
+                        &group_spec,
+                        idx,
+                        topo,
+                    );
+                    let order = nest_core_order(&order, scope.as_deref());
+
+                    let (_, max_cpus) = group_cpus_range(group, topo.all_cpus.len());
+                    let mut nr_cpus = 0;
+                    order
+                        .into_iter()
+                        .take_while(|core| {
+                            let fits = nr_cpus < max_cpus;
+                            nr_cpus += topo.all_cores[core].span.weight();
+                            fits
+                        })
+                        .collect()
+                });
+                scope = Some(cores.clone());
+            }
+
             let layer_growth_algo = spec.kind.common().growth_algo.clone();
             let core_order =
                 layer_growth_algo.layer_core_order(cpu_pool, layer_specs, spec, idx, topo);
+            let core_order = nest_core_order(&core_order, scope.as_deref());
             core_orders.insert(idx, core_order);
         }
 
@@ -129,6 +175,20 @@ impl LayerGrowthAlgo {
     }
 }
 
+/// Moves the cores of @order which are in @scope to the front, keeping the
+/// relative order of both parts.
+fn nest_core_order(order: &[usize], scope: Option<&[usize]>) -> Vec<usize> {
+    match scope {
+        Some(scope) => {
+            let (mut inside, outside): (Vec<usize>, Vec<usize>) =
+                order.iter().copied().partition(|core| scope.contains(core));
+            inside.extend(outside);
+            inside
+        }
+        None => order.to_vec(),
+    }
+}
+
 impl Default for LayerGrowthAlgo {
     fn default() -> Self {
         LayerGrowthAlgo::Sticky
@@ -3,7 +3,12 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
 mod config;
+mod config_file;
+mod explain;
 mod layer_core_growth;
+mod profile;
+mod suggest;
+mod user_match;
 
 pub mod bpf_intf;
 
@@ -12,19 +17,33 @@ use std::collections::BTreeMap;
 use anyhow::bail;
 use anyhow::Result;
 use bitvec::prelude::*;
+pub use config::group_cpus_range;
+pub use config::resolve_cpus_pct_range;
 pub use config::LayerCommon;
 pub use config::LayerConfig;
+pub use config::LayerGroup;
 pub use config::LayerKind;
 pub use config::LayerMatch;
 pub use config::LayerSpec;
+pub use explain::explain_layer_specs;
 pub use layer_core_growth::LayerGrowthAlgo;
 use log::info;
+pub use profile::scheduled_profile;
+pub use profile::verify_layer_profiles;
+pub use profile::CronSchedule;
+pub use profile::LayerProfile;
+pub use profile::AUTO_PROFILE;
+pub use profile::DEFAULT_PROFILE;
 use scx_utils::Core;
 use scx_utils::Cpumask;
 use scx_utils::Topology;
 use scx_utils::NR_CPUS_POSSIBLE;
 use scx_utils::NR_CPU_IDS;
 use std::sync::Arc;
+pub use suggest::parse_duration;
+pub use suggest::suggest_layer_specs;
+pub use user_match::UserMatchScanner;
+pub use user_match::UserMatches;
 
 const MAX_CPUS: usize = bpf_intf::consts_MAX_CPUS as usize;
 
@@ -9,15 +9,18 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::ffi::CString;
 use std::fs;
+use std::io::Read;
 use std::io::Write;
 use std::mem::MaybeUninit;
 use std::ops::Sub;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread::ThreadId;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
 use ::fb_procfs as procfs;
 use anyhow::anyhow;
@@ -25,6 +28,7 @@ use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 pub use bpf_skel::*;
+use chrono::Local;
 use clap::Parser;
 use crossbeam::channel::RecvTimeoutError;
 use lazy_static::lazy_static;
@@ -59,7 +63,10 @@ use scx_utils::Topology;
 use scx_utils::UserExitInfo;
 use scx_utils::NR_CPUS_POSSIBLE;
 use scx_utils::NR_CPU_IDS;
+use stats::LayerGroupStats;
 use stats::LayerStats;
+use stats::LayerTaskStats;
+use stats::LayerTasks;
 use stats::StatsReq;
 use stats::StatsRes;
 use stats::SysStats;
@@ -86,6 +93,18 @@ const NR_LAYER_USAGES: usize = bpf_intf::layer_usage_NR_LAYER_USAGES as usize;
 const NR_GSTATS: usize = bpf_intf::global_stat_id_NR_GSTATS as usize;
 const NR_LSTATS: usize = bpf_intf::layer_stat_id_NR_LSTATS as usize;
 const NR_LLC_LSTATS: usize = bpf_intf::llc_layer_stat_id_NR_LLC_LSTATS as usize;
+const NR_LAT_BUCKETS: usize = bpf_intf::consts_NR_LAT_BUCKETS as usize;
+
+const DFL_LAT_PERCENTILE: f64 = 99.0;
+// A layer with latency_target_us may shrink only if its latency is below
+// this ratio of the target.
+const LAT_SHRINK_RATIO: f64 = 0.5;
+
+// CPU bandwidth cap bounds, same as cgroup cpu.max.
+const DFL_BW_PERIOD_US: u64 = 100_000;
+const MIN_BW_PERIOD_US: u64 = 1_000;
+const MAX_BW_PERIOD_US: u64 = 1_000_000;
+const MIN_BW_QUOTA_US: u64 = 1_000;
 
 const NR_LAYER_MATCH_KINDS: usize = bpf_intf::layer_match_kind_NR_LAYER_MATCH_KINDS as usize;
 
@@ -123,11 +142,18 @@ lazy_static! {
                         xllc_mig_min_us: 1000.0,
                         growth_algo: LayerGrowthAlgo::Sticky,
                         idle_resume_us: None,
+                        latency_target_us: None,
+                        latency_percentile: None,
+                        quota_us: None,
+                        period_us: None,
+                        max_cpu_pct: None,
                         perf: 1024,
                         nodes: vec![],
                         llcs: vec![],
                     },
                 },
+                children: vec![],
+                parents: vec![],
             },
             LayerSpec {
                 name: "immediate".into(),
@@ -154,10 +180,17 @@ lazy_static! {
                         growth_algo: LayerGrowthAlgo::Sticky,
                         perf: 1024,
                         idle_resume_us: None,
+                        latency_target_us: None,
+                        latency_percentile: None,
+                        quota_us: None,
+                        period_us: None,
+                        max_cpu_pct: None,
                         nodes: vec![],
                         llcs: vec![],
                     },
                 },
+                children: vec![],
+                parents: vec![],
             },
             LayerSpec {
                 name: "stress-ng".into(),
@@ -188,10 +221,17 @@ lazy_static! {
                         growth_algo: LayerGrowthAlgo::Topo,
                         perf: 1024,
                         idle_resume_us: None,
+                        latency_target_us: None,
+                        latency_percentile: None,
+                        quota_us: None,
+                        period_us: None,
+                        max_cpu_pct: None,
                         nodes: vec![],
                         llcs: vec![],
                     },
                 },
+                children: vec![],
+                parents: vec![],
             },
             LayerSpec {
                 name: "normal".into(),
@@ -219,15 +259,30 @@ lazy_static! {
                         growth_algo: LayerGrowthAlgo::Linear,
                         perf: 1024,
                         idle_resume_us: None,
+                        latency_target_us: None,
+                        latency_percentile: None,
+                        quota_us: None,
+                        period_us: None,
+                        max_cpu_pct: None,
                         nodes: vec![],
                         llcs: vec![],
                     },
                 },
+                children: vec![],
+                parents: vec![],
             },
         ],
+        ..Default::default()
     };
 }
 
+/// Set by SIGHUP to request reloading the layer specs.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn sighup_handler(_: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
 /// scx_layered: A highly configurable multi-layer sched_ext scheduler
 ///
 /// scx_layered allows classifying tasks into multiple layers and applying
@@ -312,6 +367,33 @@ lazy_static! {
 /// - UsedGpuPid: Bool. When true, matches if the tasks which have used gpu
 ///   by tgid/pid.
 ///
+/// The following matches are too involved for BPF and are instead resolved
+/// in userspace by scanning /proc every --user-match-intv seconds. A task
+/// is classified without them until it's scanned for the first time. Up to
+/// 64 distinct userspace matches can be used.
+///
+/// - CommRegex: Matches if the regular expression matches the task's comm.
+///   The expression is not anchored, use "^" and "$" as needed.
+///
+/// - CgroupRegex: Matches if the regular expression matches the cgroup
+///   path of the task, which is formatted the same way as for CgroupPrefix,
+///   e.g. "system.slice/foo.service/".
+///
+/// - CgroupSuffix: Matches the suffix of the cgroup path of the task. As
+///   the path has the trailing '/', so should the pattern.
+///
+/// - ExePath: Matches if the executable of the task, as resolved from
+///   /proc/PID/exe, is the specified absolute path.
+///
+/// - ExePrefix: Matches the prefix of the executable of the task.
+///
+/// - EnvVarEquals: ["KEY", "VAL"]. Matches if the task's process was
+///   started with the environment variable KEY set to VAL. Changes made to
+///   the environment after exec(2) are not visible.
+///
+/// - SystemdUnit: Matches tasks in the cgroup of the specified systemd unit,
+///   e.g. "foo.service", including its sub-cgroups.
+///
 /// While there are complexity limitations as the matches are performed in
 /// BPF, it is straightforward to add more types of matches.
 ///
@@ -414,6 +496,32 @@ lazy_static! {
 ///   states. See the latest kernel docs for more details:
 ///   https://www.kernel.org/doc/html/latest/admin-guide/pm/cpuidle.html
 ///
+/// - latency_target_us: Only applicable to Confined and Grouped layers. In
+///   addition to util_range, size the layer by the latency between a task
+///   getting enqueued and starting to run. While the latency at
+///   latency_percentile exceeds the target, a CPU is added to the layer
+///   every scheduling interval. The layer is shrunk by utilization only
+///   after the latency drops below half of the target.
+///
+/// - latency_percentile: The percentile of the latency which is compared
+///   against latency_target_us. The default is 99.
+///
+/// - quota_us, period_us: Cap the CPU time the layer can consume to
+///   quota_us every period_us, similar to cgroup cpu.max. Once the quota is
+///   used up, the layer's tasks are throttled until the period ends. quota_us
+///   can be larger than period_us to allow using multiple CPUs. period_us
+///   defaults to 100ms and must be between 1ms and 1s. Tasks with
+///   restricted CPU affinities, which are queued on the low fallback DSQs,
+///   are throttled too but only on kernels with scx_bpf_dsq_move(). Per-cpu
+///   kthreads and scx_layered itself are charged but never throttled.
+///
+/// - max_cpu_pct: Shorthand for quota_us as a percentage of period_us. 100
+///   means one full CPU. Can't be combined with quota_us.
+///
+///   Caps apply to leaf layers only and can't be set on a layer with
+///   children. Adding the first cap or removing the last one on reload
+///   re-initializes the scheduler.
+///
 /// - nodes: If set the layer will use the set of NUMA nodes for scheduling
 ///   decisions. If unset then all available NUMA nodes will be used. If the
 ///   llcs value is set the cpuset of NUMA nodes will be or'ed with the LLC
@@ -428,6 +536,55 @@ lazy_static! {
 /// Similar to matches, adding new policies and extending existing ones
 /// should be relatively straightforward.
 ///
+/// Hierarchical Layers
+/// ===================
+///
+/// A Confined or Grouped layer can have nested layers in "children". The
+/// parent isn't a layer by itself but owns a CPU budget, cpus_range or
+/// cpus_range_frac, which its children share. For example, the following
+/// gives the services 40-60% of the machine, split between frontend and
+/// backend by weight:
+///
+///   {
+///     "name": "services",
+///     "matches": [[{"CgroupPrefix": "services.slice/"}]],
+///     "kind": {"Confined": {"cpus_range_frac": [0.4, 0.6], "util_range": [0.8, 0.9]}},
+///     "children": [
+///       {
+///         "name": "frontend",
+///         "matches": [[{"CgroupSuffix": "/frontend.service/"}]],
+///         "kind": {"Grouped": {"util_range": [0.8, 0.9], "weight": 200}}
+///       },
+///       {
+///         "name": "backend",
+///         "matches": [[]],
+///         "kind": {"Grouped": {"util_range": [0.8, 0.9]}}
+///       }
+///     ]
+///   }
+///
+/// Matches are evaluated top-down. A task is first matched against the
+/// parent and then against its children in order. The last child must
+/// have an empty match as a catch-all for the tasks of the parent.
+///
+/// CPUs are distributed by weight at each level. A parent's target is the
+/// sum of its children's targets clamped to its budget, and the CPUs given
+/// to the parent are distributed among the children. Children using the
+/// default growth_algo inherit the parent's. Per-group statistics are
+/// aggregated over the nested layers.
+///
+/// The growth_algo is applied at each level too. A group takes the cores
+/// covering its maximum number of CPUs out of its own core order, starting
+/// with the cores of its parent, and its layers grow onto the group's cores
+/// first in their own core orders. Cores outside the group only come after,
+/// so the layers of a group stay on the same cores and LLCs while the group
+/// is within its budget.
+///
+/// Nested layers are flattened and each counts towards the maximum number
+/// of layers. Their matches are the combinations of their own and their
+/// ancestors' OR blocks, which must stay within the limits on the number
+/// of OR blocks and of the conditions in each.
+///
 /// Configuration example and running scx_layered
 /// =============================================
 ///
@@ -452,6 +609,180 @@ lazy_static! {
 ///   ...
 ///   $ scx_layered f:example.json
 ///
+/// Config Files
+/// ============
+///
+/// Files ending in .toml, .yaml or .yml are parsed as TOML and YAML
+/// respectively and all others as JSON. A file can contain either an array
+/// of layer specs or an object with the following optional keys:
+///
+/// - include: A path or a list of paths to other config files, relative to
+///   the including file. Their specs come before the including file's. A
+///   file included more than once, e.g. a common file included by two
+///   others, is loaded only once.
+///
+/// - templates: Named match templates. A template is a list of OR blocks
+///   like the matches of a layer and { "Template": NAME } can be used in
+///   place of a match. An AND block containing a template is expanded into
+///   one AND block per OR block of the template.
+///
+/// - defaults: Layer kind fields, e.g. slice_us, which are applied to all
+///   specs which don't set them. Fields which aren't valid for a layer's
+///   kind are skipped for the layer.
+///
+/// - specs: The layer specs.
+///
+/// - profiles: Layer profiles. See "Layer Profiles" below.
+///
+/// Templates and defaults are shared between a file and the files it
+/// includes with the including file's taking precedence. For example:
+///
+///   # common.toml
+///   [templates]
+///   batch = [[{ CgroupPrefix = "system.slice/batch" }], [{ NiceAbove = 0 }]]
+///
+///   [defaults]
+///   slice_us = 20000
+///
+///   # layers.toml
+///   include = "common.toml"
+///
+///   [[specs]]
+///   name = "batch"
+///   matches = [[{ Template = "batch" }]]
+///   kind = { Confined = { util_range = [0.8, 0.9], cpus_range = [0, 4] } }
+///
+///   [[specs]]
+///   name = "normal"
+///   matches = [[]]
+///   kind = { Open = { preempt = false } }
+///
+/// To see the fully expanded config in JSON, run with
+/// --dump-resolved-config. The output can be used as a spec file as-is:
+///
+///   $ scx_layered --dump-resolved-config f:layers.toml
+///
+/// --watch-specs watches the included files too. The set of watched files
+/// is updated whenever the specs are re-read from the command line.
+///
+/// Explaining Layer Assignments
+/// =============================
+///
+/// To see which layer each task would be assigned to without loading the
+/// scheduler, run with --explain:
+///
+///   $ scx_layered --explain f:example.json
+///
+/// All tasks in /proc are evaluated against the layer matches in order and
+/// the chosen layer and its first matching OR block are printed for each
+/// task. Layers which match no task and OR blocks whose matching tasks are
+/// all claimed by earlier layers are listed at the end. CmdJoin and UsedGpu*
+/// matches depend on runtime state and are evaluated as if no join command
+/// was issued and no GPU usage was observed.
+///
+/// Suggesting a Config
+/// ===================
+///
+/// To get a starting config for a new workload, run the workload and then:
+///
+///   $ scx_layered --suggest --duration 60s > suggested.json
+///
+/// The scheduler isn't loaded and the tasks are observed through /proc
+/// under whichever scheduler is running, including scx_layered itself.
+/// Tasks are grouped by cgroup, comm and executable with kernel threads
+/// grouped by the part of the comm before '/', e.g. "kworker/". For each
+/// group, the CPU usage, wakeup frequency, average runtime per wakeup and
+/// run queue wait time are measured.
+///
+/// Groups which wake up frequently and run briefly are latency sensitive
+/// and each becomes a preempting Grouped layer. Groups which use at least
+/// half a CPU with long runs are batch and each becomes a Confined layer.
+/// util_range is fixed per class and cpus_range is sized to keep the
+/// utilization in range at both the average and the peak usage. Everything
+/// else goes to a catch-all Open layer. The observations are recorded in
+/// each layer's comment. Ctrl-C stops the observation early.
+///
+/// As the first matching layer wins, the layers are ordered from the most
+/// specific match to the least, e.g. a layer for comm "foobar" comes before
+/// the one for "foo" and a sub-cgroup before its parent.
+///
+/// The suggestion is only a starting point and the matches, which combine
+/// CgroupPrefix, CommPrefix and ExePath, are often worth generalizing.
+///
+/// Live Reconfiguration
+/// ====================
+///
+/// The layer specs can be reloaded without restarting scx_layered. The
+/// specs are re-read from the command line arguments, including the spec
+/// files, on SIGHUP:
+///
+///   $ kill -HUP $(pidof scx_layered)
+///
+/// With --watch-specs, the spec files are also reloaded whenever they are
+/// modified. New specs can also be submitted through the stats socket with
+/// the "reload" target. The "specs" argument takes the same format as the
+/// command line arguments. If omitted, the specs are re-read as on SIGHUP:
+///
+///   $ echo '{"req":"stats","args":{"target":"reload","specs":"f:new.json"}}' | \
+///     socat - UNIX-CONNECT:/var/run/scx/root/stats
+///
+/// The new specs are verified and compared against the running ones. Layer
+/// tunables and matches are updated in place and tasks are re-matched when
+/// they next become runnable. Changing the number of layers, a layer's kind
+/// or preempt, the weight order of the layers, or the disallow_*_after_us
+/// of open layers can't be applied in place and the scheduler is
+/// re-initialized with the new specs instead, which briefly moves all tasks
+/// out of scx_layered.
+///
+/// Layer Profiles
+/// ==============
+///
+/// A config can carry named profiles which override the layer tunables,
+/// e.g. to give batch workloads more CPUs at night. To use profiles, the
+/// config is an object with "specs" and "profiles" instead of an array:
+///
+///   {
+///     "specs": [ ...layer specs... ],
+///     "profiles": [
+///       {
+///         "name": "night",
+///         "schedule": "* 22-23,0-5 * * *",
+///         "layers": {
+///           "batch": {"cpus_range_frac": [0.0, 0.8], "weight": 800}
+///         }
+///       }
+///     ]
+///   }
+///
+/// Each entry in "layers" is keyed by the layer or nested layer group name
+/// and overrides the listed fields of the layer kind. The layer structure
+/// and matches can't be changed.
+///
+/// "schedule" is a cron-like "minute hour day-of-month month day-of-week"
+/// expression in local time. Each field is "*" or a comma separated list of
+/// numbers and ranges with an optional "/step". Unlike cron, the profile is
+/// active during all the minutes the schedule matches. If multiple
+/// schedules match, the first profile wins. If none matches, the specs are
+/// used as-is, which is the "default" profile.
+///
+/// A profile can also be forced through the "profile" stats target. "auto"
+/// clears the forced profile and goes back to the schedules. Without the
+/// "name" argument, the active profile is reported:
+///
+///   $ echo '{"req":"stats","args":{"target":"profile","name":"night"}}' | \
+///     socat - UNIX-CONNECT:/var/run/scx/root/stats
+///
+/// With --profile-file, the profile named in the file is forced whenever
+/// the file is modified. Removing the file or writing "auto" into it goes
+/// back to the schedules.
+///
+/// Switching profiles goes through the same path as live reconfiguration
+/// and the active profile is reported in the stats. A switch never
+/// re-initializes the scheduler. Profiles which change the weight order of
+/// the layers, preempt, the lowest disallow_*_after_us among the open
+/// layers or whether any bandwidth cap is set are rejected when the config
+/// is loaded.
+///
 /// Monitoring Statistics
 /// =====================
 ///
@@ -478,6 +809,16 @@ lazy_static! {
 ///
 /// Per-layer statistics: see [`LayerStats`]
 ///
+/// The tasks in a layer can be listed with the "layer_tasks" target. Each
+/// task is reported with its share of the layer's runtime since the
+/// previous request on the same connection and the OR block which placed
+/// it in the layer:
+///
+///   $ echo '{"req":"stats","args":{"target":"layer_tasks","layer":"batch"}}' | \
+///     socat - UNIX-CONNECT:/var/run/scx/root/stats
+///
+/// Per-task statistics: see [`LayerTaskStats`]
+///
 #[derive(Debug, Parser)]
 #[command(verbatim_doc_comment)]
 struct Opts {
@@ -598,6 +939,41 @@ struct Opts {
     #[clap(long)]
     help_stats: bool,
 
+    /// Reload the layer specs when a spec file specified with f: or file:,
+    /// or a file it includes, is modified. See "Live Reconfiguration" above.
+    #[clap(long)]
+    watch_specs: bool,
+
+    /// Interval in seconds to rescan the tasks for the matches resolved in
+    /// userspace, e.g. CommRegex.
+    #[clap(long, default_value = "1.0")]
+    user_match_intv: f64,
+
+    /// Print which layer each task would be assigned to and exit without
+    /// loading the scheduler. See "Explaining Layer Assignments" above.
+    #[clap(long)]
+    explain: bool,
+
+    /// Verify the layer config and print it with the includes, templates
+    /// and defaults expanded as JSON and exit. See "Config Files" above.
+    #[clap(long)]
+    dump_resolved_config: bool,
+
+    /// Observe the running tasks for --duration and print a suggested layer
+    /// config without loading the scheduler. See "Suggesting a Config"
+    /// above.
+    #[clap(long)]
+    suggest: bool,
+
+    /// How long --suggest observes the tasks for, e.g. "60s" or "5m".
+    #[clap(long, default_value = "60s", value_parser = parse_duration)]
+    duration: Duration,
+
+    /// Force the profile named in the file whenever it's modified. See
+    /// "Layer Profiles" above.
+    #[clap(long)]
+    profile_file: Option<String>,
+
     /// Layer specification. See --help.
     specs: Vec<String>,
 }
@@ -712,6 +1088,7 @@ struct BpfStats {
     lstats: Vec<Vec<u64>>,
     lstats_sums: Vec<u64>,
     llc_lstats: Vec<Vec<Vec<u64>>>, // [layer][llc][stat]
+    lat_hists: Vec<Vec<u64>>,       // [layer][bucket]
 }
 
 impl BpfStats {
@@ -721,6 +1098,7 @@ impl BpfStats {
         let mut gstats = vec![0u64; NR_GSTATS];
         let mut lstats = vec![vec![0u64; NR_LSTATS]; nr_layers];
         let mut llc_lstats = vec![vec![vec![0u64; NR_LLC_LSTATS]; nr_llcs]; nr_layers];
+        let mut lat_hists = vec![vec![0u64; NR_LAT_BUCKETS]; nr_layers];
 
         for cpu in 0..*NR_CPUS_POSSIBLE {
             for stat in 0..NR_GSTATS {
@@ -730,6 +1108,9 @@ impl BpfStats {
                 for stat in 0..NR_LSTATS {
                     lstats[layer][stat] += cpu_ctxs[cpu].lstats[layer][stat];
                 }
+                for bucket in 0..NR_LAT_BUCKETS {
+                    lat_hists[layer][bucket] += cpu_ctxs[cpu].lat_hist[layer][bucket];
+                }
             }
         }
 
@@ -769,7 +1150,39 @@ impl BpfStats {
             lstats,
             lstats_sums,
             llc_lstats,
+            lat_hists,
+        }
+    }
+
+    /// Returns the @pct percentile enqueue-to-running latency of @layer in
+    /// usecs, interpolated within the log2 histogram bucket. Bucket 0 holds
+    /// latencies below 2us and bucket N those in [2^N, 2^(N+1)). None if
+    /// there were no samples.
+    fn lat_percentile_us(&self, layer: usize, pct: f64) -> Option<f64> {
+        let hist = &self.lat_hists[layer];
+        let total: u64 = hist.iter().sum();
+        if total == 0 {
+            return None;
         }
+
+        let rank = total as f64 * pct / 100.0;
+        let mut seen = 0;
+        for (bucket, &cnt) in hist.iter().enumerate() {
+            if cnt == 0 {
+                continue;
+            }
+            if (seen + cnt) as f64 >= rank {
+                let lo = match bucket {
+                    0 => 0.0,
+                    _ => (1u64 << bucket) as f64,
+                };
+                let hi = (1u64 << (bucket + 1)) as f64;
+                return Some(lo + (hi - lo) * ((rank - seen as f64) / cnt as f64).max(0.0));
+            }
+            seen += cnt;
+        }
+
+        Some((1u64 << (NR_LAT_BUCKETS - 1)) as f64)
     }
 }
 
@@ -804,6 +1217,12 @@ impl<'a, 'b> Sub<&'b BpfStats> for &'a BpfStats {
                         .collect()
                 })
                 .collect(),
+            lat_hists: self
+                .lat_hists
+                .iter()
+                .zip(rhs.lat_hists.iter())
+                .map(|(l, r)| vec_sub(l, r))
+                .collect(),
         }
     }
 }
@@ -1004,32 +1423,183 @@ struct Layer {
     allowed_cpus: Cpumask,
 }
 
-fn resolve_cpus_pct_range(
-    cpus_range: &Option<(usize, usize)>,
-    cpus_range_frac: &Option<(f64, f64)>,
-    max_cpus: usize,
-) -> Result<(usize, usize)> {
-    match (cpus_range, cpus_range_frac) {
-        (Some(_x), Some(_y)) => {
-            bail!("cpus_range cannot be used with cpus_pct.");
-        }
-        (Some((cpus_range_min, cpus_range_max)), None) => Ok((*cpus_range_min, *cpus_range_max)),
-        (None, Some((cpus_frac_min, cpus_frac_max))) => {
-            if *cpus_frac_min < 0_f64
-                || *cpus_frac_min > 1_f64
-                || *cpus_frac_max < 0_f64
-                || *cpus_frac_max > 1_f64
+/// Returns @target adjusted for the latency target of a layer with @nr_cpus
+/// CPUs whose latency is @lat_us. The layer grows while the target is
+/// missed and isn't shrunk until the latency is well under the target.
+fn lat_target_nr_cpus(
+    target: usize,
+    nr_cpus: usize,
+    lat_us: Option<f64>,
+    lat_target_us: u64,
+) -> usize {
+    match lat_us {
+        Some(lat) if lat > lat_target_us as f64 => target.max(nr_cpus + 1),
+        Some(lat) if lat >= lat_target_us as f64 * LAT_SHRINK_RATIO => target.max(nr_cpus),
+        _ => target,
+    }
+}
+
+/// A node in the layer hierarchy, see layer_nodes().
+struct LayerNode<'a> {
+    group: Option<&'a LayerGroup>,
+    layers: Vec<usize>,
+}
+
+/// Given (target, min, weight) for each candidate, distribute @nr_cpus
+/// according to the weights.
+fn weighted_shares(nr_cpus: usize, cands: &[(usize, usize, usize)]) -> Vec<usize> {
+    let mut nr_left = nr_cpus;
+    let mut cands: BTreeMap<usize, (usize, usize, usize)> =
+        cands.iter().copied().enumerate().collect();
+    let mut weight_sum: usize = cands.values().map(|(_, _, weight)| weight).sum();
+    let mut weighted: Vec<usize> = vec![0; cands.len()];
+
+    trace!("cands: {:?}", &cands);
+
+    // First, accept all layers that are <= min.
+    cands.retain(|&i, &mut (target, min, weight)| {
+        if target <= min {
+            let target = target.min(nr_left);
+            weighted[i] = target;
+            weight_sum -= weight;
+            nr_left -= target;
+            false
+        } else {
+            true
+        }
+    });
+
+    trace!("cands after accepting mins: {:?}", &cands);
+
+    // Keep accepting ones under their allotted share.
+    let calc_share = |nr_left, weight, weight_sum| {
+        (((nr_left * weight) as f64 / weight_sum as f64).ceil() as usize).min(nr_left)
+    };
+
+    while !cands.is_empty() {
+        let mut progress = false;
+
+        cands.retain(|&i, &mut (target, _min, weight)| {
+            let share = calc_share(nr_left, weight, weight_sum);
+            if target <= share {
+                weighted[i] = target;
+                weight_sum -= weight;
+                nr_left -= target;
+                progress = true;
+                false
+            } else {
+                true
+            }
+        });
+
+        if !progress {
+            break;
+        }
+    }
+
+    trace!("cands after accepting under allotted: {:?}", &cands);
+
+    // The remaining candidates are in contention with each other,
+    // distribute according to the shares.
+    let nr_to_share = nr_left;
+    for (i, (_target, _min, weight)) in cands.into_iter() {
+        let share = calc_share(nr_to_share, weight, weight_sum).min(nr_left);
+        weighted[i] = share;
+        nr_left -= share;
+    }
+
+    weighted
+}
+
+/// Splits @layers, which share the same ancestors up to @depth, into the
+/// nodes at @depth of the layer hierarchy of @specs. Each node is either a
+/// single layer or a group with the layers below it.
+fn layer_nodes<'a>(specs: &'a [LayerSpec], layers: &[usize], depth: usize) -> Vec<LayerNode<'a>> {
+    let mut nodes: Vec<LayerNode> = vec![];
+
+    for &idx in layers.iter() {
+        let group = specs[idx].parents.get(depth);
+        match (nodes.last_mut(), group) {
+            (Some(node), Some(group))
+                if node
+                    .group
+                    .is_some_and(|node_group| node_group.name == group.name) =>
             {
-                bail!("cpus_range_frac values must be between 0.0 and 1.0");
+                node.layers.push(idx)
+            }
+            _ => nodes.push(LayerNode {
+                group,
+                layers: vec![idx],
+            }),
+        }
+    }
+
+    nodes
+}
+
+/// Returns the (target, min) pair of @node at @depth. A group's target is
+/// the sum of its children's targets clamped to its own cpus_range.
+fn layer_node_target(
+    specs: &[LayerSpec],
+    node: &LayerNode,
+    depth: usize,
+    max_cpus: usize,
+    targets: &[(usize, usize)],
+) -> (usize, usize) {
+    let Some(group) = node.group else {
+        return targets[node.layers[0]];
+    };
+
+    let sum: usize = layer_nodes(specs, &node.layers, depth + 1)
+        .iter()
+        .map(|child| layer_node_target(specs, child, depth + 1, max_cpus, targets).0)
+        .sum();
+
+    let cpus_range = group_cpus_range(group, max_cpus);
+
+    (sum.clamp(cpus_range.0, cpus_range.1), cpus_range.0)
+}
+
+/// Distribute @nr_cpus among @layers which share the same ancestors up to
+/// @depth according to the weights at each level of the hierarchy.
+fn distribute_nr_cpus(
+    specs: &[LayerSpec],
+    layers: &[usize],
+    depth: usize,
+    nr_cpus: usize,
+    max_cpus: usize,
+    targets: &[(usize, usize)],
+    weighted: &mut [usize],
+) {
+    let nodes = layer_nodes(specs, layers, depth);
+    let cands: Vec<(usize, usize, usize)> = nodes
+        .iter()
+        .map(|node| {
+            let (target, min) = layer_node_target(specs, node, depth, max_cpus, targets);
+            let weight = match node.group {
+                Some(group) => group.kind.common().weight,
+                None => specs[node.layers[0]].kind.common().weight,
+            };
+            (target, min, weight as usize)
+        })
+        .collect();
+
+    for (node, share) in nodes.iter().zip(weighted_shares(nr_cpus, &cands)) {
+        match node.group {
+            Some(group) => {
+                trace!("group {} share: {}", &group.name, share);
+                distribute_nr_cpus(
+                    specs,
+                    &node.layers,
+                    depth + 1,
+                    share,
+                    max_cpus,
+                    targets,
+                    weighted,
+                );
             }
-            let cpus_min_count = ((max_cpus as f64) * cpus_frac_min).round_ties_even() as usize;
-            let cpus_max_count = ((max_cpus as f64) * cpus_frac_max).round_ties_even() as usize;
-            Ok((
-                std::cmp::max(cpus_min_count, 1),
-                std::cmp::min(cpus_max_count, max_cpus),
-            ))
+            None => weighted[node.layers[0]] = share,
         }
-        (None, None) => Ok((0, max_cpus)),
     }
 }
 
@@ -1179,15 +1749,109 @@ impl Layer {
     }
 }
 
+/// Layer profile switching state. It's carried over when the scheduler is
+/// re-initialized.
+#[derive(Debug, Default)]
+struct ProfileState {
+    /// The layer specs and profiles as loaded without any profile applied.
+    config: LayerConfig,
+    active: String,
+    forced: Option<String>,
+    /// The last profile which failed to apply, not retried until a
+    /// different one is selected.
+    failed: Option<String>,
+    file_mtime: Option<SystemTime>,
+}
+
+impl ProfileState {
+    fn new(config: LayerConfig, opts: &Opts) -> Result<Self> {
+        verify_layer_config(&config, opts)?;
+        let mut state = Self {
+            config,
+            ..Default::default()
+        };
+        state.active = state.target();
+        Ok(state)
+    }
+
+    /// Returns the profile which should be active now.
+    fn target(&self) -> String {
+        if let Some(forced) = &self.forced {
+            return forced.clone();
+        }
+        match scheduled_profile(&self.config.profiles, &Local::now()) {
+            Some(profile) => profile.name.clone(),
+            None => DEFAULT_PROFILE.to_string(),
+        }
+    }
+
+    /// Forces @name, or goes back to the schedules if AUTO_PROFILE.
+    fn force(&mut self, name: &str) -> Result<()> {
+        if name == AUTO_PROFILE {
+            self.forced = None;
+        } else if name == DEFAULT_PROFILE || self.config.profiles.iter().any(|p| p.name == name) {
+            self.forced = Some(name.to_string());
+        } else {
+            bail!("Unknown profile {:?}", name);
+        }
+        Ok(())
+    }
+
+    /// Forces the profile named in @path if it has been modified since the
+    /// last read.
+    fn read_profile_file(&mut self, path: &str) {
+        let mtime = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if mtime == self.file_mtime {
+            return;
+        }
+        self.file_mtime = mtime;
+
+        let name = match mtime {
+            Some(_) => match fs::read_to_string(path) {
+                Ok(content) => content.trim().to_string(),
+                Err(e) => {
+                    warn!("Failed to read profile file {:?}: {}", path, e);
+                    return;
+                }
+            },
+            None => AUTO_PROFILE.to_string(),
+        };
+        let name = if name.is_empty() {
+            AUTO_PROFILE
+        } else {
+            name.as_str()
+        };
+
+        if let Err(e) = self.force(name) {
+            warn!("Ignoring profile file {:?}: {}", path, e);
+        }
+    }
+}
+
 struct Scheduler<'a> {
     skel: BpfSkel<'a>,
     struct_ops: Option<libbpf_rs::Link>,
+    opts: &'a Opts,
     layer_specs: Vec<LayerSpec>,
+    disable_topology: bool,
+    spec_file_mtimes: BTreeMap<PathBuf, Option<SystemTime>>,
+    restart_specs: Option<Vec<LayerSpec>>,
+    profiles: ProfileState,
+
+    user_matches: Arc<UserMatches>,
+    user_match_gen: u64,
+    user_match_scanner: UserMatchScanner,
+    user_match_scanning: bool,
+    user_match_hints: BTreeMap<u32, u64>,
+    user_match_seq: u64,
+    user_match_intv: Duration,
+    next_user_match_scan_at: Instant,
 
     sched_intv: Duration,
 
     cpu_pool: CpuPool,
     layers: Vec<Layer>,
+    layers_reset: bool,
     idle_qos_enabled: bool,
 
     proc_reader: procfs::ProcReader,
@@ -1202,174 +1866,208 @@ struct Scheduler<'a> {
 }
 
 impl<'a> Scheduler<'a> {
-    fn init_layers(skel: &mut OpenBpfSkel, specs: &[LayerSpec], topo: &Topology) -> Result<()> {
-        skel.maps.rodata_data.nr_layers = specs.len() as u32;
-        let mut perf_set = false;
-
-        let mut layer_iteration_order = (0..specs.len()).collect::<Vec<_>>();
-        let mut layer_weights: Vec<usize> = vec![];
-
-        for (spec_i, spec) in specs.iter().enumerate() {
-            let layer = &mut skel.maps.bss_data.layers[spec_i];
-
-            for (or_i, or) in spec.matches.iter().enumerate() {
-                for (and_i, and) in or.iter().enumerate() {
-                    let mt = &mut layer.matches[or_i].matches[and_i];
-                    match and {
-                        LayerMatch::CgroupPrefix(prefix) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_PREFIX as i32;
-                            copy_into_cstr(&mut mt.cgroup_prefix, prefix.as_str());
-                        }
-                        LayerMatch::CommPrefix(prefix) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_COMM_PREFIX as i32;
-                            copy_into_cstr(&mut mt.comm_prefix, prefix.as_str());
-                        }
-                        LayerMatch::PcommPrefix(prefix) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_PCOMM_PREFIX as i32;
-                            copy_into_cstr(&mut mt.pcomm_prefix, prefix.as_str());
-                        }
-                        LayerMatch::NiceAbove(nice) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_ABOVE as i32;
-                            mt.nice = *nice;
-                        }
-                        LayerMatch::NiceBelow(nice) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_BELOW as i32;
-                            mt.nice = *nice;
-                        }
-                        LayerMatch::NiceEquals(nice) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_EQUALS as i32;
-                            mt.nice = *nice;
-                        }
-                        LayerMatch::UIDEquals(user_id) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_USER_ID_EQUALS as i32;
-                            mt.user_id = *user_id;
-                        }
-                        LayerMatch::GIDEquals(group_id) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_GROUP_ID_EQUALS as i32;
-                            mt.group_id = *group_id;
-                        }
-                        LayerMatch::PIDEquals(pid) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_PID_EQUALS as i32;
-                            mt.pid = *pid;
-                        }
-                        LayerMatch::PPIDEquals(ppid) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_PPID_EQUALS as i32;
-                            mt.ppid = *ppid;
-                        }
-                        LayerMatch::TGIDEquals(tgid) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_TGID_EQUALS as i32;
-                            mt.tgid = *tgid;
-                        }
-                        LayerMatch::NSPIDEquals(nsid, pid) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NSPID_EQUALS as i32;
-                            mt.nsid = *nsid;
-                            mt.pid = *pid;
-                        }
-                        LayerMatch::NSEquals(nsid) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NS_EQUALS as i32;
-                            mt.nsid = *nsid as u64;
-                        }
-                        LayerMatch::CmdJoin(joincmd) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_SCXCMD_JOIN as i32;
-                            copy_into_cstr(&mut mt.comm_prefix, joincmd);
-                        }
-                        LayerMatch::IsGroupLeader(polarity) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_IS_GROUP_LEADER as i32;
-                            mt.is_group_leader.write(*polarity);
-                        }
-                        LayerMatch::IsKthread(polarity) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_IS_KTHREAD as i32;
-                            mt.is_kthread.write(*polarity);
-                        }
-                        LayerMatch::UsedGpuTid(polarity) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_USED_GPU_TID as i32;
-                            mt.used_gpu_tid.write(*polarity);
-                        }
-                        LayerMatch::UsedGpuPid(polarity) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_USED_GPU_PID as i32;
-                            mt.used_gpu_pid.write(*polarity);
-                        }
+    fn init_layer(
+        layer: &mut types::layer,
+        spec: &LayerSpec,
+        topo: &Topology,
+        user_matches: &UserMatches,
+    ) -> Result<()> {
+        for (or_i, or) in spec.matches.iter().enumerate() {
+            for (and_i, and) in or.iter().enumerate() {
+                let mt = &mut layer.matches[or_i].matches[and_i];
+                match and {
+                    LayerMatch::CgroupPrefix(prefix) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_PREFIX as i32;
+                        copy_into_cstr(&mut mt.cgroup_prefix, prefix.as_str());
+                    }
+                    LayerMatch::CommPrefix(prefix) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_COMM_PREFIX as i32;
+                        copy_into_cstr(&mut mt.comm_prefix, prefix.as_str());
+                    }
+                    LayerMatch::PcommPrefix(prefix) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_PCOMM_PREFIX as i32;
+                        copy_into_cstr(&mut mt.pcomm_prefix, prefix.as_str());
+                    }
+                    LayerMatch::NiceAbove(nice) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_ABOVE as i32;
+                        mt.nice = *nice;
+                    }
+                    LayerMatch::NiceBelow(nice) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_BELOW as i32;
+                        mt.nice = *nice;
+                    }
+                    LayerMatch::NiceEquals(nice) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_EQUALS as i32;
+                        mt.nice = *nice;
+                    }
+                    LayerMatch::UIDEquals(user_id) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_USER_ID_EQUALS as i32;
+                        mt.user_id = *user_id;
+                    }
+                    LayerMatch::GIDEquals(group_id) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_GROUP_ID_EQUALS as i32;
+                        mt.group_id = *group_id;
+                    }
+                    LayerMatch::PIDEquals(pid) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_PID_EQUALS as i32;
+                        mt.pid = *pid;
+                    }
+                    LayerMatch::PPIDEquals(ppid) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_PPID_EQUALS as i32;
+                        mt.ppid = *ppid;
+                    }
+                    LayerMatch::TGIDEquals(tgid) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_TGID_EQUALS as i32;
+                        mt.tgid = *tgid;
+                    }
+                    LayerMatch::NSPIDEquals(nsid, pid) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NSPID_EQUALS as i32;
+                        mt.nsid = *nsid;
+                        mt.pid = *pid;
+                    }
+                    LayerMatch::NSEquals(nsid) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NS_EQUALS as i32;
+                        mt.nsid = *nsid as u64;
+                    }
+                    LayerMatch::CmdJoin(joincmd) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_SCXCMD_JOIN as i32;
+                        copy_into_cstr(&mut mt.comm_prefix, joincmd);
+                    }
+                    LayerMatch::IsGroupLeader(polarity) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_IS_GROUP_LEADER as i32;
+                        mt.is_group_leader.write(*polarity);
+                    }
+                    LayerMatch::IsKthread(polarity) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_IS_KTHREAD as i32;
+                        mt.is_kthread.write(*polarity);
+                    }
+                    LayerMatch::UsedGpuTid(polarity) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_USED_GPU_TID as i32;
+                        mt.used_gpu_tid.write(*polarity);
+                    }
+                    LayerMatch::UsedGpuPid(polarity) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_USED_GPU_PID as i32;
+                        mt.used_gpu_pid.write(*polarity);
+                    }
+                    LayerMatch::CommRegex(_)
+                    | LayerMatch::CgroupRegex(_)
+                    | LayerMatch::CgroupSuffix(_)
+                    | LayerMatch::ExePath(_)
+                    | LayerMatch::ExePrefix(_)
+                    | LayerMatch::EnvVarEquals(_, _)
+                    | LayerMatch::SystemdUnit(_) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_USER_HINT as i32;
+                        mt.user_match_id = user_matches
+                            .id(and)
+                            .with_context(|| format!("{:?} has no user match ID", and))?
+                            as u32;
                     }
                 }
-                layer.matches[or_i].nr_match_ands = or.len() as i32;
             }
+            layer.matches[or_i].nr_match_ands = or.len() as i32;
+        }
 
-            layer.nr_match_ors = spec.matches.len() as u32;
-            layer.kind = spec.kind.as_bpf_enum();
+        layer.nr_match_ors = spec.matches.len() as u32;
+        layer.kind = spec.kind.as_bpf_enum();
 
-            {
-                let LayerCommon {
-                    min_exec_us,
-                    yield_ignore,
-                    perf,
-                    preempt,
-                    preempt_first,
-                    exclusive,
-                    allow_node_aligned,
-                    growth_algo,
-                    nodes,
-                    slice_us,
-                    fifo,
-                    weight,
-                    disallow_open_after_us,
-                    disallow_preempt_after_us,
-                    xllc_mig_min_us,
-                    ..
-                } = spec.kind.common();
-
-                layer.slice_ns = *slice_us * 1000;
-                layer.fifo.write(*fifo);
-                layer.min_exec_ns = min_exec_us * 1000;
-                layer.yield_step_ns = if *yield_ignore > 0.999 {
-                    0
-                } else if *yield_ignore < 0.001 {
-                    layer.slice_ns
-                } else {
-                    (layer.slice_ns as f64 * (1.0 - *yield_ignore)) as u64
-                };
-                let mut layer_name: String = spec.name.clone();
-                layer_name.truncate(MAX_LAYER_NAME);
-                copy_into_cstr(&mut layer.name, layer_name.as_str());
-                layer.preempt.write(*preempt);
-                layer.preempt_first.write(*preempt_first);
-                layer.exclusive.write(*exclusive);
-                layer.allow_node_aligned.write(*allow_node_aligned);
-                layer.growth_algo = growth_algo.as_bpf_enum();
-                layer.weight = *weight;
-                layer.disallow_open_after_ns = match disallow_open_after_us.unwrap() {
-                    v if v == u64::MAX => v,
-                    v => v * 1000,
-                };
-                layer.disallow_preempt_after_ns = match disallow_preempt_after_us.unwrap() {
-                    v if v == u64::MAX => v,
-                    v => v * 1000,
-                };
-                layer.xllc_mig_min_ns = (xllc_mig_min_us * 1000.0) as u64;
-                layer_weights.push(layer.weight.try_into().unwrap());
-                layer.perf = u32::try_from(*perf)?;
-                layer.node_mask = nodemask_from_nodes(nodes) as u64;
-                for (topo_node_id, topo_node) in &topo.nodes {
-                    if !nodes.is_empty() && !nodes.contains(topo_node_id) {
-                        continue;
-                    }
-                    layer.llc_mask |= llcmask_from_llcs(&topo_node.llcs) as u64;
-                }
+        {
+            let LayerCommon {
+                min_exec_us,
+                yield_ignore,
+                perf,
+                preempt,
+                preempt_first,
+                exclusive,
+                allow_node_aligned,
+                growth_algo,
+                nodes,
+                slice_us,
+                fifo,
+                weight,
+                disallow_open_after_us,
+                disallow_preempt_after_us,
+                xllc_mig_min_us,
+                quota_us,
+                period_us,
+                ..
+            } = spec.kind.common();
+
+            layer.slice_ns = *slice_us * 1000;
+            layer.fifo.write(*fifo);
+            layer.min_exec_ns = min_exec_us * 1000;
+            layer.yield_step_ns = if *yield_ignore > 0.999 {
+                0
+            } else if *yield_ignore < 0.001 {
+                layer.slice_ns
+            } else {
+                (layer.slice_ns as f64 * (1.0 - *yield_ignore)) as u64
+            };
+            let mut layer_name: String = spec.name.clone();
+            layer_name.truncate(MAX_LAYER_NAME);
+            copy_into_cstr(&mut layer.name, layer_name.as_str());
+            layer.preempt.write(*preempt);
+            layer.preempt_first.write(*preempt_first);
+            layer.exclusive.write(*exclusive);
+            layer.allow_node_aligned.write(*allow_node_aligned);
+            layer.growth_algo = growth_algo.as_bpf_enum();
+            layer.weight = *weight;
+            layer.disallow_open_after_ns = match disallow_open_after_us.unwrap() {
+                v if v == u64::MAX => v,
+                v => v * 1000,
+            };
+            layer.disallow_preempt_after_ns = match disallow_preempt_after_us.unwrap() {
+                v if v == u64::MAX => v,
+                v => v * 1000,
+            };
+            layer.xllc_mig_min_ns = (xllc_mig_min_us * 1000.0) as u64;
+            // Clear caps removed by a reload or a profile switch.
+            if let (Some(quota_us), Some(period_us)) = (quota_us, period_us) {
+                layer.bw_quota_ns = quota_us * 1000;
+                layer.bw_period_ns = period_us * 1000;
+            } else {
+                layer.bw_quota_ns = 0;
+                layer.bw_period_ns = 0;
             }
-
-            layer.is_protected.write(match spec.kind {
-                LayerKind::Open { .. } => false,
-                LayerKind::Confined { protected, .. } | LayerKind::Grouped { protected, .. } => {
-                    protected
+            layer.perf = u32::try_from(*perf)?;
+            layer.node_mask = nodemask_from_nodes(nodes) as u64;
+            layer.llc_mask = 0;
+            for (topo_node_id, topo_node) in &topo.nodes {
+                if !nodes.is_empty() && !nodes.contains(topo_node_id) {
+                    continue;
                 }
-            });
-
-            perf_set |= layer.perf > 0;
+                layer.llc_mask |= llcmask_from_llcs(&topo_node.llcs) as u64;
+            }
         }
 
-        layer_iteration_order.sort_by(|i, j| layer_weights[*i].cmp(&layer_weights[*j]));
-        for (idx, layer_idx) in layer_iteration_order.iter().enumerate() {
-            skel.maps.rodata_data.layer_iteration_order[idx] = *layer_idx as u32;
-        }
+        layer.is_protected.write(match spec.kind {
+            LayerKind::Open { .. } => false,
+            LayerKind::Confined { protected, .. } | LayerKind::Grouped { protected, .. } => {
+                protected
+            }
+        });
+
+        Ok(())
+    }
+
+    fn init_layers(
+        skel: &mut OpenBpfSkel,
+        specs: &[LayerSpec],
+        topo: &Topology,
+        user_matches: &UserMatches,
+    ) -> Result<()> {
+        skel.maps.rodata_data.nr_layers = specs.len() as u32;
+        skel.maps.bss_data.nr_user_matches = user_matches.len() as u32;
+        let mut perf_set = false;
+
+        for (spec_i, spec) in specs.iter().enumerate() {
+            let layer = &mut skel.maps.bss_data.layers[spec_i];
+            Self::init_layer(layer, spec, topo, user_matches)?;
+            perf_set |= layer.perf > 0;
+        }
+
+        for (idx, layer_idx) in layer_iteration_order(specs).iter().enumerate() {
+            skel.maps.rodata_data.layer_iteration_order[idx] = *layer_idx as u32;
+        }
 
         if perf_set && !compat::ksym_exists("scx_bpf_cpuperf_set")? {
             warn!("cpufreq support not available, ignoring perf configurations");
@@ -1694,6 +2392,7 @@ impl<'a> Scheduler<'a> {
     fn init(
         opts: &'a Opts,
         layer_specs: &[LayerSpec],
+        profiles: ProfileState,
         open_object: &'a mut MaybeUninit<OpenObject>,
     ) -> Result<Self> {
         let nr_layers = layer_specs.len();
@@ -1809,6 +2508,13 @@ impl<'a> Scheduler<'a> {
         skel.maps.rodata_data.lo_fb_share_ppk = ((opts.lo_fb_share * 1024.0) as u32).clamp(1, 1024);
         skel.maps.rodata_data.enable_antistall = !opts.disable_antistall;
         skel.maps.rodata_data.enable_gpu_support = opts.enable_gpu_support;
+        skel.maps.rodata_data.enable_bw_caps = bw_caps_enabled(layer_specs);
+        if bw_caps_enabled(layer_specs)
+            && !compat::ksym_exists("scx_bpf_dsq_move")?
+            && !compat::ksym_exists("scx_bpf_dispatch_from_dsq")?
+        {
+            warn!("scx_bpf_dsq_move() not available, tasks with restricted CPU affinities won't be throttled by the bandwidth caps");
+        }
 
         for (cpu, sib) in topo.sibling_cpus().iter().enumerate() {
             skel.maps.rodata_data.__sibling_cpu[cpu] = *sib;
@@ -1846,15 +2552,7 @@ impl<'a> Scheduler<'a> {
             })
             .count() as u32;
 
-        let mut min_open = u64::MAX;
-        let mut min_preempt = u64::MAX;
-
-        for spec in layer_specs.iter() {
-            if let LayerKind::Open { common, .. } = &spec.kind {
-                min_open = min_open.min(common.disallow_open_after_us.unwrap());
-                min_preempt = min_preempt.min(common.disallow_preempt_after_us.unwrap());
-            }
-        }
+        let (min_open, min_preempt) = open_layer_disallow_mins(&layer_specs);
 
         skel.maps.rodata_data.min_open_layer_disallow_open_after_ns = match min_open {
             u64::MAX => *DFL_DISALLOW_OPEN_AFTER_US,
@@ -1873,7 +2571,8 @@ impl<'a> Scheduler<'a> {
         }
         skel.maps.bss_data.nr_empty_layer_ids = nr_layers as u32;
 
-        Self::init_layers(&mut skel, &layer_specs, &topo)?;
+        let user_matches = UserMatches::new(&layer_specs)?;
+        Self::init_layers(&mut skel, &layer_specs, &topo, &user_matches)?;
         Self::init_nodes(&mut skel, opts, &topo);
 
         let mut skel = scx_ops_load!(skel, layered, uei)?;
@@ -1920,14 +2619,30 @@ impl<'a> Scheduler<'a> {
         let struct_ops = scx_ops_attach!(skel, layered)?;
         let stats_server = StatsServer::new(stats::server_data()).launch()?;
 
+        let spec_file_mtimes = spec_file_mtimes(profiles.config.files.iter());
         let sched = Self {
             struct_ops: Some(struct_ops),
+            opts,
             layer_specs,
+            disable_topology,
+            spec_file_mtimes,
+            restart_specs: None,
+            profiles,
+
+            user_matches: Arc::new(user_matches),
+            user_match_gen: 0,
+            user_match_scanner: UserMatchScanner::new()?,
+            user_match_scanning: false,
+            user_match_hints: BTreeMap::new(),
+            user_match_seq: 0,
+            user_match_intv: Duration::from_secs_f64(opts.user_match_intv),
+            next_user_match_scan_at: Instant::now(),
 
             sched_intv: Duration::from_secs_f64(opts.interval),
 
             cpu_pool,
             layers,
+            layers_reset: false,
             idle_qos_enabled,
 
             sched_stats: Stats::new(&mut skel, &proc_reader)?,
@@ -2045,7 +2760,25 @@ impl<'a> Scheduler<'a> {
                     let util = if util < 0.01 { 0.0 } else { util };
                     let low = (util / util_range.1).ceil() as usize;
                     let high = ((util / util_range.0).floor() as usize).max(low);
-                    let target = layer.cpus.weight().clamp(low, high);
+                    let mut target = layer.cpus.weight().clamp(low, high);
+
+                    // If the layer has a latency target, grow it while the
+                    // target is missed and don't let utilization shrink it
+                    // until the latency is well under the target.
+                    let common = layer.kind.common();
+                    if let Some(lat_target_us) = common.latency_target_us {
+                        let pct = common.latency_percentile.unwrap_or(DFL_LAT_PERCENTILE);
+                        let lat_us = self.sched_stats.bpf_stats.lat_percentile_us(idx, pct);
+                        target =
+                            lat_target_nr_cpus(target, layer.cpus.weight(), lat_us, lat_target_us);
+                        trace!(
+                            "layer {} p{} latency {:?}us target {}us",
+                            &layer.name,
+                            pct,
+                            lat_us,
+                            lat_target_us
+                        );
+                    }
                     let cpus_range =
                         resolve_cpus_pct_range(cpus_range, cpus_range_frac, nr_cpus).unwrap();
 
@@ -2071,76 +2804,22 @@ impl<'a> Scheduler<'a> {
 
     /// Given (target, min) pair for each layer which was determined
     /// assuming infinite number of CPUs, distribute the actual CPUs
-    /// according to their weights.
+    /// according to their weights. Nested layers share the CPUs
+    /// distributed to their parents.
     fn weighted_target_nr_cpus(&self, targets: &[(usize, usize)]) -> Vec<usize> {
-        let mut nr_left = self.cpu_pool.topo.all_cpus.len();
-        let weights: Vec<usize> = self
-            .layers
-            .iter()
-            .map(|layer| layer.kind.common().weight as usize)
-            .collect();
-        let mut cands: BTreeMap<usize, (usize, usize, usize)> = targets
-            .iter()
-            .zip(&weights)
-            .enumerate()
-            .map(|(i, ((target, min), weight))| (i, (*target, *min, *weight)))
-            .collect();
-        let mut weight_sum: usize = weights.iter().sum();
+        let nr_cpus = self.cpu_pool.topo.all_cpus.len();
+        let layers: Vec<usize> = (0..self.layers.len()).collect();
         let mut weighted: Vec<usize> = vec![0; self.layers.len()];
 
-        trace!("cands: {:?}", &cands);
-
-        // First, accept all layers that are <= min.
-        cands.retain(|&i, &mut (target, min, weight)| {
-            if target <= min {
-                let target = target.min(nr_left);
-                weighted[i] = target;
-                weight_sum -= weight;
-                nr_left -= target;
-                false
-            } else {
-                true
-            }
-        });
-
-        trace!("cands after accepting mins: {:?}", &cands);
-
-        // Keep accepting ones under their allotted share.
-        let calc_share = |nr_left, weight, weight_sum| {
-            (((nr_left * weight) as f64 / weight_sum as f64).ceil() as usize).min(nr_left)
-        };
-
-        while !cands.is_empty() {
-            let mut progress = false;
-
-            cands.retain(|&i, &mut (target, _min, weight)| {
-                let share = calc_share(nr_left, weight, weight_sum);
-                if target <= share {
-                    weighted[i] = target;
-                    weight_sum -= weight;
-                    nr_left -= target;
-                    progress = true;
-                    false
-                } else {
-                    true
-                }
-            });
-
-            if !progress {
-                break;
-            }
-        }
-
-        trace!("cands after accepting under allotted: {:?}", &cands);
-
-        // The remaining candidates are in contention with each other,
-        // distribute according to the shares.
-        let nr_to_share = nr_left;
-        for (i, (_target, _min, weight)) in cands.into_iter() {
-            let share = calc_share(nr_to_share, weight, weight_sum).min(nr_left);
-            weighted[i] = share;
-            nr_left -= share;
-        }
+        distribute_nr_cpus(
+            &self.layer_specs,
+            &layers,
+            0,
+            nr_cpus,
+            nr_cpus,
+            targets,
+            &mut weighted,
+        );
 
         trace!("weighted: {:?}", &weighted);
 
@@ -2150,7 +2829,7 @@ impl<'a> Scheduler<'a> {
     fn refresh_cpumasks(&mut self) -> Result<()> {
         let layer_is_open = |layer: &Layer| matches!(layer.kind, LayerKind::Open { .. });
 
-        let mut updated = false;
+        let mut updated = std::mem::take(&mut self.layers_reset);
         let targets = self.calc_target_nr_cpus();
         let targets = self.weighted_target_nr_cpus(&targets);
 
@@ -2323,6 +3002,316 @@ impl<'a> Scheduler<'a> {
         Ok(())
     }
 
+    fn clear_user_match_hints(&mut self) {
+        for tid in std::mem::take(&mut self.user_match_hints).keys() {
+            let _ = self.skel.maps.user_match_hints.delete(&tid.to_ne_bytes());
+        }
+    }
+
+    /// Applies the result of the last rescan of the tasks for the user
+    /// matches and requests the next one once due. The scans run on the
+    /// user_match_scan thread.
+    fn refresh_user_match_hints(&mut self) -> Result<()> {
+        if let Some((gen, task_hints)) = self.user_match_scanner.try_recv() {
+            self.user_match_scanning = false;
+            // Results of replaced user matches have stale IDs. A failed scan,
+            // e.g. due to a transient /proc error, keeps the previous hints
+            // until the next scan.
+            if gen == self.user_match_gen {
+                match task_hints {
+                    Ok(task_hints) => self.update_user_match_hints(&task_hints),
+                    Err(e) => warn!("Failed to scan user matches ({:#})", &e),
+                }
+            }
+        }
+
+        let now = Instant::now();
+        if self.user_matches.is_empty()
+            || self.user_match_scanning
+            || now < self.next_user_match_scan_at
+        {
+            return Ok(());
+        }
+        self.next_user_match_scan_at = now + self.user_match_intv;
+        self.user_match_scanner
+            .request(self.user_match_gen, self.user_matches.clone())?;
+        self.user_match_scanning = true;
+        Ok(())
+    }
+
+    /// Updates the hints of the tasks whose results changed in @task_hints,
+    /// which makes BPF re-match them. The tasks whose hints can't be updated,
+    /// e.g. because the map is full, keep their previous hints and are
+    /// retried on the next scan.
+    fn update_user_match_hints(&mut self, task_hints: &BTreeMap<u32, u64>) {
+        self.user_match_seq += 1;
+
+        // Drop the exited tasks first to make room for the new ones.
+        let exited: Vec<u32> = self
+            .user_match_hints
+            .keys()
+            .filter(|tid| !task_hints.contains_key(tid))
+            .copied()
+            .collect();
+        for tid in exited {
+            let _ = self.skel.maps.user_match_hints.delete(&tid.to_ne_bytes());
+            self.user_match_hints.remove(&tid);
+        }
+
+        let mut nr_failed = 0;
+        let mut last_err = None;
+        for (tid, &hints) in task_hints.iter() {
+            if self.user_match_hints.get(tid).copied().unwrap_or(0) != hints {
+                let hint = [hints.to_ne_bytes(), self.user_match_seq.to_ne_bytes()].concat();
+                match self.skel.maps.user_match_hints.update(
+                    &tid.to_ne_bytes(),
+                    &hint,
+                    libbpf_rs::MapFlags::ANY,
+                ) {
+                    Ok(()) => {
+                        self.user_match_hints.insert(*tid, hints);
+                    }
+                    Err(e) => {
+                        nr_failed += 1;
+                        last_err = Some(e);
+                    }
+                }
+            }
+        }
+
+        if let Some(e) = last_err {
+            warn!(
+                "Failed to update user_match_hints of {} tasks ({})",
+                nr_failed, &e
+            );
+        }
+    }
+
+    /// Applies @specs to the running scheduler. Changes which can't be
+    /// applied in place are deferred to re-initialization through
+    /// restart_specs. Returns the description of the outcome.
+    fn apply_layer_specs(&mut self, specs: Vec<LayerSpec>) -> Result<String> {
+        let raw_specs = specs.clone();
+        let specs: Vec<LayerSpec> = if self.disable_topology {
+            specs
+                .into_iter()
+                .map(|mut s| {
+                    s.kind.common_mut().nodes.clear();
+                    s.kind.common_mut().llcs.clear();
+                    s
+                })
+                .collect()
+        } else {
+            specs
+        };
+
+        if specs == self.layer_specs {
+            return Ok("layer specs unchanged".into());
+        }
+
+        // Build the new layers first to catch invalid specs before touching
+        // anything.
+        let layer_growth_orders =
+            LayerGrowthAlgo::layer_core_orders(&self.cpu_pool, &specs, &self.topo);
+        let mut new_layers = vec![];
+        for (idx, spec) in specs.iter().enumerate() {
+            let growth_order = layer_growth_orders
+                .get(&idx)
+                .with_context(|| "layer has no growth order".to_string())?;
+            new_layers.push(
+                Layer::new(spec, &self.topo, growth_order)
+                    .with_context(|| format!("Invalid layer {:?}", &spec.name))?,
+            );
+        }
+
+        let user_matches = UserMatches::new(&specs)?;
+        let user_matches_changed = user_matches.matches() != self.user_matches.matches();
+
+        if let Some(reason) = layer_specs_restart_reason(&self.layer_specs, &specs) {
+            self.restart_specs = Some(raw_specs);
+            return Ok(format!("re-initializing, {}", reason));
+        }
+
+        let mut nr_updated = 0;
+        let mut matches_changed = false;
+
+        for (idx, (spec, new_layer)) in specs.iter().zip(new_layers).enumerate() {
+            let layer = &mut self.layers[idx];
+            let cpus_changed = new_layer.core_order != layer.core_order
+                || new_layer.allowed_cpus != layer.allowed_cpus;
+            if *spec == self.layer_specs[idx] && !cpus_changed && !user_matches_changed {
+                continue;
+            }
+
+            let bpf_layer = &mut self.skel.maps.bss_data.layers[idx];
+            Self::init_layer(bpf_layer, spec, &self.topo, &user_matches)?;
+            matches_changed |= spec.matches != self.layer_specs[idx].matches;
+
+            if cpus_changed {
+                // Start over from no CPUs. The layer will be grown back
+                // according to the new core order on the next refresh.
+                if !matches!(layer.kind, LayerKind::Open { .. }) && layer.nr_cpus > 0 {
+                    self.cpu_pool.free(&layer.cpus)?;
+                }
+                *layer = new_layer;
+                Self::update_bpf_layer_cpumask(layer, bpf_layer);
+                self.layers_reset = true;
+            } else {
+                layer.name = new_layer.name;
+                layer.kind = new_layer.kind;
+            }
+
+            nr_updated += 1;
+        }
+
+        // The user match IDs may have been reassigned. Start over and rescan
+        // right away.
+        if user_matches_changed {
+            self.clear_user_match_hints();
+            self.skel.maps.bss_data.nr_user_matches = user_matches.len() as u32;
+            self.user_matches = Arc::new(user_matches);
+            self.user_match_gen += 1;
+            self.next_user_match_scan_at = Instant::now();
+            matches_changed = true;
+        }
+
+        // Tasks record the seq before matching, so bumping it after all the
+        // matches are written re-matches the tasks which raced the update.
+        if matches_changed {
+            self.skel.maps.bss_data.layer_match_seq += 1;
+        }
+
+        let idle_qos_enabled = self
+            .layers
+            .iter()
+            .any(|layer| layer.kind.common().idle_resume_us.unwrap_or(0) > 0)
+            && cpu_idle_resume_latency_supported();
+        if self.idle_qos_enabled && !idle_qos_enabled {
+            for cpu in 0..*NR_CPU_IDS {
+                update_cpu_idle_resume_latency(cpu, 0)?;
+            }
+        }
+        self.idle_qos_enabled = idle_qos_enabled;
+
+        self.layer_specs = specs;
+
+        Ok(format!(
+            "updated {} layer(s) in place{}",
+            nr_updated,
+            if matches_changed {
+                ", re-matching tasks"
+            } else {
+                ""
+            }
+        ))
+    }
+
+    /// Reloads the layer specs from @input, which is in the same format as
+    /// the spec command line arguments, or from the command line arguments
+    /// if None.
+    fn reload_layer_specs(&mut self, input: Option<&str>) -> Result<String> {
+        let config = match input {
+            Some(input) => LayerConfig::parse(input).context("Failed to parse specs")?,
+            None => {
+                // Includes may have changed, watch the files loaded now.
+                let config = load_layer_config(self.opts)?;
+                self.spec_file_mtimes = spec_file_mtimes(config.files.iter());
+                config
+            }
+        };
+        let mut profiles = ProfileState::new(config, self.opts)?;
+        profiles.file_mtime = self.profiles.file_mtime;
+        if let Some(forced) = &self.profiles.forced {
+            if let Err(e) = profiles.force(forced) {
+                warn!("Dropping forced profile: {}", e);
+            }
+        }
+
+        let target = profiles.target();
+        let mut specs = profiles.config.resolve(&target)?;
+        finalize_layer_specs(&mut specs, self.opts)?;
+        let msg = self.apply_layer_specs(specs)?;
+
+        profiles.active = target;
+        self.profiles = profiles;
+        Ok(msg)
+    }
+
+    /// Applies profile @name on top of the loaded layer specs.
+    fn switch_profile(&mut self, name: &str) -> Result<String> {
+        let mut specs = self.profiles.config.resolve(name)?;
+        finalize_layer_specs(&mut specs, self.opts)?;
+        let msg = self.apply_layer_specs(specs)?;
+        self.profiles.active = name.to_string();
+        self.profiles.failed = None;
+        Ok(msg)
+    }
+
+    fn maybe_switch_profile(&mut self) {
+        if let Some(path) = &self.opts.profile_file {
+            self.profiles.read_profile_file(path);
+        }
+
+        let target = self.profiles.target();
+        if target == self.profiles.active || Some(&target) == self.profiles.failed.as_ref() {
+            return;
+        }
+
+        match self.switch_profile(&target) {
+            Ok(msg) => info!("Switching to profile {:?}: {}", &target, msg),
+            Err(e) => {
+                warn!("Failed to switch to profile {:?}: {:?}", &target, e);
+                self.profiles.failed = Some(target);
+            }
+        }
+    }
+
+    /// Forces profile @name if specified and returns the description of the
+    /// active profile.
+    fn request_profile(&mut self, name: Option<&str>) -> Result<String> {
+        if let Some(name) = name {
+            self.profiles.force(name)?;
+        }
+
+        let target = self.profiles.target();
+        let msg = if target != self.profiles.active {
+            format!(", {}", self.switch_profile(&target)?)
+        } else {
+            String::new()
+        };
+
+        Ok(format!(
+            "profile {:?}{}{}",
+            &self.profiles.active,
+            if self.profiles.forced.is_some() {
+                " (forced)"
+            } else {
+                ""
+            },
+            msg
+        ))
+    }
+
+    fn maybe_reload_layer_specs(&mut self) {
+        let trigger = if RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+            "SIGHUP"
+        } else if self.opts.watch_specs {
+            let mtimes = spec_file_mtimes(self.spec_file_mtimes.keys());
+            if mtimes == self.spec_file_mtimes {
+                return;
+            }
+            self.spec_file_mtimes = mtimes;
+            "spec file change"
+        } else {
+            return;
+        };
+
+        match self.reload_layer_specs(None) {
+            Ok(msg) => info!("Reloading layer specs on {}: {}", trigger, msg),
+            Err(e) => warn!("Failed to reload layer specs on {}: {:?}", trigger, e),
+        }
+    }
+
     fn step(&mut self) -> Result<()> {
         let started_at = Instant::now();
         self.sched_stats.refresh(
@@ -2333,6 +3322,7 @@ impl<'a> Scheduler<'a> {
         )?;
         self.refresh_cpumasks()?;
         self.refresh_idle_qos()?;
+        self.refresh_user_match_hints()?;
         self.processing_dur += Instant::now().duration_since(started_at);
         Ok(())
     }
@@ -2344,9 +3334,20 @@ impl<'a> Scheduler<'a> {
     ) -> Result<SysStats> {
         let bstats = &stats.bpf_stats;
         let mut sys_stats = SysStats::new(stats, bstats, self.cpu_pool.fallback_cpu)?;
+        sys_stats.profile = self.profiles.active.clone();
 
+        let nr_cpus = self.cpu_pool.topo.all_cpus.len();
         for (lidx, (spec, layer)) in self.layer_specs.iter().zip(self.layers.iter()).enumerate() {
             let layer_stats = LayerStats::new(lidx, layer, stats, bstats, cpus_ranges[lidx]);
+            for (depth, group) in spec.parents.iter().enumerate() {
+                sys_stats
+                    .groups
+                    .entry(group.name.clone())
+                    .or_insert_with(|| {
+                        LayerGroupStats::new(lidx, depth, group_cpus_range(group, nr_cpus))
+                    })
+                    .add_layer(&layer_stats);
+            }
             sys_stats.layers.insert(spec.name.to_string(), layer_stats);
             cpus_ranges[lidx] = (layer.nr_cpus, layer.nr_cpus);
         }
@@ -2354,15 +3355,76 @@ impl<'a> Scheduler<'a> {
         Ok(sys_stats)
     }
 
+    /// Collects the tasks in the layer @name from the task contexts. The
+    /// cgroups are left empty to be filled in by the stats reader so that
+    /// /proc isn't read per task on the scheduler thread.
+    fn layer_tasks(&self, name: &str) -> Result<LayerTasks> {
+        let Some(layer_id) = self.layers.iter().position(|layer| layer.name == name) else {
+            bail!("Unknown layer {:?}", name);
+        };
+        let spec = &self.layer_specs[layer_id];
+        let link = self
+            .skel
+            .links
+            .dump_task_ctxs
+            .as_ref()
+            .ok_or_else(|| anyhow!("dump_task_ctxs iterator is not attached"))?;
+
+        let mut buf = vec![];
+        libbpf_rs::Iter::new(link)?.read_to_end(&mut buf)?;
+
+        let mut tasks = vec![];
+        for rec in buf.chunks_exact(std::mem::size_of::<bpf_intf::task_ctx_dump>()) {
+            let dump: bpf_intf::task_ctx_dump =
+                unsafe { std::ptr::read_unaligned(rec.as_ptr() as *const _) };
+            if dump.layer_id as usize != layer_id {
+                continue;
+            }
+
+            let comm: Vec<u8> = dump
+                .comm
+                .iter()
+                .take_while(|&&c| c != 0)
+                .map(|&c| c as u8)
+                .collect();
+            let match_index = dump.match_or_id as usize;
+
+            tasks.push(LayerTaskStats {
+                pid: dump.tgid as u32,
+                tid: dump.pid as u32,
+                comm: String::from_utf8_lossy(&comm).into_owned(),
+                cgroup: String::new(),
+                runtime_us: dump.runtime / 1000,
+                runtime_frac: 0.0,
+                match_index,
+                matched: spec
+                    .matches
+                    .get(match_index)
+                    .map(|ands| format!("{:?}", ands))
+                    .unwrap_or_default(),
+            });
+        }
+
+        LayerTasks::new(name, tasks)
+    }
+
     fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
         let (res_ch, req_ch) = self.stats_server.channels();
         let mut next_sched_at = Instant::now() + self.sched_intv;
         let mut cpus_ranges = HashMap::<ThreadId, Vec<(usize, usize)>>::new();
 
-        while !shutdown.load(Ordering::Relaxed) && !uei_exited!(&self.skel, uei) {
+        while !shutdown.load(Ordering::Relaxed)
+            && !uei_exited!(&self.skel, uei)
+            && self.restart_specs.is_none()
+        {
             let now = Instant::now();
 
             if now >= next_sched_at {
+                self.maybe_reload_layer_specs();
+                self.maybe_switch_profile();
+                if self.restart_specs.is_some() {
+                    break;
+                }
                 self.step()?;
                 while next_sched_at < now {
                     next_sched_at += self.sched_intv;
@@ -2400,6 +3462,25 @@ impl<'a> Scheduler<'a> {
                     cpus_ranges.remove(&tid);
                     res_ch.send(StatsRes::Bye)?;
                 }
+                Ok(StatsReq::LayerTasks(name)) => {
+                    res_ch.send(StatsRes::LayerTasks(self.layer_tasks(&name)))?;
+                }
+                Ok(StatsReq::Reload(input)) => {
+                    let res = self.reload_layer_specs(input.as_deref());
+                    match &res {
+                        Ok(msg) => info!("Reloading layer specs on stats request: {}", msg),
+                        Err(e) => warn!("Failed to reload layer specs on stats request: {:?}", e),
+                    }
+                    res_ch.send(StatsRes::Reloaded(res))?;
+                }
+                Ok(StatsReq::Profile(name)) => {
+                    let res = self.request_profile(name.as_deref());
+                    match &res {
+                        Ok(msg) => info!("Profile stats request: {}", msg),
+                        Err(e) => warn!("Failed to switch profile on stats request: {:?}", e),
+                    }
+                    res_ch.send(StatsRes::Profile(res))?;
+                }
                 Err(RecvTimeoutError::Timeout) => {}
                 Err(e) => Err(e)?,
             }
@@ -2423,7 +3504,208 @@ fn write_example_file(path: &str) -> Result<()> {
         .create_new(true)
         .write(true)
         .open(path)?;
-    Ok(f.write_all(serde_json::to_string_pretty(&*EXAMPLE_CONFIG)?.as_bytes())?)
+    Ok(f.write_all(serde_json::to_string_pretty(&EXAMPLE_CONFIG.specs)?.as_bytes())?)
+}
+
+/// Returns the layer indices sorted by ascending weight.
+fn layer_iteration_order(specs: &[LayerSpec]) -> Vec<usize> {
+    let mut order = (0..specs.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&idx| specs[idx].kind.common().weight);
+    order
+}
+
+/// Returns whether any layer has a CPU bandwidth cap.
+fn bw_caps_enabled(specs: &[LayerSpec]) -> bool {
+    specs
+        .iter()
+        .any(|spec| spec.kind.common().quota_us.is_some())
+}
+
+/// Returns the minimum disallow_open_after_us and disallow_preempt_after_us
+/// of the open layers.
+fn open_layer_disallow_mins(specs: &[LayerSpec]) -> (u64, u64) {
+    let mut min_open = u64::MAX;
+    let mut min_preempt = u64::MAX;
+
+    for spec in specs.iter() {
+        if let LayerKind::Open { common, .. } = &spec.kind {
+            min_open = min_open.min(common.disallow_open_after_us.unwrap());
+            min_preempt = min_preempt.min(common.disallow_preempt_after_us.unwrap());
+        }
+    }
+
+    (min_open, min_preempt)
+}
+
+/// Loads the layer specs specified in @opts with the includes, templates
+/// and defaults expanded. Nested layer specs are not flattened yet.
+fn expand_layer_config(opts: &Opts) -> Result<LayerConfig> {
+    let mut config = match opts.run_example {
+        true => EXAMPLE_CONFIG.clone(),
+        false => LayerConfig::default(),
+    };
+
+    for (idx, input) in opts.specs.iter().enumerate() {
+        let mut loaded = LayerConfig::load(input)
+            .context(format!("Failed to parse specs[{}] ({:?})", idx, input))?;
+        config.specs.append(&mut loaded.specs);
+        config.profiles.append(&mut loaded.profiles);
+        config.files.append(&mut loaded.files);
+    }
+
+    Ok(config)
+}
+
+/// Parses the layer specs specified in @opts.
+fn load_layer_config(opts: &Opts) -> Result<LayerConfig> {
+    expand_layer_config(opts)?.flatten()
+}
+
+/// Verifies that the layer specs are valid with each of the profiles.
+fn verify_layer_config(config: &LayerConfig, opts: &Opts) -> Result<()> {
+    verify_layer_profiles(&config.profiles, &config.specs)?;
+    let resolve = |name: &str| -> Result<Vec<LayerSpec>> {
+        let mut specs = config.resolve(name)?;
+        finalize_layer_specs(&mut specs, opts)
+            .with_context(|| format!("Invalid layer specs with profile {:?}", name))?;
+        Ok(specs)
+    };
+
+    // Profiles are switched in place and can't change what's fixed when the
+    // BPF program is loaded.
+    let default = resolve(DEFAULT_PROFILE)?;
+    for profile in config.profiles.iter() {
+        let specs = resolve(&profile.name)?;
+        if let Some(reason) = layer_specs_restart_reason(&default, &specs) {
+            bail!(
+                "Profile {:?} can't be switched to without restarting, {}",
+                &profile.name,
+                reason
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fills in the unspecified fields of @specs from @opts and verifies them.
+fn finalize_layer_specs(specs: &mut [LayerSpec], opts: &Opts) -> Result<()> {
+    for spec in specs.iter_mut() {
+        let common = spec.kind.common_mut();
+
+        if common.slice_us == 0 {
+            common.slice_us = opts.slice_us;
+        }
+
+        if common.weight == 0 {
+            common.weight = DEFAULT_LAYER_WEIGHT;
+        }
+        common.weight = common.weight.clamp(MIN_LAYER_WEIGHT, MAX_LAYER_WEIGHT);
+
+        if common.preempt {
+            if common.disallow_open_after_us.is_some() {
+                warn!(
+                    "Preempt layer {} has non-null disallow_open_after_us, ignored",
+                    &spec.name
+                );
+            }
+            if common.disallow_preempt_after_us.is_some() {
+                warn!(
+                    "Preempt layer {} has non-null disallow_preempt_after_us, ignored",
+                    &spec.name
+                );
+            }
+            common.disallow_open_after_us = Some(u64::MAX);
+            common.disallow_preempt_after_us = Some(u64::MAX);
+        } else {
+            if common.disallow_open_after_us.is_none() {
+                common.disallow_open_after_us = Some(*DFL_DISALLOW_OPEN_AFTER_US);
+            }
+
+            if common.disallow_preempt_after_us.is_none() {
+                common.disallow_preempt_after_us = Some(*DFL_DISALLOW_PREEMPT_AFTER_US);
+            }
+        }
+
+        if common.idle_smt.is_some() {
+            warn!("Layer {} has deprecated flag \"idle_smt\"", &spec.name);
+        }
+
+        if let Some(pct) = common.max_cpu_pct {
+            if common.quota_us.is_some() {
+                bail!("Layer {} has both quota_us and max_cpu_pct", &spec.name);
+            }
+            if pct <= 0.0 {
+                bail!("Layer {} has invalid max_cpu_pct {}", &spec.name, pct);
+            }
+            let period_us = common.period_us.unwrap_or(DFL_BW_PERIOD_US);
+            common.quota_us = Some((period_us as f64 * pct / 100.0) as u64);
+        }
+        if common.quota_us.is_some() && common.period_us.is_none() {
+            common.period_us = Some(DFL_BW_PERIOD_US);
+        }
+
+        for group in spec.parents.iter_mut() {
+            let common = group.kind.common_mut();
+            if common.weight == 0 {
+                common.weight = DEFAULT_LAYER_WEIGHT;
+            }
+            common.weight = common.weight.clamp(MIN_LAYER_WEIGHT, MAX_LAYER_WEIGHT);
+        }
+    }
+
+    debug!("specs={}", serde_json::to_string_pretty(&specs)?);
+    verify_layer_specs(specs)
+}
+
+/// Returns the modification times of @files.
+fn spec_file_mtimes<'a>(
+    files: impl Iterator<Item = &'a PathBuf>,
+) -> BTreeMap<PathBuf, Option<SystemTime>> {
+    files
+        .map(|path| {
+            let mtime = fs::metadata(path).and_then(|md| md.modified()).ok();
+            (path.clone(), mtime)
+        })
+        .collect()
+}
+
+/// Returns why @new can't be applied to the scheduler running @cur in
+/// place, if so. The number of layers, the kind and preempt based layer
+/// groupings, the layer iteration order, the open layer disallow
+/// thresholds and whether bandwidth caps are enforced at all are fixed
+/// when the BPF program is loaded.
+fn layer_specs_restart_reason(cur: &[LayerSpec], new: &[LayerSpec]) -> Option<String> {
+    if cur.len() != new.len() {
+        return Some(format!(
+            "number of layers changed from {} to {}",
+            cur.len(),
+            new.len()
+        ));
+    }
+
+    for (cur, new) in cur.iter().zip(new.iter()) {
+        if cur.kind.as_bpf_enum() != new.kind.as_bpf_enum() {
+            return Some(format!("kind of layer {:?} changed", new.name));
+        }
+        if cur.kind.common().preempt != new.kind.common().preempt {
+            return Some(format!("preempt of layer {:?} changed", new.name));
+        }
+    }
+
+    if layer_iteration_order(cur) != layer_iteration_order(new) {
+        return Some("weight order of layers changed".into());
+    }
+    if open_layer_disallow_mins(cur) != open_layer_disallow_mins(new) {
+        return Some("disallow_*_after_us of open layers changed".into());
+    }
+    if bw_caps_enabled(cur) != bw_caps_enabled(new) {
+        return Some(match bw_caps_enabled(new) {
+            true => "bandwidth caps enabled".into(),
+            false => "bandwidth caps disabled".into(),
+        });
+    }
+
+    None
 }
 
 fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
@@ -2480,6 +3762,45 @@ fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
                             bail!("Spec {:?} has too long a process name prefix", spec.name);
                         }
                     }
+                    LayerMatch::CgroupSuffix(suffix) => {
+                        if suffix.is_empty() || suffix.len() > MAX_PATH {
+                            bail!("Spec {:?} has an invalid cgroup suffix", spec.name);
+                        }
+                    }
+                    LayerMatch::ExePath(path) | LayerMatch::ExePrefix(path) => {
+                        if !path.starts_with('/') {
+                            bail!(
+                                "Spec {:?} has a non-absolute executable path {:?}",
+                                spec.name,
+                                path
+                            );
+                        }
+                    }
+                    LayerMatch::EnvVarEquals(key, val) => {
+                        if key.is_empty() || key.contains('=') || key.contains('\0') {
+                            bail!(
+                                "Spec {:?} has an invalid environment variable name {:?}",
+                                spec.name,
+                                key
+                            );
+                        }
+                        if val.contains('\0') {
+                            bail!(
+                                "Spec {:?} has an invalid environment variable value {:?}",
+                                spec.name,
+                                val
+                            );
+                        }
+                    }
+                    LayerMatch::SystemdUnit(unit) => {
+                        if unit.is_empty() || unit.contains('/') {
+                            bail!(
+                                "Spec {:?} has an invalid systemd unit {:?}",
+                                spec.name,
+                                unit
+                            );
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -2515,10 +3836,119 @@ fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
                     );
                 }
             }
+            _ => {
+                if spec.kind.common().latency_target_us.is_some() {
+                    bail!(
+                        "Spec {:?} has latency_target_us which is only supported by \
+                         confined and grouped layers",
+                        spec.name
+                    );
+                }
+            }
+        }
+
+        let common = spec.kind.common();
+        if common.latency_target_us == Some(0) {
+            bail!("Spec {:?} has zero latency_target_us", spec.name);
+        }
+        if let Some(pct) = common.latency_percentile {
+            if pct <= 0.0 || pct > 100.0 {
+                bail!(
+                    "Spec {:?} has invalid latency_percentile {}",
+                    spec.name,
+                    pct
+                );
+            }
+        }
+        match (common.quota_us, common.period_us) {
+            (Some(quota_us), Some(period_us)) => {
+                if !(MIN_BW_PERIOD_US..=MAX_BW_PERIOD_US).contains(&period_us) {
+                    bail!(
+                        "Spec {:?} has period_us {} outside [{}, {}]",
+                        spec.name,
+                        period_us,
+                        MIN_BW_PERIOD_US,
+                        MAX_BW_PERIOD_US
+                    );
+                }
+                if quota_us < MIN_BW_QUOTA_US {
+                    bail!(
+                        "Spec {:?} has quota_us {} below {}",
+                        spec.name,
+                        quota_us,
+                        MIN_BW_QUOTA_US
+                    );
+                }
+            }
+            (None, Some(_)) => {
+                bail!(
+                    "Spec {:?} has period_us without quota_us or max_cpu_pct",
+                    spec.name
+                );
+            }
             _ => {}
         }
     }
 
+    // Nested layers are flattened in depth-first order, so the layers of a
+    // group must be contiguous and share the same group definition.
+    let mut groups: BTreeMap<&str, (usize, &LayerGroup)> = BTreeMap::new();
+    for (idx, spec) in specs.iter().enumerate() {
+        for group in spec.parents.iter() {
+            if specs.iter().any(|spec| spec.name == group.name) {
+                bail!("Layer group {:?} has the same name as a layer", group.name);
+            }
+            match groups.get(group.name.as_str()) {
+                Some((last_idx, last)) if *last_idx + 1 != idx || *last != group => {
+                    bail!("Duplicate layer group {:?}", group.name);
+                }
+                _ => {}
+            }
+            groups.insert(&group.name, (idx, group));
+
+            // Bandwidth caps are enforced per layer only.
+            let common = group.kind.common();
+            if common.quota_us.is_some()
+                || common.max_cpu_pct.is_some()
+                || common.period_us.is_some()
+            {
+                bail!(
+                    "Layer group {:?} can't have quota_us, period_us or max_cpu_pct, \
+                     set them on its layers instead",
+                    group.name
+                );
+            }
+
+            if let LayerKind::Confined {
+                cpus_range,
+                cpus_range_frac,
+                ..
+            }
+            | LayerKind::Grouped {
+                cpus_range,
+                cpus_range_frac,
+                ..
+            } = &group.kind
+            {
+                if let Some((cpus_min, cpus_max)) = cpus_range {
+                    if cpus_min > cpus_max {
+                        bail!(
+                            "Layer group {:?} has invalid cpus_range({}, {})",
+                            group.name,
+                            cpus_min,
+                            cpus_max
+                        );
+                    }
+                }
+                resolve_cpus_pct_range(cpus_range, cpus_range_frac, *NR_CPU_IDS)
+                    .with_context(|| format!("Layer group {:?}", group.name))?;
+            }
+        }
+    }
+
+    // Compiles the regexes and checks the number of user matches.
+    UserMatches::new(specs)?;
+
     Ok(())
 }
 
@@ -2595,70 +4025,319 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    let mut layer_config = match opts.run_example {
-        true => EXAMPLE_CONFIG.clone(),
-        false => LayerConfig { specs: vec![] },
-    };
+    if opts.suggest {
+        let specs = suggest_layer_specs(opts.duration, *NR_CPU_IDS, &shutdown)?;
+        finalize_layer_specs(&mut specs.clone(), &opts).context("Invalid suggestion")?;
+        println!("{}", serde_json::to_string_pretty(&specs)?);
+        return Ok(());
+    }
 
-    for (idx, input) in opts.specs.iter().enumerate() {
-        layer_config.specs.append(
-            &mut LayerSpec::parse(input)
-                .context(format!("Failed to parse specs[{}] ({:?})", idx, input))?,
-        );
+    if opts.dump_resolved_config {
+        // Validate like a regular load so that the output is usable as-is.
+        let config = expand_layer_config(&opts)?;
+        verify_layer_config(&config.clone().flatten()?, &opts)?;
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
     }
 
-    for spec in layer_config.specs.iter_mut() {
-        let common = spec.kind.common_mut();
+    let mut profiles = ProfileState::new(load_layer_config(&opts)?, &opts)?;
+    let mut layer_specs = profiles.config.resolve(&profiles.active)?;
+    finalize_layer_specs(&mut layer_specs, &opts)?;
+    if profiles.active != DEFAULT_PROFILE {
+        info!("Starting with profile {:?}", &profiles.active);
+    }
 
-        if common.slice_us == 0 {
-            common.slice_us = opts.slice_us;
-        }
+    if opts.explain {
+        explain_layer_specs(&layer_specs, &mut std::io::stdout())?;
+        return Ok(());
+    }
 
-        if common.weight == 0 {
-            common.weight = DEFAULT_LAYER_WEIGHT;
+    // Replace the SIGHUP handler installed by ctrlc so that SIGHUP reloads
+    // the layer specs instead of shutting down.
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            sighup_handler as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+
+    let mut open_object = MaybeUninit::uninit();
+    loop {
+        let mut sched = Scheduler::init(&opts, &layer_specs, profiles, &mut open_object)?;
+        let uei = sched.run(shutdown.clone())?;
+        profiles = std::mem::take(&mut sched.profiles);
+        if let Some(specs) = sched.restart_specs.take() {
+            layer_specs = specs;
+            continue;
         }
-        common.weight = common.weight.clamp(MIN_LAYER_WEIGHT, MAX_LAYER_WEIGHT);
+        if !uei.should_restart() {
+            break;
+        }
+    }
 
-        if common.preempt {
-            if common.disallow_open_after_us.is_some() {
-                warn!(
-                    "Preempt layer {} has non-null disallow_open_after_us, ignored",
-                    &spec.name
-                );
-            }
-            if common.disallow_preempt_after_us.is_some() {
-                warn!(
-                    "Preempt layer {} has non-null disallow_preempt_after_us, ignored",
-                    &spec.name
-                );
-            }
-            common.disallow_open_after_us = Some(u64::MAX);
-            common.disallow_preempt_after_us = Some(u64::MAX);
-        } else {
-            if common.disallow_open_after_us.is_none() {
-                common.disallow_open_after_us = Some(*DFL_DISALLOW_OPEN_AFTER_US);
-            }
+    Ok(())
+}
 
-            if common.disallow_preempt_after_us.is_none() {
-                common.disallow_preempt_after_us = Some(*DFL_DISALLOW_PREEMPT_AFTER_US);
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_specs() -> Vec<LayerSpec> {
+        LayerSpec::parse(
+            r#"[
+                {
+                    "name": "confined",
+                    "matches": [[{"CommPrefix": "c"}]],
+                    "kind": {"Confined": {"util_range": [0.5, 0.6], "weight": 200}}
+                },
+                {
+                    "name": "open",
+                    "matches": [[{"CommPrefix": "o"}]],
+                    "kind": {"Open": {
+                        "weight": 100,
+                        "disallow_open_after_us": 1000,
+                        "disallow_preempt_after_us": 4000
+                    }}
+                },
+                {
+                    "name": "rest",
+                    "matches": [[]],
+                    "kind": {"Open": {
+                        "weight": 300,
+                        "disallow_open_after_us": 3000,
+                        "disallow_preempt_after_us": 2000
+                    }}
+                }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_layer_iteration_order() {
+        let mut specs = test_specs();
+        assert_eq!(layer_iteration_order(&specs), vec![1, 0, 2]);
+        specs[2].kind.common_mut().weight = 50;
+        assert_eq!(layer_iteration_order(&specs), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_open_layer_disallow_mins() {
+        let mut specs = test_specs();
+        assert_eq!(open_layer_disallow_mins(&specs), (1000, 2000));
+        // confined layers don't count
+        specs[0].kind.common_mut().disallow_open_after_us = Some(10);
+        assert_eq!(open_layer_disallow_mins(&specs), (1000, 2000));
+        specs.truncate(1);
+        assert_eq!(open_layer_disallow_mins(&specs), (u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn test_layer_specs_restart_reason() {
+        let cur = test_specs();
+
+        // changes that can be applied in place
+        let mut new = test_specs();
+        new[0].kind.common_mut().slice_us = 5000;
+        new[1].kind.common_mut().weight = 150;
+        new[2].matches = vec![vec![]];
+        if let LayerKind::Confined { util_range, .. } = &mut new[0].kind {
+            *util_range = (0.2, 0.3);
         }
+        assert_eq!(layer_specs_restart_reason(&cur, &new), None);
+
+        let mut new = test_specs();
+        new.pop();
+        assert!(layer_specs_restart_reason(&cur, &new)
+            .unwrap()
+            .starts_with("number of layers"));
+
+        let mut new = test_specs();
+        new[1].kind = LayerKind::Grouped {
+            util_range: (0.5, 0.6),
+            cpus_range: None,
+            cpus_range_frac: None,
+            protected: false,
+            common: new[1].kind.common().clone(),
+        };
+        assert!(layer_specs_restart_reason(&cur, &new)
+            .unwrap()
+            .starts_with("kind of layer \"open\""));
+
+        let mut new = test_specs();
+        new[0].kind.common_mut().preempt = true;
+        assert!(layer_specs_restart_reason(&cur, &new)
+            .unwrap()
+            .starts_with("preempt of layer \"confined\""));
+
+        let mut new = test_specs();
+        new[1].kind.common_mut().weight = 250;
+        assert!(layer_specs_restart_reason(&cur, &new)
+            .unwrap()
+            .starts_with("weight order"));
+
+        let mut new = test_specs();
+        new[2].kind.common_mut().disallow_preempt_after_us = Some(1500);
+        assert!(layer_specs_restart_reason(&cur, &new)
+            .unwrap()
+            .starts_with("disallow_*_after_us"));
+
+        let mut new = test_specs();
+        new[0].kind.common_mut().quota_us = Some(10000);
+        assert_eq!(
+            layer_specs_restart_reason(&cur, &new).as_deref(),
+            Some("bandwidth caps enabled")
+        );
+        assert_eq!(
+            layer_specs_restart_reason(&new, &cur).as_deref(),
+            Some("bandwidth caps disabled")
+        );
+        // Caps added or removed while some remain are applied in place.
+        let mut capped = new.clone();
+        capped[1].kind.common_mut().quota_us = Some(20000);
+        assert_eq!(layer_specs_restart_reason(&new, &capped), None);
+        assert_eq!(layer_specs_restart_reason(&capped, &new), None);
+    }
 
-        if common.idle_smt.is_some() {
-            warn!("Layer {} has deprecated flag \"idle_smt\"", &spec.name);
+    fn lat_stats(buckets: &[(usize, u64)]) -> BpfStats {
+        let mut hist = vec![0; NR_LAT_BUCKETS];
+        for &(bucket, cnt) in buckets.iter() {
+            hist[bucket] = cnt;
+        }
+        BpfStats {
+            gstats: vec![],
+            lstats: vec![],
+            lstats_sums: vec![],
+            llc_lstats: vec![],
+            lat_hists: vec![hist],
         }
     }
 
-    debug!("specs={}", serde_json::to_string_pretty(&layer_config)?);
-    verify_layer_specs(&layer_config.specs)?;
+    #[test]
+    fn test_lat_percentile_us() {
+        assert_eq!(lat_stats(&[]).lat_percentile_us(0, 99.0), None);
+
+        // Bucket 0 is [0, 2) and bucket 1 [2, 4).
+        let stats = lat_stats(&[(0, 10)]);
+        assert_eq!(stats.lat_percentile_us(0, 50.0), Some(1.0));
+        assert_eq!(stats.lat_percentile_us(0, 100.0), Some(2.0));
+        let stats = lat_stats(&[(1, 4)]);
+        assert_eq!(stats.lat_percentile_us(0, 0.0), Some(2.0));
+        assert_eq!(stats.lat_percentile_us(0, 50.0), Some(3.0));
+        assert_eq!(stats.lat_percentile_us(0, 100.0), Some(4.0));
+
+        // Empty buckets are skipped and the upper half lands in [8, 16).
+        let stats = lat_stats(&[(0, 4), (3, 4)]);
+        assert_eq!(stats.lat_percentile_us(0, 50.0), Some(2.0));
+        assert_eq!(stats.lat_percentile_us(0, 75.0), Some(12.0));
+        assert_eq!(stats.lat_percentile_us(0, 100.0), Some(16.0));
+
+        let stats = lat_stats(&[(NR_LAT_BUCKETS - 1, 1)]);
+        assert_eq!(
+            stats.lat_percentile_us(0, 100.0),
+            Some((1u64 << NR_LAT_BUCKETS) as f64)
+        );
+    }
 
-    let mut open_object = MaybeUninit::uninit();
-    loop {
-        let mut sched = Scheduler::init(&opts, &layer_config.specs, &mut open_object)?;
-        if !sched.run(shutdown.clone())?.should_restart() {
-            break;
-        }
+    #[test]
+    fn test_lat_target_nr_cpus() {
+        // Missing the target grows the layer by one CPU at a time unless
+        // utilization asks for more.
+        assert_eq!(lat_target_nr_cpus(2, 4, Some(1500.0), 1000), 5);
+        assert_eq!(lat_target_nr_cpus(8, 4, Some(1500.0), 1000), 8);
+
+        // Between LAT_SHRINK_RATIO of the target and the target, the layer
+        // is held at its size.
+        assert_eq!(lat_target_nr_cpus(2, 4, Some(1000.0), 1000), 4);
+        assert_eq!(lat_target_nr_cpus(2, 4, Some(500.0), 1000), 4);
+        assert_eq!(lat_target_nr_cpus(6, 4, Some(800.0), 1000), 6);
+
+        // Well under the target or without samples, utilization decides.
+        assert_eq!(lat_target_nr_cpus(2, 4, Some(499.0), 1000), 2);
+        assert_eq!(lat_target_nr_cpus(2, 4, None, 1000), 2);
     }
 
-    Ok(())
+    #[test]
+    fn test_weighted_shares() {
+        // targets under the min are accepted as-is
+        assert_eq!(weighted_shares(10, &[(2, 4, 100), (3, 4, 100)]), vec![2, 3]);
+        // targets under the share are accepted, the rest goes to the others
+        assert_eq!(
+            weighted_shares(10, &[(2, 0, 100), (20, 0, 100)]),
+            vec![2, 8]
+        );
+        // contended CPUs are distributed by weight
+        assert_eq!(
+            weighted_shares(8, &[(20, 0, 100), (20, 0, 300)]),
+            vec![2, 6]
+        );
+        assert_eq!(
+            weighted_shares(0, &[(20, 0, 100), (20, 0, 300)]),
+            vec![0, 0]
+        );
+    }
+
+    #[test]
+    fn test_distribute_nr_cpus() {
+        let specs = LayerSpec::parse(
+            r#"[
+                {
+                    "name": "services",
+                    "matches": [[{"CgroupPrefix": "services/"}]],
+                    "kind": {"Confined": {
+                        "util_range": [0.5, 0.6],
+                        "cpus_range": [2, 4],
+                        "weight": 100
+                    }},
+                    "children": [
+                        {
+                            "name": "frontend",
+                            "matches": [[{"CommPrefix": "fe"}]],
+                            "kind": {"Grouped": {"util_range": [0.5, 0.6], "weight": 300}}
+                        },
+                        {
+                            "name": "backend",
+                            "matches": [[]],
+                            "kind": {"Grouped": {"util_range": [0.5, 0.6], "weight": 100}}
+                        }
+                    ]
+                },
+                {
+                    "name": "rest",
+                    "matches": [[]],
+                    "kind": {"Confined": {"util_range": [0.5, 0.6], "weight": 100}}
+                }
+            ]"#,
+        )
+        .unwrap();
+        let layers: Vec<usize> = (0..specs.len()).collect();
+
+        let nodes = layer_nodes(&specs, &layers, 0);
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(
+            nodes[0].group.map(|group| group.name.as_str()),
+            Some("services")
+        );
+        assert_eq!(nodes[0].layers, vec![0, 1]);
+        assert_eq!(nodes[1].layers, vec![2]);
+
+        // The group's target is the sum of its children's clamped to its
+        // cpus_range.
+        let targets = [(8, 0), (8, 0), (8, 0)];
+        assert_eq!(
+            layer_node_target(&specs, &nodes[0], 0, 16, &targets),
+            (4, 2)
+        );
+        let targets_low = [(1, 0), (0, 0), (8, 0)];
+        assert_eq!(
+            layer_node_target(&specs, &nodes[0], 0, 16, &targets_low),
+            (2, 2)
+        );
+
+        // The group gets its clamped target and splits it by the children's
+        // weights.
+        let mut weighted = vec![0; specs.len()];
+        distribute_nr_cpus(&specs, &layers, 0, 16, 16, &targets, &mut weighted);
+        assert_eq!(weighted, vec![3, 1, 8]);
+    }
 }
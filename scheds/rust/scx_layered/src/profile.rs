@@ -0,0 +1,443 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Datelike;
+use chrono::Timelike;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::LayerKind;
+use crate::LayerSpec;
+
+/// Name which selects the layer specs without any profile applied.
+pub const DEFAULT_PROFILE: &str = "default";
+/// Name which clears a forced profile and goes back to the schedules.
+pub const AUTO_PROFILE: &str = "auto";
+
+/// A named set of overrides of the layer tunables. The layer structure and
+/// matches are shared by all profiles and only the fields of the layer kinds
+/// can be overridden.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerProfile {
+    pub name: String,
+    pub comment: Option<String>,
+    /// The profile is active while the current local time matches the
+    /// schedule unless another profile is forced.
+    #[serde(default)]
+    pub schedule: Option<CronSchedule>,
+    /// Per-layer overrides keyed by the layer or nested layer group name.
+    /// Each value is an object with the fields of the layer kind, e.g.
+    /// {"util_range": [0.6, 0.8], "weight": 800}.
+    #[serde(default)]
+    pub layers: BTreeMap<String, serde_json::Value>,
+}
+
+impl LayerProfile {
+    /// Returns @specs with the overrides of the profile applied.
+    pub fn apply(&self, specs: &[LayerSpec]) -> Result<Vec<LayerSpec>> {
+        let mut specs = specs.to_vec();
+        let mut unused: BTreeSet<&str> = self.layers.keys().map(|name| name.as_str()).collect();
+
+        for spec in specs.iter_mut() {
+            if let Some(ovr) = self.layers.get(&spec.name) {
+                spec.kind = Self::override_kind(&spec.kind, ovr)
+                    .with_context(|| format!("Failed to override layer {:?}", &spec.name))?;
+                unused.remove(spec.name.as_str());
+            }
+            for group in spec.parents.iter_mut() {
+                if let Some(ovr) = self.layers.get(&group.name) {
+                    group.kind = Self::override_kind(&group.kind, ovr)
+                        .with_context(|| format!("Failed to override group {:?}", &group.name))?;
+                    unused.remove(group.name.as_str());
+                }
+            }
+        }
+
+        if let Some(name) = unused.iter().next() {
+            bail!(
+                "Profile {:?} overrides unknown layer {:?}",
+                &self.name,
+                name
+            );
+        }
+        Ok(specs)
+    }
+
+    fn override_kind(kind: &LayerKind, ovr: &serde_json::Value) -> Result<LayerKind> {
+        let Some(ovr) = ovr.as_object() else {
+            bail!("override must be an object");
+        };
+
+        // LayerKind is externally tagged, {"Confined": {...}}.
+        let mut val = serde_json::to_value(kind)?;
+        let Some(fields) = val
+            .as_object_mut()
+            .and_then(|tagged| tagged.values_mut().next())
+            .and_then(|fields| fields.as_object_mut())
+        else {
+            bail!("unexpected layer kind format");
+        };
+
+        for (key, v) in ovr.iter() {
+            if !fields.contains_key(key) {
+                bail!("unknown field {:?}", key);
+            }
+            fields.insert(key.clone(), v.clone());
+        }
+
+        Ok(serde_json::from_value(val)?)
+    }
+}
+
+/// Verifies the profile names and that each profile applies cleanly to @specs.
+pub fn verify_layer_profiles(profiles: &[LayerProfile], specs: &[LayerSpec]) -> Result<()> {
+    let mut names = BTreeSet::new();
+    for profile in profiles.iter() {
+        if profile.name == DEFAULT_PROFILE || profile.name == AUTO_PROFILE {
+            bail!("Profile name {:?} is reserved", &profile.name);
+        }
+        if !names.insert(profile.name.as_str()) {
+            bail!("Duplicate profile {:?}", &profile.name);
+        }
+        profile.apply(specs)?;
+    }
+    Ok(())
+}
+
+/// Returns the first profile whose schedule matches @at.
+pub fn scheduled_profile<'a, T: Datelike + Timelike>(
+    profiles: &'a [LayerProfile],
+    at: &T,
+) -> Option<&'a LayerProfile> {
+    profiles.iter().find(|profile| {
+        profile
+            .schedule
+            .as_ref()
+            .is_some_and(|schedule| schedule.matches(at))
+    })
+}
+
+/// Cron-like "minute hour day-of-month month day-of-week" schedule. Each
+/// field is "*" or a comma separated list of numbers and ranges, optionally
+/// followed by "/step". Day-of-week is 0-7 with both 0 and 7 meaning Sunday.
+/// As with cron, if both day-of-month and day-of-week are restricted, either
+/// matching is enough. Unlike cron, the schedule matches every minute it
+/// covers rather than triggering once.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    doms: u64,
+    months: u64,
+    dows: u64,
+    dom_any: bool,
+    dow_any: bool,
+}
+
+impl CronSchedule {
+    /// Parses a single field into a bitmask of the values in [min, max].
+    fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+        let mut mask = 0u64;
+
+        for item in field.split(',') {
+            let (range, step) = match item.split_once('/') {
+                Some((range, step)) => (range, step.parse::<u32>()?),
+                None => (item, 1),
+            };
+            let (lo, hi) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((lo, hi)) => (lo.parse::<u32>()?, hi.parse::<u32>()?),
+                    None => {
+                        let v = range.parse::<u32>()?;
+                        (v, if step > 1 { max } else { v })
+                    }
+                },
+            };
+            if step == 0 || lo < min || hi > max || lo > hi {
+                bail!("invalid item {:?}, must be within [{}, {}]", item, min, max);
+            }
+            for v in (lo..=hi).step_by(step as usize) {
+                mask |= 1 << v;
+            }
+        }
+
+        Ok(mask)
+    }
+
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            bail!("Schedule {:?} must have 5 fields", expr);
+        }
+
+        let parse = |idx: usize, min, max| {
+            Self::parse_field(fields[idx], min, max)
+                .with_context(|| format!("Invalid schedule {:?}", expr))
+        };
+
+        let mut dows = parse(4, 0, 7)?;
+        // Sunday can be either 0 or 7.
+        if dows & (1 << 7) != 0 {
+            dows |= 1;
+        }
+
+        Ok(Self {
+            expr: expr.to_string(),
+            minutes: parse(0, 0, 59)?,
+            hours: parse(1, 0, 23)?,
+            doms: parse(2, 1, 31)?,
+            months: parse(3, 1, 12)?,
+            dows,
+            dom_any: fields[2] == "*",
+            dow_any: fields[4] == "*",
+        })
+    }
+
+    pub fn matches<T: Datelike + Timelike>(&self, at: &T) -> bool {
+        let test = |mask: u64, v: u32| mask & (1 << v) != 0;
+
+        let dom = test(self.doms, at.day());
+        let dow = test(self.dows, at.weekday().num_days_from_sunday());
+        let day = match (self.dom_any, self.dow_any) {
+            (false, false) => dom || dow,
+            _ => dom && dow,
+        };
+
+        test(self.minutes, at.minute())
+            && test(self.hours, at.hour())
+            && test(self.months, at.month())
+            && day
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = anyhow::Error;
+
+    fn try_from(expr: String) -> Result<Self> {
+        Self::parse(&expr)
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use chrono::NaiveDateTime;
+
+    // 2024-01-01 is a Monday and 2024-01-07 a Sunday.
+    fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    fn specs() -> Vec<LayerSpec> {
+        LayerSpec::parse(
+            r#"[
+                {
+                    "name": "services",
+                    "matches": [[{"CgroupPrefix": "services/"}]],
+                    "kind": {"Confined": {"util_range": [0.5, 0.6]}},
+                    "children": [
+                        {
+                            "name": "frontend",
+                            "matches": [[{"CommPrefix": "fe"}]],
+                            "kind": {"Grouped": {"util_range": [0.5, 0.6]}}
+                        },
+                        {
+                            "name": "backend",
+                            "matches": [[]],
+                            "kind": {"Grouped": {"util_range": [0.5, 0.6]}}
+                        }
+                    ]
+                },
+                {"name": "rest", "matches": [[]], "kind": {"Open": {"weight": 100}}}
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn profile(layers: &str) -> LayerProfile {
+        serde_json::from_str(&format!(r#"{{"name": "night", "layers": {}}}"#, layers)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_field() {
+        assert_eq!(CronSchedule::parse_field("*", 0, 7).unwrap(), 0xff);
+        assert_eq!(CronSchedule::parse_field("5", 0, 59).unwrap(), 1 << 5);
+        assert_eq!(
+            CronSchedule::parse_field("1,3,5", 0, 7).unwrap(),
+            (1 << 1) | (1 << 3) | (1 << 5)
+        );
+        assert_eq!(CronSchedule::parse_field("2-4", 0, 7).unwrap(), 0b11100);
+        assert_eq!(
+            CronSchedule::parse_field("*/15", 0, 59).unwrap(),
+            (1 << 0) | (1 << 15) | (1 << 30) | (1 << 45)
+        );
+        assert_eq!(
+            CronSchedule::parse_field("1-10/3", 1, 31).unwrap(),
+            (1 << 1) | (1 << 4) | (1 << 7) | (1 << 10)
+        );
+        // A single value with a step runs to the end of the range.
+        assert_eq!(
+            CronSchedule::parse_field("20/2", 0, 23).unwrap(),
+            (1 << 20) | (1 << 22)
+        );
+        assert_eq!(
+            CronSchedule::parse_field("1-2,6-7", 0, 7).unwrap(),
+            0b11000110
+        );
+
+        for bad in ["", "x", "60", "5-3", "*/0", "1-", "-1", "3/x"] {
+            assert!(CronSchedule::parse_field(bad, 0, 59).is_err(), "{:?}", bad);
+        }
+        assert!(CronSchedule::parse_field("0", 1, 31).is_err());
+        assert!(CronSchedule::parse_field("13", 1, 12).is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("* * * * * *").is_err());
+        assert!(CronSchedule::parse("* 24 * * *").is_err());
+        assert!(CronSchedule::parse("* * * * 8").is_err());
+
+        let schedule = CronSchedule::parse("0 22 * * 1-5").unwrap();
+        assert_eq!(String::from(schedule.clone()), "0 22 * * 1-5");
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert_eq!(
+            serde_json::from_str::<CronSchedule>(&json).unwrap(),
+            schedule
+        );
+        assert!(serde_json::from_str::<CronSchedule>(r#""* * *""#).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let schedule = CronSchedule::parse("*/30 9-17 * * 1-5").unwrap();
+        assert!(schedule.matches(&at(8, 9, 0)));
+        assert!(schedule.matches(&at(12, 17, 30)));
+        assert!(!schedule.matches(&at(8, 9, 15)));
+        assert!(!schedule.matches(&at(8, 18, 0)));
+        // Saturday and Sunday.
+        assert!(!schedule.matches(&at(6, 12, 0)));
+        assert!(!schedule.matches(&at(7, 12, 0)));
+
+        let schedule = CronSchedule::parse("* * * 2 *").unwrap();
+        assert!(!schedule.matches(&at(8, 12, 0)));
+    }
+
+    #[test]
+    fn test_matches_sunday() {
+        for expr in ["* * * * 0", "* * * * 7"] {
+            let schedule = CronSchedule::parse(expr).unwrap();
+            assert!(schedule.matches(&at(7, 12, 0)), "{}", expr);
+            assert!(schedule.matches(&at(14, 0, 0)), "{}", expr);
+            assert!(!schedule.matches(&at(8, 12, 0)), "{}", expr);
+            assert!(!schedule.matches(&at(6, 12, 0)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_matches_dom_dow() {
+        // Both restricted, either matching is enough.
+        let schedule = CronSchedule::parse("* * 1,15 * 1").unwrap();
+        assert!(schedule.matches(&at(1, 12, 0)));
+        assert!(schedule.matches(&at(15, 12, 0)));
+        assert!(schedule.matches(&at(8, 12, 0)));
+        assert!(!schedule.matches(&at(9, 12, 0)));
+
+        // Only one restricted, it alone decides.
+        let schedule = CronSchedule::parse("* * 1,15 * *").unwrap();
+        assert!(schedule.matches(&at(1, 12, 0)));
+        assert!(!schedule.matches(&at(8, 12, 0)));
+
+        let schedule = CronSchedule::parse("* * * * 1").unwrap();
+        assert!(schedule.matches(&at(8, 12, 0)));
+        assert!(!schedule.matches(&at(2, 12, 0)));
+    }
+
+    #[test]
+    fn test_apply() {
+        let specs = specs();
+        let applied = profile(
+            r#"{
+                "frontend": {"util_range": [0.7, 0.8], "weight": 500},
+                "services": {"util_range": [0.2, 0.3]},
+                "rest": {"weight": 50}
+            }"#,
+        )
+        .apply(&specs)
+        .unwrap();
+
+        assert_eq!(applied.len(), specs.len());
+        assert_eq!(applied[0].name, "frontend");
+        match &applied[0].kind {
+            LayerKind::Grouped {
+                util_range, common, ..
+            } => {
+                assert_eq!(*util_range, (0.7, 0.8));
+                assert_eq!(common.weight, 500);
+            }
+            kind => panic!("unexpected kind {:?}", kind),
+        }
+        // The group override applies to the copy in every child.
+        for spec in applied[..2].iter() {
+            match &spec.parents[0].kind {
+                LayerKind::Confined { util_range, .. } => assert_eq!(*util_range, (0.2, 0.3)),
+                kind => panic!("unexpected kind {:?}", kind),
+            }
+        }
+        // Layers without overrides are left alone.
+        assert_eq!(applied[1].kind, specs[1].kind);
+        assert_eq!(applied[1].matches, specs[1].matches);
+        assert_eq!(applied[2].kind.common().weight, 50);
+
+        // An empty profile is the identity.
+        assert_eq!(profile("{}").apply(&specs).unwrap(), specs);
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let specs = specs();
+        for layers in [
+            r#"{"nosuch": {"weight": 50}}"#,
+            r#"{"rest": {"nosuch": 50}}"#,
+            r#"{"rest": {"weight": "heavy"}}"#,
+            r#"{"rest": 50}"#,
+        ] {
+            assert!(profile(layers).apply(&specs).is_err(), "{}", layers);
+        }
+
+        let err = profile(r#"{"nosuch": {"weight": 50}}"#)
+            .apply(&specs)
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown layer \"nosuch\""));
+        let err = profile(r#"{"rest": {"nosuch": 50}}"#)
+            .apply(&specs)
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown field \"nosuch\""));
+
+        let reserved = serde_json::from_str::<LayerProfile>(r#"{"name": "auto"}"#).unwrap();
+        assert!(verify_layer_profiles(&[reserved], &specs).is_err());
+        let dup = profile("{}");
+        assert!(verify_layer_profiles(&[dup.clone(), dup], &specs).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::fs;
 use std::io::Write;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
@@ -25,6 +26,7 @@ use crate::bpf_intf;
 use crate::BpfStats;
 use crate::Layer;
 use crate::Stats;
+use crate::DFL_LAT_PERCENTILE;
 use crate::LAYER_USAGE_OPEN;
 use crate::LAYER_USAGE_PROTECTED;
 use crate::LAYER_USAGE_PROTECTED_PREEMPT;
@@ -69,6 +71,8 @@ const LSTAT_XLAYER_WAKE: usize = bpf_intf::layer_stat_id_LSTAT_XLAYER_WAKE as us
 const LSTAT_XLAYER_REWAKE: usize = bpf_intf::layer_stat_id_LSTAT_XLAYER_REWAKE as usize;
 const LSTAT_LLC_DRAIN_TRY: usize = bpf_intf::layer_stat_id_LSTAT_LLC_DRAIN_TRY as usize;
 const LSTAT_LLC_DRAIN: usize = bpf_intf::layer_stat_id_LSTAT_LLC_DRAIN as usize;
+const LSTAT_BW_THROTTLE: usize = bpf_intf::layer_stat_id_LSTAT_BW_THROTTLE as usize;
+const LSTAT_BW_THROTTLE_NS: usize = bpf_intf::layer_stat_id_LSTAT_BW_THROTTLE_NS as usize;
 
 const LLC_LSTAT_LAT: usize = bpf_intf::llc_layer_stat_id_LLC_LSTAT_LAT as usize;
 const LLC_LSTAT_CNT: usize = bpf_intf::llc_layer_stat_id_LLC_LSTAT_CNT as usize;
@@ -199,6 +203,18 @@ pub struct LayerStats {
     pub llc_fracs: Vec<f64>,
     #[stat(desc = "Per-LLC average latency")]
     pub llc_lats: Vec<f64>,
+    #[stat(desc = "latency target in usecs, 0 if not set")]
+    pub lat_target_us: u64,
+    #[stat(desc = "percentile of the enqueue-to-running latency reported in lat_us")]
+    pub lat_pct: f64,
+    #[stat(desc = "enqueue-to-running latency at lat_pct in usecs")]
+    pub lat_us: f64,
+    #[stat(desc = "CPU bandwidth cap (100% means one full CPU), 0 if not set")]
+    pub bw_cap: f64,
+    #[stat(desc = "% of time throttled due to the CPU bandwidth cap")]
+    pub bw_throttled: f64,
+    #[stat(desc = "count of times throttled due to the CPU bandwidth cap")]
+    pub bw_throttle: u64,
 }
 
 impl LayerStats {
@@ -244,6 +260,9 @@ impl LayerStats {
             .take(LAYER_USAGE_SUM_UPTO + 1)
             .sum::<f64>();
 
+        let common = layer.kind.common();
+        let lat_pct = common.latency_percentile.unwrap_or(DFL_LAT_PERCENTILE);
+
         Self {
             index: lidx,
             util: util_sum * 100.0,
@@ -311,6 +330,18 @@ impl LayerStats {
                 .iter()
                 .map(|lstats| lstats[LLC_LSTAT_LAT] as f64 / 1_000_000_000.0)
                 .collect(),
+            lat_target_us: common.latency_target_us.unwrap_or(0),
+            lat_pct,
+            lat_us: bstats.lat_percentile_us(lidx, lat_pct).unwrap_or(0.0),
+            bw_cap: match (common.quota_us, common.period_us) {
+                (Some(quota_us), Some(period_us)) => calc_frac(quota_us as f64, period_us as f64),
+                _ => 0.0,
+            },
+            bw_throttled: calc_frac(
+                lstat(LSTAT_BW_THROTTLE_NS) as f64,
+                stats.elapsed.as_nanos() as f64,
+            ),
+            bw_throttle: lstat(LSTAT_BW_THROTTLE),
         }
     }
 
@@ -392,14 +423,38 @@ impl LayerStats {
 
         writeln!(
             w,
-            "  {:<width$}  slice={}ms min_exec={}/{:7.2}ms",
+            "  {:<width$}  slice={}ms min_exec={}/{:7.2}ms lat_p{}={:7.2}ms",
             "",
             self.slice_us as f64 / 1000.0,
             fmt_pct(self.min_exec),
             self.min_exec_us as f64 / 1000.0,
+            self.lat_pct,
+            self.lat_us / 1000.0,
             width = header_width
         )?;
 
+        if self.lat_target_us != 0 {
+            writeln!(
+                w,
+                "  {:<width$}  lat_target={:7.2}ms",
+                "",
+                self.lat_target_us as f64 / 1000.0,
+                width = header_width
+            )?;
+        }
+
+        if self.bw_cap != 0.0 {
+            writeln!(
+                w,
+                "  {:<width$}  bw_cap={:6.1} throttled={} nr_throttled={}",
+                "",
+                self.bw_cap,
+                fmt_pct(self.bw_throttled),
+                fmt_num(self.bw_throttle),
+                width = header_width
+            )?;
+        }
+
         let mut cpus = self
             .cpus
             .iter()
@@ -463,6 +518,67 @@ impl LayerStats {
     }
 }
 
+#[stat_doc]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Stats)]
+#[stat(_om_prefix = "g_", _om_label = "group_name")]
+pub struct LayerGroupStats {
+    #[stat(desc = "index of the first layer in the group", _om_skip)]
+    pub index: usize,
+    #[stat(desc = "nesting depth, 0 for top-level groups", _om_skip)]
+    pub depth: usize,
+    #[stat(desc = "number of layers in the group")]
+    pub nr_layers: usize,
+    #[stat(desc = "Total CPU utilization (100% means one full CPU)")]
+    pub util: f64,
+    #[stat(desc = "fraction of total CPU utilization")]
+    pub util_frac: f64,
+    #[stat(desc = "number of tasks")]
+    pub tasks: u32,
+    #[stat(desc = "count of CPUs assigned")]
+    pub cur_nr_cpus: u32,
+    #[stat(desc = "minimum # of CPUs in the budget")]
+    pub min_nr_cpus: u32,
+    #[stat(desc = "maximum # of CPUs in the budget")]
+    pub max_nr_cpus: u32,
+}
+
+impl LayerGroupStats {
+    pub fn new(index: usize, depth: usize, cpus_range: (usize, usize)) -> Self {
+        Self {
+            index,
+            depth,
+            min_nr_cpus: cpus_range.0 as u32,
+            max_nr_cpus: cpus_range.1 as u32,
+            ..Default::default()
+        }
+    }
+
+    pub fn add_layer(&mut self, layer_stats: &LayerStats) {
+        self.nr_layers += 1;
+        self.util += layer_stats.util;
+        self.util_frac += layer_stats.util_frac;
+        self.tasks += layer_stats.tasks;
+        self.cur_nr_cpus += layer_stats.cur_nr_cpus;
+    }
+
+    pub fn format<W: Write>(&self, w: &mut W, name: &str, header_width: usize) -> Result<()> {
+        writeln!(
+            w,
+            "  {:<width$}: [group] util/frac={:6.1}/{:7.1} tasks={:6} layers={} cpus={:3} [{:3},{:3}]",
+            name,
+            self.util,
+            self.util_frac,
+            self.tasks,
+            self.nr_layers,
+            self.cur_nr_cpus,
+            self.min_nr_cpus,
+            self.max_nr_cpus,
+            width = header_width,
+        )?;
+        Ok(())
+    }
+}
+
 #[stat_doc]
 #[derive(Clone, Debug, Default, Serialize, Deserialize, Stats)]
 #[stat(top)]
@@ -513,6 +629,10 @@ pub struct SysStats {
     pub fallback_cpu_util: f64,
     #[stat(desc = "fallback CPU util %")]
     pub layers: BTreeMap<String, LayerStats>,
+    #[stat(desc = "per-group statistics aggregated over the nested layers")]
+    pub groups: BTreeMap<String, LayerGroupStats>,
+    #[stat(desc = "active layer profile")]
+    pub profile: String,
 }
 
 impl SysStats {
@@ -566,6 +686,8 @@ impl SysStats {
                 / elapsed_ns as f64
                 * 100.0,
             layers: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            profile: String::new(),
         })
     }
 
@@ -597,8 +719,8 @@ impl SysStats {
 
         writeln!(
             w,
-            "excl_coll={:.2} excl_preempt={:.2} excl_idle={:.2} excl_wakeup={:.2}",
-            self.excl_collision, self.excl_preempt, self.excl_idle, self.excl_wakeup
+            "excl_coll={:.2} excl_preempt={:.2} excl_idle={:.2} excl_wakeup={:.2} profile={}",
+            self.excl_collision, self.excl_preempt, self.excl_idle, self.excl_wakeup, self.profile
         )?;
 
         Ok(())
@@ -610,6 +732,7 @@ impl SysStats {
         let header_width = self
             .layers
             .keys()
+            .chain(self.groups.keys())
             .map(|name| name.len())
             .max()
             .unwrap_or(0)
@@ -620,7 +743,19 @@ impl SysStats {
 
         idx_to_name.sort();
 
-        for (_idx, name) in &idx_to_name {
+        // Print each group before its first layer, outer ones first.
+        let mut groups: Vec<(usize, usize, &String)> = self
+            .groups
+            .iter()
+            .map(|(k, v)| (v.index, v.depth, k))
+            .collect();
+
+        groups.sort();
+
+        for (idx, name) in &idx_to_name {
+            for (_, _, group) in groups.iter().filter(|(gidx, _, _)| gidx == idx) {
+                self.groups[*group].format(w, group, header_width)?;
+            }
             self.layers[*name].format(w, name, header_width)?;
         }
 
@@ -628,11 +763,98 @@ impl SysStats {
     }
 }
 
+#[stat_doc]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Stats)]
+pub struct LayerTaskStats {
+    #[stat(desc = "thread group ID")]
+    pub pid: u32,
+    #[stat(desc = "thread ID")]
+    pub tid: u32,
+    #[stat(desc = "command name")]
+    pub comm: String,
+    #[stat(desc = "cgroup path")]
+    pub cgroup: String,
+    #[stat(desc = "runtime in the layer in usecs")]
+    pub runtime_us: u64,
+    #[stat(desc = "% of the layer's runtime since the last read, or since joining the layer")]
+    pub runtime_frac: f64,
+    #[stat(desc = "index of the OR block which placed the task in the layer")]
+    pub match_index: usize,
+    #[stat(desc = "the OR block which placed the task in the layer")]
+    pub matched: String,
+}
+
+#[stat_doc]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Stats)]
+pub struct LayerTasks {
+    #[stat(desc = "timestamp")]
+    pub at: f64,
+    #[stat(desc = "layer name")]
+    pub layer: String,
+    #[stat(desc = "tasks in the layer")]
+    pub tasks: Vec<LayerTaskStats>,
+}
+
+impl LayerTasks {
+    pub fn new(layer: &str, tasks: Vec<LayerTaskStats>) -> Result<Self> {
+        Ok(Self {
+            at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs_f64(),
+            layer: layer.to_string(),
+            tasks,
+        })
+    }
+
+    /// Reads the cgroup paths of the tasks. Tasks which are gone are left
+    /// with empty paths.
+    fn read_cgroups(&mut self) {
+        for task in self.tasks.iter_mut() {
+            task.cgroup =
+                fs::read_to_string(format!("/proc/{}/task/{}/cgroup", task.pid, task.tid))
+                    .ok()
+                    .and_then(|cgroup| {
+                        cgroup
+                            .lines()
+                            .find_map(|line| line.strip_prefix("0::"))
+                            .map(|path| path.to_string())
+                    })
+                    .unwrap_or_default();
+        }
+    }
+
+    /// Calculates runtime_frac from the runtime deltas since @prev which
+    /// maps tid to runtime_us. @prev is updated to the current runtimes.
+    fn calc_runtime_fracs(&mut self, prev: &mut BTreeMap<u32, u64>) {
+        let deltas: Vec<u64> = self
+            .tasks
+            .iter()
+            .map(|task| match prev.get(&task.tid) {
+                // The runtime is reset when the task switches layers.
+                Some(&prev_us) if prev_us <= task.runtime_us => task.runtime_us - prev_us,
+                _ => task.runtime_us,
+            })
+            .collect();
+        let total: u64 = deltas.iter().sum();
+
+        for (task, delta) in self.tasks.iter_mut().zip(deltas.iter()) {
+            task.runtime_frac = calc_frac(*delta as f64, total as f64);
+        }
+
+        *prev = self
+            .tasks
+            .iter()
+            .map(|task| (task.tid, task.runtime_us))
+            .collect();
+    }
+}
+
 #[derive(Debug)]
 pub enum StatsReq {
     Hello(ThreadId),
     Refresh(ThreadId, Stats),
     Bye(ThreadId),
+    Reload(Option<String>),
+    LayerTasks(String),
+    Profile(Option<String>),
 }
 
 #[derive(Debug)]
@@ -640,6 +862,9 @@ pub enum StatsRes {
     Hello(Stats),
     Refreshed((Stats, SysStats)),
     Bye,
+    Reloaded(Result<String>),
+    LayerTasks(Result<LayerTasks>),
+    Profile(Result<String>),
 }
 
 pub fn server_data() -> StatsServerData<StatsReq, StatsRes> {
@@ -673,8 +898,55 @@ pub fn server_data() -> StatsServerData<StatsReq, StatsRes> {
         }
     });
 
+    let reload_open: Box<dyn StatsOpener<StatsReq, StatsRes>> = Box::new(move |_| {
+        let read: Box<dyn StatsReader<StatsReq, StatsRes>> =
+            Box::new(move |args, (req_ch, res_ch)| {
+                req_ch.send(StatsReq::Reload(args.get("specs").cloned()))?;
+                match res_ch.recv()? {
+                    StatsRes::Reloaded(res) => Ok(serde_json::Value::String(res?)),
+                    res => bail!("invalid response to Reload: {:?}", &res),
+                }
+            });
+        Ok(read)
+    });
+
+    let profile_open: Box<dyn StatsOpener<StatsReq, StatsRes>> = Box::new(move |_| {
+        let read: Box<dyn StatsReader<StatsReq, StatsRes>> =
+            Box::new(move |args, (req_ch, res_ch)| {
+                req_ch.send(StatsReq::Profile(args.get("name").cloned()))?;
+                match res_ch.recv()? {
+                    StatsRes::Profile(res) => Ok(serde_json::Value::String(res?)),
+                    res => bail!("invalid response to Profile: {:?}", &res),
+                }
+            });
+        Ok(read)
+    });
+
+    let layer_tasks_open: Box<dyn StatsOpener<StatsReq, StatsRes>> = Box::new(move |_| {
+        let mut prev: BTreeMap<String, BTreeMap<u32, u64>> = BTreeMap::new();
+
+        let read: Box<dyn StatsReader<StatsReq, StatsRes>> =
+            Box::new(move |args, (req_ch, res_ch)| {
+                let Some(layer) = args.get("layer") else {
+                    bail!("\"layer\" argument missing");
+                };
+                req_ch.send(StatsReq::LayerTasks(layer.clone()))?;
+                let mut layer_tasks = match res_ch.recv()? {
+                    StatsRes::LayerTasks(res) => res?,
+                    res => bail!("invalid response to LayerTasks: {:?}", &res),
+                };
+                layer_tasks.read_cgroups();
+                layer_tasks.calc_runtime_fracs(prev.entry(layer.clone()).or_default());
+                layer_tasks.to_json()
+            });
+        Ok(read)
+    });
+
     StatsServerData::new()
         .add_meta(LayerStats::meta())
+        .add_meta(LayerGroupStats::meta())
+        .add_meta(LayerTaskStats::meta())
+        .add_meta(LayerTasks::meta())
         .add_meta(SysStats::meta())
         .add_ops(
             "top",
@@ -683,6 +955,27 @@ pub fn server_data() -> StatsServerData<StatsReq, StatsRes> {
                 close: Some(close),
             },
         )
+        .add_ops(
+            "reload",
+            StatsOps {
+                open: reload_open,
+                close: None,
+            },
+        )
+        .add_ops(
+            "layer_tasks",
+            StatsOps {
+                open: layer_tasks_open,
+                close: None,
+            },
+        )
+        .add_ops(
+            "profile",
+            StatsOps {
+                open: profile_open,
+                close: None,
+            },
+        )
 }
 
 pub fn monitor(intv: Duration, shutdown: Arc<AtomicBool>) -> Result<()> {
@@ -697,3 +990,69 @@ pub fn monitor(intv: Duration, shutdown: Arc<AtomicBool>) -> Result<()> {
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_data_meta() {
+        // Fails if any nested stats struct isn't registered.
+        let mut buf = vec![];
+        server_data().describe_meta(&mut buf, None).unwrap();
+        let desc = String::from_utf8(buf).unwrap();
+        for name in ["SysStats", "LayerStats", "LayerGroupStats"] {
+            assert!(desc.contains(name), "{} missing", name);
+        }
+
+        let mut buf = vec![];
+        server_data()
+            .describe_meta(&mut buf, Some(&["LayerTasks"]))
+            .unwrap();
+        assert!(String::from_utf8(buf).unwrap().contains("LayerTaskStats"));
+    }
+
+    fn layer_tasks(runtimes: &[(u32, u64)]) -> LayerTasks {
+        let tasks = runtimes
+            .iter()
+            .map(|&(tid, runtime_us)| LayerTaskStats {
+                tid,
+                runtime_us,
+                ..Default::default()
+            })
+            .collect();
+        LayerTasks::new("test", tasks).unwrap()
+    }
+
+    fn runtime_fracs(layer_tasks: &LayerTasks) -> Vec<f64> {
+        layer_tasks
+            .tasks
+            .iter()
+            .map(|task| task.runtime_frac)
+            .collect()
+    }
+
+    #[test]
+    fn test_calc_runtime_fracs() {
+        let mut prev = BTreeMap::new();
+
+        // Without a previous read, the whole runtimes are used.
+        let mut lt = layer_tasks(&[(1, 100), (2, 300)]);
+        lt.calc_runtime_fracs(&mut prev);
+        assert_eq!(runtime_fracs(&lt), vec![25.0, 75.0]);
+        assert_eq!(prev, BTreeMap::from([(1, 100), (2, 300)]));
+
+        // Task 2 left and rejoined the layer which reset its runtime and
+        // task 3 is new. Task 4 is gone.
+        prev.insert(4, 1000);
+        let mut lt = layer_tasks(&[(1, 200), (2, 50), (3, 50)]);
+        lt.calc_runtime_fracs(&mut prev);
+        assert_eq!(runtime_fracs(&lt), vec![50.0, 25.0, 25.0]);
+        assert_eq!(prev, BTreeMap::from([(1, 200), (2, 50), (3, 50)]));
+
+        // No runtime since the last read.
+        let mut lt = layer_tasks(&[(1, 200)]);
+        lt.calc_runtime_fracs(&mut prev);
+        assert_eq!(runtime_fracs(&lt), vec![0.0]);
+    }
+}
@@ -0,0 +1,544 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Result;
+use log::info;
+use serde_json::json;
+
+use crate::user_match::ProcInfo;
+use crate::user_match::ThreadInfo;
+use crate::user_match::ThreadStat;
+use crate::LayerMatch;
+use crate::LayerSpec;
+use crate::MAX_LAYERS;
+
+const SAMPLE_INTV: Duration = Duration::from_secs(1);
+
+/// Groups using less CPU than this are left to the catch-all layer.
+const MIN_UTIL: f64 = 0.05;
+/// A group is latency sensitive if it wakes up at least this often and runs
+/// for no longer than LAT_MAX_RUN_US on average after each wakeup.
+const LAT_MIN_WAKEUP_FREQ: f64 = 100.0;
+const LAT_MAX_RUN_US: f64 = 1000.0;
+const LAT_UTIL_RANGE: (f64, f64) = (0.4, 0.6);
+/// A group is batch if it uses at least this many CPUs and runs for at
+/// least BATCH_MIN_RUN_US on average after each wakeup.
+const BATCH_MIN_UTIL: f64 = 0.5;
+const BATCH_MIN_RUN_US: f64 = 5000.0;
+const BATCH_UTIL_RANGE: (f64, f64) = (0.8, 0.9);
+/// Maximum number of layers suggested for each of the two classes. The total
+/// is further limited to leave room for the catch-all layer in MAX_LAYERS.
+const MAX_LAYERS_PER_CLASS: usize = 8;
+
+/// Tasks are grouped by cgroup, comm and exe. Kernel threads have no exe and
+/// their comm is cut after the first '/' so that e.g. all kworkers share a
+/// group.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct GroupKey {
+    cgroup: String,
+    comm: String,
+    exe: Option<String>,
+}
+
+impl GroupKey {
+    fn matches(&self) -> Vec<LayerMatch> {
+        let mut ands = vec![
+            LayerMatch::CgroupPrefix(self.cgroup.clone()),
+            LayerMatch::CommPrefix(self.comm.clone()),
+        ];
+        if let Some(exe) = &self.exe {
+            ands.push(LayerMatch::ExePath(exe.clone()));
+        }
+        ands
+    }
+
+    /// A group's matches can also match the tasks of another group only if
+    /// the other group's key is longer or has an exe where this one doesn't.
+    /// Ordering the layers by descending specificity lets the more specific
+    /// group claim its tasks first.
+    fn specificity(&self) -> (usize, usize, bool) {
+        (self.cgroup.len(), self.comm.len(), self.exe.is_some())
+    }
+}
+
+/// Cumulative counters of a thread.
+#[derive(Clone, Copy, Debug, Default)]
+struct ThreadCounters {
+    /// From /proc/PID/schedstat.
+    run_ns: u64,
+    wait_ns: u64,
+    nr_slices: u64,
+    /// voluntary_ctxt_switches from /proc/PID/status, i.e. the number of
+    /// times the thread went to sleep and thus woke up again.
+    nr_wakeups: u64,
+}
+
+impl ThreadCounters {
+    fn read(tgid: &str, tid: &str) -> Option<Self> {
+        let dir = format!("/proc/{}/task/{}", tgid, tid);
+        let mut counters = Self::default();
+
+        let schedstat = fs::read_to_string(format!("{}/schedstat", dir)).ok()?;
+        let mut fields = schedstat.split_whitespace();
+        counters.run_ns = fields.next()?.parse().ok()?;
+        counters.wait_ns = fields.next()?.parse().ok()?;
+        counters.nr_slices = fields.next()?.parse().ok()?;
+
+        let status = fs::read_to_string(format!("{}/status", dir)).ok()?;
+        counters.nr_wakeups = status
+            .lines()
+            .find_map(|line| line.strip_prefix("voluntary_ctxt_switches:"))?
+            .trim()
+            .parse()
+            .ok()?;
+
+        Some(counters)
+    }
+
+    fn delta(&self, prev: &Self) -> Self {
+        Self {
+            run_ns: self.run_ns.saturating_sub(prev.run_ns),
+            wait_ns: self.wait_ns.saturating_sub(prev.wait_ns),
+            nr_slices: self.nr_slices.saturating_sub(prev.nr_slices),
+            nr_wakeups: self.nr_wakeups.saturating_sub(prev.nr_wakeups),
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.run_ns += other.run_ns;
+        self.wait_ns += other.wait_ns;
+        self.nr_slices += other.nr_slices;
+        self.nr_wakeups += other.nr_wakeups;
+    }
+}
+
+/// What was observed for a group over the whole duration.
+#[derive(Debug, Default)]
+struct GroupStats {
+    total: ThreadCounters,
+    /// Highest number of CPUs used in a single sampling interval.
+    peak_util: f64,
+    nr_threads: BTreeSet<u32>,
+}
+
+/// Per-group averages over @dur_ns.
+#[derive(Debug)]
+struct GroupSummary {
+    util: f64,
+    peak_util: f64,
+    wakeup_freq: f64,
+    run_us: f64,
+    wait_us: f64,
+    nr_threads: usize,
+}
+
+impl GroupSummary {
+    fn new(stats: &GroupStats, dur_ns: u64) -> Self {
+        let total = &stats.total;
+        Self {
+            util: total.run_ns as f64 / dur_ns as f64,
+            peak_util: stats.peak_util,
+            wakeup_freq: total.nr_wakeups as f64 * 1_000_000_000.0 / dur_ns as f64,
+            run_us: total.run_ns as f64 / 1000.0 / total.nr_wakeups.max(1) as f64,
+            wait_us: total.wait_ns as f64 / 1000.0 / total.nr_slices.max(1) as f64,
+            nr_threads: stats.nr_threads.len(),
+        }
+    }
+
+    fn is_latency_sensitive(&self) -> bool {
+        self.wakeup_freq >= LAT_MIN_WAKEUP_FREQ && self.run_us <= LAT_MAX_RUN_US
+    }
+
+    fn is_batch(&self) -> bool {
+        self.util >= BATCH_MIN_UTIL && self.run_us >= BATCH_MIN_RUN_US
+    }
+
+    /// CPU range which keeps the layer's utilization within @util_range at
+    /// both the average and the peak observed usage.
+    fn cpus_range(&self, util_range: (f64, f64), nr_cpus: usize) -> (usize, usize) {
+        let clamp = |v: f64| (v.ceil() as usize).clamp(1, nr_cpus);
+        let min = clamp(self.util / util_range.1);
+        (min, clamp(self.peak_util / util_range.0).max(min))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "observed {:.2} CPUs avg, {:.2} peak, {} threads, {:.0} wakeups/s, \
+             {:.1}us run/wakeup, {:.1}us wait/run",
+            self.util, self.peak_util, self.nr_threads, self.wakeup_freq, self.run_us, self.wait_us
+        )
+    }
+}
+
+/// Reads the counters of all threads in /proc along with their groups.
+fn scan_threads() -> Result<BTreeMap<u32, (GroupKey, ThreadCounters)>> {
+    let mut threads = BTreeMap::new();
+
+    for tgid_ent in fs::read_dir("/proc")?.flatten() {
+        let tgid = tgid_ent.file_name().to_string_lossy().into_owned();
+        if tgid.parse::<u32>().is_err() {
+            continue;
+        }
+        let Ok(tasks) = fs::read_dir(format!("/proc/{}/task", tgid)) else {
+            continue;
+        };
+        let proc_info = ProcInfo::read(&tgid, true, false);
+
+        for tid_ent in tasks.flatten() {
+            let tid = tid_ent.file_name().to_string_lossy().into_owned();
+            let Ok(tid_num) = tid.parse::<u32>() else {
+                continue;
+            };
+            let (Some(thread), Some(counters)) = (
+                ThreadInfo::read(&tgid, &tid, true, true),
+                ThreadCounters::read(&tgid, &tid),
+            ) else {
+                continue;
+            };
+
+            let is_kthread = ThreadStat::read(&tgid, &tid).is_some_and(|stat| stat.is_kthread());
+
+            let key = match is_kthread {
+                true => GroupKey {
+                    cgroup: thread.cgroup,
+                    comm: match thread.comm.split_once('/') {
+                        Some((prefix, _)) => format!("{}/", prefix),
+                        None => thread.comm,
+                    },
+                    exe: None,
+                },
+                false => GroupKey {
+                    cgroup: thread.cgroup,
+                    comm: thread.comm,
+                    exe: proc_info.exe.clone(),
+                },
+            };
+            threads.insert(tid_num, (key, counters));
+        }
+    }
+
+    if threads.is_empty() {
+        bail!("Failed to read any thread's schedstat, is CONFIG_SCHED_INFO enabled?");
+    }
+    Ok(threads)
+}
+
+/// Returns a unique layer name for @key's group, e.g. "lat_kworker" for a
+/// latency sensitive group of kworkers.
+fn layer_name(prefix: &str, key: &GroupKey, used: &mut BTreeSet<String>) -> String {
+    let comm: String = key
+        .comm
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let base = format!("{}_{}", prefix, comm.trim_matches('_'));
+
+    let mut name = base.clone();
+    let mut seq = 1;
+    while !used.insert(name.clone()) {
+        name = format!("{}_{}", base, seq);
+        seq += 1;
+    }
+    name
+}
+
+/// Observes the tasks in /proc for @duration or until @shutdown is set and
+/// returns a starting set of layer specs. Latency sensitive groups become
+/// preempting Grouped layers and CPU heavy batch groups Confined layers,
+/// each with util_range and cpus_range derived from the observed usage. The
+/// rest is put in a catch-all Open layer.
+pub fn suggest_layer_specs(
+    duration: Duration,
+    nr_cpus: usize,
+    shutdown: &AtomicBool,
+) -> Result<Vec<LayerSpec>> {
+    info!(
+        "Observing tasks for {:.1}s, press Ctrl-C to stop early",
+        duration.as_secs_f64()
+    );
+
+    let started_at = Instant::now();
+    let mut prev = scan_threads()?;
+    let mut prev_at = started_at;
+    let mut groups: BTreeMap<GroupKey, GroupStats> = BTreeMap::new();
+
+    while !shutdown.load(Ordering::Relaxed) {
+        let left = duration.saturating_sub(started_at.elapsed());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(SAMPLE_INTV));
+
+        let cur = scan_threads()?;
+        let now = Instant::now();
+        let intv_ns = now.duration_since(prev_at).as_nanos().max(1) as f64;
+
+        // Threads which appeared during the interval have no baseline and
+        // start counting from the next interval.
+        let mut intv_run: BTreeMap<&GroupKey, u64> = BTreeMap::new();
+        for (tid, (key, counters)) in cur.iter() {
+            let Some((_, prev_counters)) = prev.get(tid) else {
+                continue;
+            };
+            let delta = counters.delta(prev_counters);
+            let stats = groups.entry(key.clone()).or_default();
+            stats.total.add(&delta);
+            stats.nr_threads.insert(*tid);
+            *intv_run.entry(key).or_default() += delta.run_ns;
+        }
+        for (key, run_ns) in intv_run.into_iter() {
+            let stats = groups.get_mut(key).unwrap();
+            stats.peak_util = stats.peak_util.max(run_ns as f64 / intv_ns);
+        }
+
+        prev = cur;
+        prev_at = now;
+    }
+
+    let dur_ns = prev_at.duration_since(started_at).as_nanos() as u64;
+    if dur_ns == 0 {
+        bail!("Stopped before any observation was made");
+    }
+
+    let mut summaries: Vec<(&GroupKey, GroupSummary)> = groups
+        .iter()
+        .map(|(key, stats)| (key, GroupSummary::new(stats, dur_ns)))
+        .filter(|(_, sum)| sum.util >= MIN_UTIL)
+        .collect();
+    summaries.sort_by(|a, b| b.1.util.total_cmp(&a.1.util));
+
+    info!(
+        "Observed {} groups for {:.1}s",
+        groups.len(),
+        dur_ns as f64 / 1_000_000_000.0
+    );
+    build_layer_specs(&summaries, nr_cpus)
+}
+
+/// Builds the layer specs for @summaries which are sorted by descending CPU
+/// usage. The groups which don't fit in the class or total layer limits are
+/// left to the catch-all layer.
+fn build_layer_specs(
+    summaries: &[(&GroupKey, GroupSummary)],
+    nr_cpus: usize,
+) -> Result<Vec<LayerSpec>> {
+    let mut used_names = BTreeSet::new();
+    let mut specs: Vec<(&GroupKey, LayerSpec)> = vec![];
+    let (mut nr_lat, mut nr_batch) = (0, 0);
+
+    for (key, sum) in summaries.iter() {
+        if specs.len() >= MAX_LAYERS - 1 {
+            break;
+        }
+        let (nr, prefix, kind, util_range, preempt) = if sum.is_latency_sensitive() {
+            (&mut nr_lat, "lat", "Grouped", LAT_UTIL_RANGE, true)
+        } else if sum.is_batch() {
+            (&mut nr_batch, "batch", "Confined", BATCH_UTIL_RANGE, false)
+        } else {
+            continue;
+        };
+        if *nr >= MAX_LAYERS_PER_CLASS {
+            continue;
+        }
+        *nr += 1;
+
+        let cpus_range = sum.cpus_range(util_range, nr_cpus);
+        let spec = json!({
+            "name": layer_name(prefix, key, &mut used_names),
+            "comment": sum.describe(),
+            "matches": [key.matches()],
+            "kind": {
+                kind: {
+                    "util_range": util_range,
+                    "cpus_range": cpus_range,
+                    "preempt": preempt,
+                }
+            },
+        });
+        specs.push((key, serde_json::from_value::<LayerSpec>(spec)?));
+    }
+
+    info!(
+        "Suggesting {} latency sensitive and {} batch layers",
+        nr_lat, nr_batch
+    );
+
+    // The first matching layer wins, e.g. "foo" would take "foobar"'s tasks.
+    // The sort is stable and keeps the CPU usage order among equals.
+    specs.sort_by_key(|(key, _)| std::cmp::Reverse(key.specificity()));
+    let mut specs: Vec<LayerSpec> = specs.into_iter().map(|(_, spec)| spec).collect();
+    specs.push(serde_json::from_value(json!({
+        "name": "normal",
+        "comment": "the rest",
+        "matches": [[]],
+        "kind": { "Open": {} },
+    }))?);
+    Ok(specs)
+}
+
+/// Parses a duration such as "60s", "5m", "1h" or "500ms". A bare number is
+/// in seconds.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (num, unit) = input.split_at(split);
+    let Ok(num) = num.parse::<f64>() else {
+        bail!("Invalid duration {:?}", input);
+    };
+    let secs = match unit {
+        "ms" => num / 1000.0,
+        "" | "s" => num,
+        "m" => num * 60.0,
+        "h" => num * 3600.0,
+        _ => bail!("Invalid duration unit {:?} in {:?}", unit, input),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| anyhow!("Invalid duration {:?}: {}", input, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(cgroup: &str, comm: &str, exe: Option<&str>) -> GroupKey {
+        GroupKey {
+            cgroup: cgroup.into(),
+            comm: comm.into(),
+            exe: exe.map(|exe| exe.into()),
+        }
+    }
+
+    #[test]
+    fn test_specificity_order() {
+        let mut keys = [
+            key("app/", "foo", Some("/bin/foo")),
+            key("/", "kworker/", None),
+            key("app/", "foobar", Some("/bin/foo")),
+            key("app/sub/", "foo", Some("/bin/foo")),
+            key("app/", "foo", None),
+        ];
+        keys.sort_by_key(|key| std::cmp::Reverse(key.specificity()));
+
+        // A key must come before any key whose matches would also match it.
+        let swallows = |a: &GroupKey, b: &GroupKey| {
+            b.cgroup.starts_with(&a.cgroup)
+                && b.comm.starts_with(&a.comm)
+                && (a.exe.is_none() || a.exe == b.exe)
+        };
+        for (idx, key) in keys.iter().enumerate() {
+            for later in keys[idx + 1..].iter() {
+                assert!(!swallows(key, later), "{:?} before {:?}", key, later);
+            }
+        }
+        assert_eq!(keys[0], key("app/sub/", "foo", Some("/bin/foo")));
+        assert_eq!(keys[4], key("/", "kworker/", None));
+    }
+
+    fn summary(util: f64, wakeup_freq: f64, run_us: f64) -> GroupSummary {
+        GroupSummary {
+            util,
+            peak_util: util,
+            wakeup_freq,
+            run_us,
+            wait_us: 0.0,
+            nr_threads: 1,
+        }
+    }
+
+    #[test]
+    fn test_group_classes() {
+        // The thresholds are inclusive.
+        let lat = summary(0.2, LAT_MIN_WAKEUP_FREQ, LAT_MAX_RUN_US);
+        assert!(lat.is_latency_sensitive() && !lat.is_batch());
+        assert!(!summary(0.2, LAT_MIN_WAKEUP_FREQ - 1.0, 500.0).is_latency_sensitive());
+        assert!(!summary(0.2, 1000.0, LAT_MAX_RUN_US + 1.0).is_latency_sensitive());
+
+        let batch = summary(BATCH_MIN_UTIL, 10.0, BATCH_MIN_RUN_US);
+        assert!(batch.is_batch() && !batch.is_latency_sensitive());
+        assert!(!summary(BATCH_MIN_UTIL - 0.01, 10.0, 10000.0).is_batch());
+        assert!(!summary(2.0, 10.0, BATCH_MIN_RUN_US - 1.0).is_batch());
+
+        // Neither frequent short runs nor long heavy ones.
+        let normal = summary(0.3, 50.0, 2000.0);
+        assert!(!normal.is_latency_sensitive() && !normal.is_batch());
+    }
+
+    #[test]
+    fn test_cpus_range() {
+        let mut s = summary(1.0, 10.0, 10000.0);
+        assert_eq!(s.cpus_range((0.8, 0.9), 8), (2, 2));
+
+        // The max covers the peak at the low end of the range.
+        s.peak_util = 3.0;
+        assert_eq!(s.cpus_range((0.8, 0.9), 8), (2, 4));
+
+        // The max never goes below the min.
+        s.peak_util = 0.5;
+        assert_eq!(s.cpus_range((0.8, 0.9), 8), (2, 2));
+
+        // Clamped to [1, nr_cpus].
+        assert_eq!(
+            summary(0.01, 10.0, 10000.0).cpus_range((0.8, 0.9), 8),
+            (1, 1)
+        );
+        assert_eq!(
+            summary(100.0, 10.0, 10000.0).cpus_range((0.8, 0.9), 8),
+            (8, 8)
+        );
+    }
+
+    #[test]
+    fn test_build_layer_specs_limits() {
+        let keys: Vec<GroupKey> = (0..24)
+            .map(|i| key("app/", &format!("task{}", i), None))
+            .collect();
+        // Batch groups use more CPU and thus come first.
+        let summaries: Vec<(&GroupKey, GroupSummary)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| match i < 12 {
+                true => (key, summary(2.0, 10.0, 10000.0)),
+                false => (key, summary(0.2, 1000.0, 100.0)),
+            })
+            .collect();
+
+        let specs = build_layer_specs(&summaries, 64).unwrap();
+        assert_eq!(specs.len(), MAX_LAYERS);
+        assert_eq!(specs.last().unwrap().name, "normal");
+
+        let nr_class = |prefix: &str| {
+            specs
+                .iter()
+                .filter(|spec| spec.name.starts_with(prefix))
+                .count()
+        };
+        assert_eq!(nr_class("batch_"), MAX_LAYERS_PER_CLASS);
+        assert_eq!(nr_class("lat_"), MAX_LAYERS - 1 - MAX_LAYERS_PER_CLASS);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("60").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+
+        for bad in ["", "s", "1d", "1.2.3s", "-1s", "1e3s"] {
+            assert!(parse_duration(bad).is_err(), "{:?}", bad);
+        }
+        // Too long for Duration.
+        assert!(parse_duration(&format!("{}h", "9".repeat(30))).is_err());
+    }
+}
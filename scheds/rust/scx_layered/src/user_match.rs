@@ -0,0 +1,471 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use crossbeam::channel::Receiver;
+use crossbeam::channel::Sender;
+use regex::Regex;
+
+use crate::bpf_intf;
+use crate::LayerMatch;
+use crate::LayerSpec;
+
+pub const MAX_USER_MATCHES: usize = bpf_intf::consts_MAX_USER_MATCHES as usize;
+
+pub(crate) const PF_KTHREAD: u64 = 0x00200000;
+
+/// Attributes of a thread group shared by all its threads.
+#[derive(Debug, Default)]
+pub(crate) struct ProcInfo {
+    pub(crate) exe: Option<String>,
+    pub(crate) environ: Vec<String>,
+}
+
+impl ProcInfo {
+    pub(crate) fn read(tgid: &str, exe: bool, environ: bool) -> Self {
+        let mut proc_info = Self::default();
+
+        if exe {
+            proc_info.exe = fs::read_link(format!("/proc/{}/exe", tgid))
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned());
+        }
+
+        if environ {
+            if let Ok(environ) = fs::read(format!("/proc/{}/environ", tgid)) {
+                proc_info.environ = environ
+                    .split(|&c| c == 0)
+                    .filter(|var| !var.is_empty())
+                    .map(|var| String::from_utf8_lossy(var).into_owned())
+                    .collect();
+            }
+        }
+
+        proc_info
+    }
+}
+
+/// Attributes of a thread.
+#[derive(Debug, Default)]
+pub(crate) struct ThreadInfo {
+    pub(crate) comm: String,
+    pub(crate) cgroup: String,
+}
+
+impl ThreadInfo {
+    /// Returns None if the thread is gone.
+    pub(crate) fn read(tgid: &str, tid: &str, comm: bool, cgroup: bool) -> Option<Self> {
+        let mut thread_info = Self::default();
+
+        if comm {
+            let comm = fs::read_to_string(format!("/proc/{}/task/{}/comm", tgid, tid)).ok()?;
+            thread_info.comm = comm.trim_end_matches('\n').to_string();
+        }
+
+        if cgroup {
+            let cgroup = fs::read_to_string(format!("/proc/{}/task/{}/cgroup", tgid, tid)).ok()?;
+            thread_info.cgroup = Self::parse_cgroup(&cgroup)?;
+        }
+
+        Some(thread_info)
+    }
+
+    /// Extracts the cgroup2 path from the content of /proc/PID/cgroup and
+    /// formats it the same way as the BPF side does for CgroupPrefix.
+    pub(crate) fn parse_cgroup(cgroup: &str) -> Option<String> {
+        let path = cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+        Some(match path.trim_start_matches('/') {
+            "" => "/".to_string(),
+            path => format!("{}/", path),
+        })
+    }
+}
+
+/// Fields of /proc/PID/task/TID/stat.
+#[derive(Debug, Default)]
+pub(crate) struct ThreadStat {
+    pub(crate) ppid: u32,
+    pub(crate) flags: u64,
+    pub(crate) nice: i32,
+}
+
+impl ThreadStat {
+    /// Returns None if the thread is gone.
+    pub(crate) fn read(tgid: &str, tid: &str) -> Option<Self> {
+        Self::parse(&fs::read_to_string(format!("/proc/{}/task/{}/stat", tgid, tid)).ok()?)
+    }
+
+    pub(crate) fn parse(stat: &str) -> Option<Self> {
+        // comm may contain spaces and parentheses, skip past the last ')'.
+        let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+        Some(Self {
+            ppid: fields.get(1)?.parse().ok()?,
+            flags: fields.get(6)?.parse().ok()?,
+            nice: fields.get(16)?.parse().ok()?,
+        })
+    }
+
+    pub(crate) fn is_kthread(&self) -> bool {
+        self.flags & PF_KTHREAD != 0
+    }
+}
+
+/// Layer matches which are too involved to be evaluated in BPF. They are
+/// resolved by periodically scanning /proc and passed to BPF as per-task
+/// bitmaps where bit N is set if the task satisfies the match with ID N.
+#[derive(Debug, Default)]
+pub struct UserMatches {
+    matches: Vec<LayerMatch>,
+    regexes: Vec<Option<Regex>>,
+}
+
+impl UserMatches {
+    /// Collects the user matches of @specs. Identical matches share the ID.
+    pub fn new(specs: &[LayerSpec]) -> Result<Self> {
+        let mut user_matches = Self::default();
+
+        for spec in specs.iter() {
+            for one in spec.matches.iter().flatten() {
+                if !one.is_user_match() || user_matches.matches.contains(one) {
+                    continue;
+                }
+
+                let regex = match one {
+                    LayerMatch::CommRegex(re) | LayerMatch::CgroupRegex(re) => Some(
+                        Regex::new(re)
+                            .with_context(|| format!("Spec {:?} has invalid regex", spec.name))?,
+                    ),
+                    _ => None,
+                };
+                user_matches.matches.push(one.clone());
+                user_matches.regexes.push(regex);
+            }
+        }
+
+        if user_matches.matches.len() > MAX_USER_MATCHES {
+            bail!(
+                "Too many ({}) distinct userspace matches, max {}",
+                user_matches.matches.len(),
+                MAX_USER_MATCHES
+            );
+        }
+
+        Ok(user_matches)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn matches(&self) -> &[LayerMatch] {
+        &self.matches
+    }
+
+    /// Returns the ID of @one.
+    pub fn id(&self, one: &LayerMatch) -> Option<usize> {
+        self.matches.iter().position(|mt| mt == one)
+    }
+
+    fn needs(&self, pred: fn(&LayerMatch) -> bool) -> bool {
+        self.matches.iter().any(pred)
+    }
+
+    fn read_proc(&self, tgid: &str) -> ProcInfo {
+        ProcInfo::read(
+            tgid,
+            self.needs(|mt| matches!(mt, LayerMatch::ExePath(_) | LayerMatch::ExePrefix(_))),
+            self.needs(|mt| matches!(mt, LayerMatch::EnvVarEquals(_, _))),
+        )
+    }
+
+    fn read_thread(&self, tgid: &str, tid: &str) -> Option<ThreadInfo> {
+        ThreadInfo::read(
+            tgid,
+            tid,
+            self.needs(|mt| matches!(mt, LayerMatch::CommRegex(_))),
+            self.needs(|mt| {
+                matches!(
+                    mt,
+                    LayerMatch::CgroupRegex(_)
+                        | LayerMatch::CgroupSuffix(_)
+                        | LayerMatch::SystemdUnit(_)
+                )
+            }),
+        )
+    }
+
+    /// Returns whether the task satisfies the match with ID @id.
+    pub(crate) fn is_match(
+        &self,
+        id: usize,
+        proc_info: &ProcInfo,
+        thread_info: &ThreadInfo,
+    ) -> bool {
+        let regex = &self.regexes[id];
+        match &self.matches[id] {
+            LayerMatch::CommRegex(_) => regex.as_ref().unwrap().is_match(&thread_info.comm),
+            LayerMatch::CgroupRegex(_) => regex.as_ref().unwrap().is_match(&thread_info.cgroup),
+            LayerMatch::CgroupSuffix(suffix) => thread_info.cgroup.ends_with(suffix.as_str()),
+            LayerMatch::ExePath(path) => proc_info.exe.as_deref() == Some(path.as_str()),
+            LayerMatch::ExePrefix(prefix) => proc_info
+                .exe
+                .as_deref()
+                .is_some_and(|exe| exe.starts_with(prefix.as_str())),
+            LayerMatch::EnvVarEquals(key, val) => {
+                proc_info.environ.contains(&format!("{}={}", key, val))
+            }
+            LayerMatch::SystemdUnit(unit) => thread_info.cgroup.split('/').any(|comp| comp == unit),
+            _ => false,
+        }
+    }
+
+    fn hints(&self, proc_info: &ProcInfo, thread_info: &ThreadInfo) -> u64 {
+        let mut hints = 0;
+
+        for id in 0..self.matches.len() {
+            if self.is_match(id, proc_info, thread_info) {
+                hints |= 1 << id;
+            }
+        }
+
+        hints
+    }
+
+    /// Scans all tasks in the system and returns the user match bitmaps
+    /// indexed by tid.
+    pub fn scan(&self) -> Result<BTreeMap<u32, u64>> {
+        let mut task_hints = BTreeMap::new();
+
+        for entry in fs::read_dir("/proc")? {
+            let tgid = entry?.file_name().to_string_lossy().into_owned();
+            if tgid.parse::<u32>().is_err() {
+                continue;
+            }
+
+            let tasks = match fs::read_dir(format!("/proc/{}/task", tgid)) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            let proc_info = self.read_proc(&tgid);
+
+            for task in tasks.flatten() {
+                let tid = task.file_name().to_string_lossy().into_owned();
+                let Ok(tid_nr) = tid.parse::<u32>() else {
+                    continue;
+                };
+                if let Some(thread_info) = self.read_thread(&tgid, &tid) {
+                    task_hints.insert(tid_nr, self.hints(&proc_info, &thread_info));
+                }
+            }
+        }
+
+        Ok(task_hints)
+    }
+}
+
+/// Result of a scan tagged with the generation of the user matches scanned.
+pub type UserMatchScan = (u64, Result<BTreeMap<u32, u64>>);
+
+/// Runs UserMatches::scan() in a separate thread so that walking /proc and
+/// reading the environment of every process doesn't stall the scheduler
+/// loop. The thread exits once the scanner is dropped.
+pub struct UserMatchScanner {
+    req_tx: Sender<(u64, Arc<UserMatches>)>,
+    res_rx: Receiver<UserMatchScan>,
+}
+
+impl UserMatchScanner {
+    pub fn new() -> Result<Self> {
+        let (req_tx, req_rx) = crossbeam::channel::unbounded::<(u64, Arc<UserMatches>)>();
+        let (res_tx, res_rx) = crossbeam::channel::unbounded();
+
+        thread::Builder::new()
+            .name("user_match_scan".into())
+            .spawn(move || {
+                for (gen, user_matches) in req_rx.iter() {
+                    if res_tx.send((gen, user_matches.scan())).is_err() {
+                        break;
+                    }
+                }
+            })
+            .context("Failed to spawn user match scanner")?;
+
+        Ok(Self { req_tx, res_rx })
+    }
+
+    /// Requests a scan of @user_matches. The result is tagged with @gen so
+    /// that scans of replaced user matches can be told apart.
+    pub fn request(&self, gen: u64, user_matches: Arc<UserMatches>) -> Result<()> {
+        self.req_tx
+            .send((gen, user_matches))
+            .context("User match scanner exited")
+    }
+
+    /// Returns the result of a finished scan, if any.
+    pub fn try_recv(&self) -> Option<UserMatchScan> {
+        self.res_rx.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_matches(matches: &str) -> UserMatches {
+        let specs = LayerSpec::parse(&format!(
+            r#"[
+                {{"name": "a", "matches": {}, "kind": {{"Open": {{}}}}}},
+                {{"name": "b", "matches": [[]], "kind": {{"Open": {{}}}}}}
+            ]"#,
+            matches
+        ))
+        .unwrap();
+        UserMatches::new(&specs).unwrap()
+    }
+
+    fn thread_info(comm: &str, cgroup: &str) -> ThreadInfo {
+        ThreadInfo {
+            comm: comm.to_string(),
+            cgroup: cgroup.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_thread_stat() {
+        let stat = ThreadStat::parse(
+            "4321 (a) (b c) S 1 4321 4321 0 -1 4194304 10 0 0 0 5 3 0 0 39 19 1 0 100\n",
+        )
+        .unwrap();
+        assert_eq!((stat.ppid, stat.flags, stat.nice), (1, 4194304, 19));
+        assert!(!stat.is_kthread());
+
+        let stat =
+            ThreadStat::parse("2 (kthreadd) S 0 0 0 0 -1 2129984 0 0 0 0 0 0 0 0 20 0 1 0 8\n")
+                .unwrap();
+        assert!(stat.is_kthread());
+
+        assert!(ThreadStat::parse("2 (kthreadd) S 0 0").is_none());
+        assert!(ThreadStat::parse("").is_none());
+    }
+
+    #[test]
+    fn test_ids() {
+        let um = user_matches(
+            r#"[[{"CommRegex": "^kworker"}, {"NiceAbove": 0}],
+                [{"CommRegex": "^kworker"}],
+                [{"SystemdUnit": "foo.service"}]]"#,
+        );
+        assert_eq!(um.len(), 2);
+        assert_eq!(um.id(&LayerMatch::CommRegex("^kworker".into())), Some(0));
+        assert_eq!(
+            um.id(&LayerMatch::SystemdUnit("foo.service".into())),
+            Some(1)
+        );
+        assert_eq!(um.id(&LayerMatch::NiceAbove(0)), None);
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let specs = LayerSpec::parse(
+            r#"[{"name": "a", "matches": [[{"CgroupRegex": "("}]], "kind": {"Open": {}}}]"#,
+        )
+        .unwrap();
+        assert!(UserMatches::new(&specs).is_err());
+    }
+
+    #[test]
+    fn test_regex_matches() {
+        let um = user_matches(
+            r#"[[{"CommRegex": "^kworker/\\d+"}], [{"CgroupRegex": "^system\\.slice/.*\\.service/$"}]]"#,
+        );
+        let proc_info = ProcInfo::default();
+        assert!(um.is_match(0, &proc_info, &thread_info("kworker/12:1", "/")));
+        assert!(!um.is_match(0, &proc_info, &thread_info("xkworker/1", "/")));
+        assert!(um.is_match(
+            1,
+            &proc_info,
+            &thread_info("sshd", "system.slice/sshd.service/")
+        ));
+        assert!(!um.is_match(
+            1,
+            &proc_info,
+            &thread_info("sshd", "user.slice/sshd.service/")
+        ));
+    }
+
+    #[test]
+    fn test_systemd_unit_matches() {
+        let um = user_matches(r#"[[{"SystemdUnit": "foo.service"}]]"#);
+        let proc_info = ProcInfo::default();
+        // any component of the path matches, the unit may have sub-cgroups
+        assert!(um.is_match(0, &proc_info, &thread_info("", "system.slice/foo.service/")));
+        assert!(um.is_match(
+            0,
+            &proc_info,
+            &thread_info("", "system.slice/foo.service/worker/")
+        ));
+        // but only whole components
+        assert!(!um.is_match(
+            0,
+            &proc_info,
+            &thread_info("", "system.slice/foo.service2/")
+        ));
+        assert!(!um.is_match(
+            0,
+            &proc_info,
+            &thread_info("", "system.slice/xfoo.service/")
+        ));
+    }
+
+    #[test]
+    fn test_cgroup_suffix_matches() {
+        let um = user_matches(r#"[[{"CgroupSuffix": "/workload/"}]]"#);
+        let proc_info = ProcInfo::default();
+        assert!(um.is_match(0, &proc_info, &thread_info("", "a/b/workload/")));
+        assert!(!um.is_match(0, &proc_info, &thread_info("", "a/b/workload/c/")));
+        assert!(!um.is_match(0, &proc_info, &thread_info("", "a/bworkload/")));
+    }
+
+    #[test]
+    fn test_proc_matches() {
+        let um = user_matches(
+            r#"[[{"ExePath": "/usr/bin/foo"}],
+                [{"ExePrefix": "/opt/"}],
+                [{"EnvVarEquals": ["ROLE", "batch"]}]]"#,
+        );
+        let thread_info = ThreadInfo::default();
+        let proc_info = ProcInfo {
+            exe: Some("/opt/foo/bin/foo".into()),
+            environ: vec!["HOME=/root".into(), "ROLE=batch".into()],
+        };
+        assert_eq!(um.hints(&proc_info, &thread_info), 0b110);
+        let proc_info = ProcInfo {
+            exe: Some("/usr/bin/foo".into()),
+            environ: vec!["ROLE=batch2".into()],
+        };
+        assert_eq!(um.hints(&proc_info, &thread_info), 0b001);
+    }
+
+    #[test]
+    fn test_scanner() {
+        let um = Arc::new(user_matches(r#"[[{"CommRegex": "."}]]"#));
+        let scanner = UserMatchScanner::new().unwrap();
+        scanner.request(3, um).unwrap();
+        let (gen, hints) = scanner.res_rx.recv().unwrap();
+        assert_eq!(gen, 3);
+        // the test itself matches
+        assert_eq!(hints.unwrap().get(&std::process::id()), Some(&1));
+    }
+}
@@ -0,0 +1,119 @@
+use scx_loader::{dbus::LoaderClientProxyBlocking, SchedMode, SupportedSched};
+use scx_utils::compat;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Serialize)]
+pub struct Status {
+    pub scheduler: Option<String>,
+    pub mode: Option<String>,
+    pub args: Vec<String>,
+    pub pid: Option<u32>,
+    pub uptime_secs: Option<u64>,
+    pub restarts: u32,
+    pub last_exit_reason: Option<String>,
+    pub sched_ext_enabled: bool,
+    pub sched_ext_ops: Option<String>,
+}
+
+/// Operations scxctl runs either through scx_loader or directly
+pub trait Backend {
+    /// Name of the running scheduler, "unknown" if none is running
+    fn current_scheduler(&self) -> Result<String>;
+    fn scheduler_mode(&self) -> Result<SchedMode>;
+    fn supported_schedulers(&self) -> Result<Vec<String>>;
+    fn start_scheduler(&self, sched: SupportedSched, mode: SchedMode) -> Result<()>;
+    fn start_scheduler_with_args(&self, sched: SupportedSched, args: &[String]) -> Result<()>;
+    fn switch_scheduler(&self, sched: SupportedSched, mode: SchedMode) -> Result<()>;
+    fn switch_scheduler_with_args(&self, sched: SupportedSched, args: &[String]) -> Result<()>;
+    fn stop_scheduler(&self) -> Result<()>;
+    fn status(&self) -> Result<Status>;
+    /// Lines of scheduler output following the line with the given sequence
+    /// number, along with the sequence number of the last line
+    fn scheduler_log_since(&self, seq: u64) -> Result<(u64, Vec<String>)>;
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Whether a sched_ext scheduler is loaded and its name
+pub fn sched_ext_state() -> (bool, Option<String>) {
+    (
+        compat::is_sched_ext_enabled().unwrap_or(false),
+        compat::read_sched_ext_ops().ok().flatten(),
+    )
+}
+
+impl Backend for LoaderClientProxyBlocking<'_> {
+    fn current_scheduler(&self) -> Result<String> {
+        Ok(LoaderClientProxyBlocking::current_scheduler(self)?)
+    }
+
+    fn scheduler_mode(&self) -> Result<SchedMode> {
+        Ok(LoaderClientProxyBlocking::scheduler_mode(self)?)
+    }
+
+    fn supported_schedulers(&self) -> Result<Vec<String>> {
+        Ok(LoaderClientProxyBlocking::supported_schedulers(self)?)
+    }
+
+    fn start_scheduler(&self, sched: SupportedSched, mode: SchedMode) -> Result<()> {
+        Ok(LoaderClientProxyBlocking::start_scheduler(
+            self, sched, mode,
+        )?)
+    }
+
+    fn start_scheduler_with_args(&self, sched: SupportedSched, args: &[String]) -> Result<()> {
+        Ok(LoaderClientProxyBlocking::start_scheduler_with_args(
+            self, sched, args,
+        )?)
+    }
+
+    fn switch_scheduler(&self, sched: SupportedSched, mode: SchedMode) -> Result<()> {
+        Ok(LoaderClientProxyBlocking::switch_scheduler(
+            self, sched, mode,
+        )?)
+    }
+
+    fn switch_scheduler_with_args(&self, sched: SupportedSched, args: &[String]) -> Result<()> {
+        Ok(LoaderClientProxyBlocking::switch_scheduler_with_args(
+            self, sched, args,
+        )?)
+    }
+
+    fn stop_scheduler(&self) -> Result<()> {
+        Ok(LoaderClientProxyBlocking::stop_scheduler(self)?)
+    }
+
+    fn status(&self) -> Result<Status> {
+        let current_scheduler = LoaderClientProxyBlocking::current_scheduler(self)?;
+        let running = current_scheduler != "unknown";
+        let mode: &str = LoaderClientProxyBlocking::scheduler_mode(self)?.into();
+        let pid = self.scheduler_pid()?;
+        let start_time = self.scheduler_start_time()?;
+        let last_exit_reason = self.last_exit_reason()?;
+        let (sched_ext_enabled, sched_ext_ops) = sched_ext_state();
+
+        Ok(Status {
+            scheduler: running.then_some(current_scheduler),
+            mode: running.then(|| mode.to_string()),
+            args: self.current_args()?,
+            pid: (pid != 0).then_some(pid),
+            uptime_secs: (start_time != 0).then(|| unix_now().saturating_sub(start_time)),
+            restarts: self.scheduler_restarts()?,
+            last_exit_reason: (!last_exit_reason.is_empty()).then_some(last_exit_reason),
+            sched_ext_enabled,
+            sched_ext_ops,
+        })
+    }
+
+    fn scheduler_log_since(&self, seq: u64) -> Result<(u64, Vec<String>)> {
+        Ok(self.get_scheduler_log_since(seq)?)
+    }
+}
@@ -1,13 +1,35 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use scx_loader::SchedMode;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    #[arg(
+        short,
+        long,
+        value_enum,
+        global = true,
+        default_value = "text",
+        help = "Output format"
+    )]
+    pub output: OutputFormat,
+    #[arg(
+        long,
+        global = true,
+        help = "Run the scheduler directly instead of through scx_loader"
+    )]
+    pub direct: bool,
     #[command(subcommand)]
     pub command: Commands,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Parser, Debug)]
 #[group(required = true)]
 pub struct StartArgs {
@@ -57,6 +79,50 @@ pub struct SwitchArgs {
     pub args: Option<Vec<String>>,
 }
 
+#[derive(Parser, Debug)]
+pub struct LogsArgs {
+    #[arg(short, long, help = "Keep printing new output")]
+    pub follow: bool,
+    #[arg(
+        short = 'n',
+        long,
+        default_value = "50",
+        help = "Number of lines to show, 0 for all"
+    )]
+    pub lines: u32,
+}
+
+#[derive(Parser, Debug)]
+pub struct StatsArgs {
+    #[arg(short, long, help = "Keep printing the statistics")]
+    pub watch: bool,
+    #[arg(
+        short,
+        long,
+        default_value = "1.0",
+        value_parser = parse_interval,
+        help = "Interval between statistics updates in seconds"
+    )]
+    pub interval: Duration,
+    #[arg(
+        long,
+        default_value = "/var/run/scx/root/stats",
+        help = "Path of the scheduler statistics socket"
+    )]
+    pub path: String,
+    #[arg(short, long, default_value = "top", help = "Statistics target")]
+    pub target: String,
+}
+
+/// Parses a positive number of seconds
+fn parse_interval(secs: &str) -> Result<Duration, String> {
+    let secs: f64 = secs.parse().map_err(|err| format!("{err}"))?;
+    if secs.is_nan() || secs <= 0.0 {
+        return Err("must be greater than 0".to_string());
+    }
+    Duration::try_from_secs_f64(secs).map_err(|err| format!("{err}"))
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     #[command(about = "Get the current scheduler and mode")]
@@ -75,4 +141,32 @@ pub enum Commands {
     },
     #[command(about = "Stop the current scheduler")]
     Stop,
+    #[command(about = "Show the state of the scheduler process")]
+    Status,
+    #[command(about = "Show the output of the scheduler")]
+    Logs {
+        #[clap(flatten)]
+        args: LogsArgs,
+    },
+    #[command(about = "Show the statistics of the running scheduler")]
+    Stats {
+        #[clap(flatten)]
+        args: StatsArgs,
+    },
+    #[command(about = "Print the scheduler events of scx_loader as they happen")]
+    Events,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("1.0"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_interval("0.25"), Ok(Duration::from_millis(250)));
+        for bad in ["0", "-1", "NaN", "inf", "1e30", "", "1s"] {
+            assert!(parse_interval(bad).is_err(), "{bad:?}");
+        }
+    }
 }
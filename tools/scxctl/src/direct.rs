@@ -0,0 +1,442 @@
+use crate::backend::{sched_ext_state, unix_now, Backend, Result, Status};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use scx_loader::{config, SchedMode, SupportedSched, SUPPORTED_SCHEDS};
+use scx_utils::compat;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const RUN_DIR: &str = "/run/scxctl";
+const PID_FILE: &str = "scheduler.pid";
+const STATE_FILE: &str = "scheduler.json";
+const LOG_FILE: &str = "scheduler.log";
+/// Output of the previous scheduler, kept for one launch
+const OLD_LOG_FILE: &str = "scheduler.log.1";
+/// The kernel truncates comm to TASK_COMM_LEN - 1 bytes
+const COMM_LEN: usize = 15;
+
+/// How long to wait for a started scheduler to be enabled
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for a stopped scheduler to exit
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Scheduler started by scxctl, stored next to the pidfile
+#[derive(Serialize, Deserialize)]
+struct DirectState {
+    scheduler: SupportedSched,
+    mode: SchedMode,
+    args: Vec<String>,
+    start_time: u64,
+}
+
+/// Runs the schedulers as detached processes without scx_loader. The running
+/// scheduler is tracked with a pidfile, so later invocations can manage it.
+pub struct DirectBackend {
+    run_dir: PathBuf,
+}
+
+impl DirectBackend {
+    pub fn new() -> Self {
+        Self::with_run_dir(PathBuf::from(RUN_DIR))
+    }
+
+    fn with_run_dir(run_dir: PathBuf) -> Self {
+        Self { run_dir }
+    }
+
+    fn path(&self, file_name: &str) -> PathBuf {
+        self.run_dir.join(file_name)
+    }
+
+    /// PID of the scheduler started by scxctl, if it is still running
+    fn running_pid(&self, state: &DirectState) -> Option<u32> {
+        let pid: u32 = fs::read_to_string(self.path(PID_FILE))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+
+        // make sure the pid wasn't reused by another process
+        let sched_bin_name: &str = state.scheduler.clone().into();
+        let comm = fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+        let zombie = stat
+            .rsplit_once(')')
+            .is_some_and(|(_, rest)| rest.trim_start().starts_with('Z'));
+        (comm_matches(&comm, sched_bin_name) && !zombie).then_some(pid)
+    }
+
+    /// State of the scheduler started by scxctl, if it is still running
+    fn running_state(&self) -> Option<(DirectState, u32)> {
+        let content = fs::read_to_string(self.path(STATE_FILE)).ok()?;
+        let state: DirectState = serde_json::from_str(&content).ok()?;
+        let pid = self.running_pid(&state)?;
+        Some((state, pid))
+    }
+
+    fn launch(&self, sched: SupportedSched, mode: SchedMode, args: Vec<String>) -> Result<()> {
+        if let Some((state, _)) = self.running_state() {
+            let sched_bin_name: &str = state.scheduler.into();
+            return Err(format!("{sched_bin_name} is already running").into());
+        }
+        if compat::is_sched_ext_enabled().unwrap_or(false) {
+            let ops = compat::read_sched_ext_ops().ok().flatten();
+            return Err(format!(
+                "another sched_ext scheduler is already running ({})",
+                ops.as_deref().unwrap_or("unknown")
+            )
+            .into());
+        }
+
+        let log = self.rotate_log()?;
+
+        let sched_bin_name: &str = sched.clone().into();
+        let mut child = Command::new(sched_bin_name)
+            .args(&args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            // detach from the terminal, the scheduler outlives scxctl
+            .process_group(0)
+            .spawn()
+            .map_err(|err| format!("failed to spawn {sched_bin_name}: {err}"))?;
+
+        fs::write(self.path(PID_FILE), format!("{}\n", child.id()))?;
+        let state = DirectState {
+            scheduler: sched,
+            mode,
+            args,
+            start_time: unix_now(),
+        };
+        fs::write(self.path(STATE_FILE), serde_json::to_string(&state)?)?;
+
+        let started = Instant::now();
+        while started.elapsed() < START_TIMEOUT {
+            if let Some(status) = child.try_wait()? {
+                self.cleanup();
+                let tail = self.log_tail()?;
+                return Err(format!("{sched_bin_name} exited early ({status})\n{tail}").into());
+            }
+            if compat::is_sched_ext_enabled().unwrap_or(false) {
+                return Ok(());
+            }
+            sleep(POLL_INTERVAL);
+        }
+
+        // don't leave behind a scheduler which never attached
+        let _ = kill(Pid::from_raw(child.id() as i32), Signal::SIGINT);
+        let status = match wait_for_child(&mut child)? {
+            Some(status) => status,
+            None => {
+                child.kill()?;
+                child.wait()?
+            }
+        };
+        self.cleanup();
+        let tail = self.log_tail()?;
+        Err(format!(
+            "{sched_bin_name} didn't attach within {}s and was stopped ({status})\n{tail}",
+            START_TIMEOUT.as_secs()
+        )
+        .into())
+    }
+
+    /// Start a new log for a launch, keeping only the previous one, so the
+    /// logs don't pile up across launches
+    fn rotate_log(&self) -> Result<File> {
+        fs::create_dir_all(&self.run_dir)?;
+        match fs::rename(self.path(LOG_FILE), self.path(OLD_LOG_FILE)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        Ok(File::create(self.path(LOG_FILE))?)
+    }
+
+    /// Last lines of the scheduler output
+    fn log_tail(&self) -> Result<String> {
+        let (_, lines) = self.scheduler_log_since(0)?;
+        Ok(lines[lines.len().saturating_sub(10)..].join("\n"))
+    }
+
+    fn cleanup(&self) {
+        let _ = fs::remove_file(self.path(PID_FILE));
+        let _ = fs::remove_file(self.path(STATE_FILE));
+    }
+
+    fn launch_mode(&self, sched: SupportedSched, mode: SchedMode) -> Result<()> {
+        let config = config::init_config()?;
+        let args = config::get_scx_flags_for_mode(&config, &sched, mode.clone());
+        self.launch(sched, mode, args)
+    }
+}
+
+/// Whether a /proc comm is that of the given binary
+fn comm_matches(comm: &str, bin_name: &str) -> bool {
+    let comm = comm.strip_suffix('\n').unwrap_or(comm);
+    let bin_name = bin_name.as_bytes();
+    comm.as_bytes() == &bin_name[..bin_name.len().min(COMM_LEN)]
+}
+
+/// Reap the child, None if it didn't exit in time
+fn wait_for_child(child: &mut Child) -> Result<Option<ExitStatus>> {
+    let started = Instant::now();
+    while started.elapsed() < STOP_TIMEOUT {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        sleep(POLL_INTERVAL);
+    }
+    Ok(None)
+}
+
+fn wait_for_exit(pid: u32) -> bool {
+    let started = Instant::now();
+    while started.elapsed() < STOP_TIMEOUT {
+        if !Path::new(&format!("/proc/{pid}")).exists() {
+            return true;
+        }
+        sleep(POLL_INTERVAL);
+    }
+    false
+}
+
+impl Backend for DirectBackend {
+    fn current_scheduler(&self) -> Result<String> {
+        Ok(match self.running_state() {
+            Some((state, _)) => <&str>::from(state.scheduler).to_string(),
+            None => "unknown".to_string(),
+        })
+    }
+
+    fn scheduler_mode(&self) -> Result<SchedMode> {
+        Ok(self
+            .running_state()
+            .map_or(SchedMode::Auto, |(state, _)| state.mode))
+    }
+
+    fn supported_schedulers(&self) -> Result<Vec<String>> {
+        Ok(SUPPORTED_SCHEDS
+            .iter()
+            .map(|sched| <&str>::from(sched.clone()).to_string())
+            .collect())
+    }
+
+    fn start_scheduler(&self, sched: SupportedSched, mode: SchedMode) -> Result<()> {
+        self.launch_mode(sched, mode)
+    }
+
+    fn start_scheduler_with_args(&self, sched: SupportedSched, args: &[String]) -> Result<()> {
+        // like scx_loader, custom arguments reset the mode to auto
+        self.launch(sched, SchedMode::Auto, args.to_vec())
+    }
+
+    fn switch_scheduler(&self, sched: SupportedSched, mode: SchedMode) -> Result<()> {
+        self.stop_scheduler()?;
+        self.launch_mode(sched, mode)
+    }
+
+    fn switch_scheduler_with_args(&self, sched: SupportedSched, args: &[String]) -> Result<()> {
+        self.stop_scheduler()?;
+        self.launch(sched, SchedMode::Auto, args.to_vec())
+    }
+
+    fn stop_scheduler(&self) -> Result<()> {
+        let Some((state, pid)) = self.running_state() else {
+            self.cleanup();
+            // a scheduler not started by scxctl can't be stopped from here
+            if let (true, ops) = sched_ext_state() {
+                return Err(format!(
+                    "another sched_ext scheduler is running ({})",
+                    ops.as_deref().unwrap_or("unknown")
+                )
+                .into());
+            }
+            return Ok(());
+        };
+
+        // schedulers exit cleanly on SIGINT
+        kill(Pid::from_raw(pid as i32), Signal::SIGINT)?;
+        if !wait_for_exit(pid) {
+            let sched_bin_name: &str = state.scheduler.into();
+            return Err(format!("{sched_bin_name} (pid {pid}) didn't exit in time").into());
+        }
+        self.cleanup();
+        Ok(())
+    }
+
+    fn status(&self) -> Result<Status> {
+        let running = self.running_state();
+        let (sched_ext_enabled, sched_ext_ops) = sched_ext_state();
+
+        Ok(match running {
+            Some((state, pid)) => Status {
+                scheduler: Some(<&str>::from(state.scheduler).to_string()),
+                mode: Some(<&str>::from(state.mode).to_string()),
+                args: state.args,
+                pid: Some(pid),
+                uptime_secs: Some(unix_now().saturating_sub(state.start_time)),
+                restarts: 0,
+                last_exit_reason: None,
+                sched_ext_enabled,
+                sched_ext_ops,
+            },
+            None => Status {
+                scheduler: None,
+                mode: None,
+                args: vec![],
+                pid: None,
+                uptime_secs: None,
+                restarts: 0,
+                last_exit_reason: None,
+                sched_ext_enabled,
+                sched_ext_ops,
+            },
+        })
+    }
+
+    fn scheduler_log_since(&self, seq: u64) -> Result<(u64, Vec<String>)> {
+        let content = match fs::read_to_string(self.path(LOG_FILE)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let lines: Vec<String> = content.lines().map(String::from).collect();
+        let last_seq = lines.len() as u64;
+        // the log is truncated when a scheduler is started, then start over
+        let skip = if seq > last_seq { 0 } else { seq as usize };
+        Ok((last_seq, lines[skip..].to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(name: &str) -> DirectBackend {
+        let dir = std::env::temp_dir().join(format!("scxctl_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        DirectBackend::with_run_dir(dir)
+    }
+
+    fn write_state(backend: &DirectBackend, sched: SupportedSched, pid: u32) {
+        let state = DirectState {
+            scheduler: sched,
+            mode: SchedMode::Gaming,
+            args: vec!["--foo".into()],
+            start_time: unix_now(),
+        };
+        fs::write(backend.path(PID_FILE), format!("{pid}\n")).unwrap();
+        fs::write(
+            backend.path(STATE_FILE),
+            serde_json::to_string(&state).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_comm_matches() {
+        assert!(comm_matches("scx_rusty\n", "scx_rusty"));
+        assert!(!comm_matches("scx_rust\n", "scx_rusty"));
+        assert!(!comm_matches("scx_rusty_x\n", "scx_rusty"));
+        assert!(!comm_matches("\n", "scx_rusty"));
+        // comm is truncated to 15 bytes
+        assert!(comm_matches("scx_long_schedu\n", "scx_long_scheduler"));
+        assert!(!comm_matches("scx_long_sched\n", "scx_long_scheduler"));
+    }
+
+    #[test]
+    fn test_stale_state() {
+        let backend = backend("stale");
+        assert!(backend.running_state().is_none());
+        assert_eq!(backend.current_scheduler().unwrap(), "unknown");
+
+        // the pid is gone
+        write_state(&backend, SupportedSched::Rusty, u32::MAX);
+        assert!(backend.running_state().is_none());
+
+        // the pid was reused by another process
+        write_state(&backend, SupportedSched::Rusty, std::process::id());
+        assert!(backend.running_state().is_none());
+        assert_eq!(backend.scheduler_mode().unwrap(), SchedMode::Auto);
+
+        // unreadable state
+        fs::write(backend.path(STATE_FILE), "{").unwrap();
+        assert!(backend.running_state().is_none());
+
+        fs::remove_dir_all(&backend.run_dir).unwrap();
+    }
+
+    #[test]
+    fn test_running_state() {
+        let backend = backend("running");
+
+        // a process named like the scheduler
+        let bin = backend.path("scx_rusty");
+        fs::copy("/bin/sleep", &bin).unwrap();
+        let mut child = Command::new(&bin).arg("10").spawn().unwrap();
+        write_state(&backend, SupportedSched::Rusty, child.id());
+
+        let (state, pid) = backend.running_state().unwrap();
+        assert_eq!(pid, child.id());
+        assert_eq!(state.args, vec!["--foo".to_string()]);
+        assert_eq!(backend.current_scheduler().unwrap(), "scx_rusty");
+        assert_eq!(backend.scheduler_mode().unwrap(), SchedMode::Gaming);
+
+        // recorded as another scheduler
+        write_state(&backend, SupportedSched::Lavd, child.id());
+        assert!(backend.running_state().is_none());
+        write_state(&backend, SupportedSched::Rusty, child.id());
+
+        // an exited but not yet reaped scheduler isn't running
+        child.kill().unwrap();
+        let started = Instant::now();
+        while backend.running_state().is_some() {
+            assert!(started.elapsed() < STOP_TIMEOUT);
+            sleep(POLL_INTERVAL);
+        }
+        child.wait().unwrap();
+        assert!(backend.running_state().is_none());
+
+        fs::remove_dir_all(&backend.run_dir).unwrap();
+    }
+
+    #[test]
+    fn test_scheduler_log_since() {
+        let backend = backend("log");
+        assert_eq!(backend.scheduler_log_since(0).unwrap(), (0, vec![]));
+
+        fs::write(backend.path(LOG_FILE), "a\nb\nc\n").unwrap();
+        assert_eq!(
+            backend.scheduler_log_since(0).unwrap(),
+            (3, vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(
+            backend.scheduler_log_since(2).unwrap(),
+            (3, vec!["c".into()])
+        );
+        assert_eq!(backend.scheduler_log_since(3).unwrap(), (3, vec![]));
+
+        // the log was truncated by a new launch
+        let log = backend.rotate_log().unwrap();
+        assert_eq!(log.metadata().unwrap().len(), 0);
+        assert_eq!(
+            fs::read_to_string(backend.path(OLD_LOG_FILE)).unwrap(),
+            "a\nb\nc\n"
+        );
+        fs::write(backend.path(LOG_FILE), "d\n").unwrap();
+        assert_eq!(
+            backend.scheduler_log_since(3).unwrap(),
+            (1, vec!["d".into()])
+        );
+        assert_eq!(backend.log_tail().unwrap(), "d");
+
+        fs::remove_dir_all(&backend.run_dir).unwrap();
+    }
+}
@@ -1,15 +1,38 @@
+mod backend;
 mod cli;
+mod direct;
 
+use backend::Backend;
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, LogsArgs, OutputFormat, StatsArgs};
 use colored::Colorize;
+use direct::DirectBackend;
 use scx_loader::{dbus::LoaderClientProxyBlocking, SchedMode, SupportedSched};
+use scx_stats::prelude::StatsClient;
+use serde::Serialize;
+use serde_json::{json, Value};
 use std::process::exit;
-use zbus::blocking::Connection;
+use std::thread::sleep;
+use std::time::Duration;
+use zbus::blocking::{Connection, MessageIterator};
+use zbus::message::Type as MessageType;
+use zbus::MatchRule;
+
+const LOG_FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+fn cmd_get(backend: &dyn Backend, output: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let current_scheduler: String = backend.current_scheduler().unwrap();
+    let sched_mode: SchedMode = backend.scheduler_mode().unwrap();
+    let running = current_scheduler != "unknown";
+    if output == OutputFormat::Json {
+        let mode: &str = sched_mode.into();
+        print_json(&json!({
+            "scheduler": running.then_some(current_scheduler),
+            "mode": running.then_some(mode),
+        }));
+        return Ok(());
+    }
 
-fn cmd_get(scx_loader: LoaderClientProxyBlocking) -> Result<(), Box<dyn std::error::Error>> {
-    let current_scheduler: String = scx_loader.current_scheduler().unwrap();
-    let sched_mode: SchedMode = scx_loader.scheduler_mode().unwrap();
     match current_scheduler.as_str() {
         "unknown" => println!("no scx scheduler running"),
         _ => {
@@ -20,25 +43,29 @@ fn cmd_get(scx_loader: LoaderClientProxyBlocking) -> Result<(), Box<dyn std::err
     Ok(())
 }
 
-fn cmd_list(scx_loader: LoaderClientProxyBlocking) -> Result<(), Box<dyn std::error::Error>> {
-    let supported_scheds: Vec<String> = scx_loader
-        .supported_schedulers()
-        .unwrap()
+fn cmd_list(backend: &dyn Backend, output: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let supported_scheds: Vec<String> = backend
+        .supported_schedulers()?
         .iter()
-        .map(|s| remove_scx_prefix(&s.to_string()))
+        .map(String::as_str)
+        .map(remove_scx_prefix)
         .collect();
-    println!("supported schedulers: {:?}", supported_scheds);
+    match output {
+        OutputFormat::Json => print_json(&json!({ "schedulers": supported_scheds })),
+        OutputFormat::Text => println!("supported schedulers: {:?}", supported_scheds),
+    }
     Ok(())
 }
 
 fn cmd_start(
-    scx_loader: LoaderClientProxyBlocking,
+    backend: &dyn Backend,
+    output: OutputFormat,
     sched_name: String,
     mode_name: Option<SchedMode>,
     args: Option<Vec<String>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Verify scx_loader is not running a scheduler
-    if scx_loader.current_scheduler().unwrap() != "unknown" {
+    if backend.current_scheduler().unwrap() != "unknown" {
         println!(
             "{} scx scheduler already running, use '{}' instead of '{}'",
             "error:".red().bold(),
@@ -49,29 +76,38 @@ fn cmd_start(
         exit(1);
     }
 
-    let sched: SupportedSched = validate_sched(scx_loader.clone(), sched_name);
-    let mode: SchedMode = mode_name.unwrap_or_else(|| SchedMode::Auto);
+    let sched: SupportedSched = validate_sched(backend, sched_name);
+    let mode: SchedMode = mode_name.unwrap_or(SchedMode::Auto);
     match args {
         Some(args) => {
-            scx_loader.start_scheduler_with_args(sched.clone(), &args.clone())?;
-            println!("started {sched:?} with arguments \"{}\"", args.join(" "));
+            backend.start_scheduler_with_args(sched.clone(), &args.clone())?;
+            match output {
+                OutputFormat::Json => print_sched_json("started", sched, None, Some(&args)),
+                OutputFormat::Text => {
+                    println!("started {sched:?} with arguments \"{}\"", args.join(" "))
+                }
+            }
         }
         None => {
-            scx_loader.start_scheduler(sched.clone(), mode.clone())?;
-            println!("started {sched:?} in {mode:?} mode");
+            backend.start_scheduler(sched.clone(), mode.clone())?;
+            match output {
+                OutputFormat::Json => print_sched_json("started", sched, Some(mode), None),
+                OutputFormat::Text => println!("started {sched:?} in {mode:?} mode"),
+            }
         }
     }
     Ok(())
 }
 
 fn cmd_switch(
-    scx_loader: LoaderClientProxyBlocking,
+    backend: &dyn Backend,
+    output: OutputFormat,
     sched_name: Option<String>,
     mode_name: Option<SchedMode>,
     args: Option<Vec<String>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Verify scx_loader is running a scheduler
-    if scx_loader.current_scheduler().unwrap() == "unknown" {
+    if backend.current_scheduler().unwrap() == "unknown" {
         println!(
             "{} no scx scheduler running, use '{}' instead of '{}'",
             "error:".red().bold(),
@@ -83,46 +119,236 @@ fn cmd_switch(
     }
 
     let sched: SupportedSched = match sched_name {
-        Some(sched_name) => validate_sched(scx_loader.clone(), sched_name),
-        None => SupportedSched::try_from(scx_loader.current_scheduler().unwrap().as_str()).unwrap(),
+        Some(sched_name) => validate_sched(backend, sched_name),
+        None => SupportedSched::try_from(backend.current_scheduler().unwrap().as_str()).unwrap(),
     };
     let mode: SchedMode = match mode_name {
         Some(mode_name) => mode_name,
-        None => scx_loader.scheduler_mode().unwrap(),
+        None => backend.scheduler_mode().unwrap(),
     };
     match args {
         Some(args) => {
-            scx_loader.switch_scheduler_with_args(sched.clone(), &args.clone())?;
-            println!(
-                "switched to {sched:?} with arguments \"{}\"",
-                args.join(" ")
-            );
+            backend.switch_scheduler_with_args(sched.clone(), &args.clone())?;
+            match output {
+                OutputFormat::Json => print_sched_json("switched", sched, None, Some(&args)),
+                OutputFormat::Text => println!(
+                    "switched to {sched:?} with arguments \"{}\"",
+                    args.join(" ")
+                ),
+            }
         }
         None => {
-            scx_loader.switch_scheduler(sched.clone(), mode.clone())?;
-            println!("switched to {sched:?} in {mode:?} mode");
+            backend.switch_scheduler(sched.clone(), mode.clone())?;
+            match output {
+                OutputFormat::Json => print_sched_json("switched", sched, Some(mode), None),
+                OutputFormat::Text => println!("switched to {sched:?} in {mode:?} mode"),
+            }
         }
     }
     Ok(())
 }
 
-fn cmd_stop(scx_loader: LoaderClientProxyBlocking) -> Result<(), Box<dyn std::error::Error>> {
-    scx_loader.stop_scheduler()?;
-    println!("stopped");
+fn cmd_stop(backend: &dyn Backend, output: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    backend.stop_scheduler()?;
+    match output {
+        OutputFormat::Json => print_json(&json!({ "action": "stopped" })),
+        OutputFormat::Text => println!("stopped"),
+    }
+    Ok(())
+}
+
+fn cmd_status(
+    backend: &dyn Backend,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let status = backend.status()?;
+
+    if output == OutputFormat::Json {
+        print_json(&status);
+        return Ok(());
+    }
+
+    let none = || "-".to_string();
+    println!(
+        "{:<11}{}",
+        "scheduler:".bold(),
+        status.scheduler.clone().unwrap_or_else(none)
+    );
+    println!("{:<11}{}", "mode:".bold(), status.mode.unwrap_or_else(none));
+    println!("{:<11}{}", "args:".bold(), status.args.join(" "));
+    println!(
+        "{:<11}{}",
+        "pid:".bold(),
+        status.pid.map_or_else(none, |pid| pid.to_string())
+    );
+    println!(
+        "{:<11}{}",
+        "uptime:".bold(),
+        status.uptime_secs.map_or_else(none, format_duration)
+    );
+    println!("{:<11}{}", "restarts:".bold(), status.restarts);
+    println!(
+        "{:<11}{}",
+        "last exit:".bold(),
+        status.last_exit_reason.unwrap_or_else(none)
+    );
+    println!(
+        "{:<11}{}",
+        "sched_ext:".bold(),
+        match (status.sched_ext_enabled, status.sched_ext_ops) {
+            (true, Some(ops)) => format!("enabled ({ops})"),
+            (true, None) => "enabled".to_string(),
+            (false, _) => "disabled".to_string(),
+        }
+    );
+    Ok(())
+}
+
+fn cmd_logs(
+    backend: &dyn Backend,
+    output: OutputFormat,
+    args: LogsArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let print_lines = |lines: &[String]| {
+        for line in lines {
+            match output {
+                OutputFormat::Json => print_json(&json!({ "line": line })),
+                OutputFormat::Text => println!("{line}"),
+            }
+        }
+    };
+
+    let (mut seq, lines) = backend.scheduler_log_since(0)?;
+    let skip = match args.lines {
+        0 => 0,
+        count => lines.len().saturating_sub(count as usize),
+    };
+    print_lines(&lines[skip..]);
+
+    if !args.follow {
+        return Ok(());
+    }
+    loop {
+        sleep(LOG_FOLLOW_INTERVAL);
+        let (last_seq, lines) = backend.scheduler_log_since(seq)?;
+        print_lines(&lines);
+        seq = last_seq;
+    }
+}
+
+fn cmd_stats(output: OutputFormat, args: StatsArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let json_output = output == OutputFormat::Json;
+    let mut client = StatsClient::new()
+        .set_path(&args.path)
+        .connect()
+        .map_err(|err| format!("failed to connect to {}: {err}", args.path))?;
+
+    loop {
+        let stats: Value = client.request("stats", vec![("target".into(), args.target.clone())])?;
+        if json_output {
+            print_json(&stats);
+        } else {
+            print_stats(&stats, 0);
+        }
+
+        if !args.watch {
+            break;
+        }
+        if !json_output {
+            println!();
+        }
+        sleep(args.interval);
+    }
+    Ok(())
+}
+
+fn cmd_events(conn: &Connection, output: OutputFormat) -> Result<(), Box<dyn std::error::Error>> {
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender("org.scx.Loader")?
+        .interface("org.scx.Loader")?
+        .path("/org/scx/Loader")?
+        .build();
+
+    for msg in MessageIterator::for_match_rule(rule, conn, None)? {
+        let msg = msg?;
+        let header = msg.header();
+        let Some(member) = header.member() else {
+            continue;
+        };
+
+        let event = match member.as_str() {
+            "SchedulerStarted" => {
+                let (scx_name, pid): (String, u32) = msg.body().deserialize()?;
+                json!({ "event": "started", "scheduler": scx_name, "pid": pid })
+            }
+            "SchedulerExited" => {
+                let (scx_name, reason): (String, String) = msg.body().deserialize()?;
+                json!({ "event": "exited", "scheduler": scx_name, "reason": reason })
+            }
+            _ => continue,
+        };
+
+        match output {
+            OutputFormat::Json => print_json(&event),
+            OutputFormat::Text => match event["event"].as_str() {
+                Some("started") => println!(
+                    "{} {} (pid {})",
+                    "started".green().bold(),
+                    event["scheduler"].as_str().unwrap_or_default(),
+                    event["pid"]
+                ),
+                _ => println!(
+                    "{} {}: {}",
+                    "exited".red().bold(),
+                    event["scheduler"].as_str().unwrap_or_default(),
+                    event["reason"].as_str().unwrap_or_default()
+                ),
+            },
+        }
+    }
     Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
+
+    // stats are served by the scheduler itself, scx_loader isn't needed
+    if let Commands::Stats { args } = cli.command {
+        return cmd_stats(cli.output, args);
+    }
+
+    if cli.direct {
+        if let Commands::Events = cli.command {
+            return Err(
+                "events are only sent by scx_loader, they aren't available with --direct".into(),
+            );
+        }
+        return run_command(&DirectBackend::new(), cli);
+    }
+
     let conn = Connection::system()?;
+    if let Commands::Events = cli.command {
+        return cmd_events(&conn, cli.output);
+    }
     let scx_loader = LoaderClientProxyBlocking::new(&conn)?;
+    run_command(&scx_loader, cli)
+}
 
+fn run_command(backend: &dyn Backend, cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
     match cli.command {
-        Commands::Get => cmd_get(scx_loader)?,
-        Commands::List => cmd_list(scx_loader)?,
-        Commands::Start { args } => cmd_start(scx_loader, args.sched, args.mode, args.args)?,
-        Commands::Switch { args } => cmd_switch(scx_loader, args.sched, args.mode, args.args)?,
-        Commands::Stop => cmd_stop(scx_loader)?,
+        Commands::Get => cmd_get(backend, cli.output)?,
+        Commands::List => cmd_list(backend, cli.output)?,
+        Commands::Start { args } => {
+            cmd_start(backend, cli.output, args.sched, args.mode, args.args)?
+        }
+        Commands::Switch { args } => {
+            cmd_switch(backend, cli.output, args.sched, args.mode, args.args)?
+        }
+        Commands::Stop => cmd_stop(backend, cli.output)?,
+        Commands::Status => cmd_status(backend, cli.output)?,
+        Commands::Logs { args } => cmd_logs(backend, cli.output, args)?,
+        Commands::Stats { .. } | Commands::Events => unreachable!(),
     }
 
     Ok(())
@@ -141,18 +367,19 @@ fn ensure_scx_prefix(input: String) -> String {
     input
 }
 
-fn remove_scx_prefix(input: &String) -> String {
-    if input.starts_with(SCHED_PREFIX) {
-        return input[SCHED_PREFIX.len()..].to_string();
-    }
-    input.to_string()
+fn remove_scx_prefix(input: &str) -> String {
+    input
+        .strip_prefix(SCHED_PREFIX)
+        .unwrap_or(input)
+        .to_string()
 }
 
-fn validate_sched(scx_loader: LoaderClientProxyBlocking, sched: String) -> SupportedSched {
-    let raw_supported_scheds: Vec<String> = scx_loader.supported_schedulers().unwrap();
+fn validate_sched(backend: &dyn Backend, sched: String) -> SupportedSched {
+    let raw_supported_scheds: Vec<String> = backend.supported_schedulers().unwrap();
     let supported_scheds: Vec<String> = raw_supported_scheds
         .iter()
-        .map(|s| remove_scx_prefix(s))
+        .map(String::as_str)
+        .map(remove_scx_prefix)
         .collect();
     if !supported_scheds.contains(&sched) && !raw_supported_scheds.contains(&sched) {
         println!(
@@ -168,3 +395,56 @@ fn validate_sched(scx_loader: LoaderClientProxyBlocking, sched: String) -> Suppo
 
     SupportedSched::try_from(ensure_scx_prefix(sched).as_str()).unwrap()
 }
+
+fn print_json<T: Serialize>(value: &T) {
+    println!("{}", serde_json::to_string(value).unwrap());
+}
+
+fn print_sched_json(
+    action: &str,
+    sched: SupportedSched,
+    mode: Option<SchedMode>,
+    args: Option<&Vec<String>>,
+) {
+    let sched: &str = sched.into();
+    let mode: Option<&str> = mode.map(|mode| mode.into());
+    print_json(&json!({
+        "action": action,
+        "scheduler": sched,
+        "mode": mode,
+        "args": args,
+    }));
+}
+
+fn format_duration(secs: u64) -> String {
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, _) => format!("{mins}m {secs}s"),
+        (0, _, _) => format!("{hours}h {mins}m {secs}s"),
+        _ => format!("{days}d {hours}h {mins}m {secs}s"),
+    }
+}
+
+fn print_stats(value: &Value, indent: usize) {
+    let Value::Object(fields) = value else {
+        println!("{:indent$}{value}", "");
+        return;
+    };
+
+    for (key, value) in fields {
+        match value {
+            Value::Object(_) => {
+                println!("{:indent$}{}:", "", key.bold());
+                print_stats(value, indent + 2);
+            }
+            Value::Array(items) if items.iter().any(Value::is_object) => {
+                println!("{:indent$}{}:", "", key.bold());
+                for item in items {
+                    print_stats(item, indent + 2);
+                }
+            }
+            _ => println!("{:indent$}{}: {value}", "", key.bold()),
+        }
+    }
+}
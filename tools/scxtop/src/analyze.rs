@@ -0,0 +1,597 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use crate::protos_gen::perfetto_scx::ftrace_event::Event as FtraceEventType;
+use crate::protos_gen::perfetto_scx::trace_packet::Data as TracePacketData;
+use crate::protos_gen::perfetto_scx::{FtraceEvent, Trace};
+use crate::StatAggregation;
+use crate::VecStats;
+
+use anyhow::Result;
+use protobuf::Message;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+
+/// Returns the name of a softirq vector.
+fn softirq_name(vec: u32) -> String {
+    match vec {
+        0 => "HI".to_string(),
+        1 => "TIMER".to_string(),
+        2 => "NET_TX".to_string(),
+        3 => "NET_RX".to_string(),
+        4 => "BLOCK".to_string(),
+        5 => "IRQ_POLL".to_string(),
+        6 => "TASKLET".to_string(),
+        7 => "SCHED".to_string(),
+        8 => "HRTIMER".to_string(),
+        9 => "RCU".to_string(),
+        _ => format!("{}", vec),
+    }
+}
+
+/// Returns the p50, p90, p99 and max of a set of values.
+fn percentiles(vals: &[u64]) -> (u64, u64, u64, u64) {
+    let stats = VecStats::new(
+        &vals.to_vec(),
+        false,
+        true,
+        false,
+        Some(HashSet::from([
+            StatAggregation::P50,
+            StatAggregation::P90,
+            StatAggregation::P99,
+        ])),
+    );
+    let percentiles = stats.percentiles.unwrap_or_default();
+    let get = |agg| percentiles.get(&agg).copied().unwrap_or(0);
+    (
+        get(StatAggregation::P50),
+        get(StatAggregation::P90),
+        get(StatAggregation::P99),
+        stats.max,
+    )
+}
+
+/// Returns a short description of a ftrace event.
+fn describe_event(event: &FtraceEvent) -> String {
+    match &event.event {
+        Some(FtraceEventType::SchedSwitch(e)) => format!(
+            "sched_switch {}({}) -> {}({})",
+            e.prev_comm(),
+            e.prev_pid(),
+            e.next_comm(),
+            e.next_pid()
+        ),
+        Some(FtraceEventType::SchedWaking(e)) => format!(
+            "sched_waking {}({}) target_cpu={}",
+            e.comm(),
+            e.pid(),
+            e.target_cpu()
+        ),
+        Some(FtraceEventType::SchedWakeup(e)) => format!(
+            "sched_wakeup {}({}) target_cpu={}",
+            e.comm(),
+            e.pid(),
+            e.target_cpu()
+        ),
+        Some(FtraceEventType::SoftirqEntry(e)) => {
+            format!("softirq_entry {}", softirq_name(e.vec()))
+        }
+        Some(FtraceEventType::SoftirqExit(e)) => format!("softirq_exit {}", softirq_name(e.vec())),
+        Some(FtraceEventType::IpiRaise(e)) => format!("ipi_raise target_cpu={}", e.target_cpus()),
+        Some(FtraceEventType::CpuhpEnter(e)) => {
+            format!("cpuhp_enter cpu={} target={}", e.cpu(), e.target())
+        }
+        Some(FtraceEventType::GpuMemTotal(e)) => {
+            format!("gpu_mem_total gpu={} size={}", e.gpu_id(), e.size())
+        }
+        Some(FtraceEventType::IrqHandlerEntry(e)) => {
+            format!("irq_handler_entry irq={} name={}", e.irq(), e.name())
+        }
+        Some(FtraceEventType::IrqHandlerExit(e)) => format!("irq_handler_exit irq={}", e.irq()),
+        Some(FtraceEventType::SchedMigrateTask(e)) => format!(
+            "sched_migrate_task {}({}) {} -> {}",
+            e.comm(),
+            e.pid(),
+            e.orig_cpu(),
+            e.dest_cpu()
+        ),
+        Some(FtraceEventType::SchedProcessFork(e)) => format!(
+            "sched_process_fork {}({}) -> {}({})",
+            e.parent_comm(),
+            e.parent_pid(),
+            e.child_comm(),
+            e.child_pid()
+        ),
+        Some(FtraceEventType::SchedProcessExec(e)) => {
+            format!("sched_process_exec {}({})", e.filename(), e.pid())
+        }
+        Some(FtraceEventType::SchedProcessExit(e)) => {
+            format!("sched_process_exit {}({})", e.comm(), e.pid())
+        }
+        Some(FtraceEventType::CpuIdle(e)) => format!("cpu_idle state={}", e.state() as i32),
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Per CPU scheduling stats of a trace.
+#[derive(Clone, Debug, Default)]
+pub struct CpuStats {
+    pub busy_ns: u64,
+    pub switches: u64,
+    last_switch_ts: Option<u64>,
+    running: bool,
+}
+
+/// Time from a task becoming runnable until it was switched in.
+#[derive(Clone, Debug)]
+pub struct SchedDelay {
+    pub pid: i32,
+    pub comm: String,
+    pub cpu: u32,
+    pub waking_ts: u64,
+    pub run_ts: u64,
+}
+
+impl SchedDelay {
+    /// Returns the delay in ns.
+    pub fn delay_ns(&self) -> u64 {
+        self.run_ts.saturating_sub(self.waking_ts)
+    }
+}
+
+/// Softirq stats for a CPU and vector.
+#[derive(Clone, Debug, Default)]
+pub struct IrqStats {
+    pub count: u64,
+    pub total_ns: u64,
+    pub max_ns: u64,
+}
+
+/// Analysis of a perfetto trace generated by scxtop.
+#[derive(Debug, Default)]
+pub struct TraceAnalysis {
+    events: Vec<(u32, FtraceEvent)>,
+    pub start_ts: u64,
+    pub end_ts: u64,
+    // tid -> tgid from the process tree of the trace
+    tgids: HashMap<i32, i32>,
+    pub cpus: BTreeMap<u32, CpuStats>,
+    /// Command name of each tgid, for display.
+    pub comms: BTreeMap<i32, String>,
+    pub wakeup_lat_ns: BTreeMap<i32, Vec<u64>>,
+    pub migrations: BTreeMap<i32, u64>,
+    pub dsq_lat_us: BTreeMap<String, Vec<u64>>,
+    pub softirqs: BTreeMap<(u32, u32), IrqStats>,
+    pub ipis: BTreeMap<(u32, u32), u64>,
+    pub delays: Vec<SchedDelay>,
+}
+
+impl TraceAnalysis {
+    /// Loads and analyzes a trace file.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let trace = Trace::parse_from_bytes(&fs::read(path)?)?;
+        Ok(Self::new(&trace))
+    }
+
+    /// Analyzes a trace.
+    pub fn new(trace: &Trace) -> Self {
+        let mut analysis = Self::default();
+        let mut dsq_tracks: HashMap<u64, String> = HashMap::new();
+
+        for packet in &trace.packet {
+            match &packet.data {
+                Some(TracePacketData::TrackDescriptor(desc)) => {
+                    if let Some(dsq) = desc
+                        .name()
+                        .strip_prefix("DSQ ")
+                        .and_then(|name| name.strip_suffix(" latency ns"))
+                    {
+                        dsq_tracks.insert(desc.uuid(), dsq.to_string());
+                    }
+                }
+                Some(TracePacketData::FtraceEvents(bundle)) => {
+                    for event in &bundle.event {
+                        analysis.events.push((bundle.cpu(), event.clone()));
+                    }
+                }
+                Some(TracePacketData::ProcessTree(tree)) => {
+                    for thread in &tree.threads {
+                        analysis.tgids.insert(thread.tid(), thread.tgid());
+                    }
+                }
+                _ => {}
+            }
+        }
+        // DSQ latency track events reference the descriptors, which may be
+        // written after them.
+        for packet in &trace.packet {
+            if let Some(TracePacketData::TrackEvent(event)) = &packet.data {
+                if let Some(dsq) = dsq_tracks.get(&event.track_uuid()) {
+                    analysis
+                        .dsq_lat_us
+                        .entry(dsq.clone())
+                        .or_default()
+                        .push(event.counter_value().max(0) as u64);
+                }
+            }
+        }
+
+        analysis.events.sort_by_key(|(_, event)| event.timestamp());
+        analysis.start_ts = analysis
+            .events
+            .first()
+            .map(|(_, e)| e.timestamp())
+            .unwrap_or(0);
+        analysis.end_ts = analysis
+            .events
+            .last()
+            .map(|(_, e)| e.timestamp())
+            .unwrap_or(0);
+        analysis.process_events();
+        analysis
+    }
+
+    /// Returns the tgid of a thread, traces without a process tree are
+    /// analyzed per thread.
+    pub fn tgid(&self, tid: i32) -> i32 {
+        self.tgids.get(&tid).copied().unwrap_or(tid)
+    }
+
+    /// Returns the command name of a tgid.
+    pub fn comm(&self, tgid: i32) -> &str {
+        self.comms.get(&tgid).map(String::as_str).unwrap_or("")
+    }
+
+    fn process_events(&mut self) {
+        // pid -> (comm, ts) of tasks that were woken up but not yet run
+        let mut pending: HashMap<i32, (String, u64)> = HashMap::new();
+        let mut last_cpu: HashMap<i32, u32> = HashMap::new();
+        let mut softirq_entry: HashMap<u32, (u32, u64)> = HashMap::new();
+
+        for (cpu, event) in &self.events {
+            let cpu = *cpu;
+            let ts = event.timestamp();
+            match &event.event {
+                Some(FtraceEventType::SchedWaking(e)) => {
+                    pending.entry(e.pid()).or_insert((e.comm().to_string(), ts));
+                }
+                Some(FtraceEventType::SchedWakeup(e)) => {
+                    pending.entry(e.pid()).or_insert((e.comm().to_string(), ts));
+                }
+                Some(FtraceEventType::SchedSwitch(e)) => {
+                    let cpu_stats = self.cpus.entry(cpu).or_default();
+                    if let (Some(last_ts), true) = (cpu_stats.last_switch_ts, cpu_stats.running) {
+                        cpu_stats.busy_ns += ts.saturating_sub(last_ts);
+                    }
+                    cpu_stats.last_switch_ts = Some(ts);
+                    cpu_stats.running = e.next_pid() > 0;
+                    cpu_stats.switches += 1;
+
+                    let next_pid = e.next_pid();
+                    if next_pid <= 0 {
+                        continue;
+                    }
+                    let comm = e.next_comm().to_string();
+                    let tgid = self.tgid(next_pid);
+                    // Prefer the name of the main thread for the process.
+                    if next_pid == tgid || !self.comms.contains_key(&tgid) {
+                        self.comms.insert(tgid, comm.clone());
+                    }
+                    if let Some((_, waking_ts)) = pending.remove(&next_pid) {
+                        let delay = SchedDelay {
+                            pid: next_pid,
+                            comm,
+                            cpu,
+                            waking_ts,
+                            run_ts: ts,
+                        };
+                        self.wakeup_lat_ns
+                            .entry(tgid)
+                            .or_default()
+                            .push(delay.delay_ns());
+                        self.delays.push(delay);
+                    }
+                    if let Some(prev_cpu) = last_cpu.insert(next_pid, cpu) {
+                        if prev_cpu != cpu {
+                            *self.migrations.entry(tgid).or_default() += 1;
+                        }
+                    }
+                }
+                Some(FtraceEventType::SoftirqEntry(e)) => {
+                    softirq_entry.insert(cpu, (e.vec(), ts));
+                }
+                Some(FtraceEventType::SoftirqExit(e)) => {
+                    if let Some((vec, entry_ts)) = softirq_entry.remove(&cpu) {
+                        if vec == e.vec() {
+                            let dur = ts.saturating_sub(entry_ts);
+                            let stats = self.softirqs.entry((cpu, vec)).or_default();
+                            stats.count += 1;
+                            stats.total_ns += dur;
+                            stats.max_ns = stats.max_ns.max(dur);
+                        }
+                    }
+                }
+                Some(FtraceEventType::IpiRaise(e)) => {
+                    *self.ipis.entry((cpu, e.target_cpus())).or_default() += 1;
+                }
+                _ => {}
+            }
+        }
+
+        let end_ts = self.end_ts;
+        for cpu_stats in self.cpus.values_mut() {
+            if let (Some(last_ts), true) = (cpu_stats.last_switch_ts, cpu_stats.running) {
+                cpu_stats.busy_ns += end_ts.saturating_sub(last_ts);
+            }
+        }
+        self.delays
+            .sort_by_key(|delay| std::cmp::Reverse(delay.delay_ns()));
+    }
+
+    /// Returns the duration of the trace in ns.
+    pub fn duration_ns(&self) -> u64 {
+        self.end_ts.saturating_sub(self.start_ts)
+    }
+
+    /// Returns the last events on the CPU of a delay while the task was waiting.
+    pub fn delay_context(&self, delay: &SchedDelay, max_events: usize) -> Vec<&(u32, FtraceEvent)> {
+        let start = self
+            .events
+            .partition_point(|(_, e)| e.timestamp() < delay.waking_ts);
+        let end = self
+            .events
+            .partition_point(|(_, e)| e.timestamp() <= delay.run_ts);
+        let events: Vec<_> = self.events[start..end]
+            .iter()
+            .filter(|(cpu, _)| *cpu == delay.cpu)
+            .collect();
+        events[events.len().saturating_sub(max_events)..].to_vec()
+    }
+
+    /// Writes the report of the trace.
+    pub fn report(&self, out: &mut dyn Write, top: usize, context: usize) -> Result<()> {
+        let duration_ns = self.duration_ns();
+        writeln!(
+            out,
+            "trace duration {:.3}s, {} events, {} CPUs",
+            duration_ns as f64 / 1_000_000_000.0,
+            self.events.len(),
+            self.cpus.len()
+        )?;
+
+        writeln!(out, "\nCPU utilization")?;
+        writeln!(out, "{:>6} {:>8} {:>10}", "cpu", "util%", "switches")?;
+        for (cpu, stats) in &self.cpus {
+            let util = if duration_ns > 0 {
+                stats.busy_ns as f64 * 100.0 / duration_ns as f64
+            } else {
+                0.0
+            };
+            writeln!(out, "{:>6} {:>8.2} {:>10}", cpu, util, stats.switches)?;
+        }
+
+        writeln!(out, "\nWakeup latency (us) by process, top {} by p99", top)?;
+        writeln!(
+            out,
+            "{:>8} {:<16} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "tgid", "comm", "count", "p50", "p90", "p99", "max"
+        )?;
+        let mut procs: Vec<_> = self
+            .wakeup_lat_ns
+            .iter()
+            .map(|(tgid, lats)| (*tgid, lats.len(), percentiles(lats)))
+            .collect();
+        procs.sort_by_key(|(_, _, (_, _, p99, _))| std::cmp::Reverse(*p99));
+        for (tgid, count, (p50, p90, p99, max)) in procs.into_iter().take(top) {
+            writeln!(
+                out,
+                "{:>8} {:<16} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                tgid,
+                self.comm(tgid),
+                count,
+                p50 / 1000,
+                p90 / 1000,
+                p99 / 1000,
+                max / 1000
+            )?;
+        }
+
+        writeln!(out, "\nDSQ latency (us)")?;
+        writeln!(
+            out,
+            "{:<20} {:>8} {:>8} {:>8} {:>8} {:>8}",
+            "dsq", "count", "p50", "p90", "p99", "max"
+        )?;
+        for (dsq, lats) in &self.dsq_lat_us {
+            let (p50, p90, p99, max) = percentiles(lats);
+            writeln!(
+                out,
+                "{:<20} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                dsq,
+                lats.len(),
+                p50,
+                p90,
+                p99,
+                max
+            )?;
+        }
+
+        writeln!(out, "\nMigrations, top {}", top)?;
+        writeln!(out, "{:>8} {:<16} {:>8}", "tgid", "comm", "count")?;
+        let mut migrations: Vec<_> = self.migrations.iter().collect();
+        migrations.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for (tgid, count) in migrations.into_iter().take(top) {
+            writeln!(out, "{:>8} {:<16} {:>8}", tgid, self.comm(*tgid), count)?;
+        }
+
+        writeln!(out, "\nSoftirq hotspots, top {} by time", top)?;
+        writeln!(
+            out,
+            "{:>6} {:<10} {:>8} {:>10} {:>8}",
+            "cpu", "softirq", "count", "total_us", "max_us"
+        )?;
+        let mut softirqs: Vec<_> = self.softirqs.iter().collect();
+        softirqs.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.total_ns));
+        for ((cpu, vec), stats) in softirqs.into_iter().take(top) {
+            writeln!(
+                out,
+                "{:>6} {:<10} {:>8} {:>10} {:>8}",
+                cpu,
+                softirq_name(*vec),
+                stats.count,
+                stats.total_ns / 1000,
+                stats.max_ns / 1000
+            )?;
+        }
+
+        writeln!(out, "\nIPI hotspots, top {}", top)?;
+        writeln!(out, "{:>6} {:>10} {:>8}", "cpu", "target_cpu", "count")?;
+        let mut ipis: Vec<_> = self.ipis.iter().collect();
+        ipis.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for ((cpu, target), count) in ipis.into_iter().take(top) {
+            writeln!(out, "{:>6} {:>10} {:>8}", cpu, target, count)?;
+        }
+
+        writeln!(out, "\nWorst scheduling delays, top {}", top)?;
+        for (i, delay) in self.delays.iter().take(top).enumerate() {
+            writeln!(
+                out,
+                "#{} {}({}) on cpu {}: {}us, woken at +{}us",
+                i + 1,
+                delay.comm,
+                delay.pid,
+                delay.cpu,
+                delay.delay_ns() / 1000,
+                delay.waking_ts.saturating_sub(self.start_ts) / 1000
+            )?;
+            for (_, event) in self.delay_context(delay, context) {
+                writeln!(
+                    out,
+                    "    +{:>10}us {}",
+                    event.timestamp().saturating_sub(self.start_ts) / 1000,
+                    describe_event(event)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protos_gen::perfetto_scx::{
+        process_tree, FtraceEventBundle, ProcessTree, SchedSwitchFtraceEvent,
+        SchedWakingFtraceEvent, TracePacket,
+    };
+
+    fn switch(ts: u64, prev_pid: i32, next_pid: i32, next_comm: &str) -> FtraceEvent {
+        let mut event = FtraceEvent::new();
+        let mut switch = SchedSwitchFtraceEvent::new();
+        switch.set_prev_pid(prev_pid);
+        switch.set_next_pid(next_pid);
+        switch.set_next_comm(next_comm.to_string());
+        event.set_timestamp(ts);
+        event.set_sched_switch(switch);
+        event
+    }
+
+    fn waking(ts: u64, pid: i32, comm: &str) -> FtraceEvent {
+        let mut event = FtraceEvent::new();
+        let mut waking = SchedWakingFtraceEvent::new();
+        waking.set_pid(pid);
+        waking.set_comm(comm.to_string());
+        event.set_timestamp(ts);
+        event.set_sched_waking(waking);
+        event
+    }
+
+    fn trace(cpus: Vec<(u32, Vec<FtraceEvent>)>) -> Trace {
+        let mut trace = Trace::new();
+        for (cpu, events) in cpus {
+            let mut bundle = FtraceEventBundle::new();
+            bundle.set_cpu(cpu);
+            bundle.event = events;
+            let mut packet = TracePacket::new();
+            packet.set_ftrace_events(bundle);
+            trace.packet.push(packet);
+        }
+        trace
+    }
+
+    #[test]
+    fn test_trace_analysis() {
+        let trace = trace(vec![
+            (
+                0,
+                vec![
+                    waking(1_000, 10, "foo"),
+                    switch(5_000, 0, 10, "foo"),
+                    switch(9_000, 10, 0, "swapper"),
+                ],
+            ),
+            (
+                1,
+                vec![waking(9_000, 10, "foo"), switch(11_000, 0, 10, "foo")],
+            ),
+        ]);
+        let analysis = TraceAnalysis::new(&trace);
+
+        assert_eq!(analysis.duration_ns(), 10_000);
+        assert_eq!(analysis.cpus[&0].busy_ns, 4_000);
+        assert_eq!(analysis.cpus[&1].busy_ns, 0);
+        assert_eq!(analysis.wakeup_lat_ns[&10], vec![4_000, 2_000]);
+        assert_eq!(analysis.migrations[&10], 1);
+        assert_eq!(analysis.comm(10), "foo");
+        assert_eq!(analysis.delays[0].delay_ns(), 4_000);
+        assert_eq!(analysis.delays[0].cpu, 0);
+        assert_eq!(analysis.delay_context(&analysis.delays[0], 8).len(), 2);
+
+        let mut out = Vec::new();
+        analysis.report(&mut out, 10, 8).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("foo(10) on cpu 0: 4us"));
+    }
+
+    #[test]
+    fn test_trace_analysis_by_tgid() {
+        let mut trace = trace(vec![(
+            0,
+            vec![
+                waking(1_000, 11, "worker"),
+                waking(1_000, 20, "worker"),
+                switch(2_000, 0, 11, "worker"),
+                switch(5_000, 11, 20, "worker"),
+                waking(6_000, 10, "foo"),
+                switch(7_000, 20, 10, "foo"),
+            ],
+        )]);
+        let mut tree = ProcessTree::new();
+        for (tid, tgid) in [(10, 10), (11, 10)] {
+            let mut thread = process_tree::Thread::new();
+            thread.set_tid(tid);
+            thread.set_tgid(tgid);
+            tree.threads.push(thread);
+        }
+        let mut packet = TracePacket::new();
+        packet.set_process_tree(tree);
+        trace.packet.push(packet);
+        let analysis = TraceAnalysis::new(&trace);
+
+        // Threads of different processes with the same comm aren't merged and
+        // threads of a process are, named after the main thread.
+        assert_eq!(analysis.wakeup_lat_ns[&10], vec![1_000, 1_000]);
+        assert_eq!(analysis.wakeup_lat_ns[&20], vec![4_000]);
+        assert_eq!(analysis.comm(10), "foo");
+        assert_eq!(analysis.comm(20), "worker");
+    }
+}
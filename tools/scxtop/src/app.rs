@@ -7,18 +7,39 @@ use crate::available_perf_events;
 use crate::bpf_intf;
 use crate::bpf_skel::BpfSkel;
 use crate::bpf_stats::BpfStats;
+use crate::cgroup_data::cgroup_dir;
 use crate::config::get_config_path;
 use crate::config::Config;
+use crate::derived_metric::{INTERVAL_NS, MAX_FREQ_KHZ};
+use crate::edm::ActionHandler;
 use crate::format_hz;
+use crate::format_ns;
+use crate::proc_data::{lat_bucket_label, lat_histogram, lat_stats, TaskCounters, TaskSummary};
 use crate::read_file_string;
+use crate::recorder::Sample;
+use crate::remote;
+use crate::remote::{Hello, TickData, TopoInfo};
+use crate::trigger;
+use crate::trigger::{TickMetrics, TriggerManager};
 use crate::AppState;
 use crate::AppTheme;
+use crate::CgroupTree;
 use crate::CpuData;
+use crate::DerivedMetric;
 use crate::EventData;
+use crate::HistData;
+use crate::HistKind;
+use crate::LatHist;
 use crate::LlcData;
 use crate::NodeData;
 use crate::PerfEvent;
+use crate::PerfEventGroup;
 use crate::PerfettoTraceManager;
+use crate::ProcData;
+use crate::ProcSort;
+use crate::SchedStatsData;
+use crate::StatAggregation;
+use crate::StatsCell;
 use crate::VecStats;
 use crate::ViewState;
 use crate::APP;
@@ -43,13 +64,15 @@ use ratatui::{
     symbols::bar::{NINE_LEVELS, THREE_LEVELS},
     text::{Line, Span},
     widgets::{
-        Bar, BarChart, BarGroup, Block, BorderType, Borders, Gauge, Paragraph, RenderDirection,
-        Scrollbar, ScrollbarOrientation, ScrollbarState, Sparkline,
+        Bar, BarChart, BarGroup, Block, BorderType, Borders, Cell, Gauge, Paragraph,
+        RenderDirection, Row, Scrollbar, ScrollbarOrientation, ScrollbarState, Sparkline, Table,
+        TableState,
     },
     Frame,
 };
 use regex::Regex;
 use scx_stats::prelude::StatsClient;
+use scx_stats::prelude::StatsMeta;
 use scx_utils::misc::read_file_usize;
 use scx_utils::scx_enums;
 use scx_utils::Topology;
@@ -57,13 +80,22 @@ use serde_json::Value as JsonValue;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Mutex;
 
+use std::cmp::Reverse;
 use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::os::fd::AsRawFd;
 use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 const DSQ_VTIME_CUTOFF: u64 = 1_000_000_000_000_000;
+const CGROUP_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// subsystem of the events of derived metrics
+const DERIVED_SUBSYSTEM: &str = "derived";
 
 /// App is the struct for scxtop application state.
 pub struct App<'a> {
@@ -72,6 +104,10 @@ pub struct App<'a> {
     locale: SystemLocale,
     stats_client: Arc<Mutex<StatsClient>>,
     sched_stats_raw: String,
+    sched_stats: SchedStatsData,
+    sched_stats_state: TableState,
+    sched_stats_table: usize,
+    sched_stats_sort: usize,
 
     scheduler: String,
     max_cpu_events: usize,
@@ -81,8 +117,8 @@ pub struct App<'a> {
     view_state: ViewState,
     pub should_quit: Arc<AtomicBool>,
     pub action_tx: UnboundedSender<Action>,
-    pub skel: BpfSkel<'a>,
-    topo: Topology,
+    pub skel: Option<BpfSkel<'a>>,
+    topo: TopoInfo,
     large_core_count: bool,
     collect_cpu_freq: bool,
     collect_uncore_freq: bool,
@@ -93,12 +129,17 @@ pub struct App<'a> {
     active_hw_event_id: usize,
     active_perf_events: BTreeMap<usize, PerfEvent>,
     available_events: Vec<PerfEvent>,
+    derived_metrics: BTreeMap<String, DerivedMetric>,
+    active_metric_groups: BTreeMap<usize, PerfEventGroup>,
+    last_metric_read: Instant,
 
     available_perf_events_list: Vec<String>,
     cpu_data: BTreeMap<usize, CpuData>,
     llc_data: BTreeMap<usize, LlcData>,
     node_data: BTreeMap<usize, NodeData>,
     dsq_data: BTreeMap<u64, EventData>,
+    proc_data: BTreeMap<u32, ProcData>,
+    hist_data: HistData,
 
     // stats from scxtop's bpf side
     bpf_stats: BpfStats,
@@ -109,12 +150,38 @@ pub struct App<'a> {
     selected_event: usize,
     non_hw_event_active: bool,
 
+    // process view related
+    proc_sort: ProcSort,
+    proc_table_state: TableState,
+    thread_table_state: TableState,
+    selected_proc: Option<u32>,
+    proc_list_size: usize,
+    last_proc_tick: Instant,
+
+    // latency view related
+    hist_kind: HistKind,
+    hist_table_state: TableState,
+    hist_list_size: usize,
+
+    // cgroup view related
+    cgroup_tree: CgroupTree,
+    cgroup_table_state: TableState,
+    cgroup_list_size: usize,
+    cgroup_filter: Option<(String, HashSet<u64>)>,
+    last_cgroup_refresh: Instant,
+
     // trace related
     trace_manager: PerfettoTraceManager,
     trace_start: u64,
     prev_bpf_sample_rate: u32,
     process_id: i32,
     trace_links: Vec<Link>,
+    trigger: TriggerManager,
+    trigger_reason: Option<String>,
+
+    // remote host related
+    remote_tx: Option<UnboundedSender<Action>>,
+    remote_sample_rate: u32,
 }
 
 impl<'a> App<'a> {
@@ -128,15 +195,84 @@ impl<'a> App<'a> {
         action_tx: UnboundedSender<Action>,
         skel: BpfSkel<'a>,
     ) -> Result<Self> {
-        let topo = Topology::new()?;
+        let topo = TopoInfo::from(&Topology::new()?);
+        let available_perf_events_list: Vec<String> = available_perf_events()?
+            .iter()
+            .flat_map(|(subsystem, events)| {
+                events
+                    .iter()
+                    .map(|event| format!("{}:{}", subsystem.clone(), event.clone()))
+            })
+            .collect();
+        let mut app = Self::build(
+            config,
+            scheduler,
+            max_cpu_events,
+            process_id,
+            action_tx,
+            Some(skel),
+            topo,
+            available_perf_events_list,
+        )?;
+        let perf_event = app.active_event.clone();
+        app.activate_perf_event(&perf_event)?;
+        app.cgroup_tree.refresh();
+
+        Ok(app)
+    }
+
+    /// Creates a new application rendering the data streamed by a `scxtop serve` agent. Control
+    /// actions are forwarded to the agent through remote_tx.
+    pub fn new_remote(
+        config: Config,
+        hello: Hello,
+        max_cpu_events: usize,
+        action_tx: UnboundedSender<Action>,
+        remote_tx: UnboundedSender<Action>,
+    ) -> Result<Self> {
+        let mut app = Self::build(
+            config,
+            "".to_string(),
+            max_cpu_events,
+            -1,
+            action_tx,
+            None,
+            hello.topo,
+            hello.perf_events,
+        )?;
+        app.remote_tx = Some(remote_tx);
+
+        Ok(app)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build(
+        config: Config,
+        scheduler: String,
+        max_cpu_events: usize,
+        process_id: i32,
+        action_tx: UnboundedSender<Action>,
+        skel: Option<BpfSkel<'a>>,
+        topo: TopoInfo,
+        available_perf_events_list: Vec<String>,
+    ) -> Result<Self> {
         let mut cpu_data = BTreeMap::new();
         let mut llc_data = BTreeMap::new();
         let mut node_data = BTreeMap::new();
         let active_event = PerfEvent::new("hw".to_string(), "cycles".to_string(), 0);
-        let mut active_perf_events = BTreeMap::new();
         let mut default_events = PerfEvent::default_events();
         let config_events = PerfEvent::from_config(&config)?;
         default_events.extend(config_events);
+        let derived_metrics: BTreeMap<String, DerivedMetric> = config
+            .derived_metrics()?
+            .into_iter()
+            .map(|metric| (metric.name.clone(), metric))
+            .collect();
+        default_events.extend(
+            derived_metrics
+                .keys()
+                .map(|name| PerfEvent::new(DERIVED_SUBSYSTEM.to_string(), name.clone(), 0)),
+        );
         let default_events_str: Vec<&str> = default_events
             .iter()
             .map(|event| {
@@ -148,9 +284,6 @@ impl<'a> App<'a> {
             })
             .collect();
         for cpu in topo.all_cpus.values() {
-            let mut event = PerfEvent::new("hw".to_string(), "cycles".to_string(), cpu.id);
-            event.attach(process_id)?;
-            active_perf_events.insert(cpu.id, event);
             let mut data =
                 CpuData::new(cpu.id, cpu.core_id, cpu.llc_id, cpu.node_id, max_cpu_events);
             data.initialize_events(&default_events_str);
@@ -167,14 +300,6 @@ impl<'a> App<'a> {
             node_data.insert(node.id, data);
         }
 
-        let available_perf_events_list: Vec<String> = available_perf_events()?
-            .iter()
-            .flat_map(|(subsystem, events)| {
-                events
-                    .iter()
-                    .map(|event| format!("{}:{}", subsystem.clone(), event.clone()))
-            })
-            .collect();
         let num_perf_events: u16 = available_perf_events_list.len() as u16;
         let mut stats_client = StatsClient::new();
         let stats_socket_path = config.stats_socket_path();
@@ -188,9 +313,18 @@ impl<'a> App<'a> {
             }
             client
         });
-        let sample_rate = skel.maps.data_data.sample_rate;
+        let sample_rate = skel
+            .as_ref()
+            .map_or(0, |skel| skel.maps.data_data.sample_rate);
         let trace_file_prefix = config.trace_file_prefix().to_string();
         let trace_manager = PerfettoTraceManager::new(trace_file_prefix, None);
+        let trigger = TriggerManager::new(
+            config.triggers().to_vec(),
+            config.trigger_pre_ns(),
+            config.trigger_max_traces(),
+            config.trigger_cooldown(),
+        );
+        let cgroup_tree = CgroupTree::new(max_cpu_events);
 
         let app = Self {
             config,
@@ -198,6 +332,10 @@ impl<'a> App<'a> {
             locale: SystemLocale::default()?,
             stats_client: Arc::new(Mutex::new(stats_client)),
             sched_stats_raw: "".to_string(),
+            sched_stats: SchedStatsData::default(),
+            sched_stats_state: TableState::default().with_selected(Some(0)),
+            sched_stats_table: 0,
+            sched_stats_sort: 0,
             scheduler,
             max_cpu_events,
             max_sched_events: max_cpu_events,
@@ -215,23 +353,50 @@ impl<'a> App<'a> {
             llc_data,
             node_data,
             dsq_data: BTreeMap::new(),
+            proc_data: BTreeMap::new(),
+            hist_data: HistData::default(),
             event_scroll_state: ScrollbarState::new(num_perf_events.into()).position(0),
             event_scroll: 0,
             active_hw_event_id: 0,
             active_event,
-            active_perf_events,
+            active_perf_events: BTreeMap::new(),
             available_events: default_events,
+            derived_metrics,
+            active_metric_groups: BTreeMap::new(),
+            last_metric_read: Instant::now(),
             available_perf_events_list,
             num_perf_events,
             events_list_size: 1,
             selected_event: 0,
             non_hw_event_active: false,
+            proc_sort: ProcSort::default(),
+            proc_table_state: TableState::default().with_selected(Some(0)),
+            thread_table_state: TableState::default().with_selected(Some(0)),
+            selected_proc: if process_id > 0 {
+                Some(process_id as u32)
+            } else {
+                None
+            },
+            proc_list_size: 1,
+            last_proc_tick: Instant::now(),
+            hist_kind: HistKind::default(),
+            hist_table_state: TableState::default().with_selected(Some(0)),
+            hist_list_size: 1,
+            cgroup_tree,
+            cgroup_table_state: TableState::default().with_selected(Some(0)),
+            cgroup_list_size: 1,
+            cgroup_filter: None,
+            last_cgroup_refresh: Instant::now(),
             prev_bpf_sample_rate: sample_rate,
             trace_start: 0,
             trace_manager,
             bpf_stats: Default::default(),
             process_id,
             trace_links: vec![],
+            trigger,
+            trigger_reason: None,
+            remote_tx: None,
+            remote_sample_rate: sample_rate,
         };
 
         Ok(app)
@@ -246,6 +411,32 @@ impl<'a> App<'a> {
     pub fn set_state(&mut self, state: AppState) {
         self.prev_state = self.state.clone();
         self.state = state;
+        // a lost agent connection is reported by the message stream
+        let _ = self.forward_to_remote(&Action::SetState(self.state.clone()));
+    }
+
+    /// Returns if the app renders the data of a remote host.
+    pub fn is_remote(&self) -> bool {
+        self.remote_tx.is_some()
+    }
+
+    /// Forwards control actions to the agent when rendering a remote host, so that it collects
+    /// the data of the current view.
+    fn forward_to_remote(&self, action: &Action) -> Result<()> {
+        if let Some(remote_tx) = &self.remote_tx {
+            if remote::is_control(action) {
+                remote_tx.send(action.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the BPF sampling rate of the observed host.
+    fn bpf_sample_rate(&self) -> u32 {
+        match &self.skel {
+            Some(skel) => skel.maps.data_data.sample_rate,
+            None => self.remote_sample_rate,
+        }
     }
 
     /// Returns the current theme of the application
@@ -264,11 +455,13 @@ impl<'a> App<'a> {
             cpu_data.data.clear();
         }
         self.active_perf_events.clear();
+        self.active_metric_groups.clear();
     }
 
     /// Activates the next event.
     fn next_event(&mut self) -> Result<()> {
         self.active_perf_events.clear();
+        self.active_metric_groups.clear();
         if self.active_hw_event_id == self.available_events.len() - 1 {
             self.active_hw_event_id = 0;
         } else {
@@ -284,6 +477,7 @@ impl<'a> App<'a> {
     /// Activates the previous event.
     fn prev_event(&mut self) -> Result<()> {
         self.active_perf_events.clear();
+        self.active_metric_groups.clear();
         if self.active_hw_event_id == 0 {
             self.active_hw_event_id = self.available_events.len() - 1;
         } else {
@@ -303,18 +497,146 @@ impl<'a> App<'a> {
 
     /// Activates a perf event, stopping any active perf events.
     fn activate_perf_event(&mut self, perf_event: &PerfEvent) -> Result<()> {
-        if !self.active_perf_events.is_empty() {
+        if !self.active_perf_events.is_empty() || !self.active_metric_groups.is_empty() {
             self.stop_perf_events();
         }
+        if self.is_remote() {
+            return Ok(());
+        }
+        let cgroup = match &self.cgroup_filter {
+            Some((path, _)) => Some(File::open(cgroup_dir(path))?),
+            None => None,
+        };
+        if perf_event.subsystem == DERIVED_SUBSYSTEM {
+            return self.activate_derived_metric(&perf_event.event, cgroup.as_ref());
+        }
         for cpu_id in self.topo.all_cpus.keys() {
             let mut event = perf_event.clone();
             event.cpu = *cpu_id;
-            event.attach(self.process_id)?;
+            match &cgroup {
+                Some(file) => event.attach_cgroup(file.as_raw_fd())?,
+                None => event.attach(self.process_id)?,
+            }
             self.active_perf_events.insert(*cpu_id, event);
         }
         Ok(())
     }
 
+    /// Activates the perf event groups of a derived metric on all cpus.
+    fn activate_derived_metric(&mut self, name: &str, cgroup: Option<&File>) -> Result<()> {
+        let metric = self
+            .derived_metrics
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("unknown derived metric: {}", name))?;
+        let events = metric
+            .events()
+            .into_iter()
+            .map(|event| {
+                self.available_events
+                    .iter()
+                    .find(|e| e.subsystem != DERIVED_SUBSYSTEM && e.event_name() == event)
+                    .cloned()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("unknown event {} of derived metric {}", event, name)
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for cpu_id in self.topo.all_cpus.keys() {
+            let mut group = PerfEventGroup::new(&events, *cpu_id);
+            match cgroup {
+                Some(file) => group.attach_cgroup(file.as_raw_fd())?,
+                None => group.attach(self.process_id)?,
+            }
+            self.active_metric_groups.insert(*cpu_id, group);
+        }
+        self.last_metric_read = Instant::now();
+        Ok(())
+    }
+
+    /// Reads the groups of the active derived metric and adds the metric of the cpus, llcs and
+    /// nodes. The metric of llcs and nodes is derived from the summed events of their cpus.
+    fn record_derived_metric(&mut self) -> Result<()> {
+        let Some(metric) = self.derived_metrics.get(self.active_event.event_name()) else {
+            return Ok(());
+        };
+        let interval_ns = self.last_metric_read.elapsed().as_nanos() as f64;
+        self.last_metric_read = Instant::now();
+
+        // the values of the events followed by the max frequency
+        let mut cpu_vals: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+        let mut llc_vals: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+        let mut node_vals: BTreeMap<usize, Vec<f64>> = BTreeMap::new();
+        for (cpu, group) in &mut self.active_metric_groups {
+            let mut vals: Vec<f64> = group.values(true)?.into_iter().map(|v| v as f64).collect();
+            let max_freq = if metric.uses_max_freq() {
+                let file = format!(
+                    "/sys/devices/system/cpu/cpu{}/cpufreq/cpuinfo_max_freq",
+                    cpu
+                );
+                read_file_usize(Path::new(&file)).unwrap_or(0)
+            } else {
+                0
+            };
+            vals.push(max_freq as f64);
+            if let Some(cpu_info) = self.topo.all_cpus.get(cpu) {
+                for (sums, id) in [
+                    (&mut llc_vals, cpu_info.llc_id),
+                    (&mut node_vals, cpu_info.node_id),
+                ] {
+                    let sum = sums.entry(id).or_insert(vec![0.0; vals.len()]);
+                    for (sum, val) in sum.iter_mut().zip(&vals) {
+                        *sum += val;
+                    }
+                }
+            }
+            cpu_vals.insert(*cpu, vals);
+        }
+
+        let events = metric.events();
+        let eval = |vals: &BTreeMap<usize, Vec<f64>>| -> Result<BTreeMap<usize, u64>> {
+            vals.iter()
+                .map(|(id, vals)| {
+                    let mut vars: BTreeMap<&str, f64> =
+                        events.iter().copied().zip(vals.iter().copied()).collect();
+                    vars.insert(MAX_FREQ_KHZ, vals.last().copied().unwrap_or(0.0));
+                    vars.insert(INTERVAL_NS, interval_ns);
+                    Ok((*id, metric.eval(&vars)?))
+                })
+                .collect()
+        };
+        let (cpus, llcs, nodes) = (eval(&cpu_vals)?, eval(&llc_vals)?, eval(&node_vals)?);
+        let name = metric.name.clone();
+        self.add_derived_values(&name, &cpus, &llcs, &nodes);
+        Ok(())
+    }
+
+    /// Adds the values of a derived metric of the cpus, llcs and nodes.
+    fn add_derived_values(
+        &mut self,
+        name: &str,
+        cpus: &BTreeMap<usize, u64>,
+        llcs: &BTreeMap<usize, u64>,
+        nodes: &BTreeMap<usize, u64>,
+    ) {
+        for (cpu, val) in cpus {
+            self.cpu_data
+                .entry(*cpu)
+                .or_insert(CpuData::new(*cpu, 0, 0, 0, self.max_cpu_events))
+                .add_event_data(name, *val);
+        }
+        // the entries of the llcs and nodes were added by add_topo_entries
+        for (llc, val) in llcs {
+            if let Some(llc_data) = self.llc_data.get_mut(llc) {
+                llc_data.add_cpu_event_data(name, *val);
+            }
+        }
+        for (node, val) in nodes {
+            if let Some(node_data) = self.node_data.get_mut(node) {
+                node_data.add_cpu_event_data(name, *val);
+            }
+        }
+    }
+
     fn record_cpu_freq(&mut self) -> Result<()> {
         for cpu_id in self.topo.all_cpus.keys() {
             let file = format!(
@@ -375,6 +697,7 @@ impl<'a> App<'a> {
         for events in self.dsq_data.values_mut() {
             events.set_max_size(max_events);
         }
+        self.sched_stats.set_max_size(max_events);
     }
 
     /// resizes existing events based on new max value.
@@ -393,7 +716,7 @@ impl<'a> App<'a> {
                     .or_insert(LlcData::new(*llc, 0, self.max_cpu_events));
             llc_data.data.set_max_size(max_events);
         }
-        for cpu in self.active_perf_events.keys() {
+        for cpu in self.topo.all_cpus.keys() {
             let cpu_data = self.cpu_data.entry(*cpu).or_insert(CpuData::new(
                 *cpu,
                 0,
@@ -413,9 +736,20 @@ impl<'a> App<'a> {
 
     /// Handles when scheduler stats are received.
     fn on_sched_stats(&mut self, stats_raw: String) {
+        if let Ok(stats) = serde_json::from_str::<JsonValue>(&stats_raw) {
+            self.sched_stats.update(&stats);
+        }
         self.sched_stats_raw = stats_raw;
     }
 
+    /// Handles when the scheduler stats metadata is received.
+    fn on_sched_stats_meta(&mut self, meta_raw: String) -> Result<()> {
+        let meta: BTreeMap<String, StatsMeta> = serde_json::from_str(&meta_raw)?;
+        self.sched_stats = SchedStatsData::new(meta, self.max_sched_events);
+        self.sched_stats_state.select(Some(0));
+        Ok(())
+    }
+
     /// Reloads stats client
     fn reload_stats_client(&mut self) -> Result<()> {
         let stats_socket_path = self.config.stats_socket_path();
@@ -425,21 +759,34 @@ impl<'a> App<'a> {
         let client_ref = self.stats_client.clone();
         let mut client = client_ref.blocking_lock();
         *client = new_client;
+        self.sched_stats = SchedStatsData::default();
 
         Ok(())
     }
 
     /// Runs callbacks to update application state on tick.
     fn on_tick(&mut self) -> Result<()> {
+        // remote hosts are updated on the ticks of the agent
+        let Some(skel) = &self.skel else {
+            return Ok(());
+        };
         // always grab updated stats
-        self.bpf_stats = BpfStats::get_from_skel(&self.skel)?;
+        self.bpf_stats = BpfStats::get_from_skel(skel)?;
 
-        if self.state == AppState::Scheduler && !self.scheduler.is_empty() {
+        if (self.state == AppState::Scheduler || self.trigger.needs_sched_stats())
+            && !self.scheduler.is_empty()
+        {
             let stats_client_read = self.stats_client.clone();
             let tx = self.action_tx.clone();
+            let fetch_meta = !self.sched_stats.has_meta();
             tokio::spawn(async move {
                 let mut client = stats_client_read.lock().await;
 
+                if fetch_meta {
+                    if let Ok(meta) = client.request::<JsonValue>("stats_meta", vec![]) {
+                        tx.send(Action::SchedStatsMeta(meta.to_string()))?;
+                    }
+                }
                 let result = client.request::<JsonValue>("stats", vec![]);
                 match result {
                     Ok(stats) => {
@@ -454,7 +801,113 @@ impl<'a> App<'a> {
                 Ok::<(), anyhow::Error>(())
             });
         };
-        // Add entry for nodes
+        self.add_topo_entries();
+
+        let mut values = Vec::with_capacity(self.active_perf_events.len());
+        for (cpu, event) in &mut self.active_perf_events {
+            values.push((*cpu, event.value(true)?));
+        }
+        let event_name = self.active_event.event_name().to_string();
+        for (cpu, val) in values {
+            self.add_perf_value(cpu, &event_name, val);
+        }
+        if !self.active_metric_groups.is_empty() {
+            self.record_derived_metric()?;
+        }
+        if self.collect_cpu_freq {
+            self.record_cpu_freq()?;
+        }
+        if self.collect_uncore_freq {
+            self.record_uncore_freq()?;
+        }
+
+        if self.state == AppState::Latency {
+            if let Some(skel) = &self.skel {
+                self.hist_data.update(skel)?;
+            }
+        }
+
+        let elapsed_ns = self.tick_procs();
+
+        if self.last_cgroup_refresh.elapsed() >= CGROUP_REFRESH_INTERVAL {
+            self.cgroup_tree.refresh();
+            self.last_cgroup_refresh = Instant::now();
+            if let Some((path, ids)) = &mut self.cgroup_filter {
+                *ids = self.cgroup_tree.subtree_ids(path);
+            }
+        }
+        self.cgroup_tree.tick(elapsed_ns);
+        if self.state == AppState::Cgroup {
+            let paths: Vec<String> = self
+                .cgroup_tree
+                .visible_rows()
+                .into_iter()
+                .map(|(_, path)| path)
+                .collect();
+            self.cgroup_tree.update_stats(&paths);
+        }
+        if self.trigger.is_enabled() {
+            self.check_triggers()?;
+        }
+        Ok(())
+    }
+
+    /// Checks the trigger rules and starts a trace if any matches.
+    fn check_triggers(&mut self) -> Result<()> {
+        let dsq_lat_p99_us = self
+            .cpu_data
+            .iter()
+            .filter(|(_, cpu_data)| cpu_data.data.contains_key("dsq_lat_us"))
+            .map(|(cpu, cpu_data)| (*cpu, lat_stats(&cpu_data.event_data_immut("dsq_lat_us")).1))
+            .collect();
+        let sched_stats = if self.trigger.needs_sched_stats() {
+            serde_json::from_str::<JsonValue>(&self.sched_stats_raw).ok()
+        } else {
+            None
+        };
+        let metrics = TickMetrics {
+            dsq_lat_p99_us,
+            dropped_events: self.bpf_stats.dropped_events,
+            sched_stats: sched_stats.as_ref(),
+        };
+        if let Some(reason) = self.trigger.on_tick(&metrics, Instant::now()) {
+            self.start_triggered_trace(reason)?;
+        }
+        Ok(())
+    }
+
+    /// Starts a trace that includes the buffered events preceding the trigger.
+    fn start_triggered_trace(&mut self, reason: String) -> Result<()> {
+        if self.state == AppState::Tracing || self.trigger_reason.is_some() {
+            return Ok(());
+        }
+        if self.trace_links.is_empty() {
+            self.attach_trace_progs()?;
+        }
+        let Some(skel) = &mut self.skel else {
+            return Ok(());
+        };
+        skel.maps.data_data.trace_duration_ns = self.config.trace_duration_ns();
+
+        let ret = skel
+            .progs
+            .start_triggered_trace
+            .test_run(ProgramInput::default())?
+            .return_value;
+        if ret != 0 {
+            return Err(anyhow::anyhow!(
+                "start_triggered_trace failed with exit code: {}",
+                ret
+            ));
+        }
+        log::info!("starting triggered trace: {}", reason);
+        self.trigger_reason = Some(reason);
+
+        Ok(())
+    }
+
+    /// Adds the entries of the active event for all nodes and llcs.
+    fn add_topo_entries(&mut self) {
         for node in self.topo.nodes.keys() {
             let node_data = self
                 .node_data
@@ -462,7 +915,6 @@ impl<'a> App<'a> {
                 .or_insert(NodeData::new(*node, self.max_cpu_events));
             node_data.add_event_data(self.active_event.event_name(), 0);
         }
-        // Add entry for llcs
         for llc in self.topo.all_llcs.keys() {
             let llc_data =
                 self.llc_data
@@ -470,36 +922,271 @@ impl<'a> App<'a> {
                     .or_insert(LlcData::new(*llc, 0, self.max_cpu_events));
             llc_data.add_event_data(self.active_event.event_name(), 0);
         }
+    }
 
-        for (cpu, event) in &mut self.active_perf_events {
-            let val = event.value(true)?;
-            let cpu_data = self
+    /// Adds a perf event value of a cpu to the cpu, llc and node data.
+    fn add_perf_value(&mut self, cpu: usize, event_name: &str, val: u64) {
+        let cpu_data = self
+            .cpu_data
+            .entry(cpu)
+            // XXX: fixme
+            .or_insert(CpuData::new(cpu, 0, 0, 0, self.max_cpu_events));
+        cpu_data.add_event_data(event_name, val);
+        let llc_data = self.llc_data.entry(cpu_data.llc).or_insert(LlcData::new(
+            cpu_data.llc,
+            0,
+            self.max_cpu_events,
+        ));
+        llc_data.add_cpu_event_data(event_name, val);
+        let node_data = self
+            .node_data
+            .entry(cpu_data.node)
+            .or_insert(NodeData::new(cpu_data.node, self.max_cpu_events));
+        node_data.add_cpu_event_data(event_name, val);
+    }
+
+    /// Ticks the process data, returning the ns elapsed since the last tick.
+    fn tick_procs(&mut self) -> u64 {
+        let elapsed_ns = self.last_proc_tick.elapsed().as_nanos() as u64;
+        self.last_proc_tick = Instant::now();
+        for proc_data in self.proc_data.values_mut() {
+            proc_data.tick(elapsed_ns);
+        }
+        self.proc_data.retain(|_, proc_data| !proc_data.is_empty());
+        elapsed_ns
+    }
+
+    /// Returns the hello sent to the clients of an agent.
+    pub fn hello(&self) -> Hello {
+        Hello {
+            version: remote::PROTOCOL_VERSION,
+            topo: self.topo.clone(),
+            perf_events: self.available_perf_events_list.clone(),
+        }
+    }
+
+    /// Returns the data of the last tick for the clients of an agent.
+    pub fn tick_data(&self) -> TickData {
+        let latest = |data: &EventData, event: &str| {
+            data.data
+                .get(event)
+                .and_then(|values| values.back().copied())
+        };
+        let event_name = self.active_event.event_name();
+        let mut tick = TickData {
+            scheduler: self.scheduler.clone(),
+            sample_rate: self.bpf_sample_rate(),
+            dropped_events: self.bpf_stats.dropped_events,
+            perf_event: event_name.to_string(),
+            ..Default::default()
+        };
+        for cpu in self
+            .active_perf_events
+            .keys()
+            .chain(self.active_metric_groups.keys())
+        {
+            if let Some(val) = self
                 .cpu_data
-                .entry(*cpu)
-                // XXX: fixme
-                .or_insert(CpuData::new(*cpu, 0, 0, 0, self.max_cpu_events));
-            cpu_data.add_event_data(event.event_name(), val);
-            let llc_data = self.llc_data.entry(cpu_data.llc).or_insert(LlcData::new(
-                cpu_data.llc,
-                0,
-                self.max_cpu_events,
-            ));
-            llc_data.add_cpu_event_data(event.event_name(), val);
-            let node_data = self
-                .node_data
-                .entry(cpu_data.node)
-                .or_insert(NodeData::new(cpu_data.node, self.max_cpu_events));
-            node_data.add_cpu_event_data(event.event_name(), val);
+                .get(cpu)
+                .and_then(|cpu_data| latest(&cpu_data.data, event_name))
+            {
+                tick.perf.insert(*cpu, val);
+            }
+        }
+        // llcs and nodes of derived metrics aren't the sum of their cpus
+        if !self.active_metric_groups.is_empty() {
+            for (llc, llc_data) in &self.llc_data {
+                if let Some(val) = latest(&llc_data.data, event_name) {
+                    tick.llc_perf.insert(*llc, val);
+                }
+            }
+            for (node, node_data) in &self.node_data {
+                if let Some(val) = latest(&node_data.data, event_name) {
+                    tick.node_perf.insert(*node, val);
+                }
+            }
         }
         if self.collect_cpu_freq {
-            self.record_cpu_freq()?;
+            for (cpu, cpu_data) in &self.cpu_data {
+                if let Some(val) = latest(&cpu_data.data, "cpu_freq") {
+                    tick.cpu_freq.insert(*cpu, val);
+                }
+            }
         }
         if self.collect_uncore_freq {
-            self.record_uncore_freq()?;
+            for (node, node_data) in &self.node_data {
+                if let Some(val) = latest(&node_data.data, "uncore_freq") {
+                    tick.uncore_freq.insert(*node, val);
+                }
+            }
+        }
+        if self.state == AppState::Latency {
+            tick.cpu_hists = self.hist_data.cpus.clone();
+            tick.dsq_hists = self.hist_data.dsqs.clone();
+        }
+        tick
+    }
+
+    /// Updates the app with the data of a tick of the agent of a remote host.
+    pub fn on_remote_tick(&mut self, tick: TickData) -> Result<()> {
+        self.scheduler = tick.scheduler;
+        self.remote_sample_rate = tick.sample_rate;
+        self.bpf_stats.dropped_events = tick.dropped_events;
+
+        // the agent may have been configured with other events
+        if self.active_event.event_name() != tick.perf_event {
+            match self
+                .available_events
+                .iter()
+                .position(|event| event.event_name() == tick.perf_event)
+            {
+                Some(idx) => {
+                    self.active_hw_event_id = idx;
+                    self.active_event = self.available_events[idx].clone();
+                }
+                None => match tick.perf_event.split_once(":") {
+                    Some((subsystem, event)) => {
+                        self.active_event =
+                            PerfEvent::new(subsystem.to_string(), event.to_string(), 0);
+                        self.non_hw_event_active = true;
+                    }
+                    // a derived metric only configured on the agent
+                    None if !tick.llc_perf.is_empty() => {
+                        self.active_event = PerfEvent::new(
+                            DERIVED_SUBSYSTEM.to_string(),
+                            tick.perf_event.clone(),
+                            0,
+                        );
+                        self.non_hw_event_active = true;
+                    }
+                    None => {}
+                },
+            }
         }
+
+        self.add_topo_entries();
+        if tick.llc_perf.is_empty() && tick.node_perf.is_empty() {
+            for (cpu, val) in tick.perf {
+                self.add_perf_value(cpu, &tick.perf_event, val);
+            }
+        } else {
+            self.add_derived_values(
+                &tick.perf_event,
+                &tick.perf,
+                &tick.llc_perf,
+                &tick.node_perf,
+            );
+        }
+        for (cpu, freq) in tick.cpu_freq {
+            let cpu_data =
+                self.cpu_data
+                    .entry(cpu)
+                    .or_insert(CpuData::new(cpu, 0, 0, 0, self.max_cpu_events));
+            cpu_data.add_event_data("cpu_freq", freq);
+        }
+        for (node, freq) in tick.uncore_freq {
+            let node_data = self
+                .node_data
+                .entry(node)
+                .or_insert(NodeData::new(node, self.max_cpu_events));
+            node_data.add_event_data("uncore_freq", freq);
+        }
+        if self.state == AppState::Latency {
+            self.hist_data.set(tick.cpu_hists, tick.dsq_hists);
+        }
+        self.tick_procs();
+
         Ok(())
     }
 
+    /// Returns the latest value of the collected metrics for recording.
+    pub fn samples(&self) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        let mut push_event_data = |scope: &'static str, id: usize, data: &EventData| {
+            let mut events: Vec<_> = data.data.iter().collect();
+            events.sort_by(|a, b| a.0.cmp(b.0));
+            for (event, values) in events {
+                if let Some(val) = values.back() {
+                    samples.push(Sample::new(scope, id, event, *val as f64));
+                }
+            }
+        };
+        for (cpu, cpu_data) in &self.cpu_data {
+            push_event_data("cpu", *cpu, &cpu_data.data);
+        }
+        for (llc, llc_data) in &self.llc_data {
+            push_event_data("llc", *llc, &llc_data.data);
+        }
+        for (node, node_data) in &self.node_data {
+            push_event_data("node", *node, &node_data.data);
+        }
+
+        for (dsq, dsq_data) in &self.dsq_data {
+            for event in ["dsq_lat_us", "dsq_slice_consumed"] {
+                if !dsq_data.data.contains_key(event) {
+                    continue;
+                }
+                let values = dsq_data.event_data_immut(event);
+                let stats = VecStats::new(
+                    &values,
+                    true,
+                    true,
+                    false,
+                    Some(HashSet::from([StatAggregation::P50, StatAggregation::P99])),
+                );
+                let dsq = format!("{:#X}", dsq);
+                samples.push(Sample::new(
+                    "dsq",
+                    &dsq,
+                    &format!("{}_avg", event),
+                    stats.avg as f64,
+                ));
+                samples.push(Sample::new(
+                    "dsq",
+                    &dsq,
+                    &format!("{}_max", event),
+                    stats.max as f64,
+                ));
+                for (agg, val) in stats.percentiles.unwrap_or_default() {
+                    samples.push(Sample::new(
+                        "dsq",
+                        &dsq,
+                        &format!("{}_{}", event, agg),
+                        val as f64,
+                    ));
+                }
+            }
+        }
+
+        for series in self.sched_stats.series.values() {
+            if !series.data.is_empty() {
+                samples.push(Sample::new("sched", "", &series.name, series.last()));
+            }
+        }
+        for table in self.sched_stats.tables.values() {
+            for (key, cells) in &table.rows {
+                for (column, cell) in table.columns.iter().zip(cells) {
+                    if let StatsCell::Num(val) = cell {
+                        samples.push(Sample::new(
+                            "sched",
+                            format!("{}.{}", table.name, key),
+                            column,
+                            *val,
+                        ));
+                    }
+                }
+            }
+        }
+
+        samples.push(Sample::new(
+            "bpf",
+            "",
+            "dropped_events",
+            self.bpf_stats.dropped_events as f64,
+        ));
+        samples
+    }
+
     /// Generates a CPU bar chart.
     fn cpu_bar(&self, cpu: usize, event: &str) -> Bar {
         let cpu_data = self.cpu_data.get(&cpu).unwrap();
@@ -988,12 +1675,9 @@ impl<'a> App<'a> {
                     .border_type(BorderType::Rounded)
                     .style(self.theme().border_style())
                     .title_top(if render_sample_rate {
-                        Line::from(format!(
-                            "sample rate {}",
-                            self.skel.maps.data_data.sample_rate
-                        ))
-                        .style(self.theme().text_important_color())
-                        .right_aligned()
+                        Line::from(format!("sample rate {}", self.bpf_sample_rate()))
+                            .style(self.theme().text_important_color())
+                            .right_aligned()
                     } else {
                         Line::from("".to_string())
                     })
@@ -1136,6 +1820,21 @@ impl<'a> App<'a> {
 
     /// Renders scheduler stats.
     fn render_scheduler_stats(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.sched_stats.has_meta() {
+            if self.sched_stats.tables.is_empty() {
+                let [top, bottom] =
+                    Layout::vertical([Constraint::Fill(2), Constraint::Fill(1)]).areas(area);
+                self.render_scheduler_stats_list(frame, top);
+                self.render_scheduler_stat_chart(frame, bottom);
+            } else {
+                let [top, center, bottom] = Layout::vertical([Constraint::Fill(1); 3]).areas(area);
+                self.render_scheduler_stats_list(frame, top);
+                self.render_scheduler_stat_chart(frame, center);
+                self.render_scheduler_stats_table(frame, bottom);
+            }
+            return Ok(());
+        }
+
         let paragraph = Paragraph::new(self.sched_stats_raw.clone());
         let block = Block::bordered()
             .title_top(
@@ -1156,9 +1855,217 @@ impl<'a> App<'a> {
         Ok(())
     }
 
-    /// Renders the scheduler state as sparklines.
-    fn render_scheduler_sparklines(
-        &mut self,
+    /// Returns a scheduler stat value formatted based on the localization setting.
+    fn format_stat(&self, val: f64, float: bool) -> String {
+        if float {
+            format!("{:.2}", val)
+        } else if val < 0.0 {
+            format!("-{}", self.format_num(-val as u64))
+        } else {
+            self.format_num(val as u64)
+        }
+    }
+
+    /// Renders the numeric scheduler stats.
+    fn render_scheduler_stats_list(&mut self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<Row> = self
+            .sched_stats
+            .series
+            .values()
+            .map(|series| {
+                Row::new(vec![
+                    Cell::from(series.name.clone()),
+                    Cell::from(self.format_stat(series.last(), series.float)),
+                    Cell::from(series.desc.clone()),
+                ])
+                .style(Style::default().fg(self.theme().text_color()))
+            })
+            .collect();
+        let header =
+            Row::new(vec!["STAT", "VALUE", "DESCRIPTION"]).style(self.theme().title_style());
+        let block = Block::bordered()
+            .title_top(
+                Line::from(self.scheduler.clone())
+                    .style(self.theme().title_style())
+                    .centered(),
+            )
+            .title_top(
+                Line::from(format!("{}ms", self.config.tick_rate_ms()))
+                    .style(self.theme().text_important_color())
+                    .right_aligned(),
+            )
+            .style(self.theme().border_style())
+            .border_type(BorderType::Rounded);
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(16),
+                Constraint::Fill(2),
+            ],
+        )
+        .header(header)
+        .row_highlight_style(
+            Style::default()
+                .fg(self.theme().text_important_color())
+                .add_modifier(Modifier::BOLD),
+        )
+        .block(block);
+        frame.render_stateful_widget(table, area, &mut self.sched_stats_state);
+    }
+
+    /// Renders the selected numeric scheduler stat over time.
+    fn render_scheduler_stat_chart(&self, frame: &mut Frame, area: Rect) {
+        let Some(series) = self
+            .sched_stats_state
+            .selected()
+            .and_then(|i| self.sched_stats.series.values().nth(i))
+        else {
+            frame.render_widget(
+                Block::bordered()
+                    .style(self.theme().border_style())
+                    .border_type(BorderType::Rounded),
+                area,
+            );
+            return;
+        };
+        // Sparklines and bars only take integers, keep some precision for floats.
+        let scale = if series.float { 1000.0 } else { 1.0 };
+        let data: Vec<u64> = series
+            .data
+            .iter()
+            .skip(series.data.len().saturating_sub(area.width as usize))
+            .map(|v| (v * scale).max(0.0) as u64)
+            .collect();
+        let (mut min, mut max, mut sum) = (f64::MAX, f64::MIN, 0.0);
+        for v in &series.data {
+            min = min.min(*v);
+            max = max.max(*v);
+            sum += v;
+        }
+        if series.data.is_empty() {
+            (min, max) = (0.0, 0.0);
+        }
+        let avg = sum / (series.data.len().max(1) as f64);
+        let block = Block::bordered()
+            .title_top(
+                Line::from(format!(
+                    "{} avg {} max {} min {}",
+                    series.name,
+                    self.format_stat(avg, series.float),
+                    self.format_stat(max, series.float),
+                    self.format_stat(min, series.float),
+                ))
+                .style(self.theme().title_style())
+                .centered(),
+            )
+            .style(self.theme().border_style())
+            .border_type(BorderType::Rounded);
+        let data_max = data.iter().copied().max().unwrap_or(0);
+        match self.view_state {
+            ViewState::Sparkline => {
+                let sparkline = Sparkline::default()
+                    .data(&data)
+                    .max(data_max)
+                    .style(self.theme().sparkline_style())
+                    .block(block);
+                frame.render_widget(sparkline, area);
+            }
+            ViewState::BarChart => {
+                let bars: Vec<Bar> = data.iter().map(|v| Bar::default().value(*v)).collect();
+                let barchart = BarChart::default()
+                    .data(BarGroup::default().bars(&bars))
+                    .max(data_max)
+                    .bar_style(self.theme().sparkline_style())
+                    .bar_gap(0)
+                    .bar_width(1)
+                    .block(block);
+                frame.render_widget(barchart, area);
+            }
+        }
+    }
+
+    /// Renders the selected Dict or Array scheduler stat as a table.
+    fn render_scheduler_stats_table(&mut self, frame: &mut Frame, area: Rect) {
+        let nr_tables = self.sched_stats.tables.len();
+        let Some(mut table) = self
+            .sched_stats
+            .tables
+            .values()
+            .nth(self.sched_stats_table % nr_tables.max(1))
+            .cloned()
+        else {
+            return;
+        };
+        let sort = self.sched_stats_sort % (table.columns.len() + 1);
+        table.sort(sort);
+
+        let sort_style = Style::default()
+            .fg(self.theme().text_important_color())
+            .add_modifier(Modifier::BOLD);
+        let header = Row::new(
+            std::iter::once(table.key.clone())
+                .chain(table.columns.iter().cloned())
+                .enumerate()
+                .map(|(i, name)| {
+                    if i == sort {
+                        Cell::from(name).style(sort_style)
+                    } else {
+                        Cell::from(name).style(self.theme().title_style())
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+        let rows: Vec<Row> = table
+            .rows
+            .iter()
+            .map(|(key, cells)| {
+                Row::new(
+                    std::iter::once(Cell::from(key.clone()))
+                        .chain(cells.iter().zip(&table.float_columns).map(|(cell, float)| {
+                            Cell::from(match cell {
+                                StatsCell::Num(v) => self.format_stat(*v, *float),
+                                StatsCell::Str(s) => s.clone(),
+                            })
+                        }))
+                        .collect::<Vec<_>>(),
+                )
+                .style(Style::default().fg(self.theme().text_color()))
+            })
+            .collect();
+        let widths = vec![Constraint::Fill(1); table.columns.len() + 1];
+        let block = Block::bordered()
+            .title_top(
+                Line::from(format!(
+                    "{} ({}/{}, {} for next)",
+                    table.name,
+                    self.sched_stats_table % nr_tables + 1,
+                    nr_tables,
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextStatsTable),
+                ))
+                .style(self.theme().title_style())
+                .centered(),
+            )
+            .title_top(
+                Line::from(format!(
+                    "sort ({})",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextStatsSort)
+                ))
+                .style(self.theme().text_important_color())
+                .right_aligned(),
+            )
+            .style(self.theme().border_style())
+            .border_type(BorderType::Rounded);
+        frame.render_widget(Table::new(rows, widths).header(header).block(block), area);
+    }
+
+    /// Renders the scheduler state as sparklines.
+    fn render_scheduler_sparklines(
+        &mut self,
         event: &str,
         frame: &mut Frame,
         area: Rect,
@@ -1280,7 +2187,7 @@ impl<'a> App<'a> {
 
         let dsq_constraints = vec![Constraint::Percentage(1), Constraint::Percentage(99)];
         let dsqs_verticle = Layout::vertical(dsq_constraints).split(area);
-        let sample_rate = self.skel.maps.data_data.sample_rate;
+        let sample_rate = self.bpf_sample_rate();
 
         let vtime_global_iter: Vec<u64> = self
             .dsq_data
@@ -1361,7 +2268,7 @@ impl<'a> App<'a> {
         }
         let dsq_constraints = vec![Constraint::Percentage(1), Constraint::Percentage(99)];
         let dsqs_verticle = Layout::vertical(dsq_constraints).split(area);
-        let sample_rate = self.skel.maps.data_data.sample_rate;
+        let sample_rate = self.bpf_sample_rate();
 
         let dsq_global_iter = self
             .dsq_data
@@ -1802,7 +2709,7 @@ impl<'a> App<'a> {
                     self.config
                         .active_keymap
                         .action_keys_string(Action::DecBpfSampleRate),
-                    self.skel.maps.data_data.sample_rate
+                    self.bpf_sample_rate()
                 ),
                 Style::default(),
             )),
@@ -1812,7 +2719,7 @@ impl<'a> App<'a> {
                     self.config
                         .active_keymap
                         .action_keys_string(Action::IncBpfSampleRate),
-                    self.skel.maps.data_data.sample_rate
+                    self.bpf_sample_rate()
                 ),
                 Style::default(),
             )),
@@ -1924,6 +2831,56 @@ impl<'a> App<'a> {
                 ),
                 Style::default(),
             )),
+            Line::from(Span::styled(
+                format!(
+                    "{}: display latency histograms ({}/{} change histogram, {} resets)",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::SetState(AppState::Latency)),
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::PrevEvent),
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextEvent),
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::ClearEvent),
+                ),
+                Style::default(),
+            )),
+            Line::from(Span::styled(
+                format!(
+                    "{}: display cgroup view ({} expands, {} filters other views by cgroup)",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::SetState(AppState::Cgroup)),
+                    self.config.active_keymap.action_keys_string(Action::Enter),
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::ToggleCgroupFilter),
+                ),
+                Style::default(),
+            )),
+            Line::from(Span::styled(
+                format!(
+                    "{}: display process view",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::SetState(AppState::Process))
+                ),
+                Style::default(),
+            )),
+            Line::from(Span::styled(
+                format!(
+                    "{}: change process view sort ({})",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextProcessSort),
+                    self.proc_sort
+                ),
+                Style::default(),
+            )),
             Line::from(Span::styled(
                 format!(
                     "{}: display scheduler view",
@@ -1933,6 +2890,24 @@ impl<'a> App<'a> {
                 ),
                 Style::default(),
             )),
+            Line::from(Span::styled(
+                format!(
+                    "{}: next scheduler stats table",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextStatsTable)
+                ),
+                Style::default(),
+            )),
+            Line::from(Span::styled(
+                format!(
+                    "{}: change scheduler stats table sort",
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextStatsSort)
+                ),
+                Style::default(),
+            )),
             Line::from(Span::styled(
                 format!(
                     "{}: change view state ({})",
@@ -2063,13 +3038,576 @@ impl<'a> App<'a> {
         Ok(())
     }
 
+    /// Returns a number formatted based on the localization setting.
+    fn format_num(&self, val: u64) -> String {
+        if self.localize {
+            val.to_formatted_string(&self.locale)
+        } else {
+            format!("{}", val)
+        }
+    }
+
+    /// Returns the tracked processes sorted by the active sort column.
+    fn sorted_procs(&self) -> Vec<u32> {
+        let mut procs: Vec<(u64, u32)> = self
+            .proc_data
+            .values()
+            .map(|proc_data| (proc_data.summary.sort_key(self.proc_sort), proc_data.tgid))
+            .collect();
+        procs.sort_by_key(|&key| Reverse(key));
+        procs.into_iter().map(|(_, tgid)| tgid).collect()
+    }
+
+    /// Returns the threads of a process sorted by the active sort column.
+    fn sorted_threads(&self, tgid: u32) -> Vec<u32> {
+        let Some(proc_data) = self.proc_data.get(&tgid) else {
+            return vec![];
+        };
+        let mut threads: Vec<(u64, u32)> = proc_data
+            .threads
+            .values()
+            .map(|thread| (thread.summary.sort_key(self.proc_sort), thread.tid))
+            .collect();
+        threads.sort_by_key(|&key| Reverse(key));
+        threads.into_iter().map(|(_, tid)| tid).collect()
+    }
+
+    /// Generates the header row of the process and thread tables.
+    fn task_header(&self, threads_column: bool) -> Row<'static> {
+        let sort_style = Style::default()
+            .fg(self.theme().text_important_color())
+            .add_modifier(Modifier::BOLD);
+        let columns = [
+            ("PID", None),
+            ("COMM", None),
+            ("THREADS", None),
+            ("CPU%", Some(ProcSort::Runtime)),
+            ("WAKEUP LAT us avg/p99", Some(ProcSort::WakeupLat)),
+            ("DSQ LAT us avg/p99", Some(ProcSort::DsqLat)),
+            ("VCSW/s", Some(ProcSort::VoluntarySwitches)),
+            ("IVCSW/s", Some(ProcSort::InvoluntarySwitches)),
+            ("MIGR/s", Some(ProcSort::Migrations)),
+        ];
+        Row::new(
+            columns
+                .into_iter()
+                .filter(|(name, _)| threads_column || *name != "THREADS")
+                .map(|(name, sort)| {
+                    if sort == Some(self.proc_sort) {
+                        Cell::from(name).style(sort_style)
+                    } else {
+                        Cell::from(name).style(self.theme().title_style())
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Returns the column widths of the process and thread tables.
+    fn task_widths(threads_column: bool) -> Vec<Constraint> {
+        let mut widths = vec![Constraint::Length(8), Constraint::Length(16)];
+        if threads_column {
+            widths.push(Constraint::Length(8));
+        }
+        widths.extend([
+            Constraint::Length(7),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ]);
+        widths
+    }
+
+    /// Generates a row of the process and thread tables.
+    fn task_row(
+        &self,
+        id: u32,
+        comm: &str,
+        nr_threads: Option<usize>,
+        summary: &TaskSummary,
+    ) -> Row<'static> {
+        let window = &summary.window;
+        let (wakeup_avg, wakeup_p99) = summary.wakeup_lat_us;
+        let (dsq_avg, dsq_p99) = summary.dsq_lat_us;
+        let mut cells = vec![Cell::from(id.to_string()), Cell::from(comm.to_string())];
+        if let Some(nr_threads) = nr_threads {
+            cells.push(Cell::from(nr_threads.to_string()));
+        }
+        cells.extend([
+            Cell::from(format!("{:.1}", window.cpu_pct())),
+            Cell::from(format!(
+                "{}/{}",
+                self.format_num(wakeup_avg),
+                self.format_num(wakeup_p99)
+            )),
+            Cell::from(format!(
+                "{}/{}",
+                self.format_num(dsq_avg),
+                self.format_num(dsq_p99)
+            )),
+            Cell::from(self.format_num(window.rate(window.nvcsw))),
+            Cell::from(self.format_num(window.rate(window.nivcsw))),
+            Cell::from(self.format_num(window.rate(window.nr_migrations))),
+        ]);
+        Row::new(cells).style(Style::default().fg(self.theme().text_color()))
+    }
+
+    /// Returns the block of the process and thread tables.
+    fn task_block(&self, title: String) -> Block<'static> {
+        Block::bordered()
+            .title_top(
+                Line::from(title)
+                    .style(self.theme().title_style())
+                    .centered(),
+            )
+            .title_top(
+                Line::from(format!(
+                    "sort {} ({}) sample rate {}",
+                    self.proc_sort,
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::NextProcessSort),
+                    self.bpf_sample_rate()
+                ))
+                .style(self.theme().text_important_color())
+                .right_aligned(),
+            )
+            .style(self.theme().border_style())
+            .border_type(BorderType::Rounded)
+    }
+
+    /// Renders the process list.
+    fn render_process_list(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        self.proc_list_size = area.height.saturating_sub(3) as usize;
+        let rows: Vec<Row> = self
+            .sorted_procs()
+            .iter()
+            .filter_map(|tgid| self.proc_data.get(tgid))
+            .map(|proc_data| {
+                self.task_row(
+                    proc_data.tgid,
+                    &proc_data.comm,
+                    Some(proc_data.threads.len()),
+                    &proc_data.summary,
+                )
+            })
+            .collect();
+        let title = format!(
+            "processes ({} for threads)",
+            self.config.active_keymap.action_keys_string(Action::Enter)
+        );
+        let table = Table::new(rows, Self::task_widths(true))
+            .header(self.task_header(true))
+            .row_highlight_style(
+                Style::default()
+                    .fg(self.theme().text_important_color())
+                    .add_modifier(Modifier::BOLD),
+            )
+            .block(self.task_block(title));
+        frame.render_stateful_widget(table, area, &mut self.proc_table_state);
+        Ok(())
+    }
+
+    /// Generates a latency histogram bar chart.
+    fn lat_histogram_chart<'b>(&self, title: String, samples: &[u64]) -> BarChart<'b> {
+        let bars: Vec<Bar> = lat_histogram(samples.iter())
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                Bar::default()
+                    .value(*count)
+                    .label(Line::from(lat_bucket_label(i)))
+                    .text_value(self.format_num(*count))
+            })
+            .collect();
+        BarChart::default()
+            .data(BarGroup::default().bars(&bars))
+            .block(
+                Block::bordered()
+                    .title_top(
+                        Line::from(title)
+                            .style(self.theme().title_style())
+                            .centered(),
+                    )
+                    .style(self.theme().border_style())
+                    .border_type(BorderType::Rounded),
+            )
+            .bar_style(self.theme().sparkline_style())
+            .bar_gap(1)
+            .bar_width(5)
+    }
+
+    /// Returns the histograms of the selected kind for all CPUs, NUMA nodes, LLCs, CPUs and DSQs.
+    fn hist_rows(&self) -> Vec<(String, LatHist)> {
+        let kind = self.hist_kind;
+        let mut rows = vec![(
+            "all".to_string(),
+            self.hist_data.cpus(self.topo.all_cpus.keys(), kind),
+        )];
+        for node in self.topo.nodes.keys() {
+            let cpus = self
+                .topo
+                .all_cpus
+                .values()
+                .filter(|cpu| cpu.node_id == *node)
+                .map(|cpu| &cpu.id);
+            rows.push((format!("node {}", node), self.hist_data.cpus(cpus, kind)));
+        }
+        for llc in self.topo.all_llcs.keys() {
+            let cpus = self
+                .topo
+                .all_cpus
+                .values()
+                .filter(|cpu| cpu.llc_id == *llc)
+                .map(|cpu| &cpu.id);
+            rows.push((format!("llc {}", llc), self.hist_data.cpus(cpus, kind)));
+        }
+        for cpu in self.topo.all_cpus.keys() {
+            rows.push((format!("cpu {}", cpu), self.hist_data.cpu(*cpu, kind)));
+        }
+        if kind != HistKind::Softirq {
+            for dsq in self.hist_data.dsqs.keys() {
+                rows.push((format!("dsq {:#X}", dsq), self.hist_data.dsq(*dsq, kind)));
+            }
+        }
+        rows
+    }
+
+    /// Renders the latency view.
+    fn render_latency(&mut self, frame: &mut Frame) -> Result<()> {
+        let [top, bottom] = Layout::vertical([Constraint::Fill(1); 2]).areas(frame.area());
+        self.hist_list_size = top.height.saturating_sub(3) as usize;
+
+        let hists = self.hist_rows();
+        let header = Row::new(
+            ["", "count", "p50", "p90", "p99", "p99.9", "max"]
+                .into_iter()
+                .map(|h| Cell::from(h).style(self.theme().title_style())),
+        );
+        let rows: Vec<Row> = hists
+            .iter()
+            .map(|(name, hist)| {
+                Row::new(vec![
+                    Cell::from(name.clone()),
+                    Cell::from(self.format_num(hist.count())),
+                    Cell::from(format_ns(hist.percentile(50.0))),
+                    Cell::from(format_ns(hist.percentile(90.0))),
+                    Cell::from(format_ns(hist.percentile(99.0))),
+                    Cell::from(format_ns(hist.percentile(99.9))),
+                    Cell::from(format_ns(hist.percentile(100.0))),
+                ])
+                .style(Style::default().fg(self.theme().text_color()))
+            })
+            .collect();
+        let widths = [
+            Constraint::Length(20),
+            Constraint::Length(14),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Length(10),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .row_highlight_style(
+                Style::default()
+                    .fg(self.theme().text_important_color())
+                    .add_modifier(Modifier::BOLD),
+            )
+            .block(
+                Block::bordered()
+                    .title_top(
+                        Line::from(format!(
+                            "{} ({}/{} to change, {} to reset)",
+                            self.hist_kind,
+                            self.config
+                                .active_keymap
+                                .action_keys_string(Action::PrevEvent),
+                            self.config
+                                .active_keymap
+                                .action_keys_string(Action::NextEvent),
+                            self.config
+                                .active_keymap
+                                .action_keys_string(Action::ClearEvent),
+                        ))
+                        .style(self.theme().title_style())
+                        .centered(),
+                    )
+                    .style(self.theme().border_style())
+                    .border_type(BorderType::Rounded),
+            );
+        frame.render_stateful_widget(table, top, &mut self.hist_table_state);
+
+        let selected = self.hist_table_state.selected().unwrap_or(0);
+        if let Some((name, hist)) = hists.get(selected) {
+            let bars: Vec<Bar> = hist
+                .log2_buckets()
+                .iter()
+                .map(|(lower, count)| {
+                    Bar::default()
+                        .value(*count)
+                        .label(Line::from(format_ns(*lower)))
+                        .text_value(self.format_num(*count))
+                })
+                .collect();
+            let chart = BarChart::default()
+                .data(BarGroup::default().bars(&bars))
+                .block(
+                    Block::bordered()
+                        .title_top(
+                            Line::from(format!("{} {}", name, self.hist_kind))
+                                .style(self.theme().title_style())
+                                .centered(),
+                        )
+                        .style(self.theme().border_style())
+                        .border_type(BorderType::Rounded),
+                )
+                .bar_style(self.theme().sparkline_style())
+                .bar_gap(1)
+                .bar_width(7);
+            frame.render_widget(chart, bottom);
+        }
+        Ok(())
+    }
+
+    /// Renders the threads of a process with the latency histograms of the selected thread.
+    fn render_process_threads(&mut self, frame: &mut Frame, tgid: u32) -> Result<()> {
+        let [top, bottom] = Layout::vertical([Constraint::Fill(1); 2]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Fill(1); 2]).areas(bottom);
+        self.proc_list_size = top.height.saturating_sub(3) as usize;
+
+        let tids = self.sorted_threads(tgid);
+        let (comm, rows): (String, Vec<Row>) = match self.proc_data.get(&tgid) {
+            Some(proc_data) => (
+                proc_data.comm.clone(),
+                tids.iter()
+                    .filter_map(|tid| proc_data.threads.get(tid))
+                    .map(|thread| self.task_row(thread.tid, &thread.comm, None, &thread.summary))
+                    .collect(),
+            ),
+            None => (String::new(), vec![]),
+        };
+        let title = if self.process_id > 0 {
+            format!("threads of {} {}", tgid, comm)
+        } else {
+            format!(
+                "threads of {} {} ({} for processes)",
+                tgid,
+                comm,
+                self.config.active_keymap.action_keys_string(Action::Enter)
+            )
+        };
+        let table = Table::new(rows, Self::task_widths(false))
+            .header(self.task_header(false))
+            .row_highlight_style(
+                Style::default()
+                    .fg(self.theme().text_important_color())
+                    .add_modifier(Modifier::BOLD),
+            )
+            .block(self.task_block(title));
+        frame.render_stateful_widget(table, top, &mut self.thread_table_state);
+
+        let thread = self
+            .thread_table_state
+            .selected()
+            .and_then(|i| tids.get(i))
+            .and_then(|tid| self.proc_data.get(&tgid)?.threads.get(tid));
+        let (tid, wakeup_lat_us, dsq_lat_us) = match thread {
+            Some(thread) => (
+                thread.tid,
+                thread.wakeup_lat_us.iter().copied().collect::<Vec<_>>(),
+                thread.dsq_lat_us.iter().copied().collect::<Vec<_>>(),
+            ),
+            None => (0, vec![], vec![]),
+        };
+        frame.render_widget(
+            self.lat_histogram_chart(format!("{} wakeup latency", tid), &wakeup_lat_us),
+            left,
+        );
+        frame.render_widget(
+            self.lat_histogram_chart(format!("{} dsq latency", tid), &dsq_lat_us),
+            right,
+        );
+        Ok(())
+    }
+
+    /// Renders the process state.
+    fn render_process(&mut self, frame: &mut Frame) -> Result<()> {
+        match self.selected_proc {
+            Some(tgid) => self.render_process_threads(frame, tgid),
+            None => self.render_process_list(frame, frame.area()),
+        }
+    }
+
+    /// Renders the cgroup view.
+    fn render_cgroup(&mut self, frame: &mut Frame) -> Result<()> {
+        let area = frame.area();
+        self.cgroup_list_size = area.height.saturating_sub(3) as usize;
+        let header = Row::new(
+            [
+                "CGROUP",
+                "CPU%",
+                "WAIT ms/s",
+                "THROTTLED/s",
+                "THROTTLED ms/s",
+                "PSI cpu/mem/io",
+                "WAKEUP LAT us avg/p99",
+            ]
+            .into_iter()
+            .map(|h| Cell::from(h).style(self.theme().title_style())),
+        );
+        let rows: Vec<Row> = self
+            .cgroup_tree
+            .visible_rows()
+            .iter()
+            .filter_map(|(depth, path)| {
+                let cgroup = self.cgroup_tree.cgroups.get(path)?;
+                let summary = self.cgroup_tree.summary(path);
+                let marker = if !self.cgroup_tree.has_children(path) {
+                    " "
+                } else if self.cgroup_tree.is_expanded(path) {
+                    "▾"
+                } else {
+                    "▸"
+                };
+                let delta = &cgroup.cpu_stat_delta;
+                let filtered = self
+                    .cgroup_filter
+                    .as_ref()
+                    .is_some_and(|(filter, _)| filter == path);
+                let style = if filtered {
+                    Style::default()
+                        .fg(self.theme().text_important_color())
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(self.theme().text_color())
+                };
+                Some(
+                    Row::new(vec![
+                        Cell::from(format!(
+                            "{}{} {}",
+                            "  ".repeat(*depth),
+                            marker,
+                            cgroup.name()
+                        )),
+                        Cell::from(format!("{:.1}", summary.cpu_pct())),
+                        Cell::from(format!(
+                            "{:.1}",
+                            cgroup.rate(cgroup.wait_us) as f64 / 1000.0
+                        )),
+                        Cell::from(self.format_num(cgroup.rate(delta.nr_throttled))),
+                        Cell::from(format!(
+                            "{:.1}",
+                            cgroup.rate(delta.throttled_usec) as f64 / 1000.0
+                        )),
+                        Cell::from(format!(
+                            "{:.1}/{:.1}/{:.1}",
+                            cgroup.cpu_pressure.some_avg10,
+                            cgroup.memory_pressure.some_avg10,
+                            cgroup.io_pressure.some_avg10
+                        )),
+                        Cell::from(format!(
+                            "{}/{}",
+                            self.format_num(summary.wakeup_lat_avg_us),
+                            self.format_num(summary.wakeup_lat_p99_us)
+                        )),
+                    ])
+                    .style(style),
+                )
+            })
+            .collect();
+        let filter = match &self.cgroup_filter {
+            Some((path, _)) => format!("filter {}", path),
+            None => "no filter".to_string(),
+        };
+        let block = Block::bordered()
+            .title_top(
+                Line::from(format!(
+                    "cgroups ({} to expand, {} to filter)",
+                    self.config.active_keymap.action_keys_string(Action::Enter),
+                    self.config
+                        .active_keymap
+                        .action_keys_string(Action::ToggleCgroupFilter)
+                ))
+                .style(self.theme().title_style())
+                .centered(),
+            )
+            .title_top(
+                Line::from(format!("{} sample rate {}", filter, self.bpf_sample_rate()))
+                    .style(self.theme().text_important_color())
+                    .right_aligned(),
+            )
+            .style(self.theme().border_style())
+            .border_type(BorderType::Rounded);
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Length(12),
+                Constraint::Length(15),
+                Constraint::Length(16),
+                Constraint::Fill(1),
+            ],
+        )
+        .header(header)
+        .row_highlight_style(
+            Style::default()
+                .fg(self.theme().text_important_color())
+                .add_modifier(Modifier::BOLD),
+        )
+        .block(block);
+        frame.render_stateful_widget(table, area, &mut self.cgroup_table_state);
+        Ok(())
+    }
+
+    /// Returns the path of the selected cgroup of the cgroup view.
+    fn selected_cgroup(&self) -> Option<String> {
+        let selected = self.cgroup_table_state.selected()?;
+        self.cgroup_tree
+            .visible_rows()
+            .get(selected)
+            .map(|(_, path)| path.clone())
+    }
+
+    /// Sets or clears the cgroup filter of the process and perf event data.
+    fn toggle_cgroup_filter(&mut self) -> Result<()> {
+        let selected = if self.state == AppState::Cgroup {
+            self.selected_cgroup()
+        } else {
+            None
+        };
+        // Selecting the filtered cgroup again or toggling from another view
+        // clears the filter.
+        self.cgroup_filter = match selected {
+            Some(path) if self.cgroup_filter.as_ref().map(|(filter, _)| filter) != Some(&path) => {
+                let ids = self.cgroup_tree.subtree_ids(&path);
+                Some((path, ids))
+            }
+            _ => None,
+        };
+        self.proc_data.clear();
+        self.selected_proc = None;
+        self.proc_table_state.select(Some(0));
+        self.dsq_data.clear();
+        let perf_event = self.active_event.clone();
+        self.activate_perf_event(&perf_event)
+    }
+
     /// Renders the application to the frame.
     pub fn render(&mut self, frame: &mut Frame) -> Result<()> {
         match self.state {
+            AppState::Cgroup => self.render_cgroup(frame),
             AppState::Help => self.render_help(frame),
             AppState::Event => self.render_event_list(frame),
             AppState::Node => self.render_node(frame),
+            AppState::Latency => self.render_latency(frame),
             AppState::Llc => self.render_llc(frame),
+            AppState::Process => self.render_process(frame),
             AppState::Scheduler => {
                 let [left, right] =
                     Layout::horizontal([Constraint::Fill(1); 2]).areas(frame.area());
@@ -2084,12 +3622,68 @@ impl<'a> App<'a> {
         }
     }
 
+    /// Moves the selected row of the process view.
+    fn move_proc_selection(&mut self, delta: isize) {
+        let (len, table_state) = match self.selected_proc {
+            Some(tgid) => (
+                self.proc_data
+                    .get(&tgid)
+                    .map_or(0, |proc_data| proc_data.threads.len()),
+                &mut self.thread_table_state,
+            ),
+            None => (self.proc_data.len(), &mut self.proc_table_state),
+        };
+        let selected = table_state.selected().unwrap_or(0) as isize + delta;
+        table_state.select(Some(
+            selected.clamp(0, len.saturating_sub(1) as isize) as usize
+        ));
+    }
+
+    /// Moves the selected row of the latency view.
+    fn move_hist_selection(&mut self, delta: isize) {
+        let len = self.hist_rows().len();
+        let selected = self.hist_table_state.selected().unwrap_or(0) as isize + delta;
+        self.hist_table_state.select(Some(
+            selected.clamp(0, len.saturating_sub(1) as isize) as usize
+        ));
+    }
+
+    /// Moves the selected row of the cgroup view.
+    fn move_cgroup_selection(&mut self, delta: isize) {
+        let len = self.cgroup_tree.visible_rows().len();
+        let selected = self.cgroup_table_state.selected().unwrap_or(0) as isize + delta;
+        self.cgroup_table_state.select(Some(
+            selected.clamp(0, len.saturating_sub(1) as isize) as usize
+        ));
+    }
+
+    /// Moves the selected scheduler stat.
+    fn move_sched_stats_selection(&mut self, delta: isize) {
+        let len = self.sched_stats.series.len();
+        let selected = self.sched_stats_state.selected().unwrap_or(0) as isize + delta;
+        self.sched_stats_state.select(Some(
+            selected.clamp(0, len.saturating_sub(1) as isize) as usize
+        ));
+    }
+
     /// Updates app state when the down arrow or mapped key is pressed.
     fn on_down(&mut self) {
         if self.state == AppState::Event && self.event_scroll <= self.num_perf_events {
             self.event_scroll += 1;
             self.selected_event += 1
         }
+        if self.state == AppState::Process {
+            self.move_proc_selection(1);
+        }
+        if self.state == AppState::Scheduler {
+            self.move_sched_stats_selection(1);
+        }
+        if self.state == AppState::Latency {
+            self.move_hist_selection(1);
+        }
+        if self.state == AppState::Cgroup {
+            self.move_cgroup_selection(1);
+        }
     }
 
     /// Updates app state when the up arrow or mapped key is pressed.
@@ -2098,10 +3692,31 @@ impl<'a> App<'a> {
             self.event_scroll -= 1;
             self.selected_event -= 1
         }
+        if self.state == AppState::Process {
+            self.move_proc_selection(-1);
+        }
+        if self.state == AppState::Scheduler {
+            self.move_sched_stats_selection(-1);
+        }
+        if self.state == AppState::Latency {
+            self.move_hist_selection(-1);
+        }
+        if self.state == AppState::Cgroup {
+            self.move_cgroup_selection(-1);
+        }
     }
 
     /// Updates app state when page down or mapped key is pressed.
     fn on_pg_down(&mut self) {
+        if self.state == AppState::Process {
+            self.move_proc_selection(self.proc_list_size as isize);
+        }
+        if self.state == AppState::Latency {
+            self.move_hist_selection(self.hist_list_size as isize);
+        }
+        if self.state == AppState::Cgroup {
+            self.move_cgroup_selection(self.cgroup_list_size as isize);
+        }
         if self.state == AppState::Event
             && self.event_scroll <= self.num_perf_events - self.events_list_size
         {
@@ -2112,6 +3727,15 @@ impl<'a> App<'a> {
 
     /// Updates app state when page up or mapped key is pressed.
     fn on_pg_up(&mut self) {
+        if self.state == AppState::Process {
+            self.move_proc_selection(-(self.proc_list_size as isize));
+        }
+        if self.state == AppState::Latency {
+            self.move_hist_selection(-(self.hist_list_size as isize));
+        }
+        if self.state == AppState::Cgroup {
+            self.move_cgroup_selection(-(self.cgroup_list_size as isize));
+        }
         if self.state == AppState::Event {
             if self.event_scroll > self.events_list_size {
                 self.event_scroll -= self.events_list_size - 1;
@@ -2140,14 +3764,44 @@ impl<'a> App<'a> {
                 self.available_events.push(perf_event.clone());
             }
         }
+        if self.state == AppState::Process {
+            if self.selected_proc.is_some() {
+                // The process filter can't be left from the thread view.
+                if self.process_id <= 0 {
+                    self.selected_proc = None;
+                }
+            } else if let Some(tgid) = self
+                .proc_table_state
+                .selected()
+                .and_then(|i| self.sorted_procs().get(i).copied())
+            {
+                self.selected_proc = Some(tgid);
+                self.thread_table_state.select(Some(0));
+            }
+        }
+        if self.state == AppState::Cgroup {
+            if let Some(path) = self.selected_cgroup() {
+                self.cgroup_tree.toggle_expand(&path);
+            }
+        }
     }
 
     /// Attaches any BPF programs required for perfetto traces.
     fn attach_trace_progs(&mut self) -> Result<()> {
+        let Some(skel) = &mut self.skel else {
+            return Ok(());
+        };
         self.trace_links = vec![
-            self.skel.progs.on_softirq_entry.attach()?,
-            self.skel.progs.on_softirq_exit.attach()?,
-            self.skel.progs.on_ipi_send_cpu.attach()?,
+            skel.progs.on_softirq_entry.attach()?,
+            skel.progs.on_softirq_exit.attach()?,
+            skel.progs.on_ipi_send_cpu.attach()?,
+            skel.progs.on_irq_handler_entry.attach()?,
+            skel.progs.on_irq_handler_exit.attach()?,
+            skel.progs.on_sched_migrate_task.attach()?,
+            skel.progs.on_sched_process_fork.attach()?,
+            skel.progs.on_sched_process_exec.attach()?,
+            skel.progs.on_sched_process_exit.attach()?,
+            skel.progs.on_cpu_idle.attach()?,
         ];
 
         Ok(())
@@ -2155,9 +3809,16 @@ impl<'a> App<'a> {
 
     /// Records the trace to perfetto output.
     fn stop_recording_trace(&mut self, ts: u64) -> Result<()> {
-        self.skel.maps.data_data.sample_rate = self.prev_bpf_sample_rate;
+        self.update_bpf_sample_rate(self.prev_bpf_sample_rate);
         self.state = self.prev_state.clone();
-        self.trace_manager.stop(None, Some(ts))?;
+        if self.trigger_reason.take().is_some() {
+            let prefix = self.config.trace_file_prefix().to_string();
+            let file = self.trigger.trace_file(&prefix);
+            self.trace_manager.stop(Some(file), Some(ts))?;
+            self.trigger.on_trace_done(&prefix, Instant::now())?;
+        } else {
+            self.trace_manager.stop(None, Some(ts))?;
+        }
         self.trace_links.clear();
 
         Ok(())
@@ -2168,16 +3829,22 @@ impl<'a> App<'a> {
         if self.state == AppState::Tracing {
             return Ok(());
         };
-
-        self.skel.maps.data_data.trace_duration_ns = self.config.trace_duration_ns();
-        self.skel.maps.data_data.trace_warmup_ns = self.config.trace_warmup_ns();
+        // the agent of a remote host starts the trace
+        if self.is_remote() {
+            return Ok(());
+        }
 
         if self.trace_links.is_empty() {
             self.attach_trace_progs()?;
         }
 
-        let ret = self
-            .skel
+        let Some(skel) = &mut self.skel else {
+            return Ok(());
+        };
+        skel.maps.data_data.trace_duration_ns = self.config.trace_duration_ns();
+        skel.maps.data_data.trace_warmup_ns = self.config.trace_warmup_ns();
+
+        let ret = skel
             .progs
             .start_trace
             .test_run(ProgramInput::default())?
@@ -2207,8 +3874,14 @@ impl<'a> App<'a> {
             start_time + self.config.trace_warmup_ns()
         };
         self.trace_manager.start()?;
+        if self.trigger_reason.is_some() {
+            for action in self.trigger.take_buffer(self.trace_start) {
+                self.trace_manager.on_action(&action)?;
+            }
+        }
 
-        if !stop_scheduled {
+        // the agent of a remote host schedules the stop itself
+        if let Some(skel) = self.skel.as_mut().filter(|_| !stop_scheduled) {
             let mut args = bpf_intf::schedule_stop_trace_args {
                 stop_timestamp: self.trace_start + self.config.trace_duration_ns(),
             };
@@ -2222,12 +3895,7 @@ impl<'a> App<'a> {
                 ..Default::default()
             };
 
-            let ret = self
-                .skel
-                .progs
-                .schedule_stop_trace
-                .test_run(input)?
-                .return_value;
+            let ret = skel.progs.schedule_stop_trace.test_run(input)?.return_value;
             if ret != 0 {
                 return Err(anyhow::anyhow!(
                     "schedule_stop_trace failed with exit code: {}",
@@ -2247,6 +3915,7 @@ impl<'a> App<'a> {
     fn on_scheduler_unload(&mut self) {
         self.scheduler = "".to_string();
         self.sched_stats_raw = "".to_string();
+        self.sched_stats = SchedStatsData::default();
         self.dsq_data.clear();
         let _ = self
             .cpu_data
@@ -2258,7 +3927,11 @@ impl<'a> App<'a> {
     fn on_scheduler_load(&mut self) -> Result<()> {
         self.dsq_data.clear();
         self.sched_stats_raw = "".to_string();
-        self.scheduler = read_file_string(SCHED_NAME_PATH)?;
+        self.sched_stats = SchedStatsData::default();
+        // the scheduler of a remote host is updated on the next tick
+        if !self.is_remote() {
+            self.scheduler = read_file_string(SCHED_NAME_PATH)?;
+        }
         Ok(())
     }
 
@@ -2287,6 +3960,56 @@ impl<'a> App<'a> {
         }
     }
 
+    /// Returns true if the process should be tracked in the process view.
+    fn track_process(&self, tgid: u32) -> bool {
+        self.process_id <= 0 || tgid == self.process_id as u32
+    }
+
+    /// Returns true if tasks of the cgroup pass the cgroup filter.
+    fn track_cgroup(&self, cgroup_id: u64) -> bool {
+        match &self.cgroup_filter {
+            Some((_, ids)) => ids.contains(&cgroup_id),
+            None => true,
+        }
+    }
+
+    /// Updates the per process data when a task is scheduled.
+    fn record_proc_switch(&mut self, action: &SchedSwitchAction) {
+        let max_data_size = self.max_cpu_events;
+        if action.prev_pid > 0
+            && self.track_process(action.prev_tgid)
+            && self.track_cgroup(action.prev_cgroup_id)
+        {
+            let comm = action.prev_comm.split('\0').next().unwrap_or("");
+            self.proc_data
+                .entry(action.prev_tgid)
+                .or_insert_with(|| ProcData::new(action.prev_tgid, max_data_size))
+                .thread_mut(action.prev_pid, comm)
+                .on_switch_out(TaskCounters {
+                    runtime_ns: action.prev_runtime_ns,
+                    nvcsw: action.prev_nvcsw,
+                    nivcsw: action.prev_nivcsw,
+                    nr_migrations: action.prev_nr_migrations,
+                });
+        }
+        if action.next_pid > 0
+            && self.track_process(action.next_tgid)
+            && self.track_cgroup(action.next_cgroup_id)
+        {
+            let comm = action.next_comm.split('\0').next().unwrap_or("");
+            let dsq_lat_us = if action.next_dsq_id != scx_enums.SCX_DSQ_INVALID {
+                action.next_dsq_lat_us
+            } else {
+                0
+            };
+            self.proc_data
+                .entry(action.next_tgid)
+                .or_insert_with(|| ProcData::new(action.next_tgid, max_data_size))
+                .thread_mut(action.next_pid, comm)
+                .on_switch_in(action.next_wakeup_lat_us, dsq_lat_us);
+        }
+    }
+
     /// Updates the app when a task is scheduled.
     fn on_sched_switch(&mut self, action: &SchedSwitchAction) {
         let SchedSwitchAction {
@@ -2305,9 +4028,19 @@ impl<'a> App<'a> {
             }
             return;
         }
+        self.record_proc_switch(action);
+        self.cgroup_tree.on_switch(
+            action.prev_pid,
+            action.prev_cgroup_id,
+            action.prev_runtime_ns,
+            action.next_cgroup_id,
+            action.next_wakeup_lat_us,
+        );
         if self.scheduler.is_empty() {
             return;
         }
+        let track_next = self.track_cgroup(action.next_cgroup_id);
+        let track_prev = self.track_cgroup(action.prev_cgroup_id);
 
         let cpu_data = self.cpu_data.entry(*cpu as usize).or_insert(CpuData::new(
             *cpu as usize,
@@ -2317,7 +4050,7 @@ impl<'a> App<'a> {
             self.max_cpu_events,
         ));
 
-        if *next_dsq_id != scx_enums.SCX_DSQ_INVALID && *next_dsq_lat_us > 0 {
+        if track_next && *next_dsq_id != scx_enums.SCX_DSQ_INVALID && *next_dsq_lat_us > 0 {
             cpu_data.add_event_data("dsq_lat_us", *next_dsq_lat_us);
             let next_dsq_data = self
                 .dsq_data
@@ -2340,7 +4073,7 @@ impl<'a> App<'a> {
             }
         }
 
-        if *prev_dsq_id != scx_enums.SCX_DSQ_INVALID && *prev_used_slice_ns > 0 {
+        if track_prev && *prev_dsq_id != scx_enums.SCX_DSQ_INVALID && *prev_used_slice_ns > 0 {
             let prev_dsq_data = self
                 .dsq_data
                 .entry(*prev_dsq_id)
@@ -2363,6 +4096,14 @@ impl<'a> App<'a> {
         }
     }
 
+    /// Handles events that are only recorded in traces.
+    fn on_trace_event(&mut self, ts: u64, action: &Action) -> Result<()> {
+        if self.state == AppState::Tracing && ts > self.trace_start {
+            self.trace_manager.on_action(action)?;
+        }
+        Ok(())
+    }
+
     pub fn on_gpu_mem(&mut self, action: &GpuMemAction) {
         if self.state == AppState::Tracing && action.ts > self.trace_start {
             self.trace_manager.on_gpu_mem(action);
@@ -2393,11 +4134,22 @@ impl<'a> App<'a> {
 
     /// Updates the bpf bpf sampling rate.
     pub fn update_bpf_sample_rate(&mut self, sample_rate: u32) {
-        self.skel.maps.data_data.sample_rate = sample_rate;
+        if let Some(skel) = &mut self.skel {
+            skel.maps.data_data.sample_rate = sample_rate;
+        }
     }
 
     /// Handles the action and updates application states.
     pub fn handle_action(&mut self, action: &Action) -> Result<()> {
+        // state changes are forwarded once resolved
+        if !matches!(action, Action::SetState(_)) {
+            self.forward_to_remote(action)?;
+        }
+        if self.skel.is_some() && self.state != AppState::Tracing {
+            if let Some(ts) = trigger::action_ts(action) {
+                self.trigger.record(ts, action);
+            }
+        }
         match action {
             Action::Tick => {
                 self.on_tick()?;
@@ -2415,6 +4167,12 @@ impl<'a> App<'a> {
                 }
             }
 
+            Action::NextEvent if self.state == AppState::Latency => {
+                self.hist_kind = self.hist_kind.next();
+            }
+            Action::PrevEvent if self.state == AppState::Latency => {
+                self.hist_kind = self.hist_kind.prev();
+            }
             Action::NextEvent => {
                 if self.next_event().is_err() {
                     // XXX handle error
@@ -2426,15 +4184,24 @@ impl<'a> App<'a> {
                 }
             }
             Action::NextViewState => self.next_view_state(),
+            Action::NextProcessSort => self.proc_sort = self.proc_sort.next(),
             Action::SchedReg => {
                 self.on_scheduler_load()?;
             }
             Action::SchedUnreg => {
                 self.on_scheduler_unload();
+                if let Some(reason) = self.trigger.on_sched_unreg(Instant::now()) {
+                    self.start_triggered_trace(reason)?;
+                }
             }
             Action::SchedStats(raw) => {
                 self.on_sched_stats(raw.clone());
             }
+            Action::SchedStatsMeta(raw) => {
+                self.on_sched_stats_meta(raw.clone())?;
+            }
+            Action::NextStatsTable => self.sched_stats_table += 1,
+            Action::NextStatsSort => self.sched_stats_sort += 1,
             Action::SchedCpuPerfSet(SchedCpuPerfSetAction { cpu, perf }) => {
                 self.on_cpu_perf(*cpu, *perf);
             }
@@ -2475,12 +4242,31 @@ impl<'a> App<'a> {
             Action::GpuMem(a) => {
                 self.on_gpu_mem(a);
             }
+            Action::HardIRQ(a) => {
+                self.on_trace_event(a.exit_ts, action)?;
+            }
+            Action::SchedMigrateTask(a) => {
+                self.on_trace_event(a.ts, action)?;
+            }
+            Action::SchedProcessFork(a) => {
+                self.on_trace_event(a.ts, action)?;
+            }
+            Action::SchedProcessExec(a) => {
+                self.on_trace_event(a.ts, action)?;
+            }
+            Action::SchedProcessExit(a) => {
+                self.on_trace_event(a.ts, action)?;
+            }
+            Action::CpuIdle(a) => {
+                self.on_trace_event(a.ts, action)?;
+            }
             Action::Cpuhp(a) => {
                 self.on_cpu_hp(a);
             }
             Action::HwPressure(a) => {
                 self.on_hw_pressure(a);
             }
+            Action::ClearEvent if self.state == AppState::Latency => self.hist_data.reset(),
             Action::ClearEvent => self.stop_perf_events(),
             Action::ChangeTheme => {
                 self.set_theme(self.theme().next());
@@ -2489,11 +4275,14 @@ impl<'a> App<'a> {
                 self.config
                     .set_tick_rate_ms(dur.as_millis().try_into().unwrap());
             }
+            Action::ToggleCgroupFilter => {
+                self.toggle_cgroup_filter()?;
+            }
             Action::ToggleCpuFreq => self.collect_cpu_freq = !self.collect_cpu_freq,
             Action::ToggleUncoreFreq => self.collect_uncore_freq = !self.collect_uncore_freq,
             Action::ToggleLocalization => self.localize = !self.localize,
             Action::IncBpfSampleRate => {
-                let sample_rate = self.skel.maps.data_data.sample_rate;
+                let sample_rate = self.bpf_sample_rate();
                 if sample_rate == 0 {
                     self.update_bpf_sample_rate(8_u32);
                 } else {
@@ -2501,7 +4290,7 @@ impl<'a> App<'a> {
                 }
             }
             Action::DecBpfSampleRate => {
-                let sample_rate = self.skel.maps.data_data.sample_rate;
+                let sample_rate = self.bpf_sample_rate();
                 if sample_rate > 0 {
                     // prevent overly aggressive bpf sampling, but allow disabling sampling
                     let new_rate = sample_rate >> 2;
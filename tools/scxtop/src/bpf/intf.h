@@ -16,6 +16,11 @@ typedef unsigned long long u64;
 
 enum consts {
 	MAX_COMM	= 16,
+	MAX_IRQ_NAME	= 32,
+	MAX_FILENAME	= 128,
+	HIST_SUB_BITS	= 3,
+	NR_HIST_BUCKETS	= 320,
+	MAX_DSQ_HISTS	= 1024,
 };
 
 enum stat_id {
@@ -23,6 +28,14 @@ enum stat_id {
 	NR_SCXTOP_STATS,
 };
 
+enum hist_id {
+	HIST_WAKEUP_LAT,
+	HIST_DSQ_LAT,
+	HIST_SLICE_CONSUMED,
+	HIST_SOFTIRQ,
+	NR_HISTS,
+};
+
 enum mode {
 	MODE_NORMAL,
 	MODE_TRACING,
@@ -31,10 +44,16 @@ enum mode {
 
 enum event_type {
 	CPU_HP,
+	CPU_IDLE,
 	CPU_PERF_SET,
 	GPU_MEM,
+	HARDIRQ,
 	HW_PRESSURE,
 	IPI,
+	SCHED_MIGRATE,
+	SCHED_PROCESS_EXEC,
+	SCHED_PROCESS_EXIT,
+	SCHED_PROCESS_FORK,
 	SCHED_REG,
 	SCHED_SWITCH,
 	SCHED_UNREG,
@@ -52,12 +71,14 @@ struct sched_switch_event {
 	char		next_comm[MAX_COMM];
 	u64		next_dsq_id;
 	u64		next_dsq_lat_us;
+	u64		next_wakeup_lat_us;
 	u32		next_dsq_nr;
 	u64		next_dsq_vtime;
 	u64		next_slice_ns;
 	u32		next_pid;
 	u32		next_tgid;
 	int		next_prio;
+	u64		next_cgroup_id;
 	char		prev_comm[MAX_COMM];
 	u64		prev_dsq_id;
 	u64		prev_used_slice_ns;
@@ -66,6 +87,11 @@ struct sched_switch_event {
 	u32		prev_tgid;
 	u64		prev_state;
 	int		prev_prio;
+	u64		prev_runtime_ns;
+	u64		prev_nvcsw;
+	u64		prev_nivcsw;
+	u64		prev_nr_migrations;
+	u64		prev_cgroup_id;
 };
 
 struct wakeup_event {
@@ -85,6 +111,48 @@ struct softirq_event {
 	int		softirq_nr;
 };
 
+struct hardirq_event {
+	u64		entry_ts;
+	u64		exit_ts;
+	u32		pid;
+	int		irq;
+	int		ret;
+	char		name[MAX_IRQ_NAME];
+};
+
+struct migrate_event {
+	u32		pid;
+	int		prio;
+	u32		orig_cpu;
+	u32		dest_cpu;
+	char		comm[MAX_COMM];
+};
+
+struct fork_event {
+	u32		parent_pid;
+	u32		child_pid;
+	char		parent_comm[MAX_COMM];
+	char		child_comm[MAX_COMM];
+};
+
+struct exec_event {
+	u32		pid;
+	u32		old_pid;
+	char		filename[MAX_FILENAME];
+};
+
+struct exit_event {
+	u32		pid;
+	u32		tgid;
+	int		prio;
+	char		comm[MAX_COMM];
+};
+
+struct cpu_idle_event {
+	u32		state;
+	u32		cpu_id;
+};
+
 struct ipi_event {
 	u32		pid;
 	u32		target_cpu;
@@ -122,6 +190,12 @@ struct bpf_event {
 		struct  gpu_mem_event gm;
 		struct  cpuhp_event chp;
 		struct	ipi_event ipi;
+		struct	hardirq_event hardirq;
+		struct	migrate_event migrate;
+		struct	fork_event fork;
+		struct	exec_event exec;
+		struct	exit_event exit;
+		struct	cpu_idle_event cpu_idle;
 		struct	sched_switch_event sched_switch;
 		struct	set_perf_event perf;
 		struct	softirq_event softirq;
@@ -140,6 +214,20 @@ struct task_ctx {
 	u64		last_run_ns;
 };
 
+/*
+ * Log-linear histogram of durations in ns. Every power of two is split into
+ * 1 << HIST_SUB_BITS linear sub-buckets.
+ */
+struct hist {
+	u64		buckets[NR_HIST_BUCKETS];
+};
+
+struct dsq_hist_key {
+	u64		dsq_id;
+	u32		hist_id;
+	u32		pad;
+};
+
 struct schedule_stop_trace_args {
 	u64		stop_timestamp;
 };
@@ -118,6 +118,67 @@ struct {
 	__uint(max_entries, 1000000);
 } long_tail_entries SEC(".maps");
 
+struct {
+	__uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+	__type(key, u32);
+	__type(value, struct hist);
+	__uint(max_entries, NR_HISTS);
+} cpu_hists SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, struct dsq_hist_key);
+	__type(value, struct hist);
+	__uint(max_entries, MAX_DSQ_HISTS);
+} dsq_hists SEC(".maps");
+
+// used to create dsq_hists entries as a hist doesn't fit on the stack
+struct hist zero_hist;
+
+static __always_inline u32 hist_bucket(u64 val)
+{
+	u32 msb, idx;
+
+	if (val < (1 << HIST_SUB_BITS))
+		return val;
+
+	msb = log2_u64(val) - 1;
+	idx = ((msb - HIST_SUB_BITS + 1) << HIST_SUB_BITS) |
+	      ((val >> (msb - HIST_SUB_BITS)) & ((1 << HIST_SUB_BITS) - 1));
+
+	return idx < NR_HIST_BUCKETS ? idx : NR_HIST_BUCKETS - 1;
+}
+
+/*
+ * Records a duration in the per CPU histogram and the histogram of the DSQ
+ * if the DSQ is valid.
+ */
+static void hist_record(u32 hist_id, u64 dsq_id, u64 val)
+{
+	struct dsq_hist_key key = {
+		.dsq_id = dsq_id,
+		.hist_id = hist_id,
+	};
+	struct hist *hist;
+	u32 idx = hist_bucket(val);
+
+	if (idx >= NR_HIST_BUCKETS)
+		return;
+
+	if ((hist = bpf_map_lookup_elem(&cpu_hists, &hist_id)))
+		hist->buckets[idx]++;
+
+	if (dsq_id == SCX_DSQ_INVALID)
+		return;
+
+	if (!(hist = bpf_map_lookup_elem(&dsq_hists, &key))) {
+		bpf_map_update_elem(&dsq_hists, &key, &zero_hist, BPF_NOEXIST);
+		if (!(hist = bpf_map_lookup_elem(&dsq_hists, &key)))
+			return;
+	}
+	__sync_fetch_and_add(&hist->buckets[idx], 1);
+}
+
 struct __softirq_event {
 	u32		pid;
 	u64		start_ts;
@@ -130,6 +191,19 @@ struct {
 	__uint(max_entries, 1);
 } softirq_events SEC(".maps");
 
+struct __hardirq_event {
+	u32		pid;
+	u64		start_ts;
+	char		name[MAX_IRQ_NAME];
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+	__type(key, int);
+	__type(value, struct __hardirq_event);
+	__uint(max_entries, 1);
+} hardirq_events SEC(".maps");
+
 
 static __always_inline u64 t_to_tptr(struct task_struct *p)
 {
@@ -171,6 +245,20 @@ static struct task_ctx *try_lookup_task_ctx(struct task_struct *p)
 	return tctx;
 }
 
+static struct task_ctx *lookup_task_ctx(struct task_struct *p)
+{
+	u64 tptr;
+
+	if (!p)
+		return NULL;
+
+	tptr = t_to_tptr(p);
+	if (tptr == 0)
+		return NULL;
+
+	return bpf_map_lookup_elem(&task_data, &tptr);
+}
+
 static int update_task_ctx(struct task_struct *p, u64 dsq, u64 vtime, u64 slice_ns)
 {
 	if (!enable_bpf_events)
@@ -410,20 +498,27 @@ static __always_inline int __on_sched_wakeup(struct task_struct *p)
 	struct task_ctx *tctx;
 	struct bpf_event *event;
 
-	if (!p || !should_sample())
+	if (!p)
 		return 0;
 
 	u64 now = bpf_ktime_get_ns();
-	tctx = try_lookup_task_ctx(p);
+
+	/*
+	 * The wakeup time is recorded for every wakeup so that the wakeup
+	 * latency can be reported by a sampled sched_switch even if the
+	 * wakeup itself was not sampled.
+	 */
+	if (enable_bpf_events && (tctx = try_lookup_task_ctx(p)))
+		tctx->wakeup_ts = now;
+
+	if (!should_sample())
+		return 0;
 
 	if (!(event = try_reserve_event()))
 		return 0;
 
 	event->type = SCHED_WAKEUP;
 
-	if (tctx)
-		tctx->wakeup_ts = now;
-
 	event->ts = now;
 	event->cpu = bpf_get_smp_processor_id();
 	event->event.wakeup.pid = p->pid;
@@ -467,6 +562,45 @@ int BPF_PROG(on_sched_waking, struct task_struct *p)
 }
 
 
+static void record_switch_hists(u64 now, struct task_ctx *next_tctx,
+				struct task_ctx *prev_tctx)
+{
+	if (next_tctx) {
+		u64 dsq_id = next_tctx->dsq_insert_time > 0 ?
+			next_tctx->dsq_id : SCX_DSQ_INVALID;
+
+		if (next_tctx->wakeup_ts > 0 && now > next_tctx->wakeup_ts)
+			hist_record(HIST_WAKEUP_LAT, dsq_id, now - next_tctx->wakeup_ts);
+		if (next_tctx->dsq_insert_time > 0 && now > next_tctx->dsq_insert_time)
+			hist_record(HIST_DSQ_LAT, dsq_id, now - next_tctx->dsq_insert_time);
+	}
+
+	if (prev_tctx && prev_tctx->last_run_ns > 0 && now > prev_tctx->last_run_ns)
+		hist_record(HIST_SLICE_CONSUMED, prev_tctx->dsq_id,
+			    now - prev_tctx->last_run_ns);
+}
+
+/*
+ * Resets the task contexts on every switch, otherwise a later sampled switch
+ * would attribute stale wakeups and DSQ inserts to a preempted run.
+ */
+static void reset_switch_task_ctxs(u64 now, struct task_ctx *next_tctx,
+				   struct task_ctx *prev_tctx)
+{
+	if (next_tctx) {
+		next_tctx->last_run_ns = now;
+		next_tctx->dsq_vtime = 0;
+		next_tctx->dsq_insert_time = 0;
+		next_tctx->wakeup_ts = 0;
+	}
+	if (prev_tctx) {
+		prev_tctx->dsq_id = SCX_DSQ_INVALID;
+		prev_tctx->dsq_vtime = 0;
+		prev_tctx->wakeup_ts = 0;
+		prev_tctx->dsq_insert_time = 0;
+	}
+}
+
 SEC("tp_btf/sched_switch")
 int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 	     struct task_struct *next, u64 prev_state)
@@ -474,8 +608,23 @@ int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 	struct task_ctx *next_tctx, *prev_tctx;
 	struct bpf_event *event;
 
-	if (!enable_bpf_events || !should_sample())
+	if (!enable_bpf_events)
+		return 0;
+
+	u64 now = bpf_ktime_get_ns();
+
+	/*
+	 * The histograms are updated on every switch, only the events sent to
+	 * userspace are sampled.
+	 */
+	next_tctx = lookup_task_ctx(next);
+	prev_tctx = lookup_task_ctx(prev);
+	record_switch_hists(now, next_tctx, prev_tctx);
+
+	if (!should_sample()) {
+		reset_switch_task_ctxs(now, next_tctx, prev_tctx);
 		return 0;
+	}
 
 	next_tctx = try_lookup_task_ctx(next);
 	prev_tctx = try_lookup_task_ctx(prev);
@@ -483,7 +632,6 @@ int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 	if (!(event = try_reserve_event()))
 		return -ENOMEM;
 
-	u64 now = bpf_ktime_get_ns();
 	event->type = SCHED_SWITCH;
 	event->cpu = bpf_get_smp_processor_id();
 	event->ts = now;
@@ -502,6 +650,7 @@ int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 		event->event.sched_switch.next_pid = next->pid;
 		event->event.sched_switch.next_tgid = next->tgid;
 		event->event.sched_switch.next_prio = (int)next->prio;
+		event->event.sched_switch.next_cgroup_id = BPF_CORE_READ(next, cgroups, dfl_cgrp, kn, id);
 		if (next_tctx && next_tctx->dsq_insert_time > 0) {
 			event->event.sched_switch.next_dsq_lat_us = (now - next_tctx->dsq_insert_time) / 1000;
 			event->event.sched_switch.next_dsq_id = next_tctx->dsq_id;
@@ -520,6 +669,10 @@ int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 			event->event.sched_switch.next_dsq_nr = 0;
 			event->event.sched_switch.next_dsq_vtime = 0;
 		}
+		if (next_tctx && next_tctx->wakeup_ts > 0 && now > next_tctx->wakeup_ts)
+			event->event.sched_switch.next_wakeup_lat_us = (now - next_tctx->wakeup_ts) / 1000;
+		else
+			event->event.sched_switch.next_wakeup_lat_us = 0;
 		__builtin_memcpy(&event->event.sched_switch.next_comm, &next->comm, MAX_COMM);
 	} else {
 		event->event.sched_switch.next_pid = 0;
@@ -531,6 +684,11 @@ int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 		event->event.sched_switch.prev_tgid = prev->tgid;
 		event->event.sched_switch.prev_prio = (int)prev->prio;
 		event->event.sched_switch.prev_state = prev_state;
+		event->event.sched_switch.prev_runtime_ns = prev->se.sum_exec_runtime;
+		event->event.sched_switch.prev_nvcsw = prev->nvcsw;
+		event->event.sched_switch.prev_nivcsw = prev->nivcsw;
+		event->event.sched_switch.prev_nr_migrations = prev->se.nr_migrations;
+		event->event.sched_switch.prev_cgroup_id = BPF_CORE_READ(prev, cgroups, dfl_cgrp, kn, id);
 		if (prev_tctx && prev_tctx->last_run_ns > 0) {
 			event->event.sched_switch.prev_used_slice_ns = prev_tctx->last_run_ns - now;
 			event->event.sched_switch.prev_dsq_id = prev_tctx->dsq_id;
@@ -546,18 +704,7 @@ int BPF_PROG(on_sched_switch, bool preempt, struct task_struct *prev,
 
 	bpf_ringbuf_submit(event, 0);
 
-	if (next_tctx) {
-		next_tctx->last_run_ns = bpf_ktime_get_ns();
-		next_tctx->dsq_vtime = 0;
-		next_tctx->dsq_insert_time = 0;
-		next_tctx->wakeup_ts = 0;
-	}
-	if (prev_tctx) {
-		prev_tctx->dsq_id = SCX_DSQ_INVALID;
-		prev_tctx->dsq_vtime = 0;
-		prev_tctx->wakeup_ts = 0;
-		prev_tctx->dsq_insert_time = 0;
-	}
+	reset_switch_task_ctxs(now, next_tctx, prev_tctx);
 
 	return 0;
 }
@@ -567,7 +714,7 @@ int BPF_PROG(on_softirq_entry, unsigned int nr)
 {
 	struct task_struct *p;
 
-	if (!enable_bpf_events || !should_sample())
+	if (!enable_bpf_events)
 		return 0;
 
 	p = (struct task_struct *)bpf_get_current_task();
@@ -590,16 +737,24 @@ int BPF_PROG(on_softirq_exit, unsigned int nr)
 	struct bpf_event *event;
 	struct __softirq_event *softirq_event;
 
-	if (!enable_bpf_events || !should_sample())
+	if (!enable_bpf_events)
 		return 0;
 
 	u64 exit_ts = bpf_ktime_get_ns();
 
 	softirq_event = bpf_map_lookup_elem(&softirq_events, &zero_int);
-	if (!softirq_event)
+	if (!softirq_event || !softirq_event->start_ts)
 		return 0;
 
-	bpf_map_delete_elem(&softirq_events, &zero_int);
+	u64 entry_ts = softirq_event->start_ts;
+	u32 pid = softirq_event->pid;
+	softirq_event->start_ts = 0;
+
+	if (exit_ts > entry_ts)
+		hist_record(HIST_SOFTIRQ, SCX_DSQ_INVALID, exit_ts - entry_ts);
+
+	if (!should_sample())
+		return 0;
 
 	if (!(event = try_reserve_event()))
 		return -ENOMEM;
@@ -607,8 +762,8 @@ int BPF_PROG(on_softirq_exit, unsigned int nr)
 	event->type = SOFTIRQ;
 	event->cpu = bpf_get_smp_processor_id();
 	event->ts = exit_ts;
-	event->event.softirq.pid = softirq_event->pid;
-	event->event.softirq.entry_ts = softirq_event->start_ts;
+	event->event.softirq.pid = pid;
+	event->event.softirq.entry_ts = entry_ts;
 	event->event.softirq.exit_ts = exit_ts;
 	event->event.softirq.softirq_nr = nr;
 
@@ -617,6 +772,193 @@ int BPF_PROG(on_softirq_exit, unsigned int nr)
 	return 0;
 }
 
+SEC("tp_btf/irq_handler_entry")
+int BPF_PROG(on_irq_handler_entry, int irq, struct irqaction *action)
+{
+	struct __hardirq_event *hardirq_event;
+	struct task_struct *p;
+
+	if (!enable_bpf_events)
+		return 0;
+
+	hardirq_event = bpf_map_lookup_elem(&hardirq_events, &zero_int);
+	if (!hardirq_event)
+		return 0;
+
+	p = (struct task_struct *)bpf_get_current_task();
+	hardirq_event->start_ts = bpf_ktime_get_ns();
+	if (p)
+		hardirq_event->pid = BPF_CORE_READ(p, pid);
+	else
+		hardirq_event->pid = 0;
+	if (bpf_probe_read_kernel_str(hardirq_event->name, sizeof(hardirq_event->name),
+				      BPF_CORE_READ(action, name)) < 0)
+		hardirq_event->name[0] = '\0';
+
+	return 0;
+}
+
+SEC("tp_btf/irq_handler_exit")
+int BPF_PROG(on_irq_handler_exit, int irq, struct irqaction *action, int ret)
+{
+	struct bpf_event *event;
+	struct __hardirq_event *hardirq_event;
+
+	if (!enable_bpf_events)
+		return 0;
+
+	u64 exit_ts = bpf_ktime_get_ns();
+
+	hardirq_event = bpf_map_lookup_elem(&hardirq_events, &zero_int);
+	if (!hardirq_event || !hardirq_event->start_ts)
+		return 0;
+
+	u64 entry_ts = hardirq_event->start_ts;
+	hardirq_event->start_ts = 0;
+
+	if (!should_sample())
+		return 0;
+
+	if (!(event = try_reserve_event()))
+		return -ENOMEM;
+
+	event->type = HARDIRQ;
+	event->cpu = bpf_get_smp_processor_id();
+	event->ts = exit_ts;
+	event->event.hardirq.pid = hardirq_event->pid;
+	event->event.hardirq.entry_ts = entry_ts;
+	event->event.hardirq.exit_ts = exit_ts;
+	event->event.hardirq.irq = irq;
+	event->event.hardirq.ret = ret;
+	__builtin_memcpy(&event->event.hardirq.name, &hardirq_event->name, MAX_IRQ_NAME);
+
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
+SEC("tp_btf/sched_migrate_task")
+int BPF_PROG(on_sched_migrate_task, struct task_struct *p, int dest_cpu)
+{
+	struct bpf_event *event;
+
+	if (!enable_bpf_events || !should_sample())
+		return 0;
+
+	if (!(event = try_reserve_event()))
+		return -ENOMEM;
+
+	event->type = SCHED_MIGRATE;
+	event->cpu = bpf_get_smp_processor_id();
+	event->ts = bpf_ktime_get_ns();
+	event->event.migrate.pid = p->pid;
+	event->event.migrate.prio = (int)p->prio;
+	event->event.migrate.orig_cpu = scx_bpf_task_cpu(p);
+	event->event.migrate.dest_cpu = dest_cpu;
+	__builtin_memcpy(&event->event.migrate.comm, &p->comm, MAX_COMM);
+
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
+SEC("tp_btf/sched_process_fork")
+int BPF_PROG(on_sched_process_fork, struct task_struct *parent, struct task_struct *child)
+{
+	struct bpf_event *event;
+
+	if (!enable_bpf_events || !should_sample())
+		return 0;
+
+	if (!(event = try_reserve_event()))
+		return -ENOMEM;
+
+	event->type = SCHED_PROCESS_FORK;
+	event->cpu = bpf_get_smp_processor_id();
+	event->ts = bpf_ktime_get_ns();
+	event->event.fork.parent_pid = parent->pid;
+	event->event.fork.child_pid = child->pid;
+	__builtin_memcpy(&event->event.fork.parent_comm, &parent->comm, MAX_COMM);
+	__builtin_memcpy(&event->event.fork.child_comm, &child->comm, MAX_COMM);
+
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
+SEC("tp_btf/sched_process_exec")
+int BPF_PROG(on_sched_process_exec, struct task_struct *p, u32 old_pid,
+	     struct linux_binprm *bprm)
+{
+	struct bpf_event *event;
+
+	if (!enable_bpf_events || !should_sample())
+		return 0;
+
+	if (!(event = try_reserve_event()))
+		return -ENOMEM;
+
+	event->type = SCHED_PROCESS_EXEC;
+	event->cpu = bpf_get_smp_processor_id();
+	event->ts = bpf_ktime_get_ns();
+	event->event.exec.pid = p->pid;
+	event->event.exec.old_pid = old_pid;
+	if (bpf_probe_read_kernel_str(event->event.exec.filename,
+				      sizeof(event->event.exec.filename),
+				      BPF_CORE_READ(bprm, filename)) < 0)
+		event->event.exec.filename[0] = '\0';
+
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
+SEC("tp_btf/sched_process_exit")
+int BPF_PROG(on_sched_process_exit, struct task_struct *p)
+{
+	struct bpf_event *event;
+
+	if (!enable_bpf_events || !should_sample())
+		return 0;
+
+	if (!(event = try_reserve_event()))
+		return -ENOMEM;
+
+	event->type = SCHED_PROCESS_EXIT;
+	event->cpu = bpf_get_smp_processor_id();
+	event->ts = bpf_ktime_get_ns();
+	event->event.exit.pid = p->pid;
+	event->event.exit.tgid = p->tgid;
+	event->event.exit.prio = (int)p->prio;
+	__builtin_memcpy(&event->event.exit.comm, &p->comm, MAX_COMM);
+
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
+SEC("tp_btf/cpu_idle")
+int BPF_PROG(on_cpu_idle, u32 state, u32 cpu_id)
+{
+	struct bpf_event *event;
+
+	if (!enable_bpf_events || !should_sample())
+		return 0;
+
+	if (!(event = try_reserve_event()))
+		return -ENOMEM;
+
+	event->type = CPU_IDLE;
+	event->cpu = bpf_get_smp_processor_id();
+	event->ts = bpf_ktime_get_ns();
+	event->event.cpu_idle.state = state;
+	event->event.cpu_idle.cpu_id = cpu_id;
+
+	bpf_ringbuf_submit(event, 0);
+
+	return 0;
+}
+
 static int stop_trace_timer_callback(void *map, int key, struct timer_wrapper *timerw)
 {
 	struct bpf_event *event;
@@ -708,6 +1050,16 @@ int BPF_PROG(start_trace)
 	return 0;
 }
 
+/*
+ * Begin a trace immediately when a trigger rule matched, userspace schedules
+ * stopping it. This is called via BPF_PROG_RUN from userspace.
+ */
+SEC("syscall")
+int BPF_PROG(start_triggered_trace)
+{
+	return start_trace_real(false /* schedule_stop */, true /* start_immediately */);
+}
+
 SEC("uprobe")
 int BPF_UPROBE(long_tail_tracker_entry)
 {
@@ -0,0 +1,540 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use crate::proc_data::lat_stats;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::time::Instant;
+
+/// Mount point of the cgroup2 hierarchy.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Max number of wakeup latency samples kept per cgroup.
+const MAX_LAT_SAMPLES: usize = 1024;
+
+/// Counters of a cgroup's cpu.stat file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuStat {
+    pub usage_usec: u64,
+    pub nr_periods: u64,
+    pub nr_throttled: u64,
+    pub throttled_usec: u64,
+}
+
+impl CpuStat {
+    /// Parses the contents of a cpu.stat file.
+    pub fn parse(contents: &str) -> Self {
+        let mut stat = Self::default();
+        for line in contents.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(key), Some(val)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let val = val.parse().unwrap_or(0);
+            match key {
+                "usage_usec" => stat.usage_usec = val,
+                "nr_periods" => stat.nr_periods = val,
+                "nr_throttled" => stat.nr_throttled = val,
+                "throttled_usec" => stat.throttled_usec = val,
+                _ => {}
+            }
+        }
+        stat
+    }
+
+    /// Returns the counters accumulated since base.
+    pub fn since(&self, base: &CpuStat) -> CpuStat {
+        CpuStat {
+            usage_usec: self.usage_usec.saturating_sub(base.usage_usec),
+            nr_periods: self.nr_periods.saturating_sub(base.nr_periods),
+            nr_throttled: self.nr_throttled.saturating_sub(base.nr_throttled),
+            throttled_usec: self.throttled_usec.saturating_sub(base.throttled_usec),
+        }
+    }
+}
+
+/// Pressure stall information of a *.pressure file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pressure {
+    pub some_avg10: f64,
+    pub full_avg10: f64,
+    pub some_total_us: u64,
+}
+
+impl Pressure {
+    /// Parses the contents of a *.pressure file.
+    pub fn parse(contents: &str) -> Self {
+        let mut pressure = Self::default();
+        for line in contents.lines() {
+            let mut parts = line.split_whitespace();
+            let kind = parts.next();
+            for part in parts {
+                let Some((key, val)) = part.split_once('=') else {
+                    continue;
+                };
+                match (kind, key) {
+                    (Some("some"), "avg10") => pressure.some_avg10 = val.parse().unwrap_or(0.0),
+                    (Some("full"), "avg10") => pressure.full_avg10 = val.parse().unwrap_or(0.0),
+                    (Some("some"), "total") => pressure.some_total_us = val.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        }
+        pressure
+    }
+}
+
+/// Container for per cgroup data.
+#[derive(Clone, Debug, Default)]
+pub struct CgroupData {
+    pub id: u64,
+    pub path: String,
+    pub runtime_ns: VecDeque<u64>,
+    pub wakeup_lat_us: VecDeque<u64>,
+    pub cpu_stat: CpuStat,
+    pub cpu_stat_delta: CpuStat,
+    pub cpu_pressure: Pressure,
+    pub memory_pressure: Pressure,
+    pub io_pressure: Pressure,
+    pub wait_us: u64,
+    tick_runtime_ns: u64,
+    last_read: Option<Instant>,
+    stats_elapsed_ns: u64,
+}
+
+impl CgroupData {
+    /// Creates a new CgroupData.
+    pub fn new(id: u64, path: &str) -> Self {
+        Self {
+            id,
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the name of the cgroup, the last path component.
+    pub fn name(&self) -> &str {
+        if self.path == "/" {
+            return "/";
+        }
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    /// Reads cpu.stat and the pressure files of the cgroup.
+    pub fn update_stats(&mut self) {
+        let dir = cgroup_dir(&self.path);
+        let last_read = self.last_read.replace(Instant::now());
+        self.stats_elapsed_ns = last_read.map_or(0, |ts| ts.elapsed().as_nanos() as u64);
+        if let Ok(contents) = fs::read_to_string(format!("{}/cpu.stat", dir)) {
+            let stat = CpuStat::parse(&contents);
+            self.cpu_stat_delta = stat.since(&self.cpu_stat);
+            self.cpu_stat = stat;
+        }
+        if let Ok(contents) = fs::read_to_string(format!("{}/cpu.pressure", dir)) {
+            let pressure = Pressure::parse(&contents);
+            self.wait_us = pressure
+                .some_total_us
+                .saturating_sub(self.cpu_pressure.some_total_us);
+            self.cpu_pressure = pressure;
+        }
+        if let Ok(contents) = fs::read_to_string(format!("{}/memory.pressure", dir)) {
+            self.memory_pressure = Pressure::parse(&contents);
+        }
+        if let Ok(contents) = fs::read_to_string(format!("{}/io.pressure", dir)) {
+            self.io_pressure = Pressure::parse(&contents);
+        }
+        if last_read.is_none() {
+            self.cpu_stat_delta = CpuStat::default();
+            self.wait_us = 0;
+        }
+    }
+
+    /// Returns the per second rate of a value accumulated between the last
+    /// two reads of the cgroup files.
+    pub fn rate(&self, val: u64) -> u64 {
+        if self.stats_elapsed_ns == 0 {
+            return 0;
+        }
+        (val as f64 * 1_000_000_000.0 / self.stats_elapsed_ns as f64) as u64
+    }
+}
+
+/// Returns the directory of a cgroup path.
+pub fn cgroup_dir(path: &str) -> String {
+    format!("{}{}", CGROUP_ROOT, path.trim_end_matches('/'))
+}
+
+/// Returns the parent path of a cgroup path.
+fn parent_path(path: &str) -> Option<&str> {
+    if path == "/" {
+        return None;
+    }
+    match path.rfind('/') {
+        Some(0) => Some("/"),
+        Some(idx) => Some(&path[..idx]),
+        None => None,
+    }
+}
+
+/// Returns the depth of a cgroup path, 0 for the root.
+fn path_depth(path: &str) -> usize {
+    if path == "/" {
+        0
+    } else {
+        path.matches('/').count()
+    }
+}
+
+/// Returns true if path is cgroup or one of its descendants.
+fn in_subtree(path: &str, cgroup: &str) -> bool {
+    cgroup == "/"
+        || path == cgroup
+        || (path.starts_with(cgroup) && path.as_bytes().get(cgroup.len()) == Some(&b'/'))
+}
+
+/// Runtime and wakeup latency of a cgroup and its descendants.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CgroupSummary {
+    pub runtime_ns: u64,
+    pub elapsed_ns: u64,
+    pub wakeup_lat_avg_us: u64,
+    pub wakeup_lat_p99_us: u64,
+}
+
+impl CgroupSummary {
+    /// Returns the CPU utilization in percent of a single CPU.
+    pub fn cpu_pct(&self) -> f64 {
+        if self.elapsed_ns == 0 {
+            return 0.0;
+        }
+        self.runtime_ns as f64 * 100.0 / self.elapsed_ns as f64
+    }
+}
+
+/// The cgroup hierarchy with data aggregated from sched_switch events.
+#[derive(Clone, Debug)]
+pub struct CgroupTree {
+    pub cgroups: BTreeMap<String, CgroupData>,
+    ids: HashMap<u64, String>,
+    // parent path -> child paths, updated on refresh
+    children: HashMap<String, Vec<String>>,
+    expanded: HashSet<String>,
+    // (depth, path) of the visible cgroups in tree order
+    rows: Vec<(usize, String)>,
+    // subtree summaries of the cgroups, updated on tick
+    summaries: HashMap<String, CgroupSummary>,
+    task_runtime_ns: HashMap<u32, u64>,
+    elapsed_ns: VecDeque<u64>,
+    max_data_size: usize,
+}
+
+impl CgroupTree {
+    /// Creates a new CgroupTree.
+    pub fn new(max_data_size: usize) -> Self {
+        Self {
+            cgroups: BTreeMap::new(),
+            ids: HashMap::new(),
+            children: HashMap::new(),
+            expanded: HashSet::from(["/".to_string()]),
+            rows: Vec::new(),
+            summaries: HashMap::new(),
+            task_runtime_ns: HashMap::new(),
+            elapsed_ns: VecDeque::new(),
+            max_data_size,
+        }
+    }
+
+    /// Rescans the cgroup hierarchy, keeping the data of existing cgroups.
+    pub fn refresh(&mut self) {
+        let mut found = HashMap::new();
+        let mut dirs = vec![CGROUP_ROOT.to_string()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(meta) = fs::metadata(&dir) else {
+                continue;
+            };
+            let path = match dir.strip_prefix(CGROUP_ROOT) {
+                Some("") | None => "/".to_string(),
+                Some(path) => path.to_string(),
+            };
+            found.insert(meta.ino(), path);
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    dirs.push(entry.path().to_string_lossy().to_string());
+                }
+            }
+        }
+        self.cgroups
+            .retain(|path, cgroup| found.get(&cgroup.id) == Some(path));
+        for (id, path) in &found {
+            self.cgroups
+                .entry(path.clone())
+                .or_insert_with(|| CgroupData::new(*id, path));
+        }
+        self.ids = found;
+        self.task_runtime_ns
+            .retain(|tid, _| fs::metadata(format!("/proc/{}", tid)).is_ok());
+        self.update_children();
+    }
+
+    fn update_children(&mut self) {
+        self.children.clear();
+        for path in self.cgroups.keys() {
+            if let Some(parent) = parent_path(path) {
+                self.children
+                    .entry(parent.to_string())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+        self.update_rows();
+    }
+
+    /// Records a sched_switch, attributing the runtime of the previous task
+    /// and the wakeup latency of the next task to their cgroups.
+    pub fn on_switch(
+        &mut self,
+        prev_pid: u32,
+        prev_cgroup_id: u64,
+        prev_runtime_ns: u64,
+        next_cgroup_id: u64,
+        next_wakeup_lat_us: u64,
+    ) {
+        if prev_pid > 0 {
+            let last = self
+                .task_runtime_ns
+                .insert(prev_pid, prev_runtime_ns)
+                .unwrap_or(prev_runtime_ns);
+            let runtime_ns = prev_runtime_ns.saturating_sub(last);
+            if let Some(cgroup) = self.cgroup_mut(prev_cgroup_id) {
+                cgroup.tick_runtime_ns += runtime_ns;
+            }
+        }
+        if next_wakeup_lat_us > 0 {
+            if let Some(cgroup) = self.cgroup_mut(next_cgroup_id) {
+                if cgroup.wakeup_lat_us.len() == MAX_LAT_SAMPLES {
+                    cgroup.wakeup_lat_us.pop_front();
+                }
+                cgroup.wakeup_lat_us.push_back(next_wakeup_lat_us);
+            }
+        }
+    }
+
+    fn cgroup_mut(&mut self, id: u64) -> Option<&mut CgroupData> {
+        let path = self.ids.get(&id)?;
+        self.cgroups.get_mut(path)
+    }
+
+    /// Records the runtime accumulated since the last tick.
+    pub fn tick(&mut self, elapsed_ns: u64) {
+        if self.elapsed_ns.len() >= self.max_data_size {
+            self.elapsed_ns.pop_front();
+        }
+        self.elapsed_ns.push_back(elapsed_ns);
+        for cgroup in self.cgroups.values_mut() {
+            if cgroup.runtime_ns.len() >= self.max_data_size {
+                cgroup.runtime_ns.pop_front();
+            }
+            cgroup.runtime_ns.push_back(cgroup.tick_runtime_ns);
+            cgroup.tick_runtime_ns = 0;
+        }
+        self.update_summaries();
+    }
+
+    /// Aggregates the runtime and wakeup latencies of every subtree bottom up.
+    fn update_summaries(&mut self) {
+        let elapsed_ns = self.elapsed_ns.iter().sum();
+        let mut runtimes: HashMap<&str, u64> = HashMap::new();
+        let mut lats: HashMap<&str, Vec<u64>> = HashMap::new();
+        let mut summaries = HashMap::with_capacity(self.cgroups.len());
+
+        let mut paths: Vec<&String> = self.cgroups.keys().collect();
+        paths.sort_by_key(|path| std::cmp::Reverse(path_depth(path)));
+        for path in paths {
+            let cgroup = &self.cgroups[path];
+            let runtime_ns =
+                runtimes.remove(path.as_str()).unwrap_or(0) + cgroup.runtime_ns.iter().sum::<u64>();
+            let mut subtree_lats = lats.remove(path.as_str()).unwrap_or_default();
+            subtree_lats.extend(cgroup.wakeup_lat_us.iter().copied());
+            let (avg, p99) = lat_stats(&subtree_lats);
+            summaries.insert(
+                path.clone(),
+                CgroupSummary {
+                    runtime_ns,
+                    elapsed_ns,
+                    wakeup_lat_avg_us: avg,
+                    wakeup_lat_p99_us: p99,
+                },
+            );
+            if let Some(parent) = parent_path(path) {
+                *runtimes.entry(parent).or_default() += runtime_ns;
+                lats.entry(parent).or_default().extend(subtree_lats);
+            }
+        }
+        self.summaries = summaries;
+    }
+
+    /// Reads the cgroup files of the given cgroups.
+    pub fn update_stats(&mut self, paths: &[String]) {
+        for path in paths {
+            if let Some(cgroup) = self.cgroups.get_mut(path) {
+                cgroup.update_stats();
+            }
+        }
+    }
+
+    /// Returns true if the cgroup is expanded.
+    pub fn is_expanded(&self, path: &str) -> bool {
+        self.expanded.contains(path)
+    }
+
+    /// Returns true if the cgroup has child cgroups.
+    pub fn has_children(&self, path: &str) -> bool {
+        self.children.contains_key(path)
+    }
+
+    /// Expands or collapses a cgroup.
+    pub fn toggle_expand(&mut self, path: &str) {
+        if !self.expanded.remove(path) {
+            self.expanded.insert(path.to_string());
+        }
+        self.update_rows();
+    }
+
+    /// Returns the cgroups whose ancestors are all expanded with their depth,
+    /// in tree order.
+    pub fn visible_rows(&self) -> &[(usize, String)] {
+        &self.rows
+    }
+
+    fn update_rows(&mut self) {
+        let mut rows = Vec::new();
+        if self.cgroups.contains_key("/") {
+            self.push_rows("/", &mut rows);
+        }
+        self.rows = rows;
+    }
+
+    fn push_rows(&self, path: &str, rows: &mut Vec<(usize, String)>) {
+        rows.push((path_depth(path), path.to_string()));
+        if !self.is_expanded(path) {
+            return;
+        }
+        for child in self.children.get(path).into_iter().flatten() {
+            self.push_rows(child, rows);
+        }
+    }
+
+    /// Returns the runtime and wakeup latency of a cgroup and its descendants
+    /// as of the last tick.
+    pub fn summary(&self, path: &str) -> CgroupSummary {
+        self.summaries.get(path).copied().unwrap_or_default()
+    }
+
+    /// Returns the ids of a cgroup and its descendants.
+    pub fn subtree_ids(&self, path: &str) -> HashSet<u64> {
+        self.cgroups
+            .values()
+            .filter(|cgroup| in_subtree(&cgroup.path, path))
+            .map(|cgroup| cgroup.id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_files() {
+        let stat = CpuStat::parse(
+            "usage_usec 100\nuser_usec 60\nsystem_usec 40\nnr_periods 5\nnr_throttled 2\nthrottled_usec 300\n",
+        );
+        assert_eq!(
+            stat,
+            CpuStat {
+                usage_usec: 100,
+                nr_periods: 5,
+                nr_throttled: 2,
+                throttled_usec: 300,
+            }
+        );
+        let pressure = Pressure::parse(
+            "some avg10=1.50 avg60=0.00 avg300=0.00 total=1234\nfull avg10=0.25 avg60=0.00 avg300=0.00 total=10\n",
+        );
+        assert_eq!(pressure.some_avg10, 1.5);
+        assert_eq!(pressure.full_avg10, 0.25);
+        assert_eq!(pressure.some_total_us, 1234);
+    }
+
+    #[test]
+    fn test_cgroup_paths() {
+        assert_eq!(parent_path("/"), None);
+        assert_eq!(parent_path("/a"), Some("/"));
+        assert_eq!(parent_path("/a/b"), Some("/a"));
+        assert_eq!(path_depth("/a/b"), 2);
+        assert!(in_subtree("/a/b", "/a"));
+        assert!(in_subtree("/a", "/"));
+        assert!(!in_subtree("/ab", "/a"));
+    }
+
+    fn test_tree(paths: &[&str]) -> CgroupTree {
+        let mut tree = CgroupTree::new(10);
+        for (id, path) in paths.iter().enumerate() {
+            tree.cgroups
+                .insert(path.to_string(), CgroupData::new(id as u64, path));
+            tree.ids.insert(id as u64, path.to_string());
+        }
+        tree.update_children();
+        tree
+    }
+
+    #[test]
+    fn test_cgroup_tree_rows() {
+        let mut tree = test_tree(&["/", "/a", "/a/b", "/ab", "/c"]);
+        assert!(tree.has_children("/a"));
+        assert!(!tree.has_children("/ab"));
+        let paths = |tree: &CgroupTree| -> Vec<String> {
+            tree.visible_rows().iter().map(|(_, p)| p.clone()).collect()
+        };
+        assert_eq!(paths(&tree), ["/", "/a", "/ab", "/c"]);
+        tree.toggle_expand("/a");
+        assert_eq!(paths(&tree), ["/", "/a", "/a/b", "/ab", "/c"]);
+        assert_eq!(tree.visible_rows()[2].0, 2);
+        tree.toggle_expand("/");
+        assert_eq!(paths(&tree), ["/"]);
+    }
+
+    #[test]
+    fn test_cgroup_tree_summaries() {
+        let mut tree = test_tree(&["/", "/a", "/a/b", "/ab"]);
+        tree.on_switch(0, 0, 0, 2, 100);
+        tree.on_switch(0, 0, 0, 1, 300);
+        tree.on_switch(0, 0, 0, 3, 1000);
+        for (id, runtime_ns) in [(1, 10), (2, 20), (3, 40)] {
+            tree.cgroup_mut(id).unwrap().tick_runtime_ns = runtime_ns;
+        }
+        // Summaries are only updated on tick.
+        assert_eq!(tree.summary("/a").runtime_ns, 0);
+        tree.tick(1000);
+
+        let a = tree.summary("/a");
+        assert_eq!(a.runtime_ns, 30);
+        assert_eq!(a.elapsed_ns, 1000);
+        assert_eq!(a.wakeup_lat_avg_us, 200);
+        assert_eq!(tree.summary("/a/b").runtime_ns, 20);
+        assert_eq!(tree.summary("/ab").wakeup_lat_avg_us, 1000);
+        let root = tree.summary("/");
+        assert_eq!(root.runtime_ns, 70);
+        assert_eq!(root.wakeup_lat_avg_us, 466);
+        assert_eq!(tree.summary("/missing").runtime_ns, 0);
+    }
+}
@@ -2,6 +2,7 @@
 //
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
+use crate::recorder::RecordFormat;
 use crate::APP;
 use crate::STATS_SOCKET_PATH;
 use crate::TRACE_FILE_PREFIX;
@@ -82,6 +83,9 @@ pub struct TuiArgs {
     /// Minimum latency to trigger a trace.
     #[arg(long, default_value_t = 100000000)]
     pub experimental_long_tail_tracing_min_latency_ns: u64,
+    /// Renders the host of a `scxtop serve` agent at host:port instead of the local host.
+    #[arg(long)]
+    pub remote: Option<String>,
 }
 
 #[derive(Clone, Parser, Debug)]
@@ -102,6 +106,70 @@ pub struct TraceArgs {
     pub verbose: u8,
 }
 
+#[derive(Clone, Parser, Debug)]
+#[command(about = "Records metrics without the TUI")]
+pub struct RecordArgs {
+    /// Record duration in ms, records until interrupted if not set.
+    #[arg(short = 'd', long)]
+    pub duration_ms: Option<u64>,
+    /// Sample interval in ms.
+    #[arg(short = 'i', long, default_value_t = 1000)]
+    pub interval_ms: u64,
+    /// Output format.
+    #[arg(short = 'f', long, value_enum, default_value_t = RecordFormat::Csv)]
+    pub format: RecordFormat,
+    /// Output file, stdout if not present.
+    #[arg(short = 'o', long)]
+    pub output_file: Option<String>,
+    /// Stats unix socket path.
+    #[arg(short, long)]
+    pub stats_socket_path: Option<String>,
+    /// Process to monitor or all.
+    #[arg(long, default_value_t = -1)]
+    pub process_id: i32,
+    /// Enable verbose output, including libbpf details. Specify multiple
+    /// times to increase verbosity.
+    #[clap(short = 'v', long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+#[derive(Clone, Parser, Debug)]
+#[command(about = "Streams events and metrics to remote scxtop clients")]
+pub struct ServeArgs {
+    /// Address to listen on for clients.
+    #[arg(short = 'l', long, default_value = "127.0.0.1:4242")]
+    pub listen: String,
+    /// Tick interval in ms.
+    #[arg(short = 'i', long, default_value_t = 250)]
+    pub interval_ms: u64,
+    /// Stats unix socket path.
+    #[arg(short, long)]
+    pub stats_socket_path: Option<String>,
+    /// Process to monitor or all.
+    #[arg(long, default_value_t = -1)]
+    pub process_id: i32,
+    /// Custom perf events colon delimited (ex: "<event_name>:<event and umask ex: 0x023>:<event_type ex: 4>")
+    #[arg(long)]
+    pub perf_events: Vec<String>,
+    /// Enable verbose output, including libbpf details. Specify multiple
+    /// times to increase verbosity.
+    #[clap(short = 'v', long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+#[derive(Clone, Parser, Debug)]
+#[command(about = "Analyzes a perfetto trace collected by scxtop")]
+pub struct AnalyzeArgs {
+    /// Trace file to analyze.
+    pub trace_file: String,
+    /// Number of entries shown per report.
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub top: usize,
+    /// Number of surrounding events shown for each scheduling delay.
+    #[arg(short = 'c', long, default_value_t = 8)]
+    pub context: usize,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Runs the scxtop TUI.
@@ -110,6 +178,15 @@ pub enum Commands {
     /// Collects a trace.
     Trace(TraceArgs),
 
+    /// Records metrics to a file without the TUI.
+    Record(RecordArgs),
+
+    /// Streams events and metrics to `scxtop --remote` clients.
+    Serve(ServeArgs),
+
+    /// Analyzes a perfetto trace.
+    Analyze(AnalyzeArgs),
+
     #[clap(hide = true)]
     GenerateCompletions {
         /// The shell type
@@ -3,18 +3,25 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
 
+use crate::cli::RecordArgs;
+use crate::cli::ServeArgs;
 use crate::cli::TuiArgs;
+use crate::derived_metric::builtin_metrics;
+use crate::derived_metric::DerivedMetric;
 use crate::keymap::parse_action;
 use crate::keymap::parse_key;
+use crate::trigger::TriggerRule;
 use crate::AppTheme;
 use crate::KeyMap;
 use crate::STATS_SOCKET_PATH;
 use crate::TRACE_FILE_PREFIX;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use xdg;
 
 /// `scxtop` can use a configuration file, which can be generated using the `S` key
@@ -46,20 +53,66 @@ use xdg;
 /// "]" = "IncBpfSampleRate"
 /// Down = "Down"
 /// l = "AppStateLlc"
+/// H = "AppStateLatency"
 /// k = "NextEvent"
 /// a = "RecordTrace"
 /// j = "PrevEvent"
 /// v = "NextViewState"
 /// h = "AppStateHelp"
 /// n = "AppStateNode"
+/// p = "AppStateProcess"
+/// o = "NextProcessSort"
+/// b = "NextStatsTable"
+/// c = "NextStatsSort"
 /// s = "AppStateScheduler"
 /// e = "AppStateEvent"
 /// w = "RecordTrace"
 /// f = "ToggleCpuFreq"
+/// F = "ToggleCgroupFilter"
+/// C = "AppStateCgroup"
 /// Enter = "Enter"
 /// "Page Up" = "PageUp"
 /// x = "ClearEvent"
 /// ```
+///
+/// Derived metrics are ratios of perf events that are read as a group, they are
+/// shown along the other events and replace built-in metrics of the same name.
+/// Events with a `-` in their name need whitespace around a subtraction, and
+/// `interval_ns` and `max_freq_khz` can be used for time and frequency
+/// normalization. Values are integers, so expressions dividing by an event or
+/// variable must be scaled by a constant factor:
+/// ```text
+/// [derived_metrics]
+/// ipc_x1000 = "1000 * instructions / cycles"
+/// llc_miss_pct = "100 * LLC-load-misses / LLC-loads"
+/// ```
+///
+/// Trigger rules start a perfetto trace automatically, including the events of
+/// `trigger_pre_ms` before the trigger. Triggered traces are written to
+/// `<trace_file_prefix>_trigger_<ms>.proto` and only the latest
+/// `trigger_max_traces` are kept. The preceding events are sampled at the
+/// configured sample rate and don't include trace only events:
+/// ```text
+/// trigger_pre_ms = 2000
+/// trigger_max_traces = 10
+/// trigger_cooldown_ms = 30000
+///
+/// [[triggers]]
+/// kind = "dsq_lat_p99"
+/// threshold_us = 10000
+///
+/// [[triggers]]
+/// kind = "sched_unreg"
+///
+/// [[triggers]]
+/// kind = "dropped_events"
+/// threshold = 1000
+///
+/// [[triggers]]
+/// kind = "sched_stat"
+/// stat = "/layers/batch/util"
+/// threshold = 95.0
+/// ```
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Config {
@@ -70,6 +123,9 @@ pub struct Config {
     pub active_keymap: KeyMap,
     /// Configured perf events. Must be in format <alias>:<event_config>
     pub perf_events: Vec<String>,
+    /// Metrics derived from expressions over perf events.
+    #[serde(default)]
+    pub derived_metrics: BTreeMap<String, String>,
     /// TUI theme.
     theme: Option<AppTheme>,
     /// App tick rate in milliseconds.
@@ -92,6 +148,15 @@ pub struct Config {
     trace_tick_warmup: Option<usize>,
     /// Duration to warmup a trace before collecting in ms.
     trace_warmup_ms: Option<u64>,
+    /// Rules that start a trace when they match.
+    #[serde(default)]
+    triggers: Vec<TriggerRule>,
+    /// Duration of the events preceding a trigger included in the trace in ms.
+    trigger_pre_ms: Option<u64>,
+    /// Number of triggered traces kept, 0 keeps all.
+    trigger_max_traces: Option<usize>,
+    /// Minimum time between a triggered trace and the next trigger in ms.
+    trigger_cooldown_ms: Option<u64>,
 }
 
 impl From<TuiArgs> for Config {
@@ -102,6 +167,7 @@ impl From<TuiArgs> for Config {
             exclude_bpf: args.exclude_bpf,
             keymap: None,
             perf_events: args.perf_events,
+            derived_metrics: BTreeMap::new(),
             stats_socket_path: args.stats_socket_path,
             theme: None,
             tick_rate_ms: args.tick_rate_ms,
@@ -111,6 +177,33 @@ impl From<TuiArgs> for Config {
             trace_ticks: args.trace_ticks,
             trace_duration_ms: args.trace_duration_ms,
             worker_threads: args.worker_threads,
+            triggers: vec![],
+            trigger_pre_ms: None,
+            trigger_max_traces: None,
+            trigger_cooldown_ms: None,
+        }
+    }
+}
+
+impl From<RecordArgs> for Config {
+    fn from(args: RecordArgs) -> Config {
+        Config {
+            debug: Some(args.verbose > 2),
+            stats_socket_path: args.stats_socket_path,
+            tick_rate_ms: Some(args.interval_ms as usize),
+            ..Config::empty_config()
+        }
+    }
+}
+
+impl From<ServeArgs> for Config {
+    fn from(args: ServeArgs) -> Config {
+        Config {
+            debug: Some(args.verbose > 2),
+            perf_events: args.perf_events,
+            stats_socket_path: args.stats_socket_path,
+            tick_rate_ms: Some(args.interval_ms as usize),
+            ..Config::empty_config()
         }
     }
 }
@@ -145,6 +238,11 @@ impl Config {
             } else {
                 rhs.perf_events
             },
+            derived_metrics: if !self.derived_metrics.is_empty() {
+                self.derived_metrics
+            } else {
+                rhs.derived_metrics
+            },
             stats_socket_path: self.stats_socket_path.or(rhs.stats_socket_path),
             trace_file_prefix: self.trace_file_prefix.or(rhs.trace_file_prefix),
             trace_ticks: self.trace_ticks.or(rhs.trace_ticks),
@@ -152,6 +250,14 @@ impl Config {
             worker_threads: self.worker_threads.or(rhs.worker_threads),
             trace_tick_warmup: self.trace_tick_warmup.or(rhs.trace_tick_warmup),
             trace_warmup_ms: self.trace_warmup_ms.or(rhs.trace_warmup_ms),
+            triggers: if !self.triggers.is_empty() {
+                self.triggers
+            } else {
+                rhs.triggers
+            },
+            trigger_pre_ms: self.trigger_pre_ms.or(rhs.trigger_pre_ms),
+            trigger_max_traces: self.trigger_max_traces.or(rhs.trigger_max_traces),
+            trigger_cooldown_ms: self.trigger_cooldown_ms.or(rhs.trigger_cooldown_ms),
         }
     }
 
@@ -227,6 +333,39 @@ impl Config {
             * 1_000_000
     }
 
+    /// Returns the built-in and configured derived metrics, configured metrics replace
+    /// built-in metrics of the same name.
+    pub fn derived_metrics(&self) -> Result<Vec<DerivedMetric>> {
+        let mut metrics: BTreeMap<&str, &str> = builtin_metrics().into_iter().collect();
+        for (name, expr) in &self.derived_metrics {
+            metrics.insert(name, expr);
+        }
+        metrics
+            .into_iter()
+            .map(|(name, expr)| DerivedMetric::new(name, expr))
+            .collect()
+    }
+
+    /// Rules that start a trace when they match.
+    pub fn triggers(&self) -> &[TriggerRule] {
+        &self.triggers
+    }
+
+    /// Duration of the events preceding a trigger included in the trace in ns.
+    pub fn trigger_pre_ns(&self) -> u64 {
+        self.trigger_pre_ms.unwrap_or(2_000) * 1_000_000
+    }
+
+    /// Number of triggered traces kept, 0 keeps all.
+    pub fn trigger_max_traces(&self) -> usize {
+        self.trigger_max_traces.unwrap_or(10)
+    }
+
+    /// Minimum time between a triggered trace and the next trigger.
+    pub fn trigger_cooldown(&self) -> Duration {
+        Duration::from_millis(self.trigger_cooldown_ms.unwrap_or(30_000))
+    }
+
     /// Returns a config with nothing set.
     pub fn empty_config() -> Config {
         Config {
@@ -236,6 +375,7 @@ impl Config {
             tick_rate_ms: None,
             debug: None,
             perf_events: vec![],
+            derived_metrics: BTreeMap::new(),
             exclude_bpf: None,
             stats_socket_path: None,
             trace_file_prefix: None,
@@ -244,6 +384,10 @@ impl Config {
             worker_threads: None,
             trace_tick_warmup: None,
             trace_warmup_ms: None,
+            triggers: vec![],
+            trigger_pre_ms: None,
+            trigger_max_traces: None,
+            trigger_cooldown_ms: None,
         }
     }
 
@@ -257,6 +401,7 @@ impl Config {
             debug: None,
             exclude_bpf: None,
             perf_events: vec![],
+            derived_metrics: BTreeMap::new(),
             stats_socket_path: None,
             trace_file_prefix: None,
             trace_ticks: None,
@@ -264,6 +409,10 @@ impl Config {
             worker_threads: None,
             trace_tick_warmup: None,
             trace_warmup_ms: None,
+            triggers: vec![],
+            trigger_pre_ms: None,
+            trigger_max_traces: None,
+            trigger_cooldown_ms: None,
         };
         config.tick_rate_ms = Some(config.tick_rate_ms());
         config.debug = Some(config.debug());
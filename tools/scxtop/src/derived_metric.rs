@@ -0,0 +1,396 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use anyhow::{anyhow, bail, Result};
+
+use std::collections::BTreeMap;
+
+/// Variable of the elapsed time in ns since the previous read of the events.
+pub const INTERVAL_NS: &str = "interval_ns";
+/// Variable of the maximum frequency in kHz of the CPUs the metric covers.
+pub const MAX_FREQ_KHZ: &str = "max_freq_khz";
+
+/// Returns the built-in derived metrics as (name, expression) pairs.
+pub fn builtin_metrics() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("ipc_x1000", "1000 * instructions / cycles"),
+        ("cache_miss_pct", "100 * cache-misses / cache-references"),
+        ("branch_miss_pct", "100 * branch-misses / branches"),
+        ("llc_miss_pct", "100 * LLC-load-misses / LLC-loads"),
+        ("backend_stall_pct", "100 * stalled-cycles-backend / cycles"),
+        (
+            "frontend_stall_pct",
+            "100 * stalled-cycles-frontend / cycles",
+        ),
+        (
+            "freq_util_pct",
+            "100000000 * cycles / (max_freq_khz * interval_ns)",
+        ),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Num(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, vars: &BTreeMap<&str, f64>) -> Result<f64> {
+        Ok(match self {
+            Expr::Num(val) => *val,
+            Expr::Var(var) => *vars
+                .get(var.as_str())
+                .ok_or_else(|| anyhow!("no value for {}", var))?,
+            Expr::Neg(expr) => -expr.eval(vars)?,
+            Expr::Binary(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval(vars)?, rhs.eval(vars)?);
+                match op {
+                    Op::Add => lhs + rhs,
+                    Op::Sub => lhs - rhs,
+                    Op::Mul => lhs * rhs,
+                    Op::Div => lhs / rhs,
+                }
+            }
+        })
+    }
+
+    /// Returns whether the expression divides by a perf event or variable, whose results are
+    /// mostly fractional.
+    fn divides_by_var(&self) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Var(_) => false,
+            Expr::Neg(expr) => expr.divides_by_var(),
+            Expr::Binary(lhs, op, rhs) => {
+                (*op == Op::Div && rhs.has_vars()) || lhs.divides_by_var() || rhs.divides_by_var()
+            }
+        }
+    }
+
+    fn has_vars(&self) -> bool {
+        let mut vars = vec![];
+        self.vars(&mut vars);
+        !vars.is_empty()
+    }
+
+    /// Returns the numeric factor the top level product of the expression is scaled by.
+    fn scale(&self) -> Option<f64> {
+        match self {
+            Expr::Num(val) => Some(*val),
+            Expr::Neg(expr) => expr.scale(),
+            Expr::Binary(lhs, Op::Mul, rhs) => match (lhs.scale(), rhs.scale()) {
+                (Some(lhs), Some(rhs)) => Some(lhs * rhs),
+                (lhs, rhs) => lhs.or(rhs),
+            },
+            Expr::Binary(lhs, Op::Div, _) => lhs.scale(),
+            _ => None,
+        }
+    }
+
+    fn vars<'a>(&'a self, vars: &mut Vec<&'a str>) {
+        match self {
+            Expr::Num(_) => {}
+            Expr::Var(var) => {
+                if !vars.contains(&var.as_str()) {
+                    vars.push(var);
+                }
+            }
+            Expr::Neg(expr) => expr.vars(vars),
+            Expr::Binary(lhs, _, rhs) => {
+                lhs.vars(vars);
+                rhs.vars(vars);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(Op),
+    LParen,
+    RParen,
+}
+
+/// Splits an expression into tokens. A `-` followed by an alphanumeric character is part of
+/// an identifier, so subtraction of events has to be separated by whitespace.
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '+' | '-' | '*' | '/' => {
+                tokens.push(Token::Op(match c {
+                    '+' => Op::Add,
+                    '-' => Op::Sub,
+                    '*' => Op::Mul,
+                    _ => Op::Div,
+                }));
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let num: String = chars[start..i].iter().collect();
+                tokens.push(Token::Num(
+                    num.parse().map_err(|_| anyhow!("invalid number {}", num))?,
+                ));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() {
+                    let c = chars[i];
+                    let continues_ident = c.is_ascii_alphanumeric()
+                        || c == '_'
+                        || c == '.'
+                        || (c == '-'
+                            && chars
+                                .get(i + 1)
+                                .is_some_and(|next| next.is_ascii_alphanumeric()));
+                    if !continues_ident {
+                        break;
+                    }
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            _ => bail!("unexpected character '{}' in {}", c, expr),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser of the tokens of an expression.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ (Op::Add | Op::Sub))) = self.peek().cloned() {
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    /// term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<Expr> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Op(op @ (Op::Mul | Op::Div))) = self.peek().cloned() {
+            self.pos += 1;
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    /// factor := number | identifier | '-' factor | '(' expr ')'
+    fn factor(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Num(val)) => Ok(Expr::Num(val)),
+            Some(Token::Ident(var)) => Ok(Expr::Var(var)),
+            Some(Token::Op(Op::Sub)) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(Token::LParen) => {
+                let expr = self.expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => bail!("missing closing parenthesis"),
+                }
+            }
+            Some(token) => bail!("unexpected token {:?}", token),
+            None => bail!("unexpected end of expression"),
+        }
+    }
+}
+
+/// Metric derived from an expression over perf events, e.g. `1000 * instructions / cycles`.
+///
+/// Metric values are integers like the values of perf events, so an expression that divides
+/// by an event or variable must be scaled by a constant factor, e.g. `ipc_x1000` rather than
+/// an `ipc` that would only ever be 0, 1 or 2.
+#[derive(Clone, Debug)]
+pub struct DerivedMetric {
+    pub name: String,
+    pub expr: String,
+    parsed: Expr,
+}
+
+impl DerivedMetric {
+    /// Creates a DerivedMetric by parsing the expression.
+    pub fn new(name: &str, expr: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(expr)?,
+            pos: 0,
+        };
+        let parsed = parser
+            .expr()
+            .map_err(|e| anyhow!("invalid expression of {}: {}", name, e))?;
+        if let Some(token) = parser.peek() {
+            bail!("invalid expression of {}: unexpected {:?}", name, token);
+        }
+        if parsed.divides_by_var() && parsed.scale().is_none() {
+            bail!(
+                "expression of {} divides by a variable without a scale, metric values are \
+                 integers so it needs a constant factor (e.g. 1000 * ({}))",
+                name,
+                expr
+            );
+        }
+        Ok(Self {
+            name: name.to_string(),
+            expr: expr.to_string(),
+            parsed,
+        })
+    }
+
+    /// Returns the perf events the metric is derived from.
+    pub fn events(&self) -> Vec<&str> {
+        let mut vars = vec![];
+        self.parsed.vars(&mut vars);
+        vars.retain(|var| *var != INTERVAL_NS && *var != MAX_FREQ_KHZ);
+        vars
+    }
+
+    /// Returns whether the metric uses the maximum frequency of the CPUs.
+    pub fn uses_max_freq(&self) -> bool {
+        let mut vars = vec![];
+        self.parsed.vars(&mut vars);
+        vars.contains(&MAX_FREQ_KHZ)
+    }
+
+    /// Evaluates the metric rounded to an integer, values that are not finite or negative (e.g.
+    /// of a division by zero on an idle CPU) are returned as 0.
+    pub fn eval(&self, vars: &BTreeMap<&str, f64>) -> Result<u64> {
+        let val = self.parsed.eval(vars)?;
+        if !val.is_finite() || val < 0.0 {
+            return Ok(0);
+        }
+        Ok(val.round() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vals: &[(&'static str, f64)]) -> BTreeMap<&'static str, f64> {
+        vals.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_precedence() {
+        let metric = DerivedMetric::new("m", "1 + 2 * 3 - (4 - 2) / 2").unwrap();
+        assert_eq!(metric.eval(&BTreeMap::new()).unwrap(), 6);
+        let metric = DerivedMetric::new("m", "-2 * -3").unwrap();
+        assert_eq!(metric.eval(&BTreeMap::new()).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_hyphenated_events() {
+        let metric = DerivedMetric::new("m", "100 * LLC-load-misses / LLC-loads").unwrap();
+        assert_eq!(metric.events(), vec!["LLC-load-misses", "LLC-loads"]);
+        let val = metric
+            .eval(&vars(&[("LLC-load-misses", 25.0), ("LLC-loads", 100.0)]))
+            .unwrap();
+        assert_eq!(val, 25);
+
+        let metric = DerivedMetric::new("m", "cycles - instructions").unwrap();
+        assert_eq!(metric.events(), vec!["cycles", "instructions"]);
+    }
+
+    #[test]
+    fn test_builtin_metrics() {
+        for (name, expr) in builtin_metrics() {
+            let metric = DerivedMetric::new(name, expr).unwrap();
+            assert!(!metric.events().is_empty());
+        }
+        let metric = DerivedMetric::new("freq_util_pct", builtin_metrics()[6].1).unwrap();
+        assert!(metric.uses_max_freq());
+        assert_eq!(metric.events(), vec!["cycles"]);
+        // 1.5 billion cycles in a second on a 3GHz CPU
+        let val = metric
+            .eval(&vars(&[
+                ("cycles", 1_500_000_000.0),
+                (MAX_FREQ_KHZ, 3_000_000.0),
+                (INTERVAL_NS, 1_000_000_000.0),
+            ]))
+            .unwrap();
+        assert_eq!(val, 50);
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let metric = DerivedMetric::new("ipc_x1000", "1000 * instructions / cycles").unwrap();
+        let val = metric
+            .eval(&vars(&[("instructions", 0.0), ("cycles", 0.0)]))
+            .unwrap();
+        assert_eq!(val, 0);
+        assert!(metric.eval(&vars(&[("instructions", 1.0)])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(DerivedMetric::new("m", "cycles /").is_err());
+        assert!(DerivedMetric::new("m", "(cycles / instructions").is_err());
+        assert!(DerivedMetric::new("m", "cycles instructions").is_err());
+        assert!(DerivedMetric::new("m", "cycles % 2").is_err());
+    }
+
+    #[test]
+    fn test_scale() {
+        // ratios of events need a constant factor
+        assert!(DerivedMetric::new("ipc", "instructions / cycles").is_err());
+        assert!(DerivedMetric::new("m", "cycles + instructions / cycles").is_err());
+        assert!(DerivedMetric::new("m", "1000 * cycles + instructions / cycles").is_err());
+
+        let metric = DerivedMetric::new("ipc_x1000", "instructions / cycles * 1000").unwrap();
+        let val = metric
+            .eval(&vars(&[("instructions", 1_500.0), ("cycles", 1_000.0)]))
+            .unwrap();
+        assert_eq!(val, 1_500);
+        assert!(DerivedMetric::new("m", "100 * (cycles - instructions) / cycles").is_ok());
+        assert!(DerivedMetric::new("m", "-100 * instructions / cycles").is_ok());
+        // division by constants and no division at all are fine
+        assert!(DerivedMetric::new("kcycles", "cycles / 1000").is_ok());
+        assert!(DerivedMetric::new("m", "cycles - instructions").is_ok());
+    }
+}
@@ -0,0 +1,305 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use crate::bpf_intf;
+use crate::bpf_skel::BpfSkel;
+
+use anyhow::Result;
+use libbpf_rs::MapCore;
+use libbpf_rs::MapFlags;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+/// Number of linear sub-buckets per power of two as a power of two.
+pub const HIST_SUB_BITS: u32 = bpf_intf::consts_HIST_SUB_BITS;
+/// Number of buckets of a histogram.
+pub const NR_HIST_BUCKETS: usize = bpf_intf::consts_NR_HIST_BUCKETS as usize;
+const NR_HISTS: usize = bpf_intf::hist_id_NR_HISTS as usize;
+
+/// Durations tracked as histograms on the BPF side.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum HistKind {
+    #[default]
+    WakeupLat,
+    DsqLat,
+    SliceConsumed,
+    Softirq,
+}
+
+impl HistKind {
+    /// Returns the id of the histogram in the BPF maps.
+    pub fn id(&self) -> usize {
+        (match self {
+            HistKind::WakeupLat => bpf_intf::hist_id_HIST_WAKEUP_LAT,
+            HistKind::DsqLat => bpf_intf::hist_id_HIST_DSQ_LAT,
+            HistKind::SliceConsumed => bpf_intf::hist_id_HIST_SLICE_CONSUMED,
+            HistKind::Softirq => bpf_intf::hist_id_HIST_SOFTIRQ,
+        }) as usize
+    }
+
+    /// Returns the next HistKind.
+    pub fn next(&self) -> Self {
+        match self {
+            HistKind::WakeupLat => HistKind::DsqLat,
+            HistKind::DsqLat => HistKind::SliceConsumed,
+            HistKind::SliceConsumed => HistKind::Softirq,
+            HistKind::Softirq => HistKind::WakeupLat,
+        }
+    }
+
+    /// Returns the previous HistKind.
+    pub fn prev(&self) -> Self {
+        match self {
+            HistKind::WakeupLat => HistKind::Softirq,
+            HistKind::DsqLat => HistKind::WakeupLat,
+            HistKind::SliceConsumed => HistKind::DsqLat,
+            HistKind::Softirq => HistKind::SliceConsumed,
+        }
+    }
+}
+
+impl std::fmt::Display for HistKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HistKind::WakeupLat => write!(f, "wakeup latency"),
+            HistKind::DsqLat => write!(f, "DSQ latency"),
+            HistKind::SliceConsumed => write!(f, "slice consumed"),
+            HistKind::Softirq => write!(f, "softirq duration"),
+        }
+    }
+}
+
+/// Returns the bucket of a value, matching hist_bucket() on the BPF side.
+pub fn hist_bucket(val: u64) -> usize {
+    let sub = 1 << HIST_SUB_BITS;
+    if val < sub {
+        return val as usize;
+    }
+    let msb = u64::BITS - 1 - val.leading_zeros();
+    let idx = ((msb - HIST_SUB_BITS + 1) << HIST_SUB_BITS) as u64
+        | ((val >> (msb - HIST_SUB_BITS)) & (sub - 1));
+    (idx as usize).min(NR_HIST_BUCKETS - 1)
+}
+
+/// Returns the lowest value of a bucket.
+pub fn bucket_lower(idx: usize) -> u64 {
+    let sub = 1 << HIST_SUB_BITS;
+    if idx < sub {
+        return idx as u64;
+    }
+    let shift = (idx >> HIST_SUB_BITS) as u32 - 1;
+    ((sub + (idx & (sub - 1))) as u64) << shift
+}
+
+/// Returns the highest value of a bucket.
+pub fn bucket_upper(idx: usize) -> u64 {
+    if idx + 1 >= NR_HIST_BUCKETS {
+        return u64::MAX;
+    }
+    bucket_lower(idx + 1) - 1
+}
+
+/// Histogram of durations in ns.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LatHist {
+    pub buckets: Vec<u64>,
+}
+
+impl Default for LatHist {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; NR_HIST_BUCKETS],
+        }
+    }
+}
+
+impl LatHist {
+    /// Creates a LatHist from the raw BPF map value.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut hist = Self::default();
+        for (bucket, chunk) in hist.buckets.iter_mut().zip(bytes.chunks_exact(8)) {
+            *bucket = u64::from_ne_bytes(chunk.try_into().expect("buckets are u64s"));
+        }
+        hist
+    }
+
+    /// Records a value.
+    pub fn record(&mut self, val: u64) {
+        self.buckets[hist_bucket(val)] += 1;
+    }
+
+    /// Adds the counts of another histogram.
+    pub fn add(&mut self, other: &LatHist) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    /// Returns the histogram of the values recorded since base.
+    pub fn since(&self, base: &LatHist) -> LatHist {
+        LatHist {
+            buckets: self
+                .buckets
+                .iter()
+                .zip(&base.buckets)
+                .map(|(count, base)| count.saturating_sub(*base))
+                .collect(),
+        }
+    }
+
+    /// Returns the number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the highest value of the bucket holding the percentile.
+    pub fn percentile(&self, pct: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((pct / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return bucket_upper(idx);
+            }
+        }
+        0
+    }
+
+    /// Returns the counts merged into power of two buckets with their lowest value.
+    pub fn log2_buckets(&self) -> Vec<(u64, u64)> {
+        let sub = 1 << HIST_SUB_BITS;
+        let mut buckets: Vec<(u64, u64)> = Vec::new();
+        for (idx, count) in self.buckets.iter().enumerate() {
+            let lower = bucket_lower(idx.max(1) / sub * sub);
+            match buckets.last_mut() {
+                Some((last, total)) if *last == lower => *total += count,
+                _ => buckets.push((lower, *count)),
+            }
+        }
+        let first = buckets.iter().position(|(_, count)| *count > 0);
+        let last = buckets.iter().rposition(|(_, count)| *count > 0);
+        match (first, last) {
+            (Some(first), Some(last)) => buckets[first..=last].to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Histograms of all kinds for a CPU or DSQ.
+pub type HistSet = [LatHist; NR_HISTS];
+
+/// Container for the histograms collected on the BPF side.
+#[derive(Clone, Debug, Default)]
+pub struct HistData {
+    pub cpus: BTreeMap<usize, HistSet>,
+    pub dsqs: BTreeMap<u64, HistSet>,
+    base_cpus: BTreeMap<usize, HistSet>,
+    base_dsqs: BTreeMap<u64, HistSet>,
+}
+
+impl HistData {
+    /// Reads the cumulative histograms from the BPF maps.
+    pub fn update(&mut self, skel: &BpfSkel<'_>) -> Result<()> {
+        for hist_id in 0..NR_HISTS {
+            let key = (hist_id as u32).to_ne_bytes();
+            let Some(all_cpus) = skel.maps.cpu_hists.lookup_percpu(&key, MapFlags::ANY)? else {
+                continue;
+            };
+            for (cpu, bytes) in all_cpus.iter().enumerate() {
+                self.cpus.entry(cpu).or_default()[hist_id] = LatHist::from_bytes(bytes);
+            }
+        }
+        for key in skel.maps.dsq_hists.keys() {
+            let Some(bytes) = skel.maps.dsq_hists.lookup(&key, MapFlags::ANY)? else {
+                continue;
+            };
+            let dsq_id = u64::from_ne_bytes(key[0..8].try_into().expect("dsq id is a u64"));
+            let hist_id = u32::from_ne_bytes(key[8..12].try_into().expect("hist id is a u32"));
+            if let Some(hist) = self
+                .dsqs
+                .entry(dsq_id)
+                .or_default()
+                .get_mut(hist_id as usize)
+            {
+                *hist = LatHist::from_bytes(&bytes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the cumulative histograms, e.g. with the ones of a remote host.
+    pub fn set(&mut self, cpus: BTreeMap<usize, HistSet>, dsqs: BTreeMap<u64, HistSet>) {
+        self.cpus = cpus;
+        self.dsqs = dsqs;
+    }
+
+    /// Resets the histograms to only show values recorded from now on.
+    pub fn reset(&mut self) {
+        self.base_cpus = self.cpus.clone();
+        self.base_dsqs = self.dsqs.clone();
+    }
+
+    /// Returns the histogram of a CPU since the last reset.
+    pub fn cpu(&self, cpu: usize, kind: HistKind) -> LatHist {
+        match (self.cpus.get(&cpu), self.base_cpus.get(&cpu)) {
+            (Some(hists), Some(base)) => hists[kind.id()].since(&base[kind.id()]),
+            (Some(hists), None) => hists[kind.id()].clone(),
+            _ => LatHist::default(),
+        }
+    }
+
+    /// Returns the histogram of a DSQ since the last reset.
+    pub fn dsq(&self, dsq: u64, kind: HistKind) -> LatHist {
+        match (self.dsqs.get(&dsq), self.base_dsqs.get(&dsq)) {
+            (Some(hists), Some(base)) => hists[kind.id()].since(&base[kind.id()]),
+            (Some(hists), None) => hists[kind.id()].clone(),
+            _ => LatHist::default(),
+        }
+    }
+
+    /// Returns the merged histogram of a set of CPUs since the last reset.
+    pub fn cpus<'a>(&self, cpus: impl Iterator<Item = &'a usize>, kind: HistKind) -> LatHist {
+        let mut hist = LatHist::default();
+        for cpu in cpus {
+            hist.add(&self.cpu(*cpu, kind));
+        }
+        hist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hist_buckets() {
+        for idx in 0..NR_HIST_BUCKETS {
+            assert_eq!(hist_bucket(bucket_lower(idx)), idx);
+            if idx + 1 < NR_HIST_BUCKETS {
+                assert_eq!(hist_bucket(bucket_upper(idx)), idx);
+            }
+        }
+        assert_eq!(hist_bucket(u64::MAX), NR_HIST_BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_hist_percentile() {
+        let mut hist = LatHist::default();
+        for val in 1..=1000 {
+            hist.record(val * 1000);
+        }
+        assert_eq!(hist.count(), 1000);
+        for (pct, val) in [(50.0, 500_000), (90.0, 900_000), (99.9, 999_000)] {
+            let p = hist.percentile(pct);
+            assert!(p >= val && p <= val + val / 8, "p{} = {}", pct, p);
+        }
+        assert_eq!(hist.since(&hist).count(), 0);
+    }
+}
@@ -37,15 +37,22 @@ impl Default for KeyMap {
     /// Returns the default keymap.
     fn default() -> Self {
         let mut bindings = HashMap::new();
+        bindings.insert(Key::Char('C'), Action::SetState(AppState::Cgroup));
         bindings.insert(Key::Char('d'), Action::SetState(AppState::Default));
         bindings.insert(Key::Char('e'), Action::SetState(AppState::Event));
         bindings.insert(Key::Char('f'), Action::ToggleCpuFreq);
+        bindings.insert(Key::Char('F'), Action::ToggleCgroupFilter);
         bindings.insert(Key::Char('u'), Action::ToggleUncoreFreq);
         bindings.insert(Key::Char('L'), Action::ToggleLocalization);
         bindings.insert(Key::Char('h'), Action::SetState(AppState::Help));
         bindings.insert(Key::Char('?'), Action::SetState(AppState::Help));
+        bindings.insert(Key::Char('H'), Action::SetState(AppState::Latency));
         bindings.insert(Key::Char('l'), Action::SetState(AppState::Llc));
         bindings.insert(Key::Char('n'), Action::SetState(AppState::Node));
+        bindings.insert(Key::Char('p'), Action::SetState(AppState::Process));
+        bindings.insert(Key::Char('o'), Action::NextProcessSort);
+        bindings.insert(Key::Char('b'), Action::NextStatsTable);
+        bindings.insert(Key::Char('c'), Action::NextStatsSort);
         bindings.insert(Key::Char('s'), Action::SetState(AppState::Scheduler));
         bindings.insert(Key::Char('S'), Action::SaveConfig);
         bindings.insert(Key::Char('a'), Action::RequestTrace);
@@ -338,14 +345,18 @@ pub fn parse_key(key_str: &str) -> Result<Key> {
 /// Parses an Action from a string.
 pub fn parse_action(action_str: &str) -> Result<Action> {
     match action_str {
+        "AppStateCgroup" => Ok(Action::SetState(AppState::Cgroup)),
         "AppStateDefault" => Ok(Action::SetState(AppState::Default)),
         "AppStateEvent" => Ok(Action::SetState(AppState::Event)),
+        "ToggleCgroupFilter" => Ok(Action::ToggleCgroupFilter),
         "ToggleCpuFreq" => Ok(Action::ToggleCpuFreq),
         "ToggleUncoreFreq" => Ok(Action::ToggleUncoreFreq),
         "ToggleLocalization" => Ok(Action::ToggleLocalization),
         "AppStateHelp" => Ok(Action::SetState(AppState::Help)),
+        "AppStateLatency" => Ok(Action::SetState(AppState::Latency)),
         "AppStateLlc" => Ok(Action::SetState(AppState::Llc)),
         "AppStateNode" => Ok(Action::SetState(AppState::Node)),
+        "AppStateProcess" => Ok(Action::SetState(AppState::Process)),
         "AppStateScheduler" => Ok(Action::SetState(AppState::Scheduler)),
         "SaveConfig" => Ok(Action::SaveConfig),
         "RequestTrace" => Ok(Action::RequestTrace),
@@ -359,6 +370,9 @@ pub fn parse_action(action_str: &str) -> Result<Action> {
         "DecBpfSampleRate" => Ok(Action::DecBpfSampleRate),
         "IncBpfSampleRate" => Ok(Action::IncBpfSampleRate),
         "NextViewState" => Ok(Action::NextViewState),
+        "NextProcessSort" => Ok(Action::NextProcessSort),
+        "NextStatsSort" => Ok(Action::NextStatsSort),
+        "NextStatsTable" => Ok(Action::NextStatsTable),
         "Down" => Ok(Action::Down),
         "Up" => Ok(Action::Up),
         "PageDown" => Ok(Action::PageDown),
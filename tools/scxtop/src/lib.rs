@@ -3,52 +3,75 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
 
+pub mod analyze;
 mod app;
 pub mod bpf_intf;
 pub mod bpf_skel;
 mod bpf_stats;
+mod cgroup_data;
 pub mod cli;
 pub mod config;
 mod cpu_data;
+mod derived_metric;
 pub mod edm;
 mod event_data;
+mod hist;
 mod keymap;
 mod llc_data;
 mod node_data;
 mod perf_event;
 mod perfetto_trace;
+mod proc_data;
 pub mod protos;
+pub mod recorder;
+pub mod remote;
+mod sched_stats;
 mod stats;
 mod theme;
 pub mod tracer;
+pub mod trigger;
 mod tui;
 mod util;
 
 pub use crate::bpf_skel::types::bpf_event;
 pub use app::App;
 pub use bpf_skel::*;
+pub use cgroup_data::CgroupTree;
 pub use cpu_data::CpuData;
+pub use derived_metric::DerivedMetric;
 pub use event_data::EventData;
+pub use hist::HistData;
+pub use hist::HistKind;
+pub use hist::HistSet;
+pub use hist::LatHist;
 pub use keymap::Key;
 pub use keymap::KeyMap;
 pub use llc_data::LlcData;
 pub use node_data::NodeData;
 pub use perf_event::available_perf_events;
 pub use perf_event::PerfEvent;
+pub use perf_event::PerfEventGroup;
 pub use perfetto_trace::PerfettoTraceManager;
+pub use proc_data::ProcData;
+pub use proc_data::ProcSort;
+pub use proc_data::ThreadData;
 pub use protos::*;
+pub use sched_stats::SchedStatsData;
+pub use sched_stats::StatsCell;
 pub use stats::StatAggregation;
 pub use stats::VecStats;
 pub use theme::AppTheme;
 pub use tui::Event;
 pub use tui::Tui;
 pub use util::format_hz;
+pub use util::format_ns;
 pub use util::read_file_string;
 
 pub use plain::Plain;
 // Generate serialization types for handling events from the bpf ring buffer.
 unsafe impl Plain for crate::bpf_skel::types::bpf_event {}
 
+use serde::{Deserialize, Serialize};
 use smartstring::alias::String as SsoString;
 
 pub const APP: &str = "scxtop";
@@ -60,18 +83,24 @@ This software may be used and distributed according to the terms of the
 GNU General Public License version 2.";
 pub const SCHED_NAME_PATH: &str = "/sys/kernel/sched_ext/root/ops";
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum AppState {
+    /// Application is in the cgroup state.
+    Cgroup,
     /// Application is in the default state.
     Default,
     /// Application is in the event state.
     Event,
     /// Application is in the help state.
     Help,
+    /// Application is in the latency histogram state.
+    Latency,
     /// Application is in the Llc state.
     Llc,
     /// Application is in the NUMA node state.
     Node,
+    /// Application is in the process state.
+    Process,
     /// Application is in the scheduler state.
     Scheduler,
     /// Application is in the tracing  state.
@@ -103,19 +132,20 @@ impl std::fmt::Display for ViewState {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct SchedCpuPerfSetAction {
     pub cpu: u32,
     pub perf: u32,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct SchedSwitchAction {
     pub ts: u64,
     pub cpu: u32,
     pub preempt: bool,
     pub next_dsq_id: u64,
     pub next_dsq_lat_us: u64,
+    pub next_wakeup_lat_us: u64,
     pub next_dsq_nr_queued: u32,
     pub next_dsq_vtime: u64,
     pub next_slice_ns: u64,
@@ -123,6 +153,7 @@ pub struct SchedSwitchAction {
     pub next_tgid: u32,
     pub next_prio: i32,
     pub next_comm: SsoString,
+    pub next_cgroup_id: u64,
     pub prev_dsq_id: u64,
     pub prev_used_slice_ns: u64,
     pub prev_slice_ns: u64,
@@ -131,9 +162,14 @@ pub struct SchedSwitchAction {
     pub prev_prio: i32,
     pub prev_comm: SsoString,
     pub prev_state: u64,
+    pub prev_runtime_ns: u64,
+    pub prev_nvcsw: u64,
+    pub prev_nivcsw: u64,
+    pub prev_nr_migrations: u64,
+    pub prev_cgroup_id: u64,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct SchedWakeActionCtx {
     pub ts: u64,
     pub cpu: u32,
@@ -146,7 +182,7 @@ pub type SchedWakeupNewAction = SchedWakeActionCtx;
 pub type SchedWakingAction = SchedWakeActionCtx;
 pub type SchedWakeupAction = SchedWakeActionCtx;
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct SoftIRQAction {
     pub cpu: u32,
     pub pid: u32,
@@ -155,19 +191,78 @@ pub struct SoftIRQAction {
     pub softirq_nr: usize,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct HardIRQAction {
+    pub cpu: u32,
+    pub pid: u32,
+    pub entry_ts: u64,
+    pub exit_ts: u64,
+    pub irq: i32,
+    pub ret: i32,
+    pub name: SsoString,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct SchedMigrateTaskAction {
+    pub ts: u64,
+    pub cpu: u32,
+    pub pid: u32,
+    pub prio: i32,
+    pub orig_cpu: u32,
+    pub dest_cpu: u32,
+    pub comm: SsoString,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct SchedProcessForkAction {
+    pub ts: u64,
+    pub cpu: u32,
+    pub parent_pid: u32,
+    pub child_pid: u32,
+    pub parent_comm: SsoString,
+    pub child_comm: SsoString,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct SchedProcessExecAction {
+    pub ts: u64,
+    pub cpu: u32,
+    pub pid: u32,
+    pub old_pid: u32,
+    pub filename: SsoString,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct SchedProcessExitAction {
+    pub ts: u64,
+    pub cpu: u32,
+    pub pid: u32,
+    pub tgid: u32,
+    pub prio: i32,
+    pub comm: SsoString,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct CpuIdleAction {
+    pub ts: u64,
+    pub cpu: u32,
+    pub state: u32,
+    pub cpu_id: u32,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct TraceStartedAction {
     pub start_immediately: bool,
     pub ts: u64,
     pub stop_scheduled: bool,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct TraceStoppedAction {
     pub ts: u64,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct IPIAction {
     pub ts: u64,
     pub cpu: u32,
@@ -175,7 +270,7 @@ pub struct IPIAction {
     pub pid: u32,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct GpuMemAction {
     pub ts: u64,
     pub size: u64,
@@ -184,7 +279,7 @@ pub struct GpuMemAction {
     pub pid: u32,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct CpuhpAction {
     pub ts: u64,
     pub cpu: u32,
@@ -193,29 +288,34 @@ pub struct CpuhpAction {
     pub pid: u32,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct HwPressureAction {
     pub hw_pressure: u64,
     pub cpu: u32,
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum Action {
     ChangeTheme,
     ClearEvent,
     Cpuhp(CpuhpAction),
+    CpuIdle(CpuIdleAction),
     DecBpfSampleRate,
     DecTickRate,
     Down,
     Enter,
     Event,
     GpuMem(GpuMemAction),
+    HardIRQ(HardIRQAction),
     Help,
     HwPressure(HwPressureAction),
     IncBpfSampleRate,
     IncTickRate,
     IPI(IPIAction),
     NextEvent,
+    NextProcessSort,
+    NextStatsSort,
+    NextStatsTable,
     NextViewState,
     PageDown,
     PageUp,
@@ -227,8 +327,13 @@ pub enum Action {
     ReloadStatsClient,
     SaveConfig,
     SchedCpuPerfSet(SchedCpuPerfSetAction),
+    SchedMigrateTask(SchedMigrateTaskAction),
+    SchedProcessExec(SchedProcessExecAction),
+    SchedProcessExit(SchedProcessExitAction),
+    SchedProcessFork(SchedProcessForkAction),
     SchedReg,
     SchedStats(String),
+    SchedStatsMeta(String),
     SchedSwitch(SchedSwitchAction),
     SchedUnreg,
     SchedWakeupNew(SchedWakeupNewAction),
@@ -238,6 +343,7 @@ pub enum Action {
     SoftIRQ(SoftIRQAction),
     Tick,
     TickRateChange(std::time::Duration),
+    ToggleCgroupFilter,
     ToggleCpuFreq,
     ToggleLocalization,
     ToggleUncoreFreq,
@@ -245,6 +351,16 @@ pub enum Action {
     None,
 }
 
+/// Returns the string of a NUL terminated char array from the BPF side.
+fn bpf_str(chars: &[std::os::raw::c_char]) -> SsoString {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).as_ref().into()
+}
+
 impl TryFrom<bpf_event> for Action {
     type Error = ();
 
@@ -310,6 +426,77 @@ impl TryFrom<&bpf_event> for Action {
                 }))
             }
             #[allow(non_upper_case_globals)]
+            bpf_intf::event_type_HARDIRQ => {
+                let hardirq = unsafe { &event.event.hardirq };
+                Ok(Action::HardIRQ(HardIRQAction {
+                    cpu: event.cpu,
+                    pid: hardirq.pid,
+                    entry_ts: hardirq.entry_ts,
+                    exit_ts: hardirq.exit_ts,
+                    irq: hardirq.irq,
+                    ret: hardirq.ret,
+                    name: bpf_str(&hardirq.name),
+                }))
+            }
+            #[allow(non_upper_case_globals)]
+            bpf_intf::event_type_SCHED_MIGRATE => {
+                let migrate = unsafe { &event.event.migrate };
+                Ok(Action::SchedMigrateTask(SchedMigrateTaskAction {
+                    ts: event.ts,
+                    cpu: event.cpu,
+                    pid: migrate.pid,
+                    prio: migrate.prio,
+                    orig_cpu: migrate.orig_cpu,
+                    dest_cpu: migrate.dest_cpu,
+                    comm: bpf_str(&migrate.comm),
+                }))
+            }
+            #[allow(non_upper_case_globals)]
+            bpf_intf::event_type_SCHED_PROCESS_FORK => {
+                let fork = unsafe { &event.event.fork };
+                Ok(Action::SchedProcessFork(SchedProcessForkAction {
+                    ts: event.ts,
+                    cpu: event.cpu,
+                    parent_pid: fork.parent_pid,
+                    child_pid: fork.child_pid,
+                    parent_comm: bpf_str(&fork.parent_comm),
+                    child_comm: bpf_str(&fork.child_comm),
+                }))
+            }
+            #[allow(non_upper_case_globals)]
+            bpf_intf::event_type_SCHED_PROCESS_EXEC => {
+                let exec = unsafe { &event.event.exec };
+                Ok(Action::SchedProcessExec(SchedProcessExecAction {
+                    ts: event.ts,
+                    cpu: event.cpu,
+                    pid: exec.pid,
+                    old_pid: exec.old_pid,
+                    filename: bpf_str(&exec.filename),
+                }))
+            }
+            #[allow(non_upper_case_globals)]
+            bpf_intf::event_type_SCHED_PROCESS_EXIT => {
+                let exit = unsafe { &event.event.exit };
+                Ok(Action::SchedProcessExit(SchedProcessExitAction {
+                    ts: event.ts,
+                    cpu: event.cpu,
+                    pid: exit.pid,
+                    tgid: exit.tgid,
+                    prio: exit.prio,
+                    comm: bpf_str(&exit.comm),
+                }))
+            }
+            #[allow(non_upper_case_globals)]
+            bpf_intf::event_type_CPU_IDLE => {
+                let cpu_idle = unsafe { &event.event.cpu_idle };
+                Ok(Action::CpuIdle(CpuIdleAction {
+                    ts: event.ts,
+                    cpu: event.cpu,
+                    state: cpu_idle.state,
+                    cpu_id: cpu_idle.cpu_id,
+                }))
+            }
+            #[allow(non_upper_case_globals)]
             bpf_intf::event_type_SCHED_WAKEUP => {
                 let wakeup = unsafe { event.event.wakeup };
                 let comm = unsafe {
@@ -366,6 +553,7 @@ impl TryFrom<&bpf_event> for Action {
                     preempt: unsafe { sched_switch.preempt.assume_init() },
                     next_dsq_id: sched_switch.next_dsq_id,
                     next_dsq_lat_us: sched_switch.next_dsq_lat_us,
+                    next_wakeup_lat_us: sched_switch.next_wakeup_lat_us,
                     next_dsq_nr_queued: sched_switch.next_dsq_nr,
                     next_dsq_vtime: sched_switch.next_dsq_vtime,
                     next_slice_ns: sched_switch.next_slice_ns,
@@ -373,6 +561,7 @@ impl TryFrom<&bpf_event> for Action {
                     next_tgid: sched_switch.next_tgid,
                     next_prio: sched_switch.next_prio,
                     next_comm: next_comm.into(),
+                    next_cgroup_id: sched_switch.next_cgroup_id,
                     prev_dsq_id: sched_switch.prev_dsq_id,
                     prev_used_slice_ns: sched_switch.prev_slice_ns,
                     prev_slice_ns: sched_switch.prev_slice_ns,
@@ -381,6 +570,11 @@ impl TryFrom<&bpf_event> for Action {
                     prev_comm: prev_comm.into(),
                     prev_prio: sched_switch.prev_prio,
                     prev_state: sched_switch.prev_state,
+                    prev_runtime_ns: sched_switch.prev_runtime_ns,
+                    prev_nvcsw: sched_switch.prev_nvcsw,
+                    prev_nivcsw: sched_switch.prev_nivcsw,
+                    prev_nr_migrations: sched_switch.prev_nr_migrations,
+                    prev_cgroup_id: sched_switch.prev_cgroup_id,
                 }))
             }
             #[allow(non_upper_case_globals)]
@@ -405,14 +599,18 @@ impl TryFrom<&bpf_event> for Action {
 impl std::fmt::Display for Action {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
+            Action::SetState(AppState::Cgroup) => write!(f, "AppStateCgroup"),
             Action::SetState(AppState::Default) => write!(f, "AppStateDefault"),
             Action::SetState(AppState::Event) => write!(f, "AppStateEvent"),
+            Action::ToggleCgroupFilter => write!(f, "ToggleCgroupFilter"),
             Action::ToggleCpuFreq => write!(f, "ToggleCpuFreq"),
             Action::ToggleUncoreFreq => write!(f, "ToggleUncoreFreq"),
             Action::ToggleLocalization => write!(f, "ToggleLocalization"),
             Action::SetState(AppState::Help) => write!(f, "AppStateHelp"),
+            Action::SetState(AppState::Latency) => write!(f, "AppStateLatency"),
             Action::SetState(AppState::Llc) => write!(f, "AppStateLlc"),
             Action::SetState(AppState::Node) => write!(f, "AppStateNode"),
+            Action::SetState(AppState::Process) => write!(f, "AppStateProcess"),
             Action::SetState(AppState::Scheduler) => write!(f, "AppStateScheduler"),
             Action::SaveConfig => write!(f, "SaveConfig"),
             Action::RequestTrace => write!(f, "RequestTrace"),
@@ -428,6 +626,9 @@ impl std::fmt::Display for Action {
             Action::DecBpfSampleRate => write!(f, "DecBpfSampleRate"),
             Action::IncBpfSampleRate => write!(f, "IncBpfSampleRate"),
             Action::NextViewState => write!(f, "NextViewState"),
+            Action::NextProcessSort => write!(f, "NextProcessSort"),
+            Action::NextStatsSort => write!(f, "NextStatsSort"),
+            Action::NextStatsTable => write!(f, "NextStatsTable"),
             Action::Down => write!(f, "Down"),
             Action::Up => write!(f, "Up"),
             Action::PageDown => write!(f, "PageDown"),
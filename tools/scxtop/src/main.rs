@@ -4,16 +4,22 @@
 // GNU General Public License version 2.
 
 use scx_utils::compat;
+use scxtop::analyze::TraceAnalysis;
 use scxtop::bpf_intf::*;
 use scxtop::bpf_skel::types::bpf_event;
 use scxtop::bpf_skel::*;
-use scxtop::cli::{generate_completions, Cli, Commands, TraceArgs, TuiArgs};
+use scxtop::cli::{
+    generate_completions, AnalyzeArgs, Cli, Commands, RecordArgs, ServeArgs, TraceArgs, TuiArgs,
+};
 use scxtop::config::get_config_path;
 use scxtop::config::Config;
 use scxtop::edm::{ActionHandler, BpfEventActionPublisher, BpfEventHandler, EventDispatchManager};
 use scxtop::read_file_string;
+use scxtop::recorder::{record_output, sample_writer};
+use scxtop::remote::{self, Message, RemotePublisher};
 use scxtop::tracer::Tracer;
 use scxtop::App;
+use scxtop::AppState;
 use scxtop::Event;
 use scxtop::Key;
 use scxtop::KeyMap;
@@ -40,12 +46,16 @@ use libbpf_rs::UprobeOpts;
 use log::debug;
 use log::info;
 use log::trace;
+use log::warn;
 use ratatui::crossterm::event::KeyCode::Char;
 use simplelog::{
     ColorChoice, Config as SimplelogConfig, LevelFilter, TermLogger, TerminalMode, WriteLogger,
 };
 use std::sync::atomic::AtomicBool;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedSender;
 
 use std::fs;
 use std::fs::File;
@@ -55,6 +65,8 @@ use std::str::FromStr;
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
 fn get_action(_app: &App, keymap: &KeyMap, event: Event) -> Action {
     match event {
@@ -71,6 +83,37 @@ fn get_action(_app: &App, keymap: &KeyMap, event: Event) -> Action {
     }
 }
 
+/// Handles an event of the TUI, returns false once the app quit.
+fn handle_tui_event(
+    app: &mut App,
+    tui: &mut Tui,
+    keymap: &KeyMap,
+    action_tx: &UnboundedSender<Action>,
+    ev: Event,
+) -> Result<bool> {
+    match ev {
+        Event::Quit => {
+            action_tx.send(Action::Quit)?;
+        }
+        Event::Tick => action_tx.send(Action::Tick)?,
+        Event::TickRateChange(tick_rate_ms) => action_tx.send(Action::TickRateChange(
+            std::time::Duration::from_millis(tick_rate_ms),
+        ))?,
+        Event::Render => {
+            if app.should_quit.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            tui.draw(|f| app.render(f).expect("Failed to render application"))?;
+        }
+        Event::Key(_) => {
+            let action = get_action(app, keymap, ev);
+            action_tx.send(action)?;
+        }
+        _ => {}
+    }
+    Ok(true)
+}
+
 /// Attaches BPF programs to the skel.
 fn attach_progs(skel: &mut BpfSkel) -> Result<Vec<Link>> {
     // Attach probes
@@ -250,6 +293,308 @@ fn run_trace(trace_args: &TraceArgs) -> Result<()> {
         })
 }
 
+fn run_record(record_args: &RecordArgs) -> Result<()> {
+    TermLogger::init(
+        match record_args.verbose {
+            0 => simplelog::LevelFilter::Info,
+            1 => simplelog::LevelFilter::Debug,
+            _ => simplelog::LevelFilter::Trace,
+        },
+        SimplelogConfig::default(),
+        // stdout may be used for the recording
+        TerminalMode::Stderr,
+        ColorChoice::Auto,
+    )?;
+
+    let config = Config::merge([
+        Config::from(record_args.clone()),
+        Config::load().unwrap_or(Config::default_config()),
+    ]);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(config.worker_threads() as usize)
+        .build()
+        .unwrap()
+        .block_on(async {
+            let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+
+            let mut open_object = MaybeUninit::uninit();
+            let mut builder = BpfSkelBuilder::default();
+            if config.debug() {
+                builder.obj_builder.debug(true);
+            }
+            let bpf_publisher = BpfEventActionPublisher::new(action_tx.clone());
+            let mut edm = EventDispatchManager::new(None, None);
+            edm.register_bpf_handler(Box::new(bpf_publisher));
+
+            let skel = builder.open(&mut open_object)?;
+            let mut skel = skel.load()?;
+            let links = attach_progs(&mut skel)?;
+            skel.progs.scxtop_init.test_run(ProgramInput::default())?;
+
+            let mut event_rbb = RingBufferBuilder::new();
+            let event_handler = move |data: &[u8]| {
+                let mut event = bpf_event::default();
+                plain::copy_from_bytes(&mut event, data).expect("Event data buffer was too short");
+                let _ = edm.on_event(&event);
+                0
+            };
+            event_rbb.add(&skel.maps.events, event_handler)?;
+            let event_rb = event_rbb.build()?;
+            let scheduler = read_file_string(SCHED_NAME_PATH).unwrap_or("".to_string());
+
+            let mut app = App::new(
+                config,
+                scheduler,
+                100,
+                record_args.process_id,
+                action_tx.clone(),
+                skel,
+            )?;
+            // scheduler stats are only fetched in the scheduler state
+            app.set_state(AppState::Scheduler);
+
+            let shutdown = app.should_quit.clone();
+            tokio::spawn(async move {
+                loop {
+                    let _ = event_rb.poll(Duration::from_millis(1));
+                    if shutdown.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+            });
+
+            let mut writer = sample_writer(
+                record_args.format,
+                record_output(record_args.output_file.as_deref())?,
+            );
+            let deadline = record_args
+                .duration_ms
+                .map(|ms| Instant::now() + Duration::from_millis(ms));
+            let mut interval =
+                tokio::time::interval(Duration::from_millis(record_args.interval_ms.max(1)));
+            // the first tick completes immediately
+            interval.tick().await;
+            let mut sigterm = signal(SignalKind::terminate())?;
+            let mut ticks = 0;
+            info!("recording every {}ms", record_args.interval_ms);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        app.handle_action(&Action::Tick)?;
+                        let timestamp_ms = SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)?
+                            .as_millis() as u64;
+                        writer.write(timestamp_ms, &app.samples())?;
+                        ticks += 1;
+                        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            break;
+                        }
+                    }
+                    ac = action_rx.recv() => {
+                        let ac = ac.ok_or(anyhow!("actions channel closed"))?;
+                        app.handle_action(&ac)?;
+                    }
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = sigterm.recv() => break,
+                }
+            }
+
+            app.should_quit.store(true, Ordering::Relaxed);
+            drop(links);
+            writer.finish()?;
+            info!("recording complete, {} samples", ticks);
+
+            Ok(())
+        })
+}
+
+fn run_serve(serve_args: &ServeArgs) -> Result<()> {
+    TermLogger::init(
+        match serve_args.verbose {
+            0 => simplelog::LevelFilter::Info,
+            1 => simplelog::LevelFilter::Debug,
+            _ => simplelog::LevelFilter::Trace,
+        },
+        SimplelogConfig::default(),
+        TerminalMode::Stderr,
+        ColorChoice::Auto,
+    )?;
+
+    let config = Config::merge([
+        Config::from(serve_args.clone()),
+        Config::load().unwrap_or(Config::default_config()),
+    ]);
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(config.worker_threads() as usize)
+        .build()
+        .unwrap()
+        .block_on(async {
+            let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+            let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+
+            let mut open_object = MaybeUninit::uninit();
+            let mut builder = BpfSkelBuilder::default();
+            if config.debug() {
+                builder.obj_builder.debug(true);
+            }
+            let bpf_publisher = BpfEventActionPublisher::new(action_tx.clone());
+            let mut edm = EventDispatchManager::new(None, None);
+            edm.register_bpf_handler(Box::new(bpf_publisher));
+
+            let skel = builder.open(&mut open_object)?;
+            let mut skel = skel.load()?;
+            let links = attach_progs(&mut skel)?;
+            skel.progs.scxtop_init.test_run(ProgramInput::default())?;
+
+            let mut event_rbb = RingBufferBuilder::new();
+            let event_handler = move |data: &[u8]| {
+                let mut event = bpf_event::default();
+                plain::copy_from_bytes(&mut event, data).expect("Event data buffer was too short");
+                let _ = edm.on_event(&event);
+                0
+            };
+            event_rbb.add(&skel.maps.events, event_handler)?;
+            let event_rb = event_rbb.build()?;
+            let scheduler = read_file_string(SCHED_NAME_PATH).unwrap_or("".to_string());
+
+            let mut app = App::new(
+                config,
+                scheduler,
+                100,
+                serve_args.process_id,
+                action_tx.clone(),
+                skel,
+            )?;
+
+            let shutdown = app.should_quit.clone();
+            tokio::spawn(async move {
+                loop {
+                    let _ = event_rb.poll(Duration::from_millis(1));
+                    if shutdown.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+            });
+
+            // Events are published to clients as they are handled, tick data once collected.
+            let remote_tx = remote::broadcast_channel();
+            let mut remote_edm = EventDispatchManager::new(None, None);
+            remote_edm.register_action_handler(Box::new(RemotePublisher::new(remote_tx.clone())));
+            let tick_publisher = RemotePublisher::new(remote_tx.clone());
+
+            let listener = TcpListener::bind(&serve_args.listen).await?;
+            info!("serving on {}", listener.local_addr()?);
+            let hello = app.hello();
+            tokio::spawn(async move {
+                if let Err(e) = remote::serve(listener, hello, remote_tx, control_tx).await {
+                    warn!("failed to accept clients: {}", e);
+                }
+            });
+
+            let mut interval =
+                tokio::time::interval(Duration::from_millis(serve_args.interval_ms.max(1)));
+            let mut sigterm = signal(SignalKind::terminate())?;
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        app.handle_action(&Action::Tick)?;
+                        tick_publisher.publish(&Message::Tick(Box::new(app.tick_data())))?;
+                    }
+                    ac = action_rx.recv() => {
+                        let ac = ac.ok_or(anyhow!("actions channel closed"))?;
+                        app.handle_action(&ac)?;
+                        remote_edm.on_action(&ac)?;
+                    }
+                    ac = control_rx.recv() => {
+                        let ac = ac.ok_or(anyhow!("control channel closed"))?;
+                        let result = match &ac {
+                            // a running trace ends in its own time
+                            Action::SetState(_) if app.state() == AppState::Tracing => Ok(()),
+                            Action::SetState(state) => {
+                                app.set_state(state.clone());
+                                Ok(())
+                            }
+                            _ => app.handle_action(&ac),
+                        };
+                        if let Err(e) = result {
+                            warn!("failed to handle client action {:?}: {}", ac, e);
+                        }
+                    }
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = sigterm.recv() => break,
+                }
+            }
+
+            app.should_quit.store(true, Ordering::Relaxed);
+            drop(links);
+
+            Ok(())
+        })
+}
+
+fn run_remote(tui_args: &TuiArgs, addr: &str) -> Result<()> {
+    let config = Config::merge([
+        Config::from(tui_args.clone()),
+        Config::load().unwrap_or(Config::default_config()),
+    ]);
+    let keymap = config.active_keymap.clone();
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(config.worker_threads() as usize)
+        .build()
+        .unwrap()
+        .block_on(async {
+            let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+            let (hello, mut remote_rx, remote_tx) = remote::connect(addr).await?;
+
+            let mut tui = Tui::new(keymap.clone(), config.tick_rate_ms())?;
+            let mut app = App::new_remote(config, hello, 100, action_tx.clone(), remote_tx)?;
+
+            tui.enter()?;
+
+            let result = loop {
+                tokio::select! {
+                    ev = tui.next() => {
+                        if !handle_tui_event(&mut app, &mut tui, &keymap, &action_tx, ev?)? {
+                            break Ok(());
+                        }
+                    }
+
+                    ac = action_rx.recv() => {
+                        let ac = ac.ok_or(anyhow!("actions channel closed"))?;
+                        app.handle_action(&ac)?;
+                    }
+
+                    msg = remote_rx.recv() => match msg {
+                        Some(Message::Action(ac)) => app.handle_action(&ac)?,
+                        Some(Message::Tick(tick)) => app.on_remote_tick(*tick)?,
+                        Some(Message::Hello(_)) => {}
+                        None => break Err(anyhow!("connection to {} closed", addr)),
+                    },
+                }
+            };
+            tui.exit()?;
+
+            result
+        })
+}
+
+fn run_analyze(analyze_args: &AnalyzeArgs) -> Result<()> {
+    let analysis = TraceAnalysis::from_file(&analyze_args.trace_file)?;
+    analysis.report(
+        &mut std::io::stdout().lock(),
+        analyze_args.top,
+        analyze_args.context,
+    )
+}
+
 fn run_tui(tui_args: &TuiArgs) -> Result<()> {
     if let Ok(log_path) = std::env::var("RUST_LOG_PATH") {
         let log_level = match std::env::var("RUST_LOG") {
@@ -372,25 +717,10 @@ fn run_tui(tui_args: &TuiArgs) -> Result<()> {
             loop {
                 tokio::select! {
                     ev = tui.next() => {
-                        let ev = ev?;
-                        match ev {
-                            Event::Quit => { action_tx.send(Action::Quit)?; },
-                            Event::Tick => action_tx.send(Action::Tick)?,
-                            Event::TickRateChange(tick_rate_ms) => action_tx.send(
-                                Action::TickRateChange(std::time::Duration::from_millis(tick_rate_ms)),
-                            )?,
-                            Event::Render => {
-                                if app.should_quit.load(Ordering::Relaxed) {
-                                    break;
-                                }
-                                tui.draw(|f| app.render(f).expect("Failed to render application"))?;
-                            }
-                            Event::Key(_) => {
-                                let action = get_action(&app, &keymap, ev);
-                                action_tx.send(action)?;
-                            }
-                            _ => {}
-                    }}
+                        if !handle_tui_event(&mut app, &mut tui, &keymap, &action_tx, ev?)? {
+                            break;
+                        }
+                    }
 
                     ac = action_rx.recv() => {
                         let ac = ac.ok_or(anyhow!("actions channel closed"))?;
@@ -409,12 +739,22 @@ fn main() -> Result<()> {
     let args = Cli::parse();
 
     match &args.command.unwrap_or(Commands::Tui(args.tui)) {
-        Commands::Tui(tui_args) => {
-            run_tui(tui_args)?;
-        }
+        Commands::Tui(tui_args) => match &tui_args.remote {
+            Some(addr) => run_remote(tui_args, addr)?,
+            None => run_tui(tui_args)?,
+        },
         Commands::Trace(trace_args) => {
             run_trace(trace_args)?;
         }
+        Commands::Record(record_args) => {
+            run_record(record_args)?;
+        }
+        Commands::Serve(serve_args) => {
+            run_serve(serve_args)?;
+        }
+        Commands::Analyze(analyze_args) => {
+            run_analyze(analyze_args)?;
+        }
         Commands::GenerateCompletions { shell, output } => {
             generate_completions(Cli::command(), *shell, output.clone())
                 .unwrap_or_else(|_| panic!("Failed to generate completions for {}", shell));
@@ -28,6 +28,7 @@ const PERF_SAMPLE_ID: u64 = 1 << 16;
 const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
 #[allow(dead_code)]
 const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
 
 /// Reads a file and returns the u64 value from a file.
 pub fn read_file_u64<P: AsRef<Path>>(path: P) -> Result<u64> {
@@ -129,6 +130,8 @@ impl PerfEvent {
             PerfEvent::new("hw".to_string(), "stalled-cycles-frontend".to_string(), 0),
             PerfEvent::new("hw".to_string(), "bus-cycles".to_string(), 0),
             PerfEvent::new("hw".to_string(), "L1-dcache-load-misses".to_string(), 0),
+            PerfEvent::new("hw".to_string(), "LLC-loads".to_string(), 0),
+            PerfEvent::new("hw".to_string(), "LLC-load-misses".to_string(), 0),
         ]
     }
 
@@ -162,6 +165,18 @@ impl PerfEvent {
 
     /// Attaches a PerfEvent struct.
     pub fn attach(&mut self, process_id: i32) -> Result<()> {
+        self.open(process_id, 0, process_id == -1, None)
+    }
+
+    /// Attaches a PerfEvent struct counting only tasks of the cgroup of the
+    /// given cgroup directory fd.
+    pub fn attach_cgroup(&mut self, cgroup_fd: i32) -> Result<()> {
+        self.open(cgroup_fd, perf::bindings::PERF_FLAG_PID_CGROUP, false, None)
+    }
+
+    /// Opens the perf event, a group leader is opened for a group of `Some(-1)` and a member
+    /// of a group of `Some(leader_fd)`.
+    fn open(&mut self, pid: i32, flags: u32, inherit: bool, group: Option<i32>) -> Result<()> {
         let mut attrs = perf::bindings::perf_event_attr::default();
         attrs.size = std::mem::size_of::<perf::bindings::perf_event_attr>() as u32;
 
@@ -202,6 +217,18 @@ impl PerfEvent {
                     "l1-dcache-load-misses" => {
                         attrs.config = perf::bindings::PERF_COUNT_HW_CACHE_RESULT_MISS as u64;
                     }
+                    "llc-loads" => {
+                        attrs.type_ = perf::bindings::PERF_TYPE_HW_CACHE;
+                        attrs.config = perf::bindings::PERF_COUNT_HW_CACHE_LL as u64
+                            | (perf::bindings::PERF_COUNT_HW_CACHE_OP_READ as u64) << 8
+                            | (perf::bindings::PERF_COUNT_HW_CACHE_RESULT_ACCESS as u64) << 16;
+                    }
+                    "llc-load-misses" => {
+                        attrs.type_ = perf::bindings::PERF_TYPE_HW_CACHE;
+                        attrs.config = perf::bindings::PERF_COUNT_HW_CACHE_LL as u64
+                            | (perf::bindings::PERF_COUNT_HW_CACHE_OP_READ as u64) << 8
+                            | (perf::bindings::PERF_COUNT_HW_CACHE_RESULT_MISS as u64) << 16;
+                    }
                     _ => {
                         return Err(anyhow!("unknown event"));
                     }
@@ -251,11 +278,26 @@ impl PerfEvent {
         attrs.set_disabled(0);
         attrs.set_exclude_kernel(0);
         attrs.set_exclude_hv(0);
-        attrs.set_inherit(if process_id == -1 { 1 } else { 0 });
-        attrs.set_pinned(1);
+        attrs.set_inherit(if inherit { 1 } else { 0 });
+        // only group leaders can be pinned
+        attrs.set_pinned(if group.is_some_and(|fd| fd != -1) {
+            0
+        } else {
+            1
+        });
+        if group == Some(-1) {
+            attrs.read_format = PERF_FORMAT_GROUP;
+        }
 
-        let result =
-            unsafe { perf::perf_event_open(&mut attrs, process_id, self.cpu as i32, -1, 0) };
+        let result = unsafe {
+            perf::perf_event_open(
+                &mut attrs,
+                pid,
+                self.cpu as i32,
+                group.unwrap_or(-1),
+                flags as libc::c_ulong,
+            )
+        };
 
         if result < 0 {
             return Err(anyhow!("failed to open perf event: {}", result));
@@ -292,6 +334,79 @@ impl PerfEvent {
     }
 }
 
+/// Group of perf events of a cpu that are scheduled together and read atomically, so ratios
+/// of the events are consistent.
+#[derive(Debug)]
+pub struct PerfEventGroup {
+    events: Vec<PerfEvent>,
+}
+
+impl PerfEventGroup {
+    /// Creates a PerfEventGroup of the events on the cpu, the first event is the leader.
+    pub fn new(events: &[PerfEvent], cpu: usize) -> Self {
+        let events = events
+            .iter()
+            .map(|event| {
+                let mut event = event.clone();
+                event.cpu = cpu;
+                event
+            })
+            .collect();
+        Self { events }
+    }
+
+    /// Attaches the group.
+    pub fn attach(&mut self, process_id: i32) -> Result<()> {
+        self.open(process_id, 0)
+    }
+
+    /// Attaches the group counting only tasks of the cgroup of the given cgroup directory fd.
+    pub fn attach_cgroup(&mut self, cgroup_fd: i32) -> Result<()> {
+        self.open(cgroup_fd, perf::bindings::PERF_FLAG_PID_CGROUP)
+    }
+
+    fn open(&mut self, pid: i32, flags: u32) -> Result<()> {
+        // inherited events can't be read as a group
+        let mut leader_fd = -1;
+        for event in &mut self.events {
+            event.open(pid, flags, false, Some(leader_fd))?;
+            if leader_fd == -1 {
+                leader_fd = event.fd as i32;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the values of the events in the order of the group.
+    pub fn values(&mut self, reset: bool) -> Result<Vec<u64>> {
+        let Some(leader) = self.events.first() else {
+            return Ok(vec![]);
+        };
+        // the group is read as the number of events followed by their values
+        let mut buf = vec![0u64; self.events.len() + 1];
+        let size = mem::size_of_val(buf.as_slice());
+        unsafe {
+            if read(
+                leader.fd as i32,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                size,
+            ) != size as isize
+            {
+                return Err(anyhow!("failed to read perf event group {:?}", self));
+            }
+            if reset
+                && perf::ioctls::RESET(leader.fd as i32, perf::bindings::PERF_IOC_FLAG_GROUP) < 0
+            {
+                return Err(anyhow!(
+                    "failed to reset perf event group: {}",
+                    leader.event
+                ));
+            }
+        }
+        Ok(buf.split_off(1))
+    }
+}
+
 /// Returns the available perf events on the system from tracefs.
 pub fn available_perf_events() -> Result<BTreeMap<String, HashSet<String>>> {
     let path = tracefs_mount()?;
@@ -17,18 +17,22 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use crate::bpf_skel::types::bpf_event;
 use crate::edm::{ActionHandler, BpfEventHandler};
 use crate::{
-    Action, CpuhpAction, GpuMemAction, HwPressureAction, IPIAction, SchedSwitchAction,
-    SchedWakeupAction, SchedWakingAction, SoftIRQAction,
+    Action, CpuIdleAction, CpuhpAction, GpuMemAction, HardIRQAction, HwPressureAction, IPIAction,
+    SchedMigrateTaskAction, SchedProcessExecAction, SchedProcessExitAction, SchedProcessForkAction,
+    SchedSwitchAction, SchedWakeupAction, SchedWakingAction, SoftIRQAction,
 };
 
 use crate::protos_gen::perfetto_scx::counter_descriptor::Unit::UNIT_COUNT;
 use crate::protos_gen::perfetto_scx::trace_packet::Data::TrackDescriptor as DataTrackDescriptor;
 use crate::protos_gen::perfetto_scx::track_event::Type as TrackEventType;
 use crate::protos_gen::perfetto_scx::{
-    CounterDescriptor, CpuhpEnterFtraceEvent, FtraceEvent, FtraceEventBundle,
-    GpuMemTotalFtraceEvent, IpiRaiseFtraceEvent, SchedSwitchFtraceEvent, SchedWakeupFtraceEvent,
-    SchedWakingFtraceEvent, SoftirqEntryFtraceEvent, SoftirqExitFtraceEvent, Trace, TracePacket,
-    TrackDescriptor, TrackEvent,
+    process_tree, CounterDescriptor, CpuIdleFtraceEvent, CpuhpEnterFtraceEvent, FtraceEvent,
+    FtraceEventBundle, GpuMemTotalFtraceEvent, IpiRaiseFtraceEvent, IrqHandlerEntryFtraceEvent,
+    IrqHandlerExitFtraceEvent, ProcessTree, SchedMigrateTaskFtraceEvent,
+    SchedProcessExecFtraceEvent, SchedProcessExitFtraceEvent, SchedProcessForkFtraceEvent,
+    SchedSwitchFtraceEvent, SchedWakeupFtraceEvent, SchedWakingFtraceEvent,
+    SoftirqEntryFtraceEvent, SoftirqExitFtraceEvent, Trace, TracePacket, TrackDescriptor,
+    TrackEvent,
 };
 
 /// Handler for perfetto traces. For details on data flow in perfetto see:
@@ -50,6 +54,8 @@ pub struct PerfettoTraceManager {
     dsq_nr_queued_events: BTreeMap<u64, Vec<TrackEvent>>,
     dsq_nr_queued_trusted_packet_seq_uuid: u32,
     dsq_uuids: BTreeMap<u64, u64>,
+    // tid -> tgid of the threads seen in the trace
+    thread_tgids: BTreeMap<u32, u32>,
 }
 
 impl PerfettoTraceManager {
@@ -78,6 +84,7 @@ impl PerfettoTraceManager {
             dsq_lat_trusted_packet_seq_uuid,
             dsq_nr_queued_events: BTreeMap::new(),
             dsq_nr_queued_trusted_packet_seq_uuid,
+            thread_tgids: BTreeMap::new(),
         }
     }
 
@@ -93,6 +100,7 @@ impl PerfettoTraceManager {
         self.ftrace_events.clear();
         self.dsq_lat_events.clear();
         self.dsq_uuids.clear();
+        self.thread_tgids.clear();
     }
 
     /// Returns the trace file.
@@ -205,6 +213,21 @@ impl PerfettoTraceManager {
             }
         }
 
+        // thread to process mapping, sched_switch events only carry tids
+        if !self.thread_tgids.is_empty() {
+            let mut process_tree = ProcessTree::new();
+            for (tid, tgid) in &self.thread_tgids {
+                let mut thread = process_tree::Thread::new();
+                thread.set_tid(*tid as i32);
+                thread.set_tgid(*tgid as i32);
+                process_tree.threads.push(thread);
+            }
+            let mut packet = TracePacket::new();
+            packet.set_process_tree(process_tree);
+            packet.trusted_pid = Some(self.trusted_pid);
+            self.trace.packet.push(packet);
+        }
+
         // ftrace events
         for cpu in &trace_cpus {
             let mut packet = TracePacket::new();
@@ -320,6 +343,156 @@ impl PerfettoTraceManager {
         });
     }
 
+    /// Adds events for the hardirq entry/exit events.
+    pub fn on_hardirq(&mut self, action: &HardIRQAction) {
+        self.ftrace_events.entry(action.cpu).or_default().extend({
+            let mut entry_ftrace_event = FtraceEvent::new();
+            let mut exit_ftrace_event = FtraceEvent::new();
+            let mut entry_event = IrqHandlerEntryFtraceEvent::new();
+            let mut exit_event = IrqHandlerExitFtraceEvent::new();
+            entry_event.set_irq(action.irq);
+            entry_event.set_name(action.name.to_string());
+            exit_event.set_irq(action.irq);
+            exit_event.set_ret(action.ret);
+
+            entry_ftrace_event.set_timestamp(action.entry_ts);
+            entry_ftrace_event.set_irq_handler_entry(entry_event);
+            entry_ftrace_event.set_pid(action.pid);
+            exit_ftrace_event.set_timestamp(action.exit_ts);
+            exit_ftrace_event.set_irq_handler_exit(exit_event);
+            exit_ftrace_event.set_pid(action.pid);
+
+            [entry_ftrace_event, exit_ftrace_event]
+        });
+    }
+
+    /// Adds events for the sched_migrate_task event.
+    pub fn on_sched_migrate_task(&mut self, action: &SchedMigrateTaskAction) {
+        let SchedMigrateTaskAction {
+            ts,
+            cpu,
+            pid,
+            prio,
+            orig_cpu,
+            dest_cpu,
+            comm,
+        } = action;
+
+        self.ftrace_events.entry(*cpu).or_default().push({
+            let mut ftrace_event = FtraceEvent::new();
+            let mut migrate_event = SchedMigrateTaskFtraceEvent::new();
+            migrate_event.set_pid(*pid as i32);
+            migrate_event.set_prio(*prio);
+            migrate_event.set_comm(comm.to_string());
+            migrate_event.set_orig_cpu(*orig_cpu as i32);
+            migrate_event.set_dest_cpu(*dest_cpu as i32);
+            ftrace_event.set_pid(*pid);
+            ftrace_event.set_timestamp(*ts);
+            ftrace_event.set_sched_migrate_task(migrate_event);
+
+            ftrace_event
+        });
+    }
+
+    /// Adds events for the sched_process_fork event.
+    pub fn on_sched_process_fork(&mut self, action: &SchedProcessForkAction) {
+        let SchedProcessForkAction {
+            ts,
+            cpu,
+            parent_pid,
+            child_pid,
+            parent_comm,
+            child_comm,
+        } = action;
+
+        self.ftrace_events.entry(*cpu).or_default().push({
+            let mut ftrace_event = FtraceEvent::new();
+            let mut fork_event = SchedProcessForkFtraceEvent::new();
+            fork_event.set_parent_pid(*parent_pid as i32);
+            fork_event.set_parent_comm(parent_comm.to_string());
+            fork_event.set_child_pid(*child_pid as i32);
+            fork_event.set_child_comm(child_comm.to_string());
+            ftrace_event.set_pid(*parent_pid);
+            ftrace_event.set_timestamp(*ts);
+            ftrace_event.set_sched_process_fork(fork_event);
+
+            ftrace_event
+        });
+    }
+
+    /// Adds events for the sched_process_exec event.
+    pub fn on_sched_process_exec(&mut self, action: &SchedProcessExecAction) {
+        let SchedProcessExecAction {
+            ts,
+            cpu,
+            pid,
+            old_pid,
+            filename,
+        } = action;
+
+        self.ftrace_events.entry(*cpu).or_default().push({
+            let mut ftrace_event = FtraceEvent::new();
+            let mut exec_event = SchedProcessExecFtraceEvent::new();
+            exec_event.set_pid(*pid as i32);
+            exec_event.set_old_pid(*old_pid as i32);
+            exec_event.set_filename(filename.to_string());
+            ftrace_event.set_pid(*pid);
+            ftrace_event.set_timestamp(*ts);
+            ftrace_event.set_sched_process_exec(exec_event);
+
+            ftrace_event
+        });
+    }
+
+    /// Adds events for the sched_process_exit event.
+    pub fn on_sched_process_exit(&mut self, action: &SchedProcessExitAction) {
+        let SchedProcessExitAction {
+            ts,
+            cpu,
+            pid,
+            tgid,
+            prio,
+            comm,
+        } = action;
+
+        self.thread_tgids.insert(*pid, *tgid);
+        self.ftrace_events.entry(*cpu).or_default().push({
+            let mut ftrace_event = FtraceEvent::new();
+            let mut exit_event = SchedProcessExitFtraceEvent::new();
+            exit_event.set_pid(*pid as i32);
+            exit_event.set_tgid(*tgid as i32);
+            exit_event.set_prio(*prio);
+            exit_event.set_comm(comm.to_string());
+            ftrace_event.set_pid(*pid);
+            ftrace_event.set_timestamp(*ts);
+            ftrace_event.set_sched_process_exit(exit_event);
+
+            ftrace_event
+        });
+    }
+
+    /// Adds events for the cpu_idle event.
+    pub fn on_cpu_idle(&mut self, action: &CpuIdleAction) {
+        let CpuIdleAction {
+            ts,
+            cpu,
+            state,
+            cpu_id,
+        } = action;
+
+        self.ftrace_events.entry(*cpu).or_default().push({
+            let mut ftrace_event = FtraceEvent::new();
+            let mut idle_event = CpuIdleFtraceEvent::new();
+            idle_event.set_state(*state);
+            idle_event.set_cpu_id(*cpu_id);
+            ftrace_event.set_pid(0);
+            ftrace_event.set_timestamp(*ts);
+            ftrace_event.set_cpu_idle(idle_event);
+
+            ftrace_event
+        });
+    }
+
     /// Adds events for the IPI entry/exit events.
     pub fn on_ipi(&mut self, action: &IPIAction) {
         let IPIAction {
@@ -397,15 +570,24 @@ impl PerfettoTraceManager {
             next_dsq_nr_queued,
             next_dsq_lat_us,
             next_pid,
+            next_tgid,
             next_prio,
             next_comm,
             prev_pid,
+            prev_tgid,
             prev_prio,
             prev_comm,
             prev_state,
             ..
         } = action;
 
+        if *next_pid > 0 {
+            self.thread_tgids.insert(*next_pid, *next_tgid);
+        }
+        if *prev_pid > 0 {
+            self.thread_tgids.insert(*prev_pid, *prev_tgid);
+        }
+
         self.ftrace_events.entry(*cpu).or_default().push({
             let mut ftrace_event = FtraceEvent::new();
             let mut switch_event = SchedSwitchFtraceEvent::new();
@@ -489,6 +671,24 @@ impl ActionHandler for PerfettoTraceManager {
             Action::IPI(a) => {
                 self.on_ipi(a);
             }
+            Action::HardIRQ(a) => {
+                self.on_hardirq(a);
+            }
+            Action::SchedMigrateTask(a) => {
+                self.on_sched_migrate_task(a);
+            }
+            Action::SchedProcessFork(a) => {
+                self.on_sched_process_fork(a);
+            }
+            Action::SchedProcessExec(a) => {
+                self.on_sched_process_exec(a);
+            }
+            Action::SchedProcessExit(a) => {
+                self.on_sched_process_exit(a);
+            }
+            Action::CpuIdle(a) => {
+                self.on_cpu_idle(a);
+            }
             Action::GpuMem(a) => {
                 self.on_gpu_mem(a);
             }
@@ -0,0 +1,399 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use crate::StatAggregation;
+use crate::VecStats;
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+/// Number of log2 buckets of the latency histograms.
+pub const LAT_HIST_BUCKETS: usize = 16;
+/// Max number of latency samples kept per thread.
+const MAX_LAT_SAMPLES: usize = 1024;
+
+/// Column the process view is sorted by.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProcSort {
+    #[default]
+    Runtime,
+    WakeupLat,
+    DsqLat,
+    VoluntarySwitches,
+    InvoluntarySwitches,
+    Migrations,
+}
+
+impl ProcSort {
+    /// Returns the next ProcSort.
+    pub fn next(&self) -> Self {
+        match self {
+            ProcSort::Runtime => ProcSort::WakeupLat,
+            ProcSort::WakeupLat => ProcSort::DsqLat,
+            ProcSort::DsqLat => ProcSort::VoluntarySwitches,
+            ProcSort::VoluntarySwitches => ProcSort::InvoluntarySwitches,
+            ProcSort::InvoluntarySwitches => ProcSort::Migrations,
+            ProcSort::Migrations => ProcSort::Runtime,
+        }
+    }
+}
+
+impl std::fmt::Display for ProcSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProcSort::Runtime => write!(f, "runtime"),
+            ProcSort::WakeupLat => write!(f, "wakeup latency"),
+            ProcSort::DsqLat => write!(f, "dsq latency"),
+            ProcSort::VoluntarySwitches => write!(f, "voluntary switches"),
+            ProcSort::InvoluntarySwitches => write!(f, "involuntary switches"),
+            ProcSort::Migrations => write!(f, "migrations"),
+        }
+    }
+}
+
+/// Cumulative counters of a thread as reported on sched_switch.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskCounters {
+    pub runtime_ns: u64,
+    pub nvcsw: u64,
+    pub nivcsw: u64,
+    pub nr_migrations: u64,
+}
+
+/// Counters accumulated over a window of ticks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskDelta {
+    pub elapsed_ns: u64,
+    pub runtime_ns: u64,
+    pub nvcsw: u64,
+    pub nivcsw: u64,
+    pub nr_migrations: u64,
+}
+
+impl TaskDelta {
+    fn add(&mut self, other: &TaskDelta) {
+        self.runtime_ns += other.runtime_ns;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+        self.nr_migrations += other.nr_migrations;
+    }
+
+    /// Returns the fraction of the window spent running in percent.
+    pub fn cpu_pct(&self) -> f64 {
+        if self.elapsed_ns == 0 {
+            return 0.0;
+        }
+        self.runtime_ns as f64 * 100.0 / self.elapsed_ns as f64
+    }
+
+    /// Returns the per second rate of a counter over the window.
+    pub fn rate(&self, val: u64) -> u64 {
+        if self.elapsed_ns == 0 {
+            return 0;
+        }
+        (val as f64 * 1_000_000_000.0 / self.elapsed_ns as f64) as u64
+    }
+}
+
+/// Returns the average and p99 of latency samples.
+pub fn lat_stats(samples: &[u64]) -> (u64, u64) {
+    let stats = VecStats::new(
+        &samples.to_vec(),
+        true,
+        false,
+        false,
+        Some(HashSet::from([StatAggregation::P99])),
+    );
+    let p99 = stats
+        .percentiles
+        .and_then(|p| p.get(&StatAggregation::P99).copied())
+        .unwrap_or(0);
+    (stats.avg, p99)
+}
+
+/// Window counters and latency stats of a thread or process, computed once
+/// per tick as rendering and sorting need them for every row.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskSummary {
+    pub window: TaskDelta,
+    /// Average and p99 of the wakeup latency samples.
+    pub wakeup_lat_us: (u64, u64),
+    /// Average and p99 of the DSQ latency samples.
+    pub dsq_lat_us: (u64, u64),
+}
+
+impl TaskSummary {
+    fn new(window: TaskDelta, wakeup_lat_us: &[u64], dsq_lat_us: &[u64]) -> Self {
+        Self {
+            window,
+            wakeup_lat_us: lat_stats(wakeup_lat_us),
+            dsq_lat_us: lat_stats(dsq_lat_us),
+        }
+    }
+
+    /// Returns the value to sort the thread or process by.
+    pub fn sort_key(&self, sort: ProcSort) -> u64 {
+        match sort {
+            ProcSort::Runtime => self.window.runtime_ns,
+            ProcSort::WakeupLat => self.wakeup_lat_us.1,
+            ProcSort::DsqLat => self.dsq_lat_us.1,
+            ProcSort::VoluntarySwitches => self.window.nvcsw,
+            ProcSort::InvoluntarySwitches => self.window.nivcsw,
+            ProcSort::Migrations => self.window.nr_migrations,
+        }
+    }
+}
+
+/// Returns the log2 histogram of latency samples in us.
+pub fn lat_histogram<'a>(samples: impl Iterator<Item = &'a u64>) -> [u64; LAT_HIST_BUCKETS] {
+    let mut hist = [0; LAT_HIST_BUCKETS];
+    for val in samples {
+        let bucket = (u64::BITS - 1 - (*val).max(1).leading_zeros()) as usize;
+        hist[bucket.min(LAT_HIST_BUCKETS - 1)] += 1;
+    }
+    hist
+}
+
+/// Returns the label of a latency histogram bucket.
+pub fn lat_bucket_label(bucket: usize) -> String {
+    let lower_us: u64 = if bucket == 0 { 0 } else { 1 << bucket };
+    let label = if lower_us >= 1000 {
+        format!("{}ms", lower_us / 1000)
+    } else {
+        format!("{}us", lower_us)
+    };
+    if bucket == LAT_HIST_BUCKETS - 1 {
+        format!("{}+", label)
+    } else {
+        label
+    }
+}
+
+/// Container for per thread data.
+#[derive(Clone, Debug)]
+pub struct ThreadData {
+    pub tid: u32,
+    pub comm: String,
+    pub wakeup_lat_us: VecDeque<u64>,
+    pub dsq_lat_us: VecDeque<u64>,
+    /// Summary as of the last tick.
+    pub summary: TaskSummary,
+    deltas: VecDeque<TaskDelta>,
+    counters: Option<TaskCounters>,
+    tick_counters: Option<TaskCounters>,
+    idle_ticks: usize,
+    max_data_size: usize,
+}
+
+impl ThreadData {
+    /// Creates a new ThreadData.
+    pub fn new(tid: u32, comm: &str, max_data_size: usize) -> ThreadData {
+        Self {
+            tid,
+            comm: comm.to_string(),
+            wakeup_lat_us: VecDeque::new(),
+            dsq_lat_us: VecDeque::new(),
+            summary: TaskSummary::default(),
+            deltas: VecDeque::new(),
+            counters: None,
+            tick_counters: None,
+            idle_ticks: 0,
+            max_data_size,
+        }
+    }
+
+    /// Updates the thread when it is switched out.
+    pub fn on_switch_out(&mut self, counters: TaskCounters) {
+        if self.tick_counters.is_none() {
+            self.tick_counters = Some(counters);
+        }
+        self.counters = Some(counters);
+        self.idle_ticks = 0;
+    }
+
+    /// Updates the thread when it is switched in.
+    pub fn on_switch_in(&mut self, wakeup_lat_us: u64, dsq_lat_us: u64) {
+        if wakeup_lat_us > 0 {
+            if self.wakeup_lat_us.len() == MAX_LAT_SAMPLES {
+                self.wakeup_lat_us.pop_front();
+            }
+            self.wakeup_lat_us.push_back(wakeup_lat_us);
+        }
+        if dsq_lat_us > 0 {
+            if self.dsq_lat_us.len() == MAX_LAT_SAMPLES {
+                self.dsq_lat_us.pop_front();
+            }
+            self.dsq_lat_us.push_back(dsq_lat_us);
+        }
+        self.idle_ticks = 0;
+    }
+
+    /// Records the counter deltas since the last tick.
+    pub fn tick(&mut self, elapsed_ns: u64) {
+        let delta = match (self.counters, self.tick_counters) {
+            (Some(cur), Some(last)) => TaskDelta {
+                elapsed_ns,
+                runtime_ns: cur.runtime_ns.saturating_sub(last.runtime_ns),
+                nvcsw: cur.nvcsw.saturating_sub(last.nvcsw),
+                nivcsw: cur.nivcsw.saturating_sub(last.nivcsw),
+                nr_migrations: cur.nr_migrations.saturating_sub(last.nr_migrations),
+            },
+            _ => TaskDelta {
+                elapsed_ns,
+                ..Default::default()
+            },
+        };
+        if self.deltas.len() >= self.max_data_size {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+        self.tick_counters = self.counters;
+        self.idle_ticks += 1;
+
+        let window = self.window();
+        self.summary = TaskSummary::new(
+            window,
+            self.wakeup_lat_us.make_contiguous(),
+            self.dsq_lat_us.make_contiguous(),
+        );
+    }
+
+    /// Returns true if the thread was not seen for the whole window.
+    pub fn is_stale(&self) -> bool {
+        self.idle_ticks > self.max_data_size
+    }
+
+    /// Returns the counters accumulated over the window.
+    pub fn window(&self) -> TaskDelta {
+        let mut total = TaskDelta::default();
+        for delta in &self.deltas {
+            total.elapsed_ns += delta.elapsed_ns;
+            total.add(delta);
+        }
+        total
+    }
+}
+
+/// Container for per process data.
+#[derive(Clone, Debug)]
+pub struct ProcData {
+    pub tgid: u32,
+    pub comm: String,
+    pub threads: BTreeMap<u32, ThreadData>,
+    /// Summary of all threads as of the last tick.
+    pub summary: TaskSummary,
+    max_data_size: usize,
+}
+
+impl ProcData {
+    /// Creates a new ProcData.
+    pub fn new(tgid: u32, max_data_size: usize) -> ProcData {
+        Self {
+            tgid,
+            comm: String::new(),
+            threads: BTreeMap::new(),
+            summary: TaskSummary::default(),
+            max_data_size,
+        }
+    }
+
+    /// Returns the data of a thread and creates it if no entry is present.
+    pub fn thread_mut(&mut self, tid: u32, comm: &str) -> &mut ThreadData {
+        if tid == self.tgid || self.comm.is_empty() {
+            self.comm = comm.to_string();
+        }
+        let thread = self
+            .threads
+            .entry(tid)
+            .or_insert_with(|| ThreadData::new(tid, comm, self.max_data_size));
+        if thread.comm != comm {
+            thread.comm = comm.to_string();
+        }
+        thread
+    }
+
+    /// Records the counter deltas of all threads and drops stale threads.
+    pub fn tick(&mut self, elapsed_ns: u64) {
+        for thread in self.threads.values_mut() {
+            thread.tick(elapsed_ns);
+        }
+        self.threads.retain(|_, thread| !thread.is_stale());
+        self.summary = TaskSummary::new(self.window(), &self.wakeup_lat_us(), &self.dsq_lat_us());
+    }
+
+    /// Returns true if the process has no threads left.
+    pub fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    /// Returns the counters of all threads accumulated over the window as of
+    /// the last tick.
+    pub fn window(&self) -> TaskDelta {
+        let mut total = TaskDelta::default();
+        for thread in self.threads.values() {
+            let window = thread.summary.window;
+            total.elapsed_ns = total.elapsed_ns.max(window.elapsed_ns);
+            total.add(&window);
+        }
+        total
+    }
+
+    /// Returns the wakeup latency samples of all threads.
+    pub fn wakeup_lat_us(&self) -> Vec<u64> {
+        self.threads
+            .values()
+            .flat_map(|thread| thread.wakeup_lat_us.iter().copied())
+            .collect()
+    }
+
+    /// Returns the DSQ latency samples of all threads.
+    pub fn dsq_lat_us(&self) -> Vec<u64> {
+        self.threads
+            .values()
+            .flat_map(|thread| thread.dsq_lat_us.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(runtime_ns: u64, nvcsw: u64) -> TaskCounters {
+        TaskCounters {
+            runtime_ns,
+            nvcsw,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summary_updated_on_tick() {
+        let mut proc_data = ProcData::new(10, 4);
+        let thread = proc_data.thread_mut(10, "main");
+        thread.on_switch_out(counters(1000, 1));
+        thread.on_switch_in(100, 10);
+        thread.on_switch_out(counters(3000, 2));
+        let thread = proc_data.thread_mut(11, "worker");
+        thread.on_switch_out(counters(0, 0));
+        thread.on_switch_in(300, 30);
+        thread.on_switch_out(counters(5000, 4));
+
+        // nothing is computed until the tick
+        assert_eq!(proc_data.summary.sort_key(ProcSort::Runtime), 0);
+
+        proc_data.tick(1_000_000);
+        let main = &proc_data.threads[&10].summary;
+        assert_eq!(main.window.runtime_ns, 2000);
+        assert_eq!(main.wakeup_lat_us, (100, 100));
+        let summary = &proc_data.summary;
+        assert_eq!(summary.window.elapsed_ns, 1_000_000);
+        assert_eq!(summary.window.runtime_ns, 7000);
+        assert_eq!(summary.sort_key(ProcSort::VoluntarySwitches), 5);
+        assert_eq!(summary.wakeup_lat_us.0, 200);
+        assert_eq!(summary.dsq_lat_us.0, 20);
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::json;
+use serde_json::Value as JsonValue;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+
+/// Output format of recorded samples.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum RecordFormat {
+    /// One row per sample with a header.
+    #[default]
+    Csv,
+    /// One JSON object per sample.
+    Jsonl,
+    /// JSON objects of columns, one column per metric, one per row group.
+    Columnar,
+}
+
+/// A single metric value sampled on a tick.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub scope: &'static str,
+    pub id: String,
+    pub metric: String,
+    pub value: f64,
+}
+
+impl Sample {
+    /// Creates a new Sample.
+    pub fn new(scope: &'static str, id: impl ToString, metric: &str, value: f64) -> Self {
+        Self {
+            scope,
+            id: id.to_string(),
+            metric: metric.to_string(),
+            value,
+        }
+    }
+
+    /// Returns the name of the column of the sample in columnar output.
+    pub fn column(&self) -> String {
+        if self.id.is_empty() {
+            format!("{}.{}", self.scope, self.metric)
+        } else {
+            format!("{}.{}.{}", self.scope, self.id, self.metric)
+        }
+    }
+}
+
+/// Writer of recorded samples.
+pub trait SampleWriter {
+    /// Writes the samples of a tick.
+    fn write(&mut self, timestamp_ms: u64, samples: &[Sample]) -> Result<()>;
+    /// Flushes any buffered output.
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Returns a SampleWriter for a format.
+pub fn sample_writer(format: RecordFormat, out: Box<dyn Write>) -> Box<dyn SampleWriter> {
+    let out = BufWriter::new(out);
+    match format {
+        RecordFormat::Csv => Box::new(CsvWriter::new(out)),
+        RecordFormat::Jsonl => Box::new(JsonlWriter { out }),
+        RecordFormat::Columnar => Box::new(ColumnarWriter::new(out)),
+    }
+}
+
+/// Returns the output of a recording, stdout if no path is given.
+pub fn record_output(path: Option<&str>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) if path != "-" => Box::new(File::create(path)?),
+        _ => Box::new(std::io::stdout()),
+    })
+}
+
+/// Writes samples as CSV.
+pub struct CsvWriter<W: Write> {
+    out: W,
+    header: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Creates a new CsvWriter.
+    pub fn new(out: W) -> Self {
+        Self { out, header: false }
+    }
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl<W: Write> SampleWriter for CsvWriter<W> {
+    fn write(&mut self, timestamp_ms: u64, samples: &[Sample]) -> Result<()> {
+        if !self.header {
+            writeln!(self.out, "timestamp_ms,scope,id,metric,value")?;
+            self.header = true;
+        }
+        for sample in samples {
+            writeln!(
+                self.out,
+                "{},{},{},{},{}",
+                timestamp_ms,
+                sample.scope,
+                csv_field(&sample.id),
+                csv_field(&sample.metric),
+                sample.value
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes samples as JSON lines.
+pub struct JsonlWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> SampleWriter for JsonlWriter<W> {
+    fn write(&mut self, timestamp_ms: u64, samples: &[Sample]) -> Result<()> {
+        for sample in samples {
+            let line = json!({
+                "timestamp_ms": timestamp_ms,
+                "scope": sample.scope,
+                "id": sample.id,
+                "metric": sample.metric,
+                "value": sample.value,
+            });
+            writeln!(self.out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Number of ticks buffered by ColumnarWriter before a row group is written.
+pub const COLUMNAR_ROW_GROUP_ROWS: usize = 1024;
+
+/// Writes samples as JSON objects of equally sized columns, one line per row
+/// group of up to COLUMNAR_ROW_GROUP_ROWS ticks, each of which can be loaded
+/// directly as a dataframe. Every row group has a column for each metric seen
+/// so far and metrics missing on a tick are null.
+pub struct ColumnarWriter<W: Write> {
+    out: W,
+    row_group_rows: usize,
+    timestamps: Vec<u64>,
+    columns: BTreeMap<String, Vec<Option<f64>>>,
+}
+
+impl<W: Write> ColumnarWriter<W> {
+    /// Creates a new ColumnarWriter.
+    pub fn new(out: W) -> Self {
+        Self::with_row_group_rows(out, COLUMNAR_ROW_GROUP_ROWS)
+    }
+
+    /// Creates a new ColumnarWriter writing row groups of at most
+    /// row_group_rows ticks.
+    pub fn with_row_group_rows(out: W, row_group_rows: usize) -> Self {
+        Self {
+            out,
+            row_group_rows: row_group_rows.max(1),
+            timestamps: Vec::new(),
+            columns: BTreeMap::new(),
+        }
+    }
+
+    /// Writes the buffered ticks as a row group, keeping the known columns.
+    fn write_row_group(&mut self) -> Result<()> {
+        if self.timestamps.is_empty() {
+            return Ok(());
+        }
+        let mut obj = serde_json::Map::new();
+        obj.insert("timestamp_ms".to_string(), json!(self.timestamps));
+        for (name, column) in &self.columns {
+            obj.insert(name.clone(), json!(column));
+        }
+        serde_json::to_writer(&mut self.out, &JsonValue::Object(obj))?;
+        writeln!(self.out)?;
+        self.out.flush()?;
+
+        self.timestamps.clear();
+        for column in self.columns.values_mut() {
+            column.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> SampleWriter for ColumnarWriter<W> {
+    fn write(&mut self, timestamp_ms: u64, samples: &[Sample]) -> Result<()> {
+        let rows = self.timestamps.len();
+        self.timestamps.push(timestamp_ms);
+        for sample in samples {
+            let column = self
+                .columns
+                .entry(sample.column())
+                .or_insert_with(|| vec![None; rows]);
+            column.resize(rows, None);
+            column.push(Some(sample.value));
+        }
+        for column in self.columns.values_mut() {
+            column.resize(rows + 1, None);
+        }
+        if self.timestamps.len() >= self.row_group_rows {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.write_row_group()?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            Sample::new("cpu", 0, "cycles", 10.0),
+            Sample::new("sched", "", "busy", 1.5),
+        ]
+    }
+
+    #[test]
+    fn test_csv_writer() {
+        let mut out = Vec::new();
+        let mut writer = CsvWriter::new(&mut out);
+        writer.write(5, &samples()).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp_ms,scope,id,metric,value\n5,cpu,0,cycles,10\n5,sched,,busy,1.5\n"
+        );
+    }
+
+    #[test]
+    fn test_columnar_writer() {
+        let mut out = Vec::new();
+        let mut writer = ColumnarWriter::new(&mut out);
+        writer.write(5, &samples()[..1]).unwrap();
+        writer.write(10, &samples()).unwrap();
+        writer.finish().unwrap();
+        let value: JsonValue = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["timestamp_ms"], json!([5, 10]));
+        assert_eq!(value["cpu.0.cycles"], json!([10.0, 10.0]));
+        assert_eq!(value["sched.busy"], json!([null, 1.5]));
+    }
+
+    #[test]
+    fn test_columnar_writer_row_groups() {
+        let mut out = Vec::new();
+        let mut writer = ColumnarWriter::with_row_group_rows(&mut out, 2);
+        writer.write(5, &samples()[..1]).unwrap();
+        writer.write(10, &samples()[..1]).unwrap();
+        writer.write(15, &samples()).unwrap();
+        writer.write(20, &[]).unwrap();
+        writer.write(25, &samples()[..1]).unwrap();
+        writer.finish().unwrap();
+
+        let groups: Vec<JsonValue> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0]["timestamp_ms"], json!([5, 10]));
+        assert!(groups[0].get("sched.busy").is_none());
+        assert_eq!(groups[1]["timestamp_ms"], json!([15, 20]));
+        assert_eq!(groups[1]["sched.busy"], json!([1.5, null]));
+        assert_eq!(groups[2]["timestamp_ms"], json!([25]));
+        assert_eq!(groups[2]["cpu.0.cycles"], json!([10.0]));
+        assert_eq!(groups[2]["sched.busy"], json!([null]));
+    }
+}
@@ -0,0 +1,404 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use crate::edm::ActionHandler;
+use crate::hist::HistSet;
+use crate::Action;
+
+use anyhow::{anyhow, Result};
+use log::{debug, info, warn};
+use scx_utils::Topology;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// Version of the protocol between `scxtop serve` and `scxtop --remote`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Number of messages buffered per client before it starts dropping them.
+const CLIENT_BACKLOG: usize = 64 * 1024;
+
+/// CPU of a topology.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CpuInfo {
+    pub id: usize,
+    pub core_id: usize,
+    pub llc_id: usize,
+    pub node_id: usize,
+    pub package_id: usize,
+}
+
+/// LLC of a topology.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LlcInfo {
+    pub id: usize,
+    pub node_id: usize,
+}
+
+/// NUMA node of a topology.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub id: usize,
+    pub all_cpus: BTreeSet<usize>,
+}
+
+/// Topology of the host being observed, which may not be the local one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TopoInfo {
+    pub all_cpus: BTreeMap<usize, CpuInfo>,
+    pub all_llcs: BTreeMap<usize, LlcInfo>,
+    pub nodes: BTreeMap<usize, NodeInfo>,
+}
+
+impl From<&Topology> for TopoInfo {
+    fn from(topo: &Topology) -> Self {
+        Self {
+            all_cpus: topo
+                .all_cpus
+                .values()
+                .map(|cpu| {
+                    let info = CpuInfo {
+                        id: cpu.id,
+                        core_id: cpu.core_id,
+                        llc_id: cpu.llc_id,
+                        node_id: cpu.node_id,
+                        package_id: cpu.package_id,
+                    };
+                    (cpu.id, info)
+                })
+                .collect(),
+            all_llcs: topo
+                .all_llcs
+                .values()
+                .map(|llc| {
+                    let info = LlcInfo {
+                        id: llc.id,
+                        node_id: llc.node_id,
+                    };
+                    (llc.id, info)
+                })
+                .collect(),
+            nodes: topo
+                .nodes
+                .values()
+                .map(|node| {
+                    let info = NodeInfo {
+                        id: node.id,
+                        all_cpus: node.all_cpus.keys().copied().collect(),
+                    };
+                    (node.id, info)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// First message sent by the agent to a client.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: u32,
+    pub topo: TopoInfo,
+    pub perf_events: Vec<String>,
+}
+
+/// Data the agent collects on every tick that isn't carried by events.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TickData {
+    pub scheduler: String,
+    pub sample_rate: u32,
+    pub dropped_events: u64,
+    pub perf_event: String,
+    pub perf: BTreeMap<usize, u64>,
+    /// Values of a derived metric derived from the summed events of the llcs and nodes.
+    #[serde(default)]
+    pub llc_perf: BTreeMap<usize, u64>,
+    #[serde(default)]
+    pub node_perf: BTreeMap<usize, u64>,
+    pub cpu_freq: BTreeMap<usize, u64>,
+    pub uncore_freq: BTreeMap<usize, u64>,
+    pub cpu_hists: BTreeMap<usize, HistSet>,
+    pub dsq_hists: BTreeMap<u64, HistSet>,
+}
+
+/// Newline delimited JSON message exchanged between the agent and clients.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Message {
+    Hello(Hello),
+    Action(Action),
+    Tick(Box<TickData>),
+}
+
+impl Message {
+    /// Returns the encoded message including the trailing newline.
+    pub fn encode(&self) -> Result<String> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+        Ok(line)
+    }
+
+    /// Decodes a message from a line.
+    pub fn decode(line: &str) -> Result<Self> {
+        Ok(serde_json::from_str(line)?)
+    }
+}
+
+/// Returns if the action is an event that the agent streams to clients.
+pub fn is_event(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::Cpuhp(_)
+            | Action::CpuIdle(_)
+            | Action::GpuMem(_)
+            | Action::HardIRQ(_)
+            | Action::HwPressure(_)
+            | Action::IPI(_)
+            | Action::SchedCpuPerfSet(_)
+            | Action::SchedMigrateTask(_)
+            | Action::SchedProcessExec(_)
+            | Action::SchedProcessExit(_)
+            | Action::SchedProcessFork(_)
+            | Action::SchedReg
+            | Action::SchedStats(_)
+            | Action::SchedStatsMeta(_)
+            | Action::SchedSwitch(_)
+            | Action::SchedUnreg
+            | Action::SchedWakeupNew(_)
+            | Action::SchedWakeup(_)
+            | Action::SchedWaking(_)
+            | Action::SoftIRQ(_)
+            | Action::TraceStarted(_)
+            | Action::TraceStopped(_)
+    )
+}
+
+/// Returns if the action is one that clients may forward to the agent.
+pub fn is_control(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::ClearEvent
+            | Action::DecBpfSampleRate
+            | Action::IncBpfSampleRate
+            | Action::NextEvent
+            | Action::PrevEvent
+            | Action::RequestTrace
+            | Action::SetState(_)
+            | Action::ToggleCpuFreq
+            | Action::ToggleUncoreFreq
+    )
+}
+
+/// RemotePublisher is an ActionHandler that streams events to the clients of an agent.
+pub struct RemotePublisher {
+    tx: broadcast::Sender<Arc<str>>,
+}
+
+impl RemotePublisher {
+    /// Returns a new RemotePublisher.
+    pub fn new(tx: broadcast::Sender<Arc<str>>) -> Self {
+        Self { tx }
+    }
+
+    /// Sends a message to all connected clients.
+    pub fn publish(&self, message: &Message) -> Result<()> {
+        if self.tx.receiver_count() > 0 {
+            // sending only fails when all clients went away in the meantime
+            let _ = self.tx.send(message.encode()?.into());
+        }
+        Ok(())
+    }
+}
+
+impl ActionHandler for RemotePublisher {
+    fn on_action(&mut self, action: &Action) -> Result<()> {
+        if is_event(action) {
+            self.publish(&Message::Action(action.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the channel used to broadcast messages to the clients of an agent.
+pub fn broadcast_channel() -> broadcast::Sender<Arc<str>> {
+    broadcast::channel(CLIENT_BACKLOG).0
+}
+
+/// Accepts clients, sending them the hello and broadcasted messages. Control actions received
+/// from clients are forwarded to control_tx.
+pub async fn serve(
+    listener: TcpListener,
+    hello: Hello,
+    tx: broadcast::Sender<Arc<str>>,
+    control_tx: UnboundedSender<Action>,
+) -> Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("client {} connected", addr);
+        let hello = Message::Hello(hello.clone()).encode()?;
+        let rx = tx.subscribe();
+        let control_tx = control_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(stream, hello, rx, control_tx).await {
+                debug!("client {}: {}", addr, e);
+            }
+            info!("client {} disconnected", addr);
+        });
+    }
+}
+
+async fn serve_client(
+    stream: TcpStream,
+    hello: String,
+    mut rx: broadcast::Receiver<Arc<str>>,
+    control_tx: UnboundedSender<Action>,
+) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(hello.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Ok(line) => writer.write_all(line.as_bytes()).await?,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("client lagging, dropped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                match Message::decode(&line)? {
+                    Message::Action(action) if is_control(&action) => control_tx.send(action)?,
+                    msg => debug!("ignoring client message: {:?}", msg),
+                }
+            }
+        }
+    }
+}
+
+/// Connects to an agent, returning its hello, a receiver of its messages and a sender for
+/// control actions.
+pub async fn connect<A: ToSocketAddrs>(
+    addr: A,
+) -> Result<(Hello, UnboundedReceiver<Message>, UnboundedSender<Action>)> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let hello = match lines.next_line().await? {
+        Some(line) => match Message::decode(&line)? {
+            Message::Hello(hello) => hello,
+            msg => return Err(anyhow!("expected hello, got {:?}", msg)),
+        },
+        None => return Err(anyhow!("connection closed before hello")),
+    };
+    if hello.version != PROTOCOL_VERSION {
+        return Err(anyhow!(
+            "unsupported protocol version {} (expected {})",
+            hello.version,
+            PROTOCOL_VERSION
+        ));
+    }
+
+    let (msg_tx, msg_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(line) = lines.next_line().await? {
+            msg_tx.send(Message::decode(&line)?)?;
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel::<Action>();
+    tokio::spawn(async move {
+        while let Some(action) = control_rx.recv().await {
+            writer
+                .write_all(Message::Action(action).encode()?.as_bytes())
+                .await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+
+    Ok((hello, msg_rx, control_tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppState, SchedCpuPerfSetAction};
+
+    #[test]
+    fn test_message_roundtrip() {
+        for action in [
+            Action::SchedCpuPerfSet(SchedCpuPerfSetAction { cpu: 3, perf: 512 }),
+            Action::SchedStats("{\n  \"nr_cpus\": 8\n}".to_string()),
+            Action::SetState(AppState::Latency),
+            Action::SchedReg,
+        ] {
+            let line = Message::Action(action.clone()).encode().unwrap();
+            assert!(line.ends_with('\n'));
+            assert!(!line.trim_end().contains('\n'));
+            match Message::decode(line.trim_end()).unwrap() {
+                Message::Action(decoded) => assert_eq!(decoded, action),
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_and_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tx = broadcast_channel();
+        let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+        let hello = Hello {
+            version: PROTOCOL_VERSION,
+            perf_events: vec!["hw:cycles".to_string()],
+            ..Default::default()
+        };
+        tokio::spawn(serve(listener, hello, tx.clone(), control_tx));
+
+        let (hello, mut rx, remote_tx) = connect(addr).await.unwrap();
+        assert_eq!(hello.perf_events, vec!["hw:cycles".to_string()]);
+
+        let mut publisher = RemotePublisher::new(tx);
+        publisher.on_action(&Action::SchedReg).unwrap();
+        publisher.on_action(&Action::Tick).unwrap();
+        publisher.on_action(&Action::SchedUnreg).unwrap();
+        for expected in [Action::SchedReg, Action::SchedUnreg] {
+            match rx.recv().await.unwrap() {
+                Message::Action(action) => assert_eq!(action, expected),
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+
+        remote_tx.send(Action::Quit).unwrap();
+        remote_tx.send(Action::RequestTrace).unwrap();
+        assert_eq!(control_rx.recv().await.unwrap(), Action::RequestTrace);
+    }
+
+    #[test]
+    fn test_event_and_control_are_disjoint() {
+        for action in [
+            Action::SchedReg,
+            Action::RequestTrace,
+            Action::SetState(AppState::Default),
+            Action::Tick,
+            Action::Quit,
+        ] {
+            assert!(!(is_event(&action) && is_control(&action)));
+        }
+        assert!(!is_event(&Action::Tick));
+        assert!(!is_control(&Action::Quit));
+    }
+}
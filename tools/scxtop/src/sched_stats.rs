@@ -0,0 +1,243 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use scx_stats::prelude::{StatsData, StatsKind, StatsMeta};
+use serde_json::Value as JsonValue;
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+
+/// Value of a scheduler stats table cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatsCell {
+    Num(f64),
+    Str(String),
+}
+
+impl StatsCell {
+    fn from_json(value: &JsonValue) -> Self {
+        match value {
+            JsonValue::Number(num) => StatsCell::Num(num.as_f64().unwrap_or(0.0)),
+            JsonValue::String(s) => StatsCell::Str(s.clone()),
+            JsonValue::Null => StatsCell::Str(String::new()),
+            v => StatsCell::Str(v.to_string()),
+        }
+    }
+
+    fn cmp_desc(&self, other: &Self) -> std::cmp::Ordering {
+        match (self, other) {
+            (StatsCell::Num(a), StatsCell::Num(b)) => b.total_cmp(a),
+            (StatsCell::Str(a), StatsCell::Str(b)) => a.cmp(b),
+            (StatsCell::Num(_), StatsCell::Str(_)) => std::cmp::Ordering::Less,
+            (StatsCell::Str(_), StatsCell::Num(_)) => std::cmp::Ordering::Greater,
+        }
+    }
+}
+
+/// A numeric scheduler stat.
+#[derive(Clone, Debug)]
+pub struct StatsSeries {
+    pub name: String,
+    pub desc: String,
+    pub float: bool,
+    pub data: VecDeque<f64>,
+}
+
+impl StatsSeries {
+    /// Returns the latest value.
+    pub fn last(&self) -> f64 {
+        self.data.back().copied().unwrap_or(0.0)
+    }
+}
+
+/// A scheduler stat of Dict or Array type, rendered as a table.
+#[derive(Clone, Debug)]
+pub struct StatsTable {
+    pub name: String,
+    pub key: String,
+    pub columns: Vec<String>,
+    pub float_columns: Vec<bool>,
+    pub rows: Vec<(String, Vec<StatsCell>)>,
+}
+
+impl StatsTable {
+    /// Sorts the rows by a column, 0 being the key column.
+    pub fn sort(&mut self, column: usize) {
+        if column == 0 {
+            self.rows.sort_by(|a, b| a.0.cmp(&b.0));
+            return;
+        }
+        self.rows
+            .sort_by(|a, b| match (a.1.get(column - 1), b.1.get(column - 1)) {
+                (Some(a), Some(b)) => a.cmp_desc(b),
+                _ => std::cmp::Ordering::Equal,
+            });
+    }
+}
+
+/// Container for scheduler stats described by the scheduler's stats_meta.
+#[derive(Clone, Debug, Default)]
+pub struct SchedStatsData {
+    meta: BTreeMap<String, StatsMeta>,
+    top: Option<String>,
+    pub series: BTreeMap<String, StatsSeries>,
+    pub tables: BTreeMap<String, StatsTable>,
+    max_data_size: usize,
+}
+
+/// Returns the name of a struct kind without the module path.
+fn struct_name(name: &str) -> &str {
+    name.rsplit("::").next().unwrap_or(name).trim()
+}
+
+impl SchedStatsData {
+    /// Creates a new SchedStatsData from the stats_meta response.
+    pub fn new(meta: BTreeMap<String, StatsMeta>, max_data_size: usize) -> Self {
+        let top = meta
+            .values()
+            .find(|m| m.attrs.top.is_some())
+            .map(|m| m.name.clone());
+        Self {
+            meta,
+            top,
+            series: BTreeMap::new(),
+            tables: BTreeMap::new(),
+            max_data_size,
+        }
+    }
+
+    /// Returns true if the stats metadata is known.
+    pub fn has_meta(&self) -> bool {
+        self.top.is_some()
+    }
+
+    /// Sets the max size and truncates the time series.
+    pub fn set_max_size(&mut self, max_data_size: usize) {
+        self.max_data_size = max_data_size;
+        for series in self.series.values_mut() {
+            while series.data.len() > max_data_size {
+                series.data.pop_front();
+            }
+        }
+    }
+
+    /// Records a stats response.
+    pub fn update(&mut self, stats: &JsonValue) {
+        let Some(top) = self.top.clone() else {
+            return;
+        };
+        self.update_struct(&top, "", stats);
+    }
+
+    fn update_struct(&mut self, meta_name: &str, prefix: &str, value: &JsonValue) {
+        let Some(meta) = self.meta.get(struct_name(meta_name)) else {
+            return;
+        };
+        for (name, field) in meta.fields.clone() {
+            let path = format!("{}{}", prefix, name);
+            let field_value = value.get(&name).unwrap_or(&JsonValue::Null);
+            match &field.data {
+                StatsData::Datum(StatsKind::I64 | StatsKind::U64 | StatsKind::Float) => {
+                    let max_data_size = self.max_data_size;
+                    let series = self
+                        .series
+                        .entry(path.clone())
+                        .or_insert_with(|| StatsSeries {
+                            name: path,
+                            desc: field.attrs.desc.clone().unwrap_or_default(),
+                            float: matches!(field.data, StatsData::Datum(StatsKind::Float)),
+                            data: VecDeque::new(),
+                        });
+                    if series.data.len() >= max_data_size {
+                        series.data.pop_front();
+                    }
+                    series.data.push_back(field_value.as_f64().unwrap_or(0.0));
+                }
+                StatsData::Datum(StatsKind::Struct(inner)) => {
+                    self.update_struct(inner, &format!("{}.", path), field_value);
+                }
+                StatsData::Datum(StatsKind::String) => {}
+                StatsData::Dict { key: _, datum } => {
+                    let rows: Vec<(String, &JsonValue)> = field_value
+                        .as_object()
+                        .map(|obj| obj.iter().map(|(k, v)| (k.clone(), v)).collect())
+                        .unwrap_or_default();
+                    let table = self.build_table(&path, &name, datum, rows);
+                    self.tables.insert(path, table);
+                }
+                StatsData::Array(datum) => {
+                    let rows: Vec<(String, &JsonValue)> = field_value
+                        .as_array()
+                        .map(|arr| {
+                            arr.iter()
+                                .enumerate()
+                                .map(|(i, v)| (i.to_string(), v))
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    let table = self.build_table(&path, "index", datum, rows);
+                    self.tables.insert(path, table);
+                }
+            }
+        }
+    }
+
+    fn build_table(
+        &self,
+        name: &str,
+        key: &str,
+        datum: &StatsKind,
+        rows: Vec<(String, &JsonValue)>,
+    ) -> StatsTable {
+        let inner = match datum {
+            StatsKind::Struct(inner) => self.meta.get(struct_name(inner)),
+            _ => None,
+        };
+        let (columns, float_columns): (Vec<String>, Vec<bool>) = match inner {
+            Some(meta) => meta
+                .fields
+                .iter()
+                .filter(|(_, field)| {
+                    matches!(
+                        field.data,
+                        StatsData::Datum(
+                            StatsKind::I64 | StatsKind::U64 | StatsKind::Float | StatsKind::String
+                        )
+                    )
+                })
+                .map(|(name, field)| {
+                    (
+                        name.clone(),
+                        matches!(field.data, StatsData::Datum(StatsKind::Float)),
+                    )
+                })
+                .unzip(),
+            None => (
+                vec!["value".to_string()],
+                vec![matches!(datum, StatsKind::Float)],
+            ),
+        };
+        let rows = rows
+            .into_iter()
+            .map(|(k, v)| {
+                let cells = match inner {
+                    Some(_) => columns
+                        .iter()
+                        .map(|c| StatsCell::from_json(v.get(c).unwrap_or(&JsonValue::Null)))
+                        .collect(),
+                    None => vec![StatsCell::from_json(v)],
+                };
+                (k, cells)
+            })
+            .collect();
+        StatsTable {
+            name: name.to_string(),
+            key: key.to_string(),
+            columns,
+            float_columns,
+            rows,
+        }
+    }
+}
@@ -33,6 +33,13 @@ impl<'a> Tracer<'a> {
             self.skel.progs.on_softirq_entry.attach()?,
             self.skel.progs.on_softirq_exit.attach()?,
             self.skel.progs.on_ipi_send_cpu.attach()?,
+            self.skel.progs.on_irq_handler_entry.attach()?,
+            self.skel.progs.on_irq_handler_exit.attach()?,
+            self.skel.progs.on_sched_migrate_task.attach()?,
+            self.skel.progs.on_sched_process_fork.attach()?,
+            self.skel.progs.on_sched_process_exec.attach()?,
+            self.skel.progs.on_sched_process_exit.attach()?,
+            self.skel.progs.on_cpu_idle.attach()?,
         ];
 
         Ok(())
@@ -0,0 +1,409 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+use crate::Action;
+
+use anyhow::Result;
+use glob::glob;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Max number of events kept in the pre-trigger buffer.
+pub const MAX_PRE_TRIGGER_EVENTS: usize = 1 << 20;
+
+/// Rule that starts a trace when it matches.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TriggerRule {
+    /// The p99 DSQ latency of any CPU exceeds the threshold.
+    DsqLatP99 { threshold_us: u64 },
+    /// The scheduler unregistered.
+    SchedUnreg,
+    /// More events than the threshold were dropped within a tick.
+    DroppedEvents { threshold: u64 },
+    /// A scheduler stat crosses the threshold. The stat is either a top level key or a JSON
+    /// pointer (e.g. "/layers/batch/util") into the scheduler stats.
+    SchedStat {
+        stat: String,
+        threshold: f64,
+        #[serde(default)]
+        below: bool,
+    },
+}
+
+impl TriggerRule {
+    /// Returns the reason of the trigger if the rule matches the metrics of a tick.
+    fn check_tick(&self, metrics: &TickMetrics) -> Option<String> {
+        match self {
+            TriggerRule::DsqLatP99 { threshold_us } => metrics
+                .dsq_lat_p99_us
+                .iter()
+                .find(|(_, p99)| **p99 > *threshold_us)
+                .map(|(cpu, p99)| {
+                    format!("cpu {} dsq latency p99 {}us > {}us", cpu, p99, threshold_us)
+                }),
+            TriggerRule::SchedUnreg => None,
+            TriggerRule::DroppedEvents { threshold } => (metrics.dropped_events > *threshold)
+                .then(|| format!("{} dropped events > {}", metrics.dropped_events, threshold)),
+            TriggerRule::SchedStat {
+                stat,
+                threshold,
+                below,
+            } => {
+                let stats = metrics.sched_stats?;
+                let value = if stat.starts_with('/') {
+                    stats.pointer(stat)
+                } else {
+                    stats.get(stat)
+                }?
+                .as_f64()?;
+                let crossed = if *below {
+                    value < *threshold
+                } else {
+                    value > *threshold
+                };
+                crossed.then(|| {
+                    format!(
+                        "{} {} {} {}",
+                        stat,
+                        value,
+                        if *below { "<" } else { ">" },
+                        threshold
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Metrics of a tick the trigger rules are checked against.
+#[derive(Default)]
+pub struct TickMetrics<'a> {
+    pub dsq_lat_p99_us: BTreeMap<usize, u64>,
+    pub dropped_events: u64,
+    pub sched_stats: Option<&'a JsonValue>,
+}
+
+/// Returns the timestamp of actions that are recorded in traces.
+pub fn action_ts(action: &Action) -> Option<u64> {
+    match action {
+        Action::SchedSwitch(a) => Some(a.ts),
+        Action::SchedWakeup(a) | Action::SchedWaking(a) | Action::SchedWakeupNew(a) => Some(a.ts),
+        Action::SoftIRQ(a) => Some(a.exit_ts),
+        Action::HardIRQ(a) => Some(a.exit_ts),
+        Action::IPI(a) => Some(a.ts),
+        Action::GpuMem(a) => Some(a.ts),
+        Action::Cpuhp(a) => Some(a.ts),
+        Action::SchedMigrateTask(a) => Some(a.ts),
+        Action::SchedProcessFork(a) => Some(a.ts),
+        Action::SchedProcessExec(a) => Some(a.ts),
+        Action::SchedProcessExit(a) => Some(a.ts),
+        Action::CpuIdle(a) => Some(a.ts),
+        _ => None,
+    }
+}
+
+/// TriggerManager checks the trigger rules and keeps the events preceding a trigger so they
+/// can be included in the trace.
+///
+/// The pre-trigger events are sparse compared to a regular trace: they are sampled at the
+/// configured sample rate and events of the trace only BPF programs (IRQs, IPIs, migrations,
+/// process lifecycle, idle) are only collected once the trace starts.
+pub struct TriggerManager {
+    rules: Vec<TriggerRule>,
+    pre_trigger_ns: u64,
+    max_events: usize,
+    max_traces: usize,
+    cooldown: Duration,
+    buffer: VecDeque<(u64, Action)>,
+    last_dropped_events: Option<u64>,
+    last_trace_end: Option<Instant>,
+}
+
+impl TriggerManager {
+    /// Creates a new TriggerManager.
+    pub fn new(
+        rules: Vec<TriggerRule>,
+        pre_trigger_ns: u64,
+        max_traces: usize,
+        cooldown: Duration,
+    ) -> Self {
+        Self {
+            rules,
+            pre_trigger_ns,
+            max_events: MAX_PRE_TRIGGER_EVENTS,
+            max_traces,
+            cooldown,
+            buffer: VecDeque::new(),
+            last_dropped_events: None,
+            last_trace_end: None,
+        }
+    }
+
+    /// Returns if any trigger rule is configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Returns if any rule needs the scheduler stats.
+    pub fn needs_sched_stats(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| matches!(rule, TriggerRule::SchedStat { .. }))
+    }
+
+    /// Adds an event to the pre-trigger buffer, dropping the ones that became too old and the
+    /// oldest ones beyond the max number of buffered events.
+    pub fn record(&mut self, ts: u64, action: &Action) {
+        if !self.is_enabled() || self.pre_trigger_ns == 0 {
+            return;
+        }
+        if self.buffer.len() >= self.max_events {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back((ts, action.clone()));
+        let cutoff = ts.saturating_sub(self.pre_trigger_ns);
+        while self.buffer.front().is_some_and(|(ts, _)| *ts < cutoff) {
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Returns the buffered events recorded within the pre-trigger duration before ts.
+    pub fn take_buffer(&mut self, ts: u64) -> Vec<Action> {
+        let cutoff = ts.saturating_sub(self.pre_trigger_ns);
+        self.buffer
+            .drain(..)
+            .filter(|(event_ts, _)| *event_ts >= cutoff && *event_ts <= ts)
+            .map(|(_, action)| action)
+            .collect()
+    }
+
+    fn cooling_down(&self, now: Instant) -> bool {
+        self.last_trace_end
+            .is_some_and(|end| now.duration_since(end) < self.cooldown)
+    }
+
+    /// Checks the rules against the metrics of a tick and returns the reason of a trigger.
+    pub fn on_tick(&mut self, metrics: &TickMetrics, now: Instant) -> Option<String> {
+        // the dropped events are cumulative
+        let dropped_events = metrics.dropped_events;
+        let metrics = TickMetrics {
+            dropped_events: dropped_events
+                .saturating_sub(self.last_dropped_events.unwrap_or(dropped_events)),
+            dsq_lat_p99_us: metrics.dsq_lat_p99_us.clone(),
+            sched_stats: metrics.sched_stats,
+        };
+        self.last_dropped_events = Some(dropped_events);
+
+        if self.cooling_down(now) {
+            return None;
+        }
+        self.rules.iter().find_map(|rule| rule.check_tick(&metrics))
+    }
+
+    /// Returns the reason of a trigger when the scheduler unregistered.
+    pub fn on_sched_unreg(&mut self, now: Instant) -> Option<String> {
+        if self.cooling_down(now) || !self.rules.contains(&TriggerRule::SchedUnreg) {
+            return None;
+        }
+        Some("scheduler unregistered".to_string())
+    }
+
+    /// Returns the file of a new triggered trace.
+    pub fn trace_file(&self, prefix: &str) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards");
+        format!("{}_trigger_{}.proto", prefix, now.as_millis())
+    }
+
+    /// Marks the end of a triggered trace and removes the oldest trace files of the prefix
+    /// beyond the maximum number of traces.
+    pub fn on_trace_done(&mut self, prefix: &str, now: Instant) -> Result<()> {
+        self.last_trace_end = Some(now);
+        self.buffer.clear();
+        if self.max_traces == 0 {
+            return Ok(());
+        }
+
+        let mut files: Vec<_> = glob(&format!("{}_trigger_*.proto", prefix))?
+            .flatten()
+            .collect();
+        // the file names end with the creation time in ms
+        files.sort();
+        if files.len() > self.max_traces {
+            for file in &files[..files.len() - self.max_traces] {
+                fs::remove_file(file)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchedCpuPerfSetAction;
+
+    fn manager(rules: Vec<TriggerRule>) -> TriggerManager {
+        TriggerManager::new(rules, 1_000, 2, Duration::from_secs(10))
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        #[derive(Deserialize)]
+        struct Rules {
+            triggers: Vec<TriggerRule>,
+        }
+        let rules: Rules = toml::from_str(
+            r#"
+            [[triggers]]
+            kind = "dsq_lat_p99"
+            threshold_us = 5000
+
+            [[triggers]]
+            kind = "sched_unreg"
+
+            [[triggers]]
+            kind = "sched_stat"
+            stat = "/layers/batch/util"
+            threshold = 10.0
+            below = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.triggers,
+            vec![
+                TriggerRule::DsqLatP99 { threshold_us: 5000 },
+                TriggerRule::SchedUnreg,
+                TriggerRule::SchedStat {
+                    stat: "/layers/batch/util".to_string(),
+                    threshold: 10.0,
+                    below: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tick_rules() {
+        let now = Instant::now();
+        let stats = serde_json::json!({"busy": 95.0, "layers": {"batch": {"util": 5.0}}});
+        let mut trigger = manager(vec![
+            TriggerRule::DroppedEvents { threshold: 100 },
+            TriggerRule::SchedStat {
+                stat: "busy".to_string(),
+                threshold: 99.0,
+                below: false,
+            },
+        ]);
+
+        let metrics = TickMetrics {
+            dropped_events: 1000,
+            sched_stats: Some(&stats),
+            ..Default::default()
+        };
+        // the first tick sets the base of the dropped events
+        assert_eq!(trigger.on_tick(&metrics, now), None);
+        let metrics = TickMetrics {
+            dropped_events: 1050,
+            sched_stats: Some(&stats),
+            ..Default::default()
+        };
+        assert_eq!(trigger.on_tick(&metrics, now), None);
+        let metrics = TickMetrics {
+            dropped_events: 1200,
+            sched_stats: Some(&stats),
+            ..Default::default()
+        };
+        assert!(trigger.on_tick(&metrics, now).is_some());
+
+        let mut trigger = manager(vec![TriggerRule::SchedStat {
+            stat: "/layers/batch/util".to_string(),
+            threshold: 10.0,
+            below: true,
+        }]);
+        let metrics = TickMetrics {
+            sched_stats: Some(&stats),
+            ..Default::default()
+        };
+        assert!(trigger.on_tick(&metrics, now).is_some());
+
+        let mut trigger = manager(vec![TriggerRule::DsqLatP99 { threshold_us: 500 }]);
+        let metrics = TickMetrics {
+            dsq_lat_p99_us: BTreeMap::from([(0, 100), (3, 700)]),
+            ..Default::default()
+        };
+        let reason = trigger.on_tick(&metrics, now).unwrap();
+        assert!(reason.starts_with("cpu 3"));
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let dir = std::env::temp_dir().join(format!("scxtop_trigger_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("trace").to_string_lossy().to_string();
+
+        let now = Instant::now();
+        let mut trigger = manager(vec![TriggerRule::SchedUnreg]);
+        assert!(trigger.on_sched_unreg(now).is_some());
+        trigger.on_trace_done(&prefix, now).unwrap();
+        assert!(trigger.on_sched_unreg(now).is_none());
+        assert!(trigger
+            .on_sched_unreg(now + Duration::from_secs(11))
+            .is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = std::env::temp_dir().join(format!("scxtop_rotation_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prefix = dir.join("trace").to_string_lossy().to_string();
+        for ms in [1000, 2000, 3000] {
+            fs::write(format!("{}_trigger_{}.proto", prefix, ms), b"").unwrap();
+        }
+
+        let mut trigger = manager(vec![TriggerRule::SchedUnreg]);
+        trigger.on_trace_done(&prefix, Instant::now()).unwrap();
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["trace_trigger_2000.proto", "trace_trigger_3000.proto"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pre_trigger_buffer() {
+        let mut trigger = manager(vec![TriggerRule::SchedUnreg]);
+        let action = Action::SchedCpuPerfSet(SchedCpuPerfSetAction { cpu: 0, perf: 0 });
+        for ts in [100, 600, 1200, 1800, 2500] {
+            trigger.record(ts, &action);
+        }
+        // events older than 1000ns before the newest one are dropped
+        assert_eq!(trigger.buffer.len(), 2);
+        assert_eq!(trigger.take_buffer(2000).len(), 1);
+        assert!(trigger.buffer.is_empty());
+
+        // the oldest events are dropped beyond the max number of events
+        trigger.max_events = 3;
+        for ts in 0..5 {
+            trigger.record(ts, &action);
+        }
+        assert_eq!(trigger.buffer.len(), 3);
+        assert_eq!(trigger.buffer.front().map(|(ts, _)| *ts), Some(2));
+    }
+}
@@ -24,3 +24,14 @@ pub fn format_hz(hz: u64) -> String {
         _ => format!("{:.3}THz", hz as f64 / 1_000_000_000.0),
     }
 }
+
+/// Formats a duration in ns to human readable.
+pub fn format_ns(ns: u64) -> String {
+    match ns {
+        0..=999 => format!("{}ns", ns),
+        1_000..=999_999 => format!("{:.1}us", ns as f64 / 1_000.0),
+        1_000_000..=999_999_999 => format!("{:.1}ms", ns as f64 / 1_000_000.0),
+        u64::MAX => "inf".to_string(),
+        _ => format!("{:.1}s", ns as f64 / 1_000_000_000.0),
+    }
+}